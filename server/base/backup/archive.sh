@@ -0,0 +1,24 @@
+#!/bin/bash
+
+# Writes a tar of the backup with the given id
+# to stdout, containing mysqldump.sql and uploads/
+
+. /opt/lib.sh
+
+if [ -z "$1" ]; then
+    echo "Backup id is required" >&2
+    exit 1
+fi
+if [ ! -d "$VAR_DIR/repo/.git" ]; then
+    echo "No backup repository yet" >&2
+    exit 1
+fi
+
+cd "$VAR_DIR/repo"
+ensure_full_history
+commit=$(backup_commit "$1")
+if [ -z "$commit" ]; then
+    echo "No backup $1" >&2
+    exit 1
+fi
+git archive --format=tar "$commit"
@@ -1,9 +1,8 @@
 #!/bin/bash
 
-GIT_USER="brygga-dev"
-GIT_EMAIL="52463886+brygga-dev@users.noreply.github.com"
-GIT_REPO="https://brygga-dev@github.com/brygga-dev/brygga-backup.git"
-
+# The backup repo is cloned, committed and pushed by
+# backup-agent, with the repo, branch and credentials
+# of its BACKUP_GIT_* config. Scripts here only read it
 VAR_DIR="/var/lib/docker-backup"
 MYSQLDUMP_FILE="$VAR_DIR/repo/mysqldump.sql"
 
@@ -17,7 +16,7 @@ wait_for_lock_file()
         lastModificationSeconds=$(date +%s -r "$LOCK_FILE")
         currentSeconds=$(date +%s)
         ignoreAt=$((currentSeconds - 600))
-        if [ $lastModificationSeconds -lt $ignoreAt ]; then
+        if [ $lastModificationSeconds -ge $ignoreAt ]; then
             waits=0
             while [ -f $LOCK_FILE ]; do
                     if [ $waits -eq 10 ]; then
@@ -49,58 +48,20 @@ restore_db()
     fi
 }
 
-# uses folders:
-# - /var/lib/docker-backup
-# - /var/lib/docker-backup/init_repo    Initial fetch of repo, which is then moved to ../repo
-# - /var/lib/docker-backup/repo         Main location of backup files, upload and mysqldump.sql is here
-
-# Setup git repo if not already
-ensure_git_repo()
+# Commit of a backup id. Ids are the author date as
+# YYYYmmddHHMMSS, as commit hashes change when
+# old backups are dropped
+backup_commit()
 {
-    if [ ! -d "$VAR_DIR/repo/.git" ]; then
-        echo "Initializing repo"
-        # todo: Check return status code
-        git clone --depth 1 $GIT_REPO "$VAR_DIR/init_repo"
-        cd "$VAR_DIR/init_repo"
-        git config user.name $GIT_USER
-        git config user.email $GIT_EMAIL
-        # Move with update flag that only
-        # replaces if newer
-        echo "Moving files from git"
-        mv -n * ../repo/
-
-        # Permissions
-        ensure_repo_permissions
-
-        # Assuming this is initial run, restore database
-        restore_db
-
-        mv .git ../repo/
-        cd ..
-        rm -rf init_repo
-    fi
+    git log --date=format:%Y%m%d%H%M%S --format="%H %ad" \
+        | awk -v id="$1" '$2 == id { print $1; exit }'
 }
 
-push_git()
+# Repo is cloned shallow, fetch history
+# when older commits are needed
+ensure_full_history()
 {
-  # First make sure repo is updated for robustness
-  # todo: check better vs origin
-  echo "Stashing"
-  git add .
-  git stash > /dev/null
-  echo "Pulling"
-  git pull
-  echo "Pop"
-  #git stash pop > /dev/null
-  #https://stackoverflow.com/questions/16606203/force-git-stash-to-overwrite-added-files
-  git checkout stash -- .
-
-  # Add, commit and push
-  echo "Add"
-  # I think not needed as I did it before stash now
-  git add .
-  echo "Commit"
-  git commit -m "$1 $(date)"
-  echo "Push"
-  git push origin master
+    if [ -f "$(git rev-parse --git-dir)/shallow" ]; then
+        git fetch --unshallow -q >&2
+    fi
 }
@@ -0,0 +1,17 @@
+#!/bin/bash
+
+# Lists backups as commits, one per line:
+# <id>|<iso date>|<message>
+# The id is the author date, which is kept when
+# old backups are dropped, see backup_commit
+
+. /opt/lib.sh
+
+if [ ! -d "$VAR_DIR/repo/.git" ]; then
+    echo "No backup repository yet" >&2
+    exit 1
+fi
+
+cd "$VAR_DIR/repo"
+ensure_full_history
+git log --date=format:%Y%m%d%H%M%S --format="%ad|%aI|%s"
@@ -1,46 +1,49 @@
 #!/bin/bash
 
-# Passing and argument will clone a certain
-# commit, and set it up as the contents of
-# the `repo` folder, then run the mysqldump
-# from the commit
+# Passing a backup id will check out the backup's
+# commit from the agent's repo, and set it up as the
+# contents of the `repo` folder, then run the mysqldump
+# from the commit. Without an id, the latest backup
+# is restored
 
 . /opt/lib.sh
 
+if [ ! -d "$VAR_DIR/repo/.git" ]; then
+    echo "No backup repository yet" >&2
+    exit 1
+fi
+
 wait_for_lock_file
 
 touch "$LOCK_FILE"
 
-ensure_git_repo
-
-# Cloning repo, this adds the checkout as a new
-# commit, but there should be better ways to do this
-# probably..
-# Not sure git reset --hard is good as keeping the history
-# can be an advantage
-echo "Cloning repo"
-git clone $GIT_REPO "$VAR_DIR/restore_repo"
-cd "$VAR_DIR/restore_repo"
+cd "$VAR_DIR/repo"
+ensure_full_history
 
-# Check if a has was provided as argument
+commit="HEAD"
+# Check if a backup id was provided as argument
 if [ ! -z "$1" ]; then
-    git checkout $1
+    commit=$(backup_commit "$1")
+    if [ -z "$commit" ]; then
+        echo "No backup $1"
+        rm "$LOCK_FILE"
+        exit 1
+    fi
 fi
 
-rm -rf .git
+# Extracting the backup outside of the repo, keeping
+# history as it is
+echo "Extracting backup"
+rm -rf "$VAR_DIR/restore_repo"
+mkdir -p "$VAR_DIR/restore_repo"
+git archive --format=tar "$commit" | tar -x -C "$VAR_DIR/restore_repo"
+cd "$VAR_DIR/restore_repo"
+
 # Just replacing all files. Possibly some option to "merge",
 # but doesn't always make sense, there wouldn't be database
 # entries any more to other files.
-# todo, possibly could do a stash, pull, pop, push for
-# any last changes. Would be nice with code sharing
 echo "Restoring files from git"
 
-# Move .git out so we can simply move repository file
-#if [ -d "$VAR_DIR/repo/.git" ]; then
-#    echo "Moving .git out of /repo/"
-#    mv "$VAR_DIR/repo/.git" "$VAR_DIR/.git"
-#fi
-
 # In dev mode, uploads is currently mounted, so
 # it's delete gives errors.
 
@@ -53,7 +56,7 @@ rm -rf "$VAR_DIR/repo/uploads/"*
 echo "Removed uploads"
 ls "$VAR_DIR/repo/uploads"
 
-# Moving the cloned/restored files to `repo` folder
+# Moving the restored files to `repo` folder
 if [ -d "uploads" ]; then
     echo "Moving uploads"
     mv uploads/* "$VAR_DIR/repo/uploads/"
@@ -70,13 +73,7 @@ fi
 # Permissions
 ensure_repo_permissions
 
-# Then moving the .git in again
-#if [ -d "$VAR_DIR/.git" ]; then
-#    echo "Moving .git back to /repo/"
-#    mv "$VAR_DIR/.git" "$VAR_DIR/repo/.git"
-#fi
-
-# Now deleting the empty restore folder
+# Now deleting the restore folder
 cd ..
 rm -rf restore_repo
 
@@ -87,9 +84,10 @@ restore_db
 
 # TODO: This could be from an earlier wp version, so we should run wp upgrade/migrate
 
-# Quick commit, todo: modularize to some functions
-push_git "Restore to $1"
-
 rm "$LOCK_FILE"
 
-echo "Restored from commit $1"
\ No newline at end of file
+# The agent commits and pushes the restored state
+# with its own repo, branch and credentials
+/usr/local/bin/backup-agent run --force
+
+echo "Restored backup $1"
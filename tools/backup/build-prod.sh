@@ -0,0 +1,3 @@
+#!/bin/bash
+cargo build --release
+cp ../target/release/backup ../../server/base/backup/backup-agent
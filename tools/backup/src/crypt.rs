@@ -0,0 +1,104 @@
+use crate::er::{err_msg, Result};
+use openssl::hash::MessageDigest;
+use openssl::symm::{self, Cipher};
+use std::collections::HashMap;
+
+/// Prefix of encrypted objects, also used as
+/// additional authenticated data
+const MAGIC: &[u8] = b"WOPBAK1";
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_ITERATIONS: usize = 100_000;
+
+/// Aes-256-gcm with key derived from a passphrase.
+/// Output is: magic, salt, iv, tag, ciphertext.
+/// Key derivation is slow by design, so one salt is used for
+/// everything encrypted by this instance, and derived keys
+/// are cached when decrypting
+pub struct Crypter {
+    passphrase: String,
+    salt: [u8; SALT_LEN],
+    keys: HashMap<Vec<u8>, [u8; 32]>,
+}
+impl Crypter {
+    pub fn new(passphrase: &str) -> Result<Crypter> {
+        let mut salt = [0; SALT_LEN];
+        openssl::rand::rand_bytes(&mut salt)?;
+        Ok(Crypter {
+            passphrase: passphrase.to_string(),
+            salt,
+            keys: HashMap::new(),
+        })
+    }
+
+    fn key(&mut self, salt: &[u8]) -> Result<[u8; 32]> {
+        if let Some(key) = self.keys.get(salt) {
+            return Ok(*key);
+        }
+        let mut key = [0; 32];
+        openssl::pkcs5::pbkdf2_hmac(
+            self.passphrase.as_bytes(),
+            salt,
+            KEY_ITERATIONS,
+            MessageDigest::sha256(),
+            &mut key,
+        )?;
+        self.keys.insert(salt.to_vec(), key);
+        Ok(key)
+    }
+
+    pub fn encrypt(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let salt = self.salt;
+        let key = self.key(&salt)?;
+        let mut iv = [0; IV_LEN];
+        openssl::rand::rand_bytes(&mut iv)?;
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = symm::encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key,
+            Some(&iv),
+            MAGIC,
+            data,
+            &mut tag,
+        )?;
+        let mut out =
+            Vec::with_capacity(MAGIC.len() + SALT_LEN + IV_LEN + TAG_LEN + ciphertext.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&salt);
+        out.extend_from_slice(&iv);
+        out.extend_from_slice(&tag);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    pub fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let header_len = MAGIC.len() + SALT_LEN + IV_LEN + TAG_LEN;
+        if data.len() < header_len || &data[..MAGIC.len()] != MAGIC {
+            return err_msg("Not an encrypted backup object");
+        }
+        let (salt, rest) = data[MAGIC.len()..].split_at(SALT_LEN);
+        let (iv, rest) = rest.split_at(IV_LEN);
+        let (tag, ciphertext) = rest.split_at(TAG_LEN);
+        let key = self.key(salt)?;
+        match symm::decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key,
+            Some(iv),
+            MAGIC,
+            ciphertext,
+            tag,
+        ) {
+            Ok(plain) => Ok(plain),
+            Err(_) => err_msg("Could not decrypt, wrong passphrase?"),
+        }
+    }
+}
+
+/// Hex encoded sha256
+pub fn sha256_hex(data: &[u8]) -> String {
+    openssl::sha::sha256(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
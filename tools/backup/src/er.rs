@@ -0,0 +1,60 @@
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, BackupError>;
+
+pub enum BackupError {
+    Db(mysql_utils::er::MyLibError),
+    Git(git2::Error),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Crypto(openssl::error::ErrorStack),
+    Msg(String),
+}
+
+pub fn err_msg<T>(msg: impl Into<String>) -> Result<T> {
+    Err(BackupError::Msg(msg.into()))
+}
+
+impl fmt::Debug for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Msg(msg) => write!(f, "{}", msg),
+            Self::Db(e) => write!(f, "Db: {:?}", e),
+            Self::Git(e) => write!(f, "Git: {}", e),
+            Self::Io(e) => write!(f, "Io: {:?}", e),
+            Self::Json(e) => write!(f, "Json: {:?}", e),
+            Self::Crypto(e) => write!(f, "Crypto: {}", e),
+        }
+    }
+}
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<mysql_utils::er::MyLibError> for BackupError {
+    fn from(e: mysql_utils::er::MyLibError) -> Self {
+        BackupError::Db(e)
+    }
+}
+impl From<git2::Error> for BackupError {
+    fn from(e: git2::Error) -> Self {
+        BackupError::Git(e)
+    }
+}
+impl From<std::io::Error> for BackupError {
+    fn from(e: std::io::Error) -> Self {
+        BackupError::Io(e)
+    }
+}
+impl From<serde_json::Error> for BackupError {
+    fn from(e: serde_json::Error) -> Self {
+        BackupError::Json(e)
+    }
+}
+impl From<openssl::error::ErrorStack> for BackupError {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        BackupError::Crypto(e)
+    }
+}
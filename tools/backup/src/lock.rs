@@ -0,0 +1,64 @@
+use crate::er::{err_msg, Result};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Lock files older than this are considered left
+/// over from a crashed run, and taken over
+const STALE_AFTER: Duration = Duration::from_secs(600);
+const WAIT_STEP: Duration = Duration::from_secs(2);
+const MAX_WAITS: u32 = 10;
+
+/// Lock file shared with the restore script, so a
+/// backup never runs while restoring and vice versa.
+/// Removed when dropped
+pub struct Lock {
+    path: PathBuf,
+}
+impl Lock {
+    pub fn acquire(path: PathBuf) -> Result<Lock> {
+        let mut waits = 0;
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(Lock { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if Self::is_stale(&path) {
+                        println!("Taking over stale lock file");
+                        fs::remove_file(&path)?;
+                        continue;
+                    }
+                    if waits == MAX_WAITS {
+                        return err_msg("Timeout waiting for lock file");
+                    }
+                    waits += 1;
+                    println!("Waiting for lock {}/{}", waits, MAX_WAITS);
+                    std::thread::sleep(WAIT_STEP);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn is_stale(path: &PathBuf) -> bool {
+        let modified = match fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            // Possibly removed meanwhile
+            Err(_) => return false,
+        };
+        match SystemTime::now().duration_since(modified) {
+            Ok(age) => age > STALE_AFTER,
+            Err(_) => false,
+        }
+    }
+}
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            eprintln!("Could not remove lock file: {:?}", e);
+        }
+    }
+}
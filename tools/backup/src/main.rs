@@ -0,0 +1,323 @@
+// Backup agent run periodically in the backup container.
+// Dumps the database as per table files, which diff well,
+// together with a full sql dump for restoring, and commits
+// them with uploads to the backup git repository.
+// When configured, also makes daily encrypted snapshots
+// to an S3 compatible storage, see offsite.rs
+
+mod crypt;
+mod er;
+mod lock;
+mod offsite;
+mod repo;
+mod status;
+
+use clap::{App, Arg, SubCommand};
+use er::{err_msg, Result};
+use lock::Lock;
+use mysql_utils::Db;
+use offsite::{OffsiteConfig, OffsiteState};
+use status::Status;
+use std::path::PathBuf;
+use std::time::Instant;
+
+pub struct Config {
+    pub var_dir: PathBuf,
+    pub db_host: String,
+    pub db_port: u16,
+    pub db_name: String,
+    pub db_user: String,
+    pub db_pass: String,
+    pub git_repo: String,
+    pub git_user: String,
+    pub git_email: String,
+    pub git_token: Option<String>,
+    /// Number of backups to keep, 0 keeps all
+    pub keep: usize,
+}
+impl Config {
+    pub fn repo_dir(&self) -> PathBuf {
+        self.var_dir.join("repo")
+    }
+
+    fn lock_file(&self) -> PathBuf {
+        self.var_dir.join(".lock")
+    }
+
+    fn status_file(&self) -> PathBuf {
+        self.var_dir.join("status.json")
+    }
+
+    fn dump_date_file(&self) -> PathBuf {
+        self.var_dir.join("mysqldump.date")
+    }
+
+    fn retention_date_file(&self) -> PathBuf {
+        self.var_dir.join("retention.date")
+    }
+}
+
+fn main() {
+    let matches = App::new("backup-agent")
+        .version("0.1")
+        .arg(
+            Arg::with_name("var-dir")
+                .long("var-dir")
+                .env("BACKUP_VAR_DIR")
+                .default_value("/var/lib/docker-backup"),
+        )
+        .arg(
+            Arg::with_name("db-host")
+                .long("db-host")
+                .env("BACKUP_DB_HOST")
+                .default_value("db"),
+        )
+        .arg(
+            Arg::with_name("db-port")
+                .long("db-port")
+                .env("BACKUP_DB_PORT")
+                .default_value("3306"),
+        )
+        .arg(
+            Arg::with_name("db-name")
+                .long("db-name")
+                .env("BACKUP_DB_NAME")
+                .default_value("wordpress"),
+        )
+        .arg(
+            Arg::with_name("db-user")
+                .long("db-user")
+                .env("BACKUP_DB_USER")
+                .default_value("wordpress"),
+        )
+        .arg(
+            Arg::with_name("db-pass")
+                .long("db-pass")
+                .env("BACKUP_DB_PASS")
+                .default_value("wordpress"),
+        )
+        .arg(
+            Arg::with_name("git-repo")
+                .long("git-repo")
+                .env("BACKUP_GIT_REPO")
+                .default_value("https://brygga-dev@github.com/brygga-dev/brygga-backup.git"),
+        )
+        .arg(
+            Arg::with_name("git-user")
+                .long("git-user")
+                .env("BACKUP_GIT_USER")
+                .default_value("brygga-dev"),
+        )
+        .arg(
+            Arg::with_name("git-email")
+                .long("git-email")
+                .env("BACKUP_GIT_EMAIL")
+                .default_value("52463886+brygga-dev@users.noreply.github.com"),
+        )
+        .arg(
+            Arg::with_name("git-token")
+                .long("git-token")
+                .env("BACKUP_GIT_TOKEN")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep")
+                .long("keep")
+                .env("BACKUP_KEEP")
+                .default_value("0")
+                .help("Number of backups to keep, 0 keeps all"),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Makes a backup if the database changed since last, and offsite snapshot when due")
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Dump, commit and make offsite snapshot regardless of changes"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("status").about("Prints status of last run as json"))
+        .get_matches();
+
+    let value = |name: &str| matches.value_of(name).unwrap_or_default().to_string();
+    let config = Config {
+        var_dir: PathBuf::from(value("var-dir")),
+        db_host: value("db-host"),
+        db_port: value("db-port").parse().unwrap_or(3306),
+        db_name: value("db-name"),
+        db_user: value("db-user"),
+        db_pass: value("db-pass"),
+        git_repo: value("git-repo"),
+        git_user: value("git-user"),
+        git_email: value("git-email"),
+        git_token: matches
+            .value_of("git-token")
+            .filter(|token| !token.is_empty())
+            .map(String::from),
+        keep: value("keep").parse().unwrap_or(0),
+    };
+    match matches.subcommand() {
+        ("status", Some(_)) => {
+            let status = Status::read(&config.status_file());
+            match serde_json::to_string_pretty(&status) {
+                Ok(json) => println!("{}", json),
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        ("run", Some(sub_matches)) => {
+            if !run(&config, sub_matches.is_present("force")) {
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            eprintln!("{}", matches.usage());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs backup and records outcome in status file
+fn run(config: &Config, force: bool) -> bool {
+    let started = Instant::now();
+    let mut status = Status::read(&config.status_file());
+    let now = chrono::Local::now().to_rfc3339();
+    status.last_run = Some(now.clone());
+    let retry_push = status.last_error.is_some();
+    let result = Lock::acquire(config.lock_file())
+        .and_then(|_lock| backup(config, force, retry_push, &mut status));
+    let success = match result {
+        Ok(()) => {
+            status.last_success = Some(now);
+            status.last_error = None;
+            true
+        }
+        Err(e) => {
+            eprintln!("Backup failed: {:?}", e);
+            status.last_error = Some(format!("{:?}", e));
+            false
+        }
+    };
+    status.duration_ms = started.elapsed().as_millis() as u64;
+    if let Err(e) = status.write(&config.status_file()) {
+        eprintln!("Could not write status: {:?}", e);
+    }
+    success
+}
+
+fn backup(config: &Config, force: bool, retry_push: bool, status: &mut Status) -> Result<()> {
+    let (repo, cloned) = repo::ensure_repo(config)?;
+    let repo_dir = config.repo_dir();
+    let dump_file = repo_dir.join("mysqldump.sql");
+    if cloned && dump_file.is_file() {
+        // Assuming this is initial run, restore database
+        restore_db(config, &dump_file)?;
+    }
+
+    let mut db = Db::new(
+        &config.db_host,
+        config.db_port,
+        &config.db_name,
+        &config.db_user,
+        &config.db_pass,
+    )?;
+    // Mysql gives null when not changed since it started,
+    // so comparing with time of last dump when there is one
+    let last_update = db.last_update()?;
+    let last_dump = std::fs::read_to_string(config.dump_date_file())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default();
+    println!(
+        "Last modification: {}, last dump: {}",
+        last_update.as_ref().map(String::as_str).unwrap_or("NULL"),
+        last_dump
+    );
+    let changed = match &last_update {
+        Some(last_update) => *last_update > last_dump,
+        None => false,
+    };
+    if force || changed || !dump_file.is_file() {
+        println!("Dumping database");
+        db.dump_sql(Box::new(std::fs::File::create(&dump_file)?))?;
+        db.tables_to_sexpr_files_in(&repo_dir.join("tables"))?;
+        std::fs::write(
+            config.dump_date_file(),
+            chrono::Local::now().format("%Y%m%d%H%M%S").to_string(),
+        )?;
+    }
+
+    let message = format!(
+        "{} {}",
+        if force {
+            "Manual backup"
+        } else {
+            "Cron backup"
+        },
+        chrono::Local::now().to_rfc2822()
+    );
+    let sig = git2::Signature::now(&config.git_user, &config.git_email)?;
+    let commit = repo::commit_all(&repo, &sig, &message)?;
+    match commit {
+        Some(oid) => {
+            println!("Changes detected, made commit {}", oid);
+            status.last_commit = Some(oid.to_string());
+            status.last_commit_time = status.last_run.clone();
+        }
+        None => println!("No changes detected"),
+    }
+    // Dropping old backups rewrites history, so
+    // only done once a day
+    let today = chrono::Local::now().format("%Y%m%d").to_string();
+    let last_retention = std::fs::read_to_string(config.retention_date_file())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default();
+    if last_retention != today {
+        if repo::apply_retention(&repo, config.keep)? {
+            // Commit ids change when rewritten
+            let head = repo.head()?.peel_to_commit()?;
+            status.last_commit = Some(head.id().to_string());
+            status.force_push = true;
+        }
+        std::fs::write(config.retention_date_file(), &today)?;
+    }
+    // Rewritten history stays force pushed until a push
+    // succeeds, also when retention ran in an earlier run
+    if commit.is_some() || status.force_push || retry_push {
+        println!("Pushing");
+        repo::push(&repo, config, status.force_push)?;
+        status.force_push = false;
+    }
+    status.commits = repo::commit_count(&repo)?;
+
+    if let Some(offsite_config) = OffsiteConfig::read(&config.var_dir)? {
+        if force || OffsiteState::read(&config.var_dir).is_due() {
+            let name = offsite::snapshot(
+                &offsite_config,
+                &config.var_dir,
+                &dump_file,
+                &repo_dir.join("uploads"),
+            )?;
+            status.last_offsite = Some(name);
+        }
+    }
+    Ok(())
+}
+
+/// Loads sql dump with the mysql client
+fn restore_db(config: &Config, dump_file: &PathBuf) -> Result<()> {
+    println!("Restoring database");
+    let exit = std::process::Command::new("mysql")
+        .arg(format!("-u{}", config.db_user))
+        .arg(format!("-p{}", config.db_pass))
+        .arg(format!("-h{}", config.db_host))
+        .arg(format!("-P{}", config.db_port))
+        .arg(&config.db_name)
+        .stdin(std::fs::File::open(dump_file)?)
+        .status()?;
+    if !exit.success() {
+        return err_msg("Failed restoring database");
+    }
+    Ok(())
+}
@@ -0,0 +1,429 @@
+// Offsite backups to an S3 compatible object storage.
+// Each snapshot is an encrypted sql dump and a manifest
+// of upload files. Upload files are stored encrypted by
+// content hash, so only new or changed files are uploaded.
+// Layout under the configured prefix:
+//  snapshots/<%Y%m%d%H%M%S>/mysqldump.sql.enc
+//  snapshots/<%Y%m%d%H%M%S>/manifest.json.enc
+//  blobs/<sha256>
+
+use crate::crypt::{self, Crypter};
+use crate::er::{err_msg, BackupError, Result};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Utc};
+use rusoto_core::{HttpClient, Region};
+use rusoto_credential::StaticProvider;
+use rusoto_s3::{
+    DeleteObjectRequest, GetObjectRequest, ListObjectsV2Request, PutObjectRequest, S3Client, S3,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::Path;
+
+const SNAPSHOT_FORMAT: &str = "%Y%m%d%H%M%S";
+/// Scheduled snapshots are made when the
+/// last is older than this
+const SNAPSHOT_INTERVAL_HOURS: i64 = 24;
+
+/// Written to offsite.json in the backup volume by the cli,
+/// see `backup offsite`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OffsiteConfig {
+    pub bucket: String,
+    pub region: String,
+    /// Custom endpoint, as for MinIO
+    pub endpoint: Option<String>,
+    pub prefix: String,
+    pub key: String,
+    pub secret: String,
+    pub passphrase: String,
+    /// Number of daily, weekly and monthly
+    /// snapshots to keep
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+}
+impl OffsiteConfig {
+    pub fn read(var_dir: &Path) -> Result<Option<OffsiteConfig>> {
+        let file = var_dir.join("offsite.json");
+        if !file.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&std::fs::read(file)?)?))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedFile {
+    size: u64,
+    modified: u64,
+    hash: String,
+}
+
+/// Local state to avoid hashing unchanged files
+/// and uploading blobs already there
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct OffsiteState {
+    pub last_snapshot: Option<String>,
+    files: BTreeMap<String, CachedFile>,
+    blobs: BTreeSet<String>,
+}
+impl OffsiteState {
+    fn file(var_dir: &Path) -> std::path::PathBuf {
+        var_dir.join("offsite-state.json")
+    }
+
+    pub fn read(var_dir: &Path) -> OffsiteState {
+        std::fs::read(Self::file(var_dir))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, var_dir: &Path) -> Result<()> {
+        std::fs::write(Self::file(var_dir), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_due(&self) -> bool {
+        match self
+            .last_snapshot
+            .as_ref()
+            .and_then(|last| NaiveDateTime::parse_from_str(last, SNAPSHOT_FORMAT).ok())
+        {
+            Some(last) => {
+                Utc::now().naive_utc() - last >= chrono::Duration::hours(SNAPSHOT_INTERVAL_HOURS)
+            }
+            None => true,
+        }
+    }
+}
+
+/// Upload files of a snapshot, path relative to
+/// uploads dir -> blob hash
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Manifest {
+    files: BTreeMap<String, String>,
+}
+
+fn s3_err<E: std::fmt::Debug>(e: rusoto_core::RusotoError<E>) -> BackupError {
+    BackupError::Msg(format!("S3: {:?}", e))
+}
+
+struct Bucket<'a> {
+    client: S3Client,
+    config: &'a OffsiteConfig,
+}
+impl<'a> Bucket<'a> {
+    fn new(config: &'a OffsiteConfig) -> Result<Bucket<'a>> {
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                name: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => match config.region.parse::<Region>() {
+                Ok(region) => region,
+                Err(_) => return err_msg(format!("Unknown region: {}", config.region)),
+            },
+        };
+        let http_client = match HttpClient::new() {
+            Ok(http_client) => http_client,
+            Err(e) => return err_msg(format!("Could not create http client: {:?}", e)),
+        };
+        let credentials = StaticProvider::new_minimal(config.key.clone(), config.secret.clone());
+        Ok(Bucket {
+            client: S3Client::new_with(http_client, credentials, region),
+            config,
+        })
+    }
+
+    fn key(&self, path: &str) -> String {
+        if self.config.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.config.prefix.trim_end_matches('/'), path)
+        }
+    }
+
+    fn put(&self, path: &str, data: Vec<u8>) -> Result<()> {
+        self.client
+            .put_object(PutObjectRequest {
+                bucket: self.config.bucket.clone(),
+                key: self.key(path),
+                body: Some(data.into()),
+                ..Default::default()
+            })
+            .sync()
+            .map_err(s3_err)?;
+        Ok(())
+    }
+
+    fn get(&self, path: &str) -> Result<Vec<u8>> {
+        let output = self
+            .client
+            .get_object(GetObjectRequest {
+                bucket: self.config.bucket.clone(),
+                key: self.key(path),
+                ..Default::default()
+            })
+            .sync()
+            .map_err(s3_err)?;
+        let mut data = Vec::new();
+        match output.body {
+            Some(body) => {
+                body.into_blocking_read().read_to_end(&mut data)?;
+            }
+            None => return err_msg(format!("Empty object: {}", path)),
+        }
+        Ok(data)
+    }
+
+    fn delete(&self, path: &str) -> Result<()> {
+        self.client
+            .delete_object(DeleteObjectRequest {
+                bucket: self.config.bucket.clone(),
+                key: self.key(path),
+                ..Default::default()
+            })
+            .sync()
+            .map_err(s3_err)?;
+        Ok(())
+    }
+
+    /// Lists paths relative to prefix. With a delimiter,
+    /// "folders" are listed instead of objects
+    fn list(&self, path: &str, delimiter: Option<&str>) -> Result<Vec<String>> {
+        let full_prefix = self.key("");
+        let mut paths = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let output = self
+                .client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.config.bucket.clone(),
+                    prefix: Some(self.key(path)),
+                    delimiter: delimiter.map(String::from),
+                    continuation_token: continuation_token.take(),
+                    ..Default::default()
+                })
+                .sync()
+                .map_err(s3_err)?;
+            let keys = match delimiter {
+                Some(_) => output
+                    .common_prefixes
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|p| p.prefix)
+                    .collect::<Vec<_>>(),
+                None => output
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|o| o.key)
+                    .collect::<Vec<_>>(),
+            };
+            for key in keys {
+                if key.starts_with(&full_prefix) {
+                    paths.push(key[full_prefix.len()..].to_string());
+                }
+            }
+            match output.next_continuation_token {
+                Some(token) if output.is_truncated == Some(true) => {
+                    continuation_token = Some(token)
+                }
+                _ => break,
+            }
+        }
+        Ok(paths)
+    }
+}
+
+fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Uploads a snapshot of sql dump and uploads dir,
+/// then applies retention. Returns snapshot name
+pub fn snapshot(
+    config: &OffsiteConfig,
+    var_dir: &Path,
+    dump_file: &Path,
+    uploads_dir: &Path,
+) -> Result<String> {
+    let bucket = Bucket::new(config)?;
+    let mut crypter = Crypter::new(&config.passphrase)?;
+    let mut state = OffsiteState::read(var_dir);
+    let name = Utc::now().format(SNAPSHOT_FORMAT).to_string();
+    println!("Offsite snapshot {}", name);
+
+    // Upload files not there yet
+    let mut manifest = Manifest::default();
+    let mut files = BTreeMap::new();
+    let mut uploaded = 0;
+    if uploads_dir.is_dir() {
+        for entry in walkdir::WalkDir::new(uploads_dir) {
+            let entry = entry.map_err(|e| BackupError::Msg(format!("{:?}", e)))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let rel = match entry.path().strip_prefix(uploads_dir) {
+                Ok(rel) => rel.to_string_lossy().to_string(),
+                Err(_) => continue,
+            };
+            let metadata = entry
+                .metadata()
+                .map_err(|e| BackupError::Msg(format!("{:?}", e)))?;
+            let (size, modified) = (metadata.len(), modified_secs(&metadata));
+            // Files are read into memory, fine for
+            // regular uploads
+            let mut data = None;
+            let hash = match state.files.remove(&rel) {
+                Some(cached) if cached.size == size && cached.modified == modified => cached.hash,
+                _ => {
+                    let read = std::fs::read(entry.path())?;
+                    let hash = crypt::sha256_hex(&read);
+                    data = Some(read);
+                    hash
+                }
+            };
+            if !state.blobs.contains(&hash) {
+                let data = match data {
+                    Some(data) => data,
+                    None => std::fs::read(entry.path())?,
+                };
+                bucket.put(&format!("blobs/{}", hash), crypter.encrypt(&data)?)?;
+                state.blobs.insert(hash.clone());
+                uploaded += 1;
+            }
+            manifest.files.insert(rel.clone(), hash.clone());
+            files.insert(
+                rel,
+                CachedFile {
+                    size,
+                    modified,
+                    hash,
+                },
+            );
+        }
+    }
+    state.files = files;
+    println!(
+        "{} upload files, {} uploaded",
+        manifest.files.len(),
+        uploaded
+    );
+
+    let snapshot_dir = format!("snapshots/{}", name);
+    bucket.put(
+        &format!("{}/mysqldump.sql.enc", snapshot_dir),
+        crypter.encrypt(&std::fs::read(dump_file)?)?,
+    )?;
+    bucket.put(
+        &format!("{}/manifest.json.enc", snapshot_dir),
+        crypter.encrypt(&serde_json::to_vec(&manifest)?)?,
+    )?;
+    state.last_snapshot = Some(name.clone());
+    state.write(var_dir)?;
+
+    apply_retention(&bucket, &mut crypter, &mut state)?;
+    state.write(var_dir)?;
+    Ok(name)
+}
+
+/// Removes snapshots not kept by the daily/weekly/monthly
+/// schedule, then blobs no longer referenced
+fn apply_retention(bucket: &Bucket, crypter: &mut Crypter, state: &mut OffsiteState) -> Result<()> {
+    let snapshots = bucket
+        .list("snapshots/", Some("/"))?
+        .into_iter()
+        .map(|path| {
+            path.trim_start_matches("snapshots/")
+                .trim_end_matches('/')
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    let keep = snapshots_to_keep(
+        &snapshots,
+        bucket.config.daily,
+        bucket.config.weekly,
+        bucket.config.monthly,
+    );
+    let remove = snapshots
+        .iter()
+        .filter(|name| !keep.contains(*name))
+        .collect::<Vec<_>>();
+    if remove.is_empty() {
+        return Ok(());
+    }
+    for name in remove {
+        println!("Removing snapshot {}", name);
+        for path in bucket.list(&format!("snapshots/{}/", name), None)? {
+            bucket.delete(&path)?;
+        }
+    }
+    // Blobs referenced by kept snapshots
+    let mut referenced = BTreeSet::new();
+    for name in &keep {
+        let manifest =
+            crypter.decrypt(&bucket.get(&format!("snapshots/{}/manifest.json.enc", name))?)?;
+        let manifest: Manifest = serde_json::from_slice(&manifest)?;
+        referenced.extend(manifest.files.into_iter().map(|(_, hash)| hash));
+    }
+    let mut removed = 0;
+    for path in bucket.list("blobs/", None)? {
+        let hash = path.trim_start_matches("blobs/").to_string();
+        if !referenced.contains(&hash) {
+            bucket.delete(&path)?;
+            state.blobs.remove(&hash);
+            removed += 1;
+        }
+    }
+    println!("Removed {} unreferenced files", removed);
+    Ok(())
+}
+
+/// Snapshot names sort by time. Keeps the newest snapshot, and
+/// the newest of each of the latest `daily` days, `weekly`
+/// weeks and `monthly` months with snapshots
+pub fn snapshots_to_keep(
+    snapshots: &[String],
+    daily: usize,
+    weekly: usize,
+    monthly: usize,
+) -> BTreeSet<String> {
+    let mut newest_first = snapshots
+        .iter()
+        .filter(|name| name.len() >= 8)
+        .collect::<Vec<_>>();
+    newest_first.sort_by(|a, b| b.cmp(a));
+    let mut keep = BTreeSet::new();
+    if let Some(newest) = newest_first.first() {
+        keep.insert(newest.to_string());
+    }
+    let week = |name: &str| match NaiveDate::parse_from_str(&name[..8], "%Y%m%d") {
+        Ok(date) => format!("{}-{}", date.iso_week().year(), date.iso_week().week()),
+        Err(_) => name[..8].to_string(),
+    };
+    let periods: [(usize, &dyn Fn(&str) -> String); 3] = [
+        (daily, &|name: &str| name[..8].to_string()),
+        (weekly, &week),
+        (monthly, &|name: &str| name[..6].to_string()),
+    ];
+    for (count, period) in periods.iter() {
+        let mut seen = BTreeSet::new();
+        for name in &newest_first {
+            if seen.len() >= *count {
+                break;
+            }
+            if seen.insert(period(name)) {
+                keep.insert(name.to_string());
+            }
+        }
+    }
+    keep
+}
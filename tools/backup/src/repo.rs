@@ -0,0 +1,187 @@
+use crate::er::Result;
+use crate::Config;
+use git2::{Commit, Cred, IndexAddOption, Oid, RemoteCallbacks, Repository, Signature};
+use std::fs;
+
+fn callbacks<'a>(config: &'a Config) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(
+        move |_url, username_from_url, _allowed| match &config.git_token {
+            Some(token) => Cred::userpass_plaintext(username_from_url.unwrap_or("git"), token),
+            None => Cred::default(),
+        },
+    );
+    callbacks
+}
+
+/// Opens backup repo, cloning it if not there yet. The repo
+/// folder exists already as uploads are mounted inside it,
+/// so cloning to init_repo, then moving files that are not
+/// already there. Returns true in second value when cloned
+pub fn ensure_repo(config: &Config) -> Result<(Repository, bool)> {
+    let repo_dir = config.repo_dir();
+    if repo_dir.join(".git").is_dir() {
+        return Ok((Repository::open(&repo_dir)?, false));
+    }
+    println!("Initializing repo");
+    let init_dir = config.var_dir.join("init_repo");
+    if init_dir.exists() {
+        fs::remove_dir_all(&init_dir)?;
+    }
+    let mut fetch_options = git2::FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks(config));
+    git2::build::RepoBuilder::new()
+        .fetch_options(fetch_options)
+        .clone(&config.git_repo, &init_dir)?;
+    println!("Moving files from git");
+    fs::create_dir_all(&repo_dir)?;
+    for entry in fs::read_dir(&init_dir)? {
+        let entry = entry?;
+        let to = repo_dir.join(entry.file_name());
+        if !to.exists() {
+            fs::rename(entry.path(), to)?;
+        }
+    }
+    fs::remove_dir_all(&init_dir)?;
+    let repo = Repository::open(&repo_dir)?;
+    {
+        let mut git_config = repo.config()?;
+        git_config.set_str("user.name", &config.git_user)?;
+        git_config.set_str("user.email", &config.git_email)?;
+    }
+    Ok((repo, true))
+}
+
+/// Branch HEAD points to, also when unborn
+pub fn current_branch(repo: &Repository) -> String {
+    repo.find_reference("HEAD")
+        .ok()
+        .and_then(|head| head.symbolic_target().map(String::from))
+        .and_then(|target| {
+            if target.starts_with("refs/heads/") {
+                Some(target["refs/heads/".len()..].to_string())
+            } else {
+                None
+            }
+        })
+        .unwrap_or_else(|| "master".to_string())
+}
+
+fn head_commit(repo: &Repository) -> Result<Option<Commit>> {
+    match repo.head() {
+        Ok(head) => Ok(Some(head.peel_to_commit()?)),
+        Err(ref e)
+            if e.code() == git2::ErrorCode::UnbornBranch
+                || e.code() == git2::ErrorCode::NotFound =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Adds all changes, including removed files, and
+/// commits. None when nothing changed
+pub fn commit_all(repo: &Repository, sig: &Signature, message: &str) -> Result<Option<Oid>> {
+    let mut index = repo.index()?;
+    index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"].iter(), None)?;
+    index.write()?;
+    let tree_id = index.write_tree()?;
+    let parent = head_commit(repo)?;
+    if let Some(parent) = &parent {
+        if parent.tree_id() == tree_id {
+            return Ok(None);
+        }
+    }
+    let tree = repo.find_tree(tree_id)?;
+    let parents = parent.iter().collect::<Vec<_>>();
+    let oid = repo.commit(Some("HEAD"), sig, sig, message, &tree, &parents)?;
+    Ok(Some(oid))
+}
+
+/// Number of commits reachable from HEAD
+pub fn commit_count(repo: &Repository) -> Result<usize> {
+    if head_commit(repo)?.is_none() {
+        return Ok(0);
+    }
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    Ok(revwalk.count())
+}
+
+/// Keeps the newest `keep` commits, by recreating them with
+/// the oldest kept as root. Each backup commit is a full
+/// snapshot, so older history can be dropped without
+/// touching the kept trees. Author dates are kept, as they
+/// identify backups. Returns true if history was rewritten,
+/// which then needs a force push
+pub fn apply_retention(repo: &Repository, keep: usize) -> Result<bool> {
+    if keep == 0 || head_commit(repo)?.is_none() {
+        return Ok(false);
+    }
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL);
+    let oids = revwalk.collect::<std::result::Result<Vec<_>, _>>()?;
+    if oids.len() <= keep {
+        return Ok(false);
+    }
+    println!("Dropping {} old backups", oids.len() - keep);
+    let mut parent: Option<Oid> = None;
+    for oid in oids[..keep].iter().rev() {
+        let commit = repo.find_commit(*oid)?;
+        let parents = match parent {
+            Some(parent) => vec![repo.find_commit(parent)?],
+            None => Vec::new(),
+        };
+        let parents = parents.iter().collect::<Vec<_>>();
+        parent = Some(repo.commit(
+            None,
+            &commit.author(),
+            &commit.committer(),
+            commit.message().unwrap_or_default(),
+            &commit.tree()?,
+            &parents,
+        )?);
+    }
+    if let Some(new_head) = parent {
+        let refname = format!("refs/heads/{}", current_branch(repo));
+        repo.reference(&refname, new_head, true, "Backup retention")?;
+    }
+    // Dropped objects are left until gc, which git2
+    // doesn't have
+    let gc = std::process::Command::new("git")
+        .args(&["gc", "--prune=now", "--quiet"])
+        .current_dir(repo.workdir().unwrap_or_else(|| repo.path()))
+        .status();
+    if let Err(e) = gc {
+        eprintln!("Could not run git gc: {:?}", e);
+    }
+    Ok(true)
+}
+
+/// Pushes current branch. Force is needed
+/// after retention has rewritten history
+pub fn push(repo: &Repository, config: &Config, force: bool) -> Result<()> {
+    let branch = current_branch(repo);
+    let refspec = format!(
+        "{}refs/heads/{}:refs/heads/{}",
+        if force { "+" } else { "" },
+        branch,
+        branch
+    );
+    let mut callbacks = callbacks(config);
+    callbacks.push_update_reference(|refname, status| match status {
+        Some(msg) => Err(git2::Error::from_str(&format!(
+            "Push of {} rejected: {}",
+            refname, msg
+        ))),
+        None => Ok(()),
+    });
+    let mut push_options = git2::PushOptions::new();
+    push_options.remote_callbacks(callbacks);
+    let mut remote = repo.find_remote("origin")?;
+    remote.push(&[refspec.as_str()], Some(&mut push_options))?;
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use crate::er::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Written to status.json after each run,
+/// read by the cli `status` command
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Status {
+    /// Rfc3339 time of last run
+    pub last_run: Option<String>,
+    /// Rfc3339 time of last successful run
+    pub last_success: Option<String>,
+    /// Last backup commit, and its time
+    pub last_commit: Option<String>,
+    pub last_commit_time: Option<String>,
+    /// Name of last offsite snapshot
+    pub last_offsite: Option<String>,
+    /// Error of last run, if it failed
+    pub last_error: Option<String>,
+    pub duration_ms: u64,
+    /// Number of commits kept in backup repo
+    pub commits: usize,
+    /// Retention rewrote history that is not pushed yet,
+    /// kept until a force push succeeds
+    #[serde(default)]
+    pub force_push: bool,
+}
+impl Status {
+    pub fn read(path: &Path) -> Status {
+        std::fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        // Through a temp file so readers don't
+        // see a half written file
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
@@ -8,4 +8,5 @@ fi
 sudo bash -c "echo '#!/bin/bash' >> $CLI_X"
 sudo bash -c "echo 'RUST_BACKTRACE=1 $(pwd)/../target/debug/project-api \"\$@\"' >> $CLI_X"
 sudo chmod +x "$CLI_X"
-source /home/vagrant/wop.bash
+# Completions from the cli, source in .bashrc
+"$CLI_X" completions bash > "$HOME/wop.bash"
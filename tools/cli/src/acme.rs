@@ -0,0 +1,706 @@
+// Certificates from an ACME server (Let's Encrypt), using
+// http-01 challenges. Challenge files are put where the proxy,
+// or the front proxy when the project is one of several sites,
+// serves /.well-known/acme-challenge/ on the server.
+// The directory url is configurable, and certificate checks
+// can be turned off, to test against a local Pebble server.
+
+use crate::er::{self, Result};
+use crate::project::{self, ProjectConfig};
+use crate::server::{ServerConfig, SshConn};
+use crate::utils::CliEnv;
+use actix_web::http::{header, Method};
+use awc::{Client, Connector};
+use futures::future::{lazy, Either};
+use futures::{Future, Stream};
+use openssl::bn::{BigNum, BigNumContext, BigNumRef};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::stack::Stack;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509NameBuilder, X509Req, X509};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const LETSENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+pub const LETSENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// Certificates are renewed when expiring within these days
+const RENEW_DAYS: i64 = 30;
+
+/// Owner of the private key on server, www-data as the
+/// prod proxy runs. The front nginx reads it as root
+const PROXY_UID: u32 = 33;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TlsConfig {
+    /// Contact of acme account
+    pub email: String,
+    /// Acme directory url
+    pub directory: String,
+    /// Skips certificate checks of the acme server,
+    /// for testing servers like Pebble
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+/// Where challenge responses are made available,
+/// by token
+pub trait ChallengeSink {
+    fn put(&mut self, token: &str, key_authorization: &str) -> Result<()>;
+    fn remove(&mut self, token: &str) -> Result<()>;
+}
+
+/// Issued certificate, as pem
+pub struct Certificate {
+    pub key_pem: Vec<u8>,
+    pub chain_pem: Vec<u8>,
+}
+
+fn b64(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+/// Big endian bytes, left padded to len
+fn padded(num: &BigNumRef, len: usize) -> Vec<u8> {
+    let bytes = num.to_vec();
+    let mut out = vec![0u8; len.saturating_sub(bytes.len())];
+    out.extend(bytes);
+    out
+}
+
+struct Response {
+    status: u16,
+    nonce: Option<String>,
+    location: Option<String>,
+    body: Vec<u8>,
+}
+impl Response {
+    fn json(&self) -> Result<Value> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// Problem document as error when not 2xx
+    fn check(self, what: &str) -> Result<Response> {
+        if (200..300).contains(&self.status) {
+            return Ok(self);
+        }
+        let detail = serde_json::from_slice::<Value>(&self.body)
+            .ok()
+            .and_then(|v| v["detail"].as_str().map(String::from))
+            .unwrap_or_else(|| String::from_utf8_lossy(&self.body).to_string());
+        Err(cli_err!(
+            Network,
+            "{} failed ({}): {}",
+            what,
+            self.status,
+            detail
+        ))
+    }
+}
+
+pub struct AcmeClient {
+    runner: actix_rt::SystemRunner,
+    connector: SslConnector,
+    key: EcKey<Private>,
+    directory: Value,
+    nonce: Option<String>,
+    kid: Option<String>,
+}
+impl AcmeClient {
+    /// Loads directory and registers, or looks up, the account of key
+    pub fn new(config: &TlsConfig, account_key_pem: &[u8]) -> Result<AcmeClient> {
+        let mut builder = SslConnector::builder(SslMethod::tls())?;
+        if config.insecure {
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+        let mut client = AcmeClient {
+            runner: actix_rt::System::new("acme"),
+            connector: builder.build(),
+            key: EcKey::private_key_from_pem(account_key_pem)?,
+            directory: Value::Null,
+            nonce: None,
+            kid: None,
+        };
+        client.directory = client
+            .request(Method::GET, &config.directory, None)?
+            .check("Directory")?
+            .json()?;
+        let payload = json!({
+            "termsOfServiceAgreed": true,
+            "contact": [format!("mailto:{}", config.email)],
+        });
+        let url = client.directory_url("newAccount")?;
+        let response = client.post(&url, Some(&payload))?.check("Account")?;
+        client.kid = response.location;
+        if client.kid.is_none() {
+            return Err(cli_err!(Network, "Account response without location"));
+        }
+        Ok(client)
+    }
+
+    /// New P-256 account key, as pem
+    pub fn generate_account_key() -> Result<Vec<u8>> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        Ok(EcKey::generate(&group)?.private_key_to_pem()?)
+    }
+
+    fn directory_url(&self, name: &str) -> Result<String> {
+        match self.directory[name].as_str() {
+            Some(url) => Ok(url.to_string()),
+            None => Err(cli_err!(Network, "Missing {} in acme directory", name)),
+        }
+    }
+
+    fn request(&mut self, method: Method, url: &str, body: Option<Vec<u8>>) -> Result<Response> {
+        let connector = self.connector.clone();
+        let url = url.to_string();
+        let response = self.runner.block_on(lazy(move || {
+            let client = Client::build()
+                .connector(
+                    Connector::new()
+                        .ssl(connector)
+                        .timeout(Duration::from_secs(30))
+                        .finish(),
+                )
+                .timeout(Duration::from_secs(30))
+                .finish();
+            let request = client
+                .request(method, url.as_str())
+                .header(header::USER_AGENT, "wop-acme");
+            let send = match body {
+                Some(body) => Either::A(
+                    request
+                        .header(header::CONTENT_TYPE, "application/jose+json")
+                        .send_body(body),
+                ),
+                None => Either::B(request.send()),
+            };
+            send.map_err(move |e| cli_err!(Network, "Request to {} failed: {:?}", url, e))
+                .and_then(|response| {
+                    let header_value = |name: &str| {
+                        response
+                            .headers()
+                            .get(name)
+                            .and_then(|v| v.to_str().ok())
+                            .map(String::from)
+                    };
+                    let status = response.status().as_u16();
+                    let nonce = header_value("replay-nonce");
+                    let location = header_value("location");
+                    response
+                        .map_err(|e| cli_err!(Network, "Reading response failed: {:?}", e))
+                        .fold(Vec::new(), |mut body, chunk| {
+                            body.extend_from_slice(&chunk);
+                            Ok::<_, failure::Error>(body)
+                        })
+                        .map(move |body| Response {
+                            status,
+                            nonce,
+                            location,
+                            body,
+                        })
+                })
+        }))?;
+        if response.nonce.is_some() {
+            self.nonce = response.nonce.clone();
+        }
+        Ok(response)
+    }
+
+    fn jwk(&self) -> Result<Value> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let mut ctx = BigNumContext::new()?;
+        let mut x = BigNum::new()?;
+        let mut y = BigNum::new()?;
+        self.key
+            .public_key()
+            .affine_coordinates_gfp(&group, &mut x, &mut y, &mut ctx)?;
+        // Members in lexical order, as required for the thumbprint
+        Ok(json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": b64(&padded(&x, 32)),
+            "y": b64(&padded(&y, 32)),
+        }))
+    }
+
+    /// Token and account key thumbprint, served as
+    /// response to http-01 challenges
+    pub fn key_authorization(&self, token: &str) -> Result<String> {
+        let thumbprint = hash(MessageDigest::sha256(), self.jwk()?.to_string().as_bytes())?;
+        Ok(format!("{}.{}", token, b64(&thumbprint)))
+    }
+
+    /// Signed request, with empty payload when none
+    /// as for POST-as-GET
+    fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Response> {
+        // A bad nonce is retried once with the new nonce
+        for attempt in 0..2 {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => {
+                    let url = self.directory_url("newNonce")?;
+                    self.request(Method::HEAD, &url, None)?;
+                    match self.nonce.take() {
+                        Some(nonce) => nonce,
+                        None => return Err(cli_err!(Network, "No nonce from acme server")),
+                    }
+                }
+            };
+            let mut protected = json!({
+                "alg": "ES256",
+                "nonce": nonce,
+                "url": url,
+            });
+            match &self.kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => protected["jwk"] = self.jwk()?,
+            }
+            let protected = b64(protected.to_string().as_bytes());
+            let payload = match payload {
+                Some(payload) => b64(payload.to_string().as_bytes()),
+                None => String::new(),
+            };
+            let digest = hash(
+                MessageDigest::sha256(),
+                format!("{}.{}", protected, payload).as_bytes(),
+            )?;
+            let sig = EcdsaSig::sign(&digest, &self.key)?;
+            let mut signature = padded(sig.r(), 32);
+            signature.extend(padded(sig.s(), 32));
+            let body = json!({
+                "protected": protected,
+                "payload": payload,
+                "signature": b64(&signature),
+            });
+            let response = self.request(Method::POST, url, Some(body.to_string().into_bytes()))?;
+            let bad_nonce = response.status == 400
+                && response
+                    .json()
+                    .map(|v| v["type"] == "urn:ietf:params:acme:error:badNonce")
+                    .unwrap_or(false);
+            if !bad_nonce || attempt == 1 {
+                return Ok(response);
+            }
+        }
+        unreachable!()
+    }
+
+    /// Polls url until status is not pending or processing
+    fn poll(&mut self, url: &str, what: &str) -> Result<Value> {
+        for _ in 0..30 {
+            let value = self.post(url, None)?.check(what)?.json()?;
+            match value["status"].as_str() {
+                Some("pending") | Some("processing") => std::thread::sleep(Duration::from_secs(2)),
+                _ => return Ok(value),
+            }
+        }
+        Err(cli_err!(Network, "{} still pending", what))
+    }
+
+    /// Orders and downloads a certificate for domains,
+    /// the first being the common name
+    pub fn issue<S: ChallengeSink>(
+        &mut self,
+        domains: &[String],
+        sink: &mut S,
+    ) -> Result<Certificate> {
+        if domains.is_empty() {
+            return Err(cli_err!(Config, "No domains to issue certificate for"));
+        }
+        let identifiers = domains
+            .iter()
+            .map(|d| json!({ "type": "dns", "value": d }))
+            .collect::<Vec<_>>();
+        let url = self.directory_url("newOrder")?;
+        let response = self
+            .post(&url, Some(&json!({ "identifiers": identifiers })))?
+            .check("Order")?;
+        let order_url = match &response.location {
+            Some(location) => location.clone(),
+            None => return Err(cli_err!(Network, "Order response without location")),
+        };
+        let order = response.json()?;
+
+        // Authorizations, responding to http-01 challenges
+        let authorizations = order["authorizations"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        for authz_url in authorizations.iter().filter_map(|a| a.as_str()) {
+            let authz = self.post(authz_url, None)?.check("Authorization")?.json()?;
+            let domain = authz["identifier"]["value"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            if authz["status"] == "valid" {
+                continue;
+            }
+            let challenge = authz["challenges"]
+                .as_array()
+                .and_then(|c| c.iter().find(|c| c["type"] == "http-01"))
+                .cloned()
+                .ok_or_else(|| cli_err!(Network, "No http-01 challenge for {}", domain))?;
+            let token = challenge["token"].as_str().unwrap_or_default().to_string();
+            let challenge_url = challenge["url"].as_str().unwrap_or_default().to_string();
+            sink.put(&token, &self.key_authorization(&token)?)?;
+            self.post(&challenge_url, Some(&json!({})))?
+                .check("Challenge")?;
+            let result = self.poll(authz_url, "Authorization");
+            sink.remove(&token)?;
+            let authz = result?;
+            if authz["status"] != "valid" {
+                let detail = authz["challenges"]
+                    .as_array()
+                    .and_then(|c| c.iter().find_map(|c| c["error"]["detail"].as_str()))
+                    .unwrap_or("no details");
+                return Err(cli_err!(
+                    Network,
+                    "Validation of {} failed: {}",
+                    domain,
+                    detail
+                ));
+            }
+            println!("Validated {}", domain);
+        }
+
+        // Finalize with csr of a new key
+        let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+        let mut req = X509Req::builder()?;
+        let mut name = X509NameBuilder::new()?;
+        name.append_entry_by_text("CN", &domains[0])?;
+        req.set_subject_name(&name.build())?;
+        req.set_pubkey(&key)?;
+        let mut san = SubjectAlternativeName::new();
+        for domain in domains {
+            san.dns(domain);
+        }
+        let mut extensions = Stack::new()?;
+        extensions.push(san.build(&req.x509v3_context(None))?)?;
+        req.add_extensions(&extensions)?;
+        req.sign(&key, MessageDigest::sha256())?;
+        let csr = req.build().to_der()?;
+        let finalize_url = order["finalize"].as_str().unwrap_or_default().to_string();
+        self.post(&finalize_url, Some(&json!({ "csr": b64(&csr) })))?
+            .check("Finalize")?;
+        let order = self.poll(&order_url, "Order")?;
+        let cert_url = match (order["status"].as_str(), order["certificate"].as_str()) {
+            (Some("valid"), Some(cert_url)) => cert_url.to_string(),
+            (status, _) => return Err(cli_err!(Network, "Order not valid: {:?}", status)),
+        };
+        let chain_pem = self.post(&cert_url, None)?.check("Certificate")?.body;
+        Ok(Certificate {
+            key_pem: key.private_key_to_pem_pkcs8()?,
+            chain_pem,
+        })
+    }
+}
+
+/// Expiry of first certificate in pem
+pub fn expires(chain_pem: &[u8]) -> Result<chrono::DateTime<chrono::Utc>> {
+    let cert = X509::from_pem(chain_pem)?;
+    // As "Jan  1 00:00:00 2027 GMT"
+    let not_after = cert.not_after().to_string();
+    let naive = chrono::NaiveDateTime::parse_from_str(&not_after, "%b %e %H:%M:%S %Y GMT")
+        .map_err(|e| cli_err!(Other, "Could not parse expiry {}: {}", not_after, e))?;
+    Ok(chrono::DateTime::from_utc(naive, chrono::Utc))
+}
+
+/// Challenge files written through sftp to a
+/// dir on the server
+struct ServerSink<'a> {
+    conn: &'a SshConn,
+    dir: PathBuf,
+}
+impl<'a> ChallengeSink for ServerSink<'a> {
+    fn put(&mut self, token: &str, key_authorization: &str) -> Result<()> {
+        write_remote(
+            self.conn,
+            &self.dir.join(token),
+            key_authorization.as_bytes(),
+            0o644,
+        )
+    }
+
+    fn remove(&mut self, token: &str) -> Result<()> {
+        let sftp = self.conn.sftp()?;
+        sftp.unlink(&self.dir.join(token)).map_err(er::ssh)?;
+        Ok(())
+    }
+}
+
+pub fn write_remote(conn: &SshConn, path: &PathBuf, content: &[u8], mode: i32) -> Result<()> {
+    use std::io::Write;
+    let sftp = conn.sftp()?;
+    let mut remote_handle = sftp
+        .open_mode(
+            path,
+            ssh2::WRITE | ssh2::TRUNCATE,
+            mode,
+            ssh2::OpenType::File,
+        )
+        .map_err(er::ssh)?;
+    remote_handle.write_all(content).map_err(er::io)?;
+    Ok(())
+}
+
+/// Acme files of project on server. With several sites they
+/// are in the front proxy dir, otherwise in the project dir,
+/// mounted into the proxy
+pub fn server_acme_dir(project: &ProjectConfig, server: &ServerConfig) -> PathBuf {
+    match project.site {
+        Some(_) => server.home_dir_and(crate::site::FRONT_DIR).join("acme"),
+        None => PathBuf::from(project::server_project_dir(project, server)).join("acme"),
+    }
+}
+
+/// Dir of key.pem and cert.pem on server
+pub fn server_cert_dir(project: &ProjectConfig, server: &ServerConfig) -> PathBuf {
+    match (&project.site, &project.domain) {
+        (Some(_), Some(domain)) => server_acme_dir(project, server).join("certs").join(domain),
+        _ => server_acme_dir(project, server).join("certs"),
+    }
+}
+
+/// Local copy of certificate, used to check expiry
+fn local_cert_dir(env: &CliEnv, domain: &str) -> PathBuf {
+    env.config_dirs
+        .config_root
+        .join("acme")
+        .join("certs")
+        .join(domain)
+}
+
+/// Account key by acme server, created when missing
+fn account_key(env: &CliEnv, config: &TlsConfig) -> Result<Vec<u8>> {
+    let host = config
+        .directory
+        .split('/')
+        .nth(2)
+        .unwrap_or("default")
+        .replace(':', "_");
+    let file = env
+        .config_dirs
+        .config_root
+        .join("acme")
+        .join(format!("account-{}.pem", host));
+    if file.is_file() {
+        return Ok(std::fs::read(&file).map_err(er::io)?);
+    }
+    let key = AcmeClient::generate_account_key()?;
+    crate::utils::ensure_parent_dir(&file)?;
+    std::fs::write(&file, &key).map_err(er::io)?;
+    Ok(key)
+}
+
+/// Prompts for acme settings of project
+pub fn cert_init(env: &CliEnv, mut project: ProjectConfig) -> Result<()> {
+    if project.domain.is_none() {
+        return Err(cli_err!(Config, "Project has no domain, add one first"));
+    }
+    let current = project.tls.clone();
+    let email = env.get_input(
+        "Contact email for certificates",
+        current.as_ref().map(|c| c.email.clone()),
+    )?;
+    let directories = vec![
+        "Let's Encrypt".to_string(),
+        "Let's Encrypt staging".to_string(),
+        "Other".to_string(),
+    ];
+    let directory = match env.select("Acme server", &directories, Some(0))? {
+        0 => LETSENCRYPT.to_string(),
+        1 => LETSENCRYPT_STAGING.to_string(),
+        _ => env.get_input(
+            "Directory url",
+            current.as_ref().map(|c| c.directory.clone()),
+        )?,
+    };
+    let insecure = directory != LETSENCRYPT
+        && directory != LETSENCRYPT_STAGING
+        && env.get_input(
+            "Skip certificate checks of acme server (y/n)",
+            Some("n".into()),
+        )? == "y";
+    project.tls = Some(TlsConfig {
+        email,
+        directory,
+        insecure,
+    });
+    project::write_config(env, &project)?;
+    println!("Issue the certificate with `cert renew`");
+    Ok(())
+}
+
+/// Issues certificate when missing, expiring or forced,
+/// uploads it and restarts the proxy serving it
+pub fn cert_renew(env: &CliEnv, project: &ProjectConfig, force: bool) -> Result<()> {
+    let (domain, config) = match (&project.domain, &project.tls) {
+        (Some(domain), Some(config)) => (domain.clone(), config.clone()),
+        _ => {
+            return Err(cli_err!(
+                Config,
+                "Project needs domain and `cert init` first"
+            ))
+        }
+    };
+    let server = project.require_server(env)?;
+    let local_dir = local_cert_dir(env, &domain);
+    if !force {
+        if let Ok(chain) = std::fs::read(local_dir.join("cert.pem")) {
+            let expires = expires(&chain)?;
+            let days = (expires - chrono::Utc::now()).num_days();
+            if days > RENEW_DAYS {
+                println!("Certificate of {} valid for {} more days", domain, days);
+                return Ok(());
+            }
+        }
+    }
+    let conn = SshConn::connect(env, &server)?;
+    // Proxies serving challenges need to run with current config
+    project::sync_prod_compose(env, project, &server, &conn)?;
+    if project.site.is_some() {
+        crate::site::sync_front(env, &server, &conn)?;
+    }
+    let acme_dir = server_acme_dir(project, &server);
+    let challenge_dir = acme_dir.join("challenge");
+    let cert_dir = server_cert_dir(project, &server);
+    conn.exec(format!(
+        "mkdir -p {} {}",
+        challenge_dir.to_string_lossy(),
+        cert_dir.to_string_lossy()
+    ))?;
+
+    let mut client = AcmeClient::new(&config, &account_key(env, &config)?)?;
+    let mut sink = ServerSink {
+        conn: &conn,
+        dir: challenge_dir,
+    };
+    let certificate = client.issue(&project.hosts(), &mut sink)?;
+    std::fs::create_dir_all(&local_dir).map_err(er::io)?;
+    std::fs::write(local_dir.join("cert.pem"), &certificate.chain_pem).map_err(er::io)?;
+
+    // Readable only by the proxy user in the container
+    let key_file = cert_dir.join("key.pem");
+    // A previous key is owned by the proxy user,
+    // removable as the dir is ours
+    conn.exec(format!("rm -f {}", key_file.to_string_lossy()))?;
+    write_remote(&conn, &key_file, &certificate.key_pem, 0o600)?;
+    let status = conn.exec(format!(
+        "sudo chown {} {}",
+        PROXY_UID,
+        key_file.to_string_lossy()
+    ))?;
+    if status != 0 {
+        return Err(cli_err!(
+            Ssh,
+            "Could not set owner of {}",
+            key_file.to_string_lossy()
+        ));
+    }
+    write_remote(
+        &conn,
+        &cert_dir.join("cert.pem"),
+        &certificate.chain_pem,
+        0o644,
+    )?;
+    if project.site.is_some() {
+        crate::site::sync_front(env, &server, &conn)?;
+    } else {
+        conn.exec(format!(
+            "cd {}; docker-compose {} up -d --force-recreate proxy",
+            project::server_project_dir(project, &server),
+            project::prod_compose_args(project).join(" ")
+        ))?;
+    }
+    println!(
+        "Certificate of {} valid until {}",
+        domain,
+        expires(&certificate.chain_pem)?
+    );
+    Ok(())
+}
+
+/// Prints expiry of project certificate
+pub fn cert_status(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    let domain = match &project.domain {
+        Some(domain) => domain,
+        None => return Err(cli_err!(Config, "Project has no domain")),
+    };
+    match std::fs::read(local_cert_dir(env, domain).join("cert.pem")) {
+        Ok(chain) => {
+            let expires = expires(&chain)?;
+            let days = (expires - chrono::Utc::now()).num_days();
+            let line = format!("{}: expires {} ({} days)", domain, expires, days);
+            if days > RENEW_DAYS {
+                println!("{}", console::style(line).green());
+            } else {
+                println!("{}", console::style(line).red());
+            }
+        }
+        Err(_) => println!("{}: no certificate issued", domain),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Challenges kept in memory, for servers
+    /// configured to skip validation
+    struct MemorySink(BTreeMap<String, String>);
+    impl ChallengeSink for MemorySink {
+        fn put(&mut self, token: &str, key_authorization: &str) -> Result<()> {
+            self.0.insert(token.into(), key_authorization.into());
+            Ok(())
+        }
+        fn remove(&mut self, token: &str) -> Result<()> {
+            self.0.remove(token);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pads_coordinates() {
+        let num = BigNum::from_u32(0x0102).unwrap();
+        assert_eq!(padded(&num, 4), vec![0, 0, 1, 2]);
+    }
+
+    /// Runs against Pebble started with PEBBLE_VA_ALWAYS_VALID=1,
+    /// `PEBBLE_DIRECTORY=https://localhost:14000/dir cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn issues_from_pebble() {
+        let config = TlsConfig {
+            email: "test@example.com".into(),
+            directory: std::env::var("PEBBLE_DIRECTORY")
+                .unwrap_or_else(|_| "https://localhost:14000/dir".into()),
+            insecure: true,
+        };
+        let key = AcmeClient::generate_account_key().unwrap();
+        let mut client = AcmeClient::new(&config, &key).unwrap();
+        let mut sink = MemorySink(BTreeMap::new());
+        let domains = vec!["example.test".to_string(), "www.example.test".to_string()];
+        let certificate = client.issue(&domains, &mut sink).unwrap();
+        assert!(sink.0.is_empty());
+        let cert = X509::from_pem(&certificate.chain_pem).unwrap();
+        let names = cert
+            .subject_alt_names()
+            .unwrap()
+            .iter()
+            .filter_map(|n| n.dnsname().map(String::from))
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 2);
+        assert!(expires(&certificate.chain_pem).unwrap() > chrono::Utc::now());
+    }
+}
@@ -1,11 +1,16 @@
+use crate::er::{self, Result};
 use crate::server;
-use crate::utils::{self, io_err, CliEnv};
+use crate::utils::{self, CliEnv};
 use rusoto_core::Region;
 use rusoto_ec2::{
     AllocateAddressRequest, AssociateAddressRequest, AuthorizeSecurityGroupIngressRequest,
     CreateKeyPairRequest, CreateSecurityGroupRequest, DescribeAddressesRequest, Ec2, Ec2Client,
     IpPermission, IpRange, RunInstancesRequest,
 };
+use rusoto_route53::{
+    Change, ChangeBatch, ChangeResourceRecordSetsRequest, GetChangeRequest, ListHostedZonesRequest,
+    ResourceRecord, ResourceRecordSet, Route53, Route53Client,
+};
 use serde::{Deserialize, Serialize};
 use std::io;
 
@@ -27,15 +32,17 @@ fn get_config_file(env: &CliEnv) -> std::path::PathBuf {
     config_file
 }
 
-pub fn get_config(env: &CliEnv) -> io::Result<AwsConfig> {
+pub fn get_config(env: &CliEnv) -> Result<AwsConfig> {
     let config_file = get_config_file(env);
-    let json_file = std::fs::File::open(config_file)?;
+    let json_file =
+        std::fs::File::open(config_file).map_err(|e| er::io_msg("Could not open aws config", e))?;
     let buf_reader = io::BufReader::new(json_file);
-    let config = serde_json::from_reader::<_, AwsConfig>(buf_reader)?;
+    let config = serde_json::from_reader::<_, AwsConfig>(buf_reader)
+        .map_err(|e| cli_err!(Config, "Invalid aws config: {}", e))?;
     Ok(config)
 }
 
-pub fn aws_config(env: &CliEnv) -> io::Result<()> {
+pub fn aws_config(env: &CliEnv) -> Result<()> {
     let config_file = get_config_file(env);
     let current_config = if config_file.is_file() {
         Some(get_config(env)?)
@@ -48,7 +55,7 @@ pub fn aws_config(env: &CliEnv) -> io::Result<()> {
         }
         None => {
             println!("No current config, creating");
-            println!("Expecting IAM user credentials with ec2 permissions");
+            println!("Expecting IAM user credentials with ec2 and route53 permissions");
         }
     }
     let key = env.get_input("Key", current_config.as_ref().map(|c| c.key.clone()))?;
@@ -56,7 +63,7 @@ pub fn aws_config(env: &CliEnv) -> io::Result<()> {
     let config = AwsConfig { key, secret };
     let content_str = match serde_json::to_string_pretty(&config) {
         Ok(content_str) => content_str,
-        Err(_) => return Err(io::Error::from(io::ErrorKind::InvalidData)),
+        Err(e) => return Err(cli_err!(Config, "Could not serialize aws config: {}", e)),
     };
     utils::ensure_parent_dir(&config_file)?;
     match std::fs::write(config_file, content_str) {
@@ -64,15 +71,12 @@ pub fn aws_config(env: &CliEnv) -> io::Result<()> {
             println!("Wrote aws config");
             Ok(())
         }
-        Err(e) => {
-            eprintln!("Couldn't write aws config: {:?}", e);
-            Err(e)
-        }
+        Err(e) => Err(er::io_msg("Couldn't write aws config", e)),
     }
 }
 
-fn to_io_err<E: std::fmt::Debug>(rusoto_error: rusoto_core::RusotoError<E>) -> io::Error {
-    utils::io_error(format!("Rusoto error: {:?}", rusoto_error))
+fn rusoto_err<E: std::fmt::Debug>(rusoto_error: rusoto_core::RusotoError<E>) -> failure::Error {
+    cli_err!(Network, "Rusoto error: {:?}", rusoto_error)
 }
 
 // Todo: Possibly os could be unix? How will it work for os x
@@ -92,7 +96,7 @@ fn set_pem_perms(pem_file: &std::path::Path) -> io::Result<()> {
     std::fs::set_permissions(pem_file, permissions)
 }
 
-pub fn create_ec2_client(env: &CliEnv) -> io::Result<Ec2Client> {
+pub fn create_ec2_client(env: &CliEnv) -> Result<Ec2Client> {
     let aws_config = get_config(env)?;
     std::env::set_var("AWS_ACCESS_KEY_ID", aws_config.key);
     std::env::set_var("AWS_SECRET_ACCESS_KEY", aws_config.secret);
@@ -106,6 +110,122 @@ pub fn create_ec2_client(env: &CliEnv) -> io::Result<Ec2Client> {
     Ok(Ec2Client::new(Region::EuNorth1))
 }
 
+pub fn create_route53_client(env: &CliEnv) -> Result<Route53Client> {
+    // Sets credentials in env as with ec2
+    create_ec2_client(env)?;
+    // Route 53 is global, served from us-east-1
+    Ok(Route53Client::new(Region::UsEast1))
+}
+
+/// Id of hosted zone with the longest name
+/// containing domain
+fn find_hosted_zone(client: &Route53Client, domain: &str) -> Result<String> {
+    let domain = domain.trim_end_matches('.');
+    let mut best: Option<(usize, String)> = None;
+    let mut marker = None;
+    loop {
+        let resp = client
+            .list_hosted_zones(ListHostedZonesRequest {
+                delegation_set_id: None,
+                marker: marker.clone(),
+                max_items: None,
+            })
+            .sync()
+            .map_err(rusoto_err)?;
+        for zone in resp.hosted_zones {
+            let zone_name = zone.name.trim_end_matches('.');
+            let matches = domain == zone_name || domain.ends_with(&format!(".{}", zone_name));
+            let longer = match &best {
+                Some((len, _)) => zone_name.len() > *len,
+                None => true,
+            };
+            if matches && longer {
+                best = Some((zone_name.len(), zone.id));
+            }
+        }
+        if resp.is_truncated && resp.next_marker.is_some() {
+            marker = resp.next_marker;
+        } else {
+            break;
+        }
+    }
+    match best {
+        // Id is returned as /hostedzone/<id>
+        Some((_, id)) => Ok(id.trim_start_matches("/hostedzone/").to_string()),
+        None => Err(cli_err!(Network, "No hosted zone found for {}", domain)),
+    }
+}
+
+/// Creates or updates A records of the names,
+/// pointing to ip, then waits for them to be in sync.
+/// Fails when they are not, as the names may not resolve yet
+pub fn upsert_a_records(env: &CliEnv, names: &[String], ip: &str) -> Result<()> {
+    let client = create_route53_client(env)?;
+    // Grouped by zone, as names may be in different zones
+    let mut zones: Vec<(String, Vec<Change>)> = Vec::new();
+    for name in names {
+        let zone_id = find_hosted_zone(&client, name)?;
+        let change = Change {
+            action: "UPSERT".into(),
+            resource_record_set: ResourceRecordSet {
+                name: name.clone(),
+                type_: "A".into(),
+                ttl: Some(300),
+                resource_records: Some(vec![ResourceRecord {
+                    value: ip.to_string(),
+                }]),
+                ..Default::default()
+            },
+        };
+        match zones.iter_mut().find(|(id, _)| *id == zone_id) {
+            Some((_, changes)) => changes.push(change),
+            None => zones.push((zone_id, vec![change])),
+        }
+    }
+    for (zone_id, changes) in zones {
+        println!("Updating records in zone {}", zone_id);
+        let resp = client
+            .change_resource_record_sets(ChangeResourceRecordSetsRequest {
+                hosted_zone_id: zone_id.clone(),
+                change_batch: ChangeBatch {
+                    changes,
+                    comment: Some("Points domain to elastic ip".into()),
+                },
+            })
+            .sync()
+            .map_err(rusoto_err)?;
+        let change_id = resp
+            .change_info
+            .id
+            .trim_start_matches("/change/")
+            .to_string();
+        // Usually in sync within a minute
+        let mut in_sync = false;
+        for _ in 0..40 {
+            let change = client
+                .get_change(GetChangeRequest {
+                    id: change_id.clone(),
+                })
+                .sync()
+                .map_err(rusoto_err)?;
+            if change.change_info.status == "INSYNC" {
+                in_sync = true;
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_secs(3));
+        }
+        if !in_sync {
+            return Err(cli_err!(
+                Network,
+                "Records of zone {} not in sync after two minutes, change {}",
+                zone_id,
+                change_id
+            ));
+        }
+    }
+    Ok(())
+}
+
 use server::ElasticIp;
 
 // todo: Keep progress and allow to continue,
@@ -115,12 +235,12 @@ use server::ElasticIp;
 // of not aborted process
 // todo: Clean up stack to run in case of failures,
 // should be persisted
-pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
+pub fn provision_server(env: &CliEnv, dry_run: bool) -> Result<()> {
     // todo: Allow "reprovision" of existing server
     let server_name = env.get_input("Server name", None)?;
     if crate::server::has_config(env, &server_name) {
         eprintln!("Server name already exist. \"Reprovision\" not implemented");
-        return io_err("Server name already exist");
+        return Err(cli_err!(Config, "Server name already exist"));
     }
     let ec2_client = create_ec2_client(&env)?;
     // Select or provision address
@@ -133,7 +253,7 @@ pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
             public_ips: None,
         })
         .sync()
-        .map_err(to_io_err)?;
+        .map_err(rusoto_err)?;
     // Allow to select from those not assigned
     let selected_addr = match desribe_addr.addresses {
         Some(addrs) => {
@@ -178,14 +298,19 @@ pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
                     public_ipv_4_pool: None,
                 })
                 .sync()
-                .map_err(to_io_err)?;
+                .map_err(rusoto_err)?;
             println!("OK");
             match (result.allocation_id, result.public_ip) {
                 (Some(allocation_id), Some(public_ip)) => ElasticIp {
                     allocation_id,
                     public_ip,
                 },
-                _ => return io_err("Could not extract allocation_id and public_ip from result"),
+                _ => {
+                    return Err(cli_err!(
+                        Network,
+                        "Could not extract allocation_id and public_ip from result"
+                    ))
+                }
             }
         }
     };
@@ -197,7 +322,7 @@ pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
             dry_run: Some(dry_run),
         })
         .sync()
-        .map_err(to_io_err)?;
+        .map_err(rusoto_err)?;
     println!("OK");
     // Write "material", .pem file
     // todo: Can we use "key_finterprint" for the private key?
@@ -209,10 +334,10 @@ pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
         .filepath(&format!(".pem/{}", pem_file));
     match key_pair.key_material {
         Some(key_material) => utils::write_file(&pem_path, &key_material)?,
-        None => return io_err("Failed to get key material (pem)"),
+        None => return Err(cli_err!(Network, "Failed to get key material (pem)")),
     }
     // Set permission to 400 or read only dependent on os
-    set_pem_perms(&pem_path)?;
+    set_pem_perms(&pem_path).map_err(er::io)?;
     // Security group
     // Todo: Should we have vpc_id?
     print!("Creating security group.. ");
@@ -224,11 +349,11 @@ pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
             vpc_id: None,
         })
         .sync()
-        .map_err(to_io_err)?
+        .map_err(rusoto_err)?
         .group_id
     {
         Some(group_id) => group_id,
-        None => return io_err("Failed to get security group_id"),
+        None => return Err(cli_err!(Network, "Failed to get security group_id")),
     };
     println!("OK");
     // Add inbound http and https rules
@@ -287,7 +412,7 @@ pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
             source_security_group_owner_id: None,
         })
         .sync()
-        .map_err(to_io_err)?;
+        .map_err(rusoto_err)?;
     println!("OK");
     print!("Creating ec2 instance.. ");
     // Create and run ec2 instance
@@ -330,13 +455,14 @@ pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
             user_data: None,
         })
         .sync()
-        .map_err(to_io_err)?;
+        .map_err(rusoto_err)?;
     println!("OK");
     // Get the instance id
     let instance_id = match reservation.instances {
         Some(instances) => {
             if instances.len() != 1 {
-                return io_err(format!(
+                return Err(cli_err!(
+                    Network,
                     "Number of instances unexpectedly not one, but: {}",
                     instances.len()
                 ));
@@ -345,10 +471,10 @@ pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
                 .into_iter()
                 .next()
                 .and_then(|i| i.instance_id)
-                .ok_or(utils::io_error("Could not get instance_id"))?
+                .ok_or_else(|| cli_err!(Network, "Could not get instance_id"))?
         }
         None => {
-            return io_err("No instances reserved after request");
+            return Err(cli_err!(Network, "No instances reserved after request"));
         }
     };
     println!("Ec2 instance launching!");
@@ -368,16 +494,13 @@ pub fn provision_server(env: &CliEnv, dry_run: bool) -> io::Result<()> {
 
 /// Waits for instance to be in running state, then
 /// requests host token
-pub fn wait_for_running_and_finish(
-    env: &CliEnv,
-    server_conf: server::ServerConfig,
-) -> io::Result<()> {
+pub fn wait_for_running_and_finish(env: &CliEnv, server_conf: server::ServerConfig) -> Result<()> {
     use rusoto_ec2::DescribeInstanceStatusRequest;
     let instance_id = match server_conf.instance_id {
         Some(instance_id) => instance_id,
         None => {
             eprintln!("No instance_id registered on the server");
-            return utils::io_err("No instance_id registered");
+            return Err(cli_err!(Network, "No instance_id registered"));
         }
     };
     // Could maybe accept ec2_client as arg, for
@@ -398,11 +521,11 @@ pub fn wait_for_running_and_finish(
                 next_token: None,
             })
             .sync()
-            .map_err(to_io_err)?;
+            .map_err(rusoto_err)?;
         match describe_instance.instance_statuses {
             Some(statuses) => {
                 if statuses.len() < 1 {
-                    return utils::io_err("0 instance statuses returned");
+                    return Err(cli_err!(Network, "0 instance statuses returned"));
                 }
                 let instance_state = match statuses
                     .into_iter()
@@ -410,10 +533,10 @@ pub fn wait_for_running_and_finish(
                 {
                     Some(status) => match status.instance_state.and_then(|s| s.name) {
                         Some(state_name) => state_name,
-                        None => return utils::io_err("No state name given"),
+                        None => return Err(cli_err!(Network, "No state name given")),
                     },
                     None => {
-                        return utils::io_err("Could not find status of instance");
+                        return Err(cli_err!(Network, "Could not find status of instance"));
                     }
                 };
                 match instance_state.as_str() {
@@ -425,17 +548,17 @@ pub fn wait_for_running_and_finish(
                     }
                     "shutting-down" | "terminated" | "stopping" | "stopped" => {
                         eprintln!("Post-running state: {}, aborting", instance_state);
-                        return utils::io_err("Post running state");
+                        return Err(cli_err!(Network, "Post running state"));
                     }
                     _ => {
                         eprintln!("Unrecognized state: {}, aborting", instance_state);
-                        return utils::io_err("Unrecognized state");
+                        return Err(cli_err!(Network, "Unrecognized state"));
                     }
                 }
             }
             None => {
                 eprintln!("Instance not found: {:?}", instance_id);
-                return utils::io_err("Instance not found");
+                return Err(cli_err!(Network, "Instance not found"));
             }
         }
         // Sleep before next iteration/check
@@ -456,7 +579,7 @@ pub fn wait_for_running_and_finish(
                     private_ip_address: None,
                 })
                 .sync()
-                .map_err(to_io_err)?;
+                .map_err(rusoto_err)?;
             println!("OK");
         }
         None => (),
@@ -0,0 +1,486 @@
+// Backups of database and uploads on the prod server.
+// Backups are made by the backup container, see
+// server/base/backup, as commits in a backup repository
+
+use crate::aws;
+use crate::compose::ComposeEnv;
+use crate::docker;
+use crate::er::{self, Result};
+use crate::project::{self, ProjectConfig};
+use crate::server::{ServerConfig, SshConn};
+use crate::utils::CliEnv;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process;
+
+pub struct BackupEntry {
+    pub id: String,
+    pub date: String,
+    pub message: String,
+}
+
+/// Offsite snapshots to an S3 compatible storage,
+/// made daily by the backup agent
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OffsiteConfig {
+    pub bucket: String,
+    pub region: String,
+    /// Custom endpoint, as for MinIO
+    pub endpoint: Option<String>,
+    pub prefix: String,
+    /// Number of daily, weekly and monthly
+    /// snapshots to keep
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+}
+
+/// Offsite config as read by the backup agent,
+/// including credentials and passphrase. These are
+/// only kept on the server, as the project config is
+/// shared through the workspace repo
+#[derive(Serialize)]
+struct AgentOffsiteConfig<'a> {
+    #[serde(flatten)]
+    offsite: &'a OffsiteConfig,
+    /// Snapshots are encrypted with a key from this
+    passphrase: String,
+    key: String,
+    secret: String,
+}
+
+/// Env var to give the offsite passphrase without a prompt
+const PASSPHRASE_VAR: &str = "WOP_BACKUP_PASSPHRASE";
+
+/// Passphrase of the offsite config on server, if configured
+fn server_passphrase(
+    project: &ProjectConfig,
+    server: &ServerConfig,
+    conn: &SshConn,
+) -> Option<String> {
+    let json = conn
+        .exec_capture(
+            backup_run_cmd(project, server, "cat /var/lib/docker-backup/offsite.json"),
+            None::<String>,
+        )
+        .ok()?;
+    let config = serde_json::from_str::<serde_json::Value>(&json).ok()?;
+    config.get("passphrase")?.as_str().map(String::from)
+}
+
+/// Status written by the backup agent after
+/// each run, see tools/backup
+#[derive(Deserialize, Debug, Default)]
+pub struct BackupStatus {
+    pub last_run: Option<String>,
+    pub last_success: Option<String>,
+    pub last_commit: Option<String>,
+    pub last_commit_time: Option<String>,
+    pub last_offsite: Option<String>,
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub commits: usize,
+}
+
+/// Compose command running a command in a one-off backup
+/// container, cd'ing to project dir on server
+fn backup_run_cmd(project: &ProjectConfig, server: &ServerConfig, cmd: &str) -> String {
+    let args = ComposeEnv::ProdBackup.args_for(&project.compose_project());
+    format!(
+        "cd {}; docker-compose {} run --rm -T backup {}",
+        project::server_project_dir(project, server),
+        args.join(" "),
+        cmd
+    )
+}
+
+fn project_server(env: &CliEnv, project: &ProjectConfig) -> Result<ServerConfig> {
+    project.require_server(env)
+}
+
+/// Connects to project server, making sure compose
+/// files with the backup service are synced
+fn connect(env: &CliEnv, project: &ProjectConfig) -> Result<(ServerConfig, SshConn)> {
+    let server = project_server(env, project)?;
+    let conn = SshConn::connect(env, &server)?;
+    project::sync_prod_compose(env, project, &server, &conn)?;
+    Ok((server, conn))
+}
+
+/// Makes a backup, regardless of changes since last
+pub fn backup_now(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    let (server, conn) = connect(env, project)?;
+    let status = conn.exec(backup_run_cmd(project, &server, "backup-agent run --force"))?;
+    if status != 0 {
+        return Err(cli_err!(Docker, "Backup failed"));
+    }
+    println!("Backup done");
+    Ok(())
+}
+
+pub fn get_status(env: &CliEnv, project: &ProjectConfig) -> Result<BackupStatus> {
+    let (server, conn) = connect(env, project)?;
+    let output = conn.exec_capture(
+        backup_run_cmd(project, &server, "backup-agent status"),
+        None::<String>,
+    )?;
+    serde_json::from_str(&output)
+        .map_err(|e| cli_err!(Docker, "Could not read backup status: {:?}", e))
+}
+
+/// Prints status of last backup run on the prod server
+pub fn backup_status(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    let status = get_status(env, project)?;
+    let or_never = |value: &Option<String>| value.clone().unwrap_or_else(|| "never".into());
+    println!("Project: {}", console::style(&project.name).green());
+    println!("Last backup run: {}", or_never(&status.last_run));
+    println!("Last successful run: {}", or_never(&status.last_success));
+    match (&status.last_commit, &status.last_commit_time) {
+        (Some(commit), Some(time)) => println!(
+            "Last backup: {} at {}",
+            console::style(&commit[..commit.len().min(10)]).yellow(),
+            time
+        ),
+        (Some(commit), None) => println!("Last backup: {}", commit),
+        _ => println!("Last backup: none"),
+    }
+    println!("Backups kept: {}", status.commits);
+    match (&project.backup_offsite, &status.last_offsite) {
+        (Some(offsite), Some(last_offsite)) => println!(
+            "Last offsite snapshot: {} in {}",
+            last_offsite, offsite.bucket
+        ),
+        (Some(offsite), None) => println!("No offsite snapshot yet in {}", offsite.bucket),
+        (None, _) => println!("Offsite backups not configured"),
+    }
+    if status.last_run.is_some() {
+        println!("Duration: {}ms", status.duration_ms);
+    }
+    if let Some(error) = &status.last_error {
+        println!(
+            "{}",
+            console::style(format!("Last run failed: {}", error)).red()
+        );
+    }
+    Ok(())
+}
+
+/// Configures offsite backups of project, and sends config
+/// with aws credentials to the backup volume on the server
+pub fn configure_offsite(env: &CliEnv, mut project: ProjectConfig) -> Result<()> {
+    let aws_config = aws::get_config(env)
+        .map_err(|e| cli_err!(Config, "Aws credentials are needed, see `aws`: {}", e))?;
+    let current = project.backup_offsite.clone();
+    let input = |prompt: &str, default: Option<String>| -> Result<String> {
+        Ok(env.get_input(prompt, default).map_err(er::io)?)
+    };
+    let count = |prompt: &str, default: usize| -> Result<usize> {
+        input(prompt, Some(default.to_string()))?
+            .parse()
+            .map_err(|_| cli_err!(Config, "Expected a number"))
+    };
+    let bucket = input("Bucket", current.as_ref().map(|c| c.bucket.clone()))?;
+    let region = input(
+        "Region",
+        Some(
+            current
+                .as_ref()
+                .map(|c| c.region.clone())
+                .unwrap_or_else(|| "eu-north-1".into()),
+        ),
+    )?;
+    println!("Endpoint is only needed for other than aws, as http://minio:9000");
+    let endpoint = input(
+        "Endpoint",
+        Some(
+            current
+                .as_ref()
+                .and_then(|c| c.endpoint.clone())
+                .unwrap_or_default(),
+        ),
+    )?;
+    let prefix = input(
+        "Prefix",
+        Some(
+            current
+                .as_ref()
+                .map(|c| c.prefix.clone())
+                .unwrap_or_else(|| project.name.clone()),
+        ),
+    )?;
+    let (server, conn) = connect(env, &project)?;
+    let passphrase = match std::env::var(PASSPHRASE_VAR) {
+        Ok(passphrase) if !passphrase.is_empty() => passphrase,
+        _ => match server_passphrase(&project, &server, &conn) {
+            Some(current) => {
+                let passphrase = env
+                    .get_pass("Encryption passphrase (empty keeps current)")
+                    .map_err(er::io)?;
+                if passphrase.is_empty() {
+                    current
+                } else {
+                    passphrase
+                }
+            }
+            None => env.get_pass("Encryption passphrase").map_err(er::io)?,
+        },
+    };
+    if passphrase.is_empty() {
+        return Err(cli_err!(Config, "Passphrase is required"));
+    }
+    let offsite = OffsiteConfig {
+        bucket,
+        region,
+        endpoint: if endpoint.is_empty() {
+            None
+        } else {
+            Some(endpoint)
+        },
+        prefix,
+        daily: count(
+            "Daily snapshots to keep",
+            current.as_ref().map_or(7, |c| c.daily),
+        )?,
+        weekly: count(
+            "Weekly snapshots to keep",
+            current.as_ref().map_or(4, |c| c.weekly),
+        )?,
+        monthly: count(
+            "Monthly snapshots to keep",
+            current.as_ref().map_or(12, |c| c.monthly),
+        )?,
+    };
+    project.backup_offsite = Some(offsite.clone());
+    project::write_config(env, &project)?;
+
+    // Through a temp file only readable by owner
+    let agent_config = AgentOffsiteConfig {
+        offsite: &offsite,
+        passphrase,
+        key: aws_config.key,
+        secret: aws_config.secret,
+    };
+    let json = serde_json::to_string(&agent_config).map_err(|e| cli_err!(Other, "{:?}", e))?;
+    let remote_file = format!("/tmp/{}-offsite.json", project.name);
+    {
+        use std::io::Write;
+        let sftp = conn.sftp()?;
+        let mut remote_handle = sftp
+            .open_mode(
+                &PathBuf::from(&remote_file),
+                ssh2::WRITE | ssh2::TRUNCATE,
+                0o600,
+                ssh2::OpenType::File,
+            )
+            .map_err(er::ssh)?;
+        remote_handle.write_all(json.as_bytes()).map_err(er::io)?;
+    }
+    let status = conn.exec(format!(
+        "{} < {}; rm {}",
+        backup_run_cmd(
+            &project,
+            &server,
+            "sh -c 'cat > /var/lib/docker-backup/offsite.json'"
+        ),
+        remote_file,
+        remote_file
+    ))?;
+    if status != 0 {
+        return Err(cli_err!(Docker, "Could not send offsite config to server"));
+    }
+    println!("Offsite backups configured, snapshot is made on next backup");
+    Ok(())
+}
+
+/// Backups from newest to oldest
+pub fn get_backups(env: &CliEnv, project: &ProjectConfig) -> Result<Vec<BackupEntry>> {
+    let (server, conn) = connect(env, project)?;
+    let output = conn.exec_capture(
+        backup_run_cmd(project, &server, "/opt/list.sh"),
+        None::<String>,
+    )?;
+    let backups = output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '|');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(id), Some(date), Some(message)) => Some(BackupEntry {
+                    id: id.to_string(),
+                    date: date.to_string(),
+                    message: message.to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect();
+    Ok(backups)
+}
+
+pub fn backup_list(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    let backups = get_backups(env, project)?;
+    if backups.is_empty() {
+        println!("No backups");
+    }
+    for backup in backups {
+        println!(
+            "{}  {}  {}",
+            console::style(&backup.id).yellow(),
+            backup.date,
+            backup.message
+        );
+    }
+    Ok(())
+}
+
+/// Restores backup with given id, either on the
+/// server, or downloads it and restores into dev.
+/// Ids are the backup time as YYYYmmddHHMMSS, which unlike
+/// commit hashes are kept when old backups are dropped
+pub fn backup_restore(env: &CliEnv, project: &ProjectConfig, id: &str, to_dev: bool) -> Result<()> {
+    if id.len() != 14 || !id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(cli_err!(
+            Config,
+            "Expected a backup id from backup list: {}",
+            id
+        ));
+    }
+    let target = if to_dev { "dev" } else { "prod" };
+    let options = vec!["No", "Yes"];
+    let prompt = format!(
+        "Replace {} database and uploads with backup {}?",
+        target, id
+    );
+    if env.select(&prompt, &options, Some(0))? != 1 {
+        println!("Aborted");
+        return Ok(());
+    }
+    if to_dev {
+        let backup_dir = download_backup(env, project, id)?;
+        restore_dev(env, project, &backup_dir)
+    } else {
+        let (server, conn) = connect(env, project)?;
+        let status = conn.exec(backup_run_cmd(
+            project,
+            &server,
+            &format!("/opt/restore.sh {}", id),
+        ))?;
+        if status != 0 {
+            return Err(cli_err!(Docker, "Restore failed"));
+        }
+        Ok(())
+    }
+}
+
+/// Downloads backup to <project>/backups/<id>,
+/// returning the directory
+pub fn download_backup(env: &CliEnv, project: &ProjectConfig, id: &str) -> Result<PathBuf> {
+    let (server, conn) = connect(env, project)?;
+    // Archive to a file outside the container,
+    // then download through sftp
+    let remote_tar = format!("/tmp/{}-backup-{}.tar", project.name, id);
+    let status = conn.exec(format!(
+        "{} > {}",
+        backup_run_cmd(project, &server, &format!("/opt/archive.sh {}", id)),
+        remote_tar
+    ))?;
+    if status != 0 {
+        return Err(cli_err!(Docker, "Could not archive backup {}", id));
+    }
+    let local_tar = project.dir_and(env, &format!("backups/{}.tar", id));
+    let sftp = conn.sftp()?;
+    SshConn::download_file(&sftp, &PathBuf::from(&remote_tar), &local_tar)?;
+    drop(sftp);
+    conn.exec(format!("rm {}", remote_tar))?;
+    // Unpack
+    let backup_dir = project.dir_and(env, &format!("backups/{}", id));
+    let tar_file = std::fs::File::open(&local_tar).map_err(er::io)?;
+    tar::Archive::new(tar_file)
+        .unpack(&backup_dir)
+        .map_err(er::io)?;
+    std::fs::remove_file(&local_tar).map_err(er::io)?;
+    println!("Downloaded backup to {:?}", backup_dir);
+    Ok(backup_dir)
+}
+
+/// Imports database dump and uploads of a downloaded
+/// backup into dev containers
+pub fn restore_dev(env: &CliEnv, project: &ProjectConfig, backup_dir: &PathBuf) -> Result<()> {
+    std::env::set_current_dir(project.dir(env)).map_err(er::io)?;
+    let compose = |args: &[&str], stdin: std::fs::File| -> Result<()> {
+        let mut cmd = process::Command::new("docker-compose");
+        cmd.args(docker::dev_compose_args()).args(args).stdin(stdin);
+        let status = cmd.status().map_err(er::io)?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(cli_err!(Docker, "Command failed: {:?}", cmd))
+        }
+    };
+    // Database
+    let dump_file = backup_dir.join("mysqldump.sql");
+    if dump_file.is_file() {
+        println!("Restoring database");
+        compose(
+            &[
+                "exec",
+                "-T",
+                "db",
+                "mysql",
+                "-uwordpress",
+                "-pwordpress",
+                "wordpress",
+            ],
+            std::fs::File::open(&dump_file).map_err(er::io)?,
+        )?;
+    } else {
+        println!("No database dump in backup");
+    }
+    // Uploads, piped as a tar into the wordpress container
+    let uploads_dir = backup_dir.join("uploads");
+    if uploads_dir.is_dir() {
+        println!("Restoring uploads");
+        let uploads_tar = backup_dir.join("uploads.tar");
+        {
+            let tar_file = std::fs::File::create(&uploads_tar).map_err(er::io)?;
+            let mut builder = tar::Builder::new(tar_file);
+            builder.append_dir_all(".", &uploads_dir).map_err(er::io)?;
+            builder.finish().map_err(er::io)?;
+        }
+        let uploads_path = "/var/www/html/wp-content/uploads";
+        compose(
+            &[
+                "exec",
+                "-T",
+                "wordpress-container",
+                "tar",
+                "-x",
+                "-C",
+                uploads_path,
+            ],
+            std::fs::File::open(&uploads_tar).map_err(er::io)?,
+        )?;
+        std::fs::remove_file(&uploads_tar).map_err(er::io)?;
+        let status = process::Command::new("docker-compose")
+            .args(docker::dev_compose_args())
+            .args(&[
+                "exec",
+                "-T",
+                "wordpress-container",
+                "chown",
+                "-R",
+                "www-data:www-data",
+                uploads_path,
+            ])
+            .status()
+            .map_err(er::io)?;
+        if !status.success() {
+            eprintln!("Could not set owner of uploads");
+        }
+    } else {
+        println!("No uploads in backup");
+    }
+    println!("Restored backup into dev");
+    Ok(())
+}
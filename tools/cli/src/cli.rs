@@ -1,8 +1,34 @@
 use clap::{self, App, Arg, SubCommand};
 
+/// Environment the command runs in, dev, prod
+/// or a named one added with `env add`
+fn env_arg(default: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name("env")
+        .long("env")
+        .takes_value(true)
+        .value_name("name")
+        .default_value(default)
+        .help("Environment, dev, prod or a name from `env list`")
+}
+
+/// Server to use, selected interactively when not given
+fn server_arg() -> Arg<'static, 'static> {
+    Arg::with_name("server")
+        .long("server")
+        .takes_value(true)
+        .value_name("name")
+        .help("Server name, as added with `server`")
+}
+
 pub fn cli_app() -> App<'static, 'static> {
     App::new("Project-cli")
         .version("0.1")
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .global(true)
+                .help("Outputs errors as json, and results where supported"),
+        )
         .subcommand(
             SubCommand::with_name("init")
                 .about("Initialize a project")
@@ -12,26 +38,143 @@ pub fn cli_app() -> App<'static, 'static> {
             SubCommand::with_name("dev")
                 .about("For a given project, creates and starts dev containers")
                 .setting(clap::AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("watch")
+                        .long("watch")
+                        .help("Starts detached and watches plugins and themes for changes"),
+                )
+                .arg(
+                    Arg::with_name("api")
+                        .long("api")
+                        .conflicts_with("watch")
+                        .help("Starts detached through the docker api, args are services"),
+                )
+                .arg(
+                    Arg::with_name("matrix")
+                        .long("matrix")
+                        .takes_value(true)
+                        .value_name("php versions")
+                        .use_delimiter(true)
+                        .conflicts_with_all(&["watch", "api"])
+                        .help("Runs the site with each php version, like 7.2,7.4,8.0"),
+                )
+                .arg(
+                    Arg::with_name("matrix-down")
+                        .long("matrix-down")
+                        .conflicts_with_all(&["watch", "api", "matrix"])
+                        .help("Removes containers started with --matrix"),
+                )
                 .arg(
                     Arg::with_name("dev-args")
                         .multiple(true)
                         .help("Arguments passed to docker-compose"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("versions")
+                .about("Wordpress and php versions of project images, prints them without args")
+                .arg(
+                    Arg::with_name("wp")
+                        .long("wp")
+                        .takes_value(true)
+                        .help("Wordpress version like 5.3, empty for image default"),
+                )
+                .arg(
+                    Arg::with_name("php")
+                        .long("php")
+                        .takes_value(true)
+                        .help("Php version like 7.4, empty for image default"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("new")
+                .about("Generates a new plugin or theme in the project, and activates it in dev")
+                .subcommand(
+                    SubCommand::with_name("plugin")
+                        .about("Creates plugins/<name> with plugin header and plugin.json")
+                        .arg(Arg::with_name("name").required(true))
+                        .arg(
+                            Arg::with_name("build")
+                                .long("build")
+                                .help("Adds package.json with @wordpress/scripts build"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("theme")
+                        .about("Creates themes/<name> with style.css header and functions.php")
+                        .arg(Arg::with_name("name").required(true))
+                        .arg(
+                            Arg::with_name("child-of")
+                                .long("child-of")
+                                .takes_value(true)
+                                .value_name("parent")
+                                .help("Parent theme, installed if not in the project"),
+                        )
+                        .arg(
+                            Arg::with_name("build")
+                                .long("build")
+                                .help("Adds package.json with @wordpress/scripts build"),
+                        ),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("sql")
-                .about("Wp sql command")
+                .about("Runs sql, or a sql shell without arguments")
                 .setting(clap::AppSettings::TrailingVarArg)
+                .arg(env_arg("dev"))
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["table", "vertical", "csv", "json"])
+                        .default_value("table")
+                        .help("Output format of results"),
+                )
                 .arg(
                     Arg::with_name("sql-args")
                         .multiple(true)
                         .help("Sql command"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("search-replace")
+                .about("Replaces text in all tables, keeping serialized values valid")
+                .arg(env_arg("dev"))
+                .arg(
+                    Arg::with_name("from")
+                        .required(true)
+                        .help("Text to search for"),
+                )
+                .arg(Arg::with_name("to").required(true).help("Replacement"))
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Reports counts without updating"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("rebuild")
                 .about("Rebuilds a given service/container")
-                .arg(Arg::with_name("service").help("Container to rebuild and restart")),
+                .arg(Arg::with_name("service").help("Container to rebuild and restart"))
+                .arg(
+                    Arg::with_name("api")
+                        .long("api")
+                        .help("Removes, builds and starts through the docker api"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("docker")
+                .about("Dev containers through the docker api")
+                .subcommand(
+                    SubCommand::with_name("ps").about("Shows state and health of dev containers"),
+                )
+                .subcommand(
+                    SubCommand::with_name("exec")
+                        .about("Runs a command in the running container of a service")
+                        .setting(clap::AppSettings::TrailingVarArg)
+                        .arg(Arg::with_name("service").required(true))
+                        .arg(Arg::with_name("cmd").required(true).multiple(true)),
+                ),
         )
         .subcommand(
             SubCommand::with_name("wp")
@@ -40,23 +183,26 @@ pub fn cli_app() -> App<'static, 'static> {
                     SubCommand::with_name("cli")
                         .about("Runs wp-cli commands")
                         .setting(clap::AppSettings::TrailingVarArg)
+                        .arg(env_arg("dev"))
                         .arg(Arg::with_name("cli-args").multiple(true)),
                 )
                 .subcommand(
                     SubCommand::with_name("install")
-                        .about("Runs wp installation process on dev server"),
+                        .about("Runs wp installation process in environment")
+                        .arg(env_arg("dev")),
                 )
                 .subcommand(
                     SubCommand::with_name("server-install")
-                        .about("Runs wp installation process on prod server"),
+                        .about("Runs wp installation process on prod server, as --env prod"),
                 )
                 .subcommand(
                     SubCommand::with_name("sync-local")
-                        .about("Install deps and activates local plugins and themes"),
+                        .about("Install deps and activates local plugins and themes")
+                        .arg(env_arg("dev")),
                 )
                 .subcommand(
                     SubCommand::with_name("server-sync-local")
-                        .about("Install deps and activates local plugins and themes"),
+                        .about("Syncs and activates local plugins and themes, as --env prod"),
                 )
                 .subcommand(
                     SubCommand::with_name("clean")
@@ -66,35 +212,213 @@ pub fn cli_app() -> App<'static, 'static> {
                     SubCommand::with_name("gen-docker-dev").about("Creates docker dev mounts yml"),
                 )
                 .subcommand(
-                    SubCommand::with_name("vscode-debug-config")
-                        .about("Creates vscode debug config"),
+                    SubCommand::with_name("debug-config")
+                        .alias("vscode-debug-config")
+                        .about("Creates debug configs with path mappings for editors")
+                        .arg(
+                            Arg::with_name("editor")
+                                .long("editor")
+                                .takes_value(true)
+                                .possible_values(&["vscode", "phpstorm", "dbgp", "all"])
+                                .default_value("all")
+                                .help("Editor, or all"),
+                        ),
                 ),
         )
         .subcommand(SubCommand::with_name("git-account").about("Adds or modifies a git account"))
         .subcommand(SubCommand::with_name("server").about("Adds or modifies server config"))
         .subcommand(
-            SubCommand::with_name("deploy").about("For a given project, pushes updates to prod"),
+            SubCommand::with_name("deploy")
+                .about("For a given project, pushes updates to prod")
+                .arg(server_arg()),
         )
         .subcommand(
             SubCommand::with_name("sync-server")
-                .about("Syncs base files like Dockerfiles to server"),
+                .about("Syncs base files like Dockerfiles to server")
+                .arg(server_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("ssh")
+                .about("For a server, enter shell through ssh")
+                .arg(server_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("wp-ssh")
+                .about("Wp-cli shell through ssh")
+                .arg(env_arg("dev")),
         )
-        .subcommand(SubCommand::with_name("ssh").about("For a server, enter shell through ssh"))
-        .subcommand(SubCommand::with_name("wp-ssh").about("Wp-cli shell through ssh"))
         .subcommand(
             SubCommand::with_name("server-wp-ssh")
-                .about("Wp-cli shell through ssh and tunnel from server"),
+                .about("Wp-cli shell through ssh and tunnel from server, as wp-ssh --env prod"),
         )
         .subcommand(
             SubCommand::with_name("prod")
-                .about("For a given project, updates, starts prod containers")
+                .about("For a given project, updates, starts containers of server environment")
                 .setting(clap::AppSettings::TrailingVarArg)
+                .arg(env_arg("prod"))
                 .arg(
                     Arg::with_name("prod-args")
                         .multiple(true)
                         .help("Arguments passed to docker-compose"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("compose")
+                .about("Resolved docker-compose config of project")
+                .subcommand(
+                    SubCommand::with_name("render")
+                        .about("Prints the merged and validated compose file of an environment")
+                        .arg(env_arg("dev"))
+                        .arg(
+                            Arg::with_name("backup")
+                                .long("backup")
+                                .help("Includes the backup service of server environments"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("env")
+                .about("Server environments of project, like staging, next to dev and prod")
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Adds or modifies environment with its server and overlay")
+                        .arg(Arg::with_name("name").required(true)),
+                )
+                .subcommand(SubCommand::with_name("list").about("Lists environments")),
+        )
+        .subcommand(
+            SubCommand::with_name("promote")
+                .about("Copies plugins and themes of project from one environment to another")
+                .arg(Arg::with_name("from").required(true))
+                .arg(Arg::with_name("to").required(true))
+                .arg(
+                    Arg::with_name("content")
+                        .long("content")
+                        .help("Also replaces database and uploads, with urls of target"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("updates")
+                .about("Wordpress core, plugin and theme updates, pinned in wp-lock.json")
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists available updates, and versions differing from lock")
+                        .arg(env_arg("dev")),
+                )
+                .subcommand(
+                    SubCommand::with_name("apply")
+                        .about("Updates dev, checks the site and pins versions in lock")
+                        .arg(
+                            Arg::with_name("names")
+                                .multiple(true)
+                                .help("Plugins, themes or wordpress for core, all when empty"),
+                        )
+                        .arg(
+                            Arg::with_name("no-smoke")
+                                .long("no-smoke")
+                                .help("Skips requesting the site through the dev proxy"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("install")
+                        .about("Installs locked versions on environment")
+                        .arg(env_arg("prod")),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("site")
+                .about("Hosting several projects on one server, routed by domain")
+                .subcommand(
+                    SubCommand::with_name("init")
+                        .about("Sets domain and allocates ports of project on its server")
+                        .arg(env_arg("prod")),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists sites on project server")
+                        .arg(env_arg("prod")),
+                )
+                .subcommand(
+                    SubCommand::with_name("front")
+                        .about("Updates and restarts the front proxy routing sites")
+                        .arg(env_arg("prod")),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("domain")
+                .about("Domain of prod, with dns records in Route 53")
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Points domain to server elastic ip and updates prod urls")
+                        .arg(Arg::with_name("project").required(true))
+                        .arg(Arg::with_name("domain").required(true))
+                        .arg(env_arg("prod")),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cert")
+                .about("Https certificates of prod domain from an acme server")
+                .subcommand(
+                    SubCommand::with_name("init")
+                        .about("Sets contact and acme server")
+                        .arg(env_arg("prod")),
+                )
+                .subcommand(
+                    SubCommand::with_name("renew")
+                        .about("Issues certificate when missing or expiring within 30 days")
+                        .arg(env_arg("prod"))
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .help("Issues regardless of expiry"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("Shows certificate expiry")
+                        .arg(env_arg("prod")),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("preview")
+                .about("Preview environments of branches on the prod server")
+                .subcommand(
+                    SubCommand::with_name("up")
+                        .about("Checks out branch and starts it with a clone of prod db")
+                        .arg(Arg::with_name("branch").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("down")
+                        .about("Removes preview environment of branch")
+                        .arg(Arg::with_name("branch").required(true)),
+                )
+                .subcommand(SubCommand::with_name("list").about("Lists preview environments")),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("Database and uploads backups on the prod server")
+                .subcommand(SubCommand::with_name("now").about("Makes a backup now"))
+                .subcommand(SubCommand::with_name("list").about("Lists backups"))
+                .subcommand(
+                    SubCommand::with_name("offsite")
+                        .about("Configures encrypted offsite backups to S3 or compatible storage"),
+                )
+                .subcommand(
+                    SubCommand::with_name("restore")
+                        .about("Restores a backup on prod, or downloads it into dev")
+                        .arg(
+                            Arg::with_name("id")
+                                .required(true)
+                                .help("Backup id from list"),
+                        )
+                        .arg(
+                            Arg::with_name("to-dev")
+                                .long("to-dev")
+                                .help("Download backup and restore into dev containers"),
+                        ),
+                ),
+        )
+        .subcommand(SubCommand::with_name("status").about("Shows status of last backup on prod"))
         .subcommand(
             SubCommand::with_name("aws")
                 .about("Configures aws credentials")
@@ -109,11 +433,69 @@ pub fn cli_app() -> App<'static, 'static> {
                 )
                 .subcommand(
                     SubCommand::with_name("push")
-                        .about("Pushes workspace repository to origin master"),
+                        .about("Commits, pulls and pushes workspace repository to origin"),
+                )
+                .subcommand(
+                    SubCommand::with_name("pull")
+                        .about("Pulls workspace from origin, resolving conflicts interactively")
+                        .arg(
+                            Arg::with_name("rebase")
+                                .long("rebase")
+                                .help("Rebase local commits instead of merging"),
+                        ),
                 )
                 .subcommand(
                     SubCommand::with_name("clone")
                         .about("Clones a given git repository into workspace location"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("git")
+                .about("Git commands on the project repository, using the project git account")
+                .subcommand(SubCommand::with_name("status").about("Shows branch and changed files"))
+                .subcommand(
+                    SubCommand::with_name("commit")
+                        .about("Adds all changes and commits on current branch")
+                        .arg(
+                            Arg::with_name("message")
+                                .short("m")
+                                .long("message")
+                                .takes_value(true)
+                                .help("Commit message"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("push").about("Pushes current branch to origin")),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Config files of projects, servers and git accounts")
+                .subcommand(
+                    SubCommand::with_name("doctor")
+                        .about("Checks config files, and references between them")
+                        .arg(
+                            Arg::with_name("fix")
+                                .long("fix")
+                                .help("Writes files migrated to the current version"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Prints completion script of shell")
+                .arg(
+                    Arg::with_name("shell")
+                        .required(true)
+                        .possible_values(&["bash", "zsh", "fish"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("complete-names")
+                .setting(clap::AppSettings::Hidden)
+                .about("Names for dynamic completion")
+                .arg(
+                    Arg::with_name("kind")
+                        .required(true)
+                        .possible_values(&["projects", "servers", "services"]),
+                ),
+        )
 }
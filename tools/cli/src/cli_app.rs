@@ -1,14 +1,30 @@
+use crate::acme;
 use crate::aws;
+use crate::backup;
 use crate::cli;
+use crate::completion;
+use crate::compose::{self, ComposeEnv};
+use crate::config;
+use crate::debug_config;
 use crate::docker;
-use crate::er;
+use crate::domain;
+use crate::environment;
+use crate::er::{self, CliError, ResultExt};
 use crate::git;
+use crate::hooks;
+use crate::modules;
+use crate::preview;
 use crate::project;
+use crate::scaffold;
 use crate::server;
+use crate::site;
+use crate::sql;
+use crate::updates;
 use crate::utils::{self, CliEnv};
+use crate::versions;
+use crate::watch;
 use crate::workspace;
 use crate::wp;
-use failure::format_err;
 use failure::Error;
 use futures::future::lazy;
 
@@ -16,37 +32,81 @@ fn with_project<F, T>(env: &CliEnv, f: F) -> Result<T, failure::Error>
 where
     F: FnOnce(project::ProjectConfig) -> Result<T, failure::Error>,
 {
-    match project::resolve_current_project_interactive(&env) {
-        Ok(project) => f(project),
-        Err(e) => {
-            println!("Could not resolve project: {:?}", e);
-            std::process::exit(1);
+    let project = project::resolve_current_project_interactive(&env)
+        .or_cli(CliError::Config, "Could not resolve project")?;
+    f(project)
+}
+
+/// Project in the environment given by --env,
+/// and whether that is on a server
+fn with_env<F, T>(env: &CliEnv, matches: &clap::ArgMatches, f: F) -> Result<T, failure::Error>
+where
+    F: FnOnce(project::ProjectConfig, bool) -> Result<T, failure::Error>,
+{
+    let env_name = matches.value_of("env").unwrap_or(environment::DEV);
+    with_project(env, |project| {
+        if env_name == environment::DEV {
+            f(project, false)
+        } else {
+            let project = project
+                .in_env(env_name)
+                .or_cli(CliError::Config, format!("Could not use env {}", env_name))?;
+            f(project, true)
         }
-    }
+    })
+}
+
+/// Project in the server environment given by --env
+fn with_server_env<F, T>(
+    env: &CliEnv,
+    matches: &clap::ArgMatches,
+    f: F,
+) -> Result<T, failure::Error>
+where
+    F: FnOnce(project::ProjectConfig) -> Result<T, failure::Error>,
+{
+    let env_name = matches.value_of("env").unwrap_or(environment::PROD);
+    with_project(env, |project| {
+        let project = project
+            .in_env(env_name)
+            .or_cli(CliError::Config, format!("Could not use env {}", env_name))?;
+        f(project)
+    })
 }
 
-fn with_server<F, T>(env: &CliEnv, f: F) -> Result<T, failure::Error>
+/// Server given by --server, otherwise selected
+fn with_server<F, T>(env: &CliEnv, matches: &clap::ArgMatches, f: F) -> Result<T, failure::Error>
 where
     F: FnOnce(server::ServerConfig) -> Result<T, failure::Error>,
 {
-    match server::select_server(env) {
-        Ok(server) => f(server),
-        Err(e) => {
-            eprintln!("Error selecting server: {:?}", e);
-            std::process::exit(1);
-        }
-    }
+    let server = match matches.value_of("server") {
+        Some(name) => server::get_config(env, name)
+            .or_cli(CliError::Config, format!("Could not read server {}", name))?,
+        None => server::select_server(env).or_cli(CliError::Config, "Could not select server")?,
+    };
+    f(server)
 }
 
-pub fn run() -> Result<(), failure::Error> {
-    let mut clap_app = cli::cli_app();
-    let matches = clap_app.clone().get_matches();
+/// Errors for projects of other modules, for
+/// commands that call into WordPress directly
+fn require_wp(project: &project::ProjectConfig) -> Result<(), failure::Error> {
+    modules::require(project, wp::MODULE)
+}
+
+/// Whether --json is given, on command or any subcommand
+pub fn json_errors(matches: &clap::ArgMatches) -> bool {
+    matches.is_present("json") || matches.subcommand().1.map_or(false, json_errors)
+}
+
+pub fn run(matches: clap::ArgMatches) -> Result<(), failure::Error> {
     //println!("{:#?}", matches);
     let home_dir = match dirs::home_dir() {
         Some(home_dir) => home_dir,
         None => {
-            println!("Couldn't resolve home directory when resolving projects dir");
-            std::process::exit(1);
+            return Err(CliError::Config(
+                "Couldn't resolve home directory when resolving projects dir".into(),
+            )
+            .into())
         }
     };
     // Workdir dir
@@ -56,6 +116,10 @@ pub fn run() -> Result<(), failure::Error> {
     let mut projects_dir = home_dir.clone();
     projects_dir.push("projects");
     let env = CliEnv::new(projects_dir, workdir_dir);
+    hooks::with_hooks(env, &matches, |env| dispatch(env, &matches))
+}
+
+fn dispatch(env: CliEnv, matches: &clap::ArgMatches) -> Result<(), failure::Error> {
     match matches.subcommand() {
         ("init", Some(_sub_matches)) => {
             actix_rt::System::new("project-api")
@@ -63,42 +127,113 @@ pub fn run() -> Result<(), failure::Error> {
                 .map_err(|e| Error::from(e))
             //env.display_result(res);
         }
-        ("git-account", Some(_sub_matches)) => git::add_user(&env).map_err(|e| e.into()),
-        ("server", Some(_sub_matches)) => server::add_server(&env).map_err(|e| e.into()),
+        ("git-account", Some(_sub_matches)) => git::add_user(&env),
+        ("server", Some(_sub_matches)) => server::add_server(&env),
         ("dev", Some(sub_matches)) => {
             let args = match sub_matches.values_of_lossy("dev-args") {
                 Some(args) => args,
                 None => Vec::new(),
             };
+            if sub_matches.is_present("watch") {
+                return with_project(&env, |project| {
+                    require_wp(&project)?;
+                    watch::dev_watch(&env, project, args)
+                });
+            }
+            if sub_matches.is_present("api") {
+                return with_project(&env, |project| docker::api_up(&env, &project, &args));
+            }
+            if let Some(php_versions) = sub_matches.values_of_lossy("matrix") {
+                return with_project(&env, |project| {
+                    versions::matrix_up(&env, &project, &php_versions)
+                });
+            }
+            if sub_matches.is_present("matrix-down") {
+                return with_project(&env, |project| versions::matrix_down(&env, &project));
+            }
             with_project(&env, |project| {
                 let current_process = utils::CurrentProcess::new();
-                docker::dev_cmd(&env, current_process, project, args).map_err(|e| e.into())
+                docker::dev_cmd(&env, current_process, project, args)
             })
             .map(|_| ())
         }
+        ("versions", Some(sub_matches)) => {
+            let wp = sub_matches.value_of("wp");
+            let php = sub_matches.value_of("php");
+            with_project(&env, |project| {
+                versions::versions_cmd(&env, project, wp, php)
+            })
+        }
         ("rebuild", Some(sub_matches)) => {
             let service = match sub_matches.value_of_lossy("service") {
                 Some(service) => service.to_string(),
-                None => {
-                    eprint!("Service name is required");
-                    std::process::exit(1);
-                }
+                None => return Err(CliError::Config("Service name is required".into()).into()),
             };
+            if sub_matches.is_present("api") {
+                return with_project(&env, |project| {
+                    docker::api_rebuild(&env, &project, &service)
+                });
+            }
             with_project(&env, |project| {
                 let current_process = utils::CurrentProcess::new();
                 docker::rebuild_container(&env, current_process, project, service)
-                    .map_err(|e| e.into())
             })
             .map(|_| ())
         }
+        ("docker", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("ps", Some(sub_matches)) => docker::api_status(sub_matches.is_present("json")),
+            ("exec", Some(sub_matches)) => {
+                let service = sub_matches.value_of("service").unwrap_or_default();
+                let cmd = sub_matches.values_of_lossy("cmd").unwrap_or_default();
+                let code = docker::api_exec(service, &cmd)?;
+                if code != 0 {
+                    return Err(CliError::Exit(code as i32).into());
+                }
+                Ok(())
+            }
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("new", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("plugin", Some(sub_matches)) => {
+                let name = sub_matches.value_of("name").unwrap_or_default();
+                let build = sub_matches.is_present("build");
+                with_project(&env, |project| {
+                    require_wp(&project)?;
+                    scaffold::new_plugin(&env, project, name, build)
+                })
+            }
+            ("theme", Some(sub_matches)) => {
+                let name = sub_matches.value_of("name").unwrap_or_default();
+                let child_of = sub_matches.value_of("child-of");
+                let build = sub_matches.is_present("build");
+                with_project(&env, |project| {
+                    require_wp(&project)?;
+                    scaffold::new_theme(&env, project, name, child_of, build)
+                })
+            }
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
         ("sql", Some(sub_matches)) => {
             let args = match sub_matches.values_of_lossy("sql-args") {
                 Some(args) => args,
                 None => Vec::new(),
             };
             let sql = args.join(" ");
-            with_project(&env, |project| {
-                wp::sql_cli(&env, &sql)
+            let format = sub_matches
+                .value_of("format")
+                .unwrap_or("table")
+                .parse::<mysql_utils::output::Format>()
+                .map_err(er::db)?;
+            with_env(&env, sub_matches, |project, on_server| {
+                sql::sql_cmd(&env, &project, on_server, &sql, format)
+            })
+        }
+        ("search-replace", Some(sub_matches)) => {
+            let from = sub_matches.value_of("from").unwrap_or_default();
+            let to = sub_matches.value_of("to").unwrap_or_default();
+            let dry_run = sub_matches.is_present("dry-run");
+            with_env(&env, sub_matches, |project, on_server| {
+                sql::search_replace(&env, &project, on_server, from, to, dry_run)
             })
         }
         ("wp", Some(sub_matches)) => match sub_matches.subcommand() {
@@ -116,87 +251,281 @@ pub fn run() -> Result<(), failure::Error> {
                         return;
                     }
                 };*/
-                with_project(&env, |project| {
+                with_env(&env, sub_matches, |project, on_server| {
+                    require_wp(&project)?;
                     let current_process = utils::CurrentProcess::new();
-                    wp::wp_cli(&env, current_process, project, args, false).map_err(|e| e.into())
+                    wp::wp_cli(&env, current_process, project, args, on_server)
                 })
                 .map(|_| ())
             }
-            ("install", Some(_sub_matches)) => with_project(&env, |project| {
+            ("install", Some(sub_matches)) => with_env(&env, sub_matches, |project, on_server| {
+                require_wp(&project)?;
                 let current_process = utils::CurrentProcess::new();
-                wp::wp_install(&env, project, current_process, false).map_err(|e| e.into())
+                wp::wp_install(&env, project, current_process, on_server)
             })
             .map(|_| ()),
             ("server-install", Some(_sub_matches)) => with_project(&env, |project| {
+                require_wp(&project)?;
                 let current_process = utils::CurrentProcess::new();
-                wp::wp_install(&env, project, current_process, true).map_err(|e| e.into())
+                wp::wp_install(&env, project, current_process, true)
             })
             .map(|_| ()),
-            ("sync-local", Some(_sub_matches)) => {
-                with_project(&env, |project| wp::sync_local(&env, project, false))
-            }
-            ("server-sync-local", Some(_sub_matches)) => {
-                with_project(&env, |project| wp::sync_local(&env, project, true))
+            ("sync-local", Some(sub_matches)) => {
+                with_env(&env, sub_matches, |project, on_server| {
+                    require_wp(&project)?;
+                    wp::sync_local(&env, project, on_server)
+                })
             }
+            ("server-sync-local", Some(_sub_matches)) => with_project(&env, |project| {
+                require_wp(&project)?;
+                wp::sync_local(&env, project, true)
+            }),
             ("clean", Some(_sub_matches)) => with_project(&env, |project| {
+                require_wp(&project)?;
                 let current_process = utils::CurrentProcess::new();
-                wp::wp_clean(&env, project, current_process).map_err(|e| e.into())
+                wp::wp_clean(&env, project, current_process)
             })
             .map(|_| ()),
             ("gen-docker-dev", Some(_sub_matches)) => with_project(&env, |project| {
-                wp::create_wp_docker_yml(&env, project).map_err(|e| e.into())
+                require_wp(&project)?;
+                wp::create_wp_docker_yml(&env, project)
             }),
-            ("vscode-debug-config", Some(_sub_matches)) => with_project(&env, |project| {
-                wp::gen_vscode_debug_config(&env, project).map_err(|e| e.into())
+            ("debug-config", Some(sub_matches)) => with_project(&env, |project| {
+                require_wp(&project)?;
+                let editors = debug_config::Editor::from_name(
+                    sub_matches.value_of("editor").unwrap_or("all"),
+                )?;
+                debug_config::gen_debug_config(&env, &project, &editors)
             }),
-            (other, _) => return Err(format_err!("Unrecognized: {}", other)),
+            (other, _) => return Err(cli_err!(Other, "Unrecognized: {}", other)),
         },
         ("workspace", Some(sub_matches)) => match sub_matches.subcommand() {
             ("init-git", Some(_sub_matches)) => actix_rt::System::new("project-api")
                 .block_on(lazy(|| workspace::init_git(&env)))
                 .map_err(|e| Error::from(e)),
-            ("push", Some(_sub_matches)) => workspace::push_workspace(&env).map_err(|e| e.into()),
-            ("clone", Some(_sub_matches)) => workspace::clone_workspace(&env).map_err(|e| e.into()),
-            // Could have push/pull here
-            (other, _) => Err(format_err!("Unrecognized: {}", other)),
+            ("push", Some(_sub_matches)) => {
+                workspace::push_workspace(&env).or_cli(CliError::Git, "Workspace push failed")
+            }
+            ("clone", Some(_sub_matches)) => {
+                workspace::clone_workspace(&env).or_cli(CliError::Git, "Workspace clone failed")
+            }
+            ("pull", Some(sub_matches)) => {
+                workspace::pull_workspace(&env, sub_matches.is_present("rebase"))
+                    .or_cli(CliError::Git, "Workspace pull failed")
+            }
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
         },
-        ("deploy", Some(_sub_matches)) => with_project(&env, |project| {
-            with_server(&env, |server| {
-                server::setup_server(&env, server).map_err(|e| e.into())
+        ("git", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("status", Some(_sub_matches)) => with_project(&env, |project| {
+                project::git_status(&env, &project).or_cli(CliError::Git, "Git status failed")
+            }),
+            ("commit", Some(sub_matches)) => {
+                let message = sub_matches.value_of("message").map(String::from);
+                with_project(&env, |project| {
+                    project::git_commit(&env, &project, message)
+                        .or_cli(CliError::Git, "Git commit failed")
+                })
+            }
+            ("push", Some(_sub_matches)) => with_project(&env, |project| {
+                project::git_push(&env, &project).or_cli(CliError::Git, "Git push failed")
+            }),
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("deploy", Some(sub_matches)) => with_project(&env, |project| {
+            with_server(&env, sub_matches, |server| {
+                server::setup_server(&env, server)
             })
         }),
-        ("sync-server", Some(_)) => {
-            with_server(&env, |server| server::sync_to_server(&env, server))
-        }
+        ("sync-server", Some(sub_matches)) => with_server(&env, sub_matches, |server| {
+            server::sync_to_server(&env, server)
+        }),
         ("prod", Some(sub_matches)) => {
             let args = match sub_matches.values_of_lossy("prod-args") {
                 Some(args) => args,
                 None => Vec::new(),
             };
-            with_project(&env, |project| project::prod(&env, &project, args))
+            with_server_env(&env, sub_matches, |project| {
+                project::prod(&env, &project, args)
+            })
+        }
+        ("compose", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("render", Some(sub_matches)) => {
+                let backup = sub_matches.is_present("backup");
+                with_env(&env, sub_matches, |project, on_server| {
+                    let compose_env = match (on_server, backup) {
+                        (false, _) => ComposeEnv::Dev,
+                        (true, false) => ComposeEnv::Prod,
+                        (true, true) => ComposeEnv::ProdBackup,
+                    };
+                    compose::render_cmd(&env, project, compose_env)
+                })
+            }
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("site", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("init", Some(sub_matches)) => {
+                with_server_env(&env, sub_matches, |project| site::site_init(&env, project))
+            }
+            ("list", Some(sub_matches)) => {
+                with_server_env(&env, sub_matches, |project| site::site_list(&env, &project))
+            }
+            ("front", Some(sub_matches)) => with_server_env(&env, sub_matches, |project| {
+                let server = project.require_server(&env)?;
+                let conn = server::SshConn::connect(&env, &server)?;
+                site::sync_front(&env, &server, &conn)
+            }),
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("domain", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("add", Some(sub_matches)) => domain::domain_add(
+                &env,
+                sub_matches.value_of("project").unwrap_or_default(),
+                sub_matches.value_of("env").unwrap_or(environment::PROD),
+                sub_matches.value_of("domain").unwrap_or_default(),
+            ),
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("cert", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("init", Some(sub_matches)) => {
+                with_server_env(&env, sub_matches, |project| acme::cert_init(&env, project))
+            }
+            ("renew", Some(sub_matches)) => {
+                let force = sub_matches.is_present("force");
+                with_server_env(&env, sub_matches, |project| {
+                    acme::cert_renew(&env, &project, force)
+                })
+            }
+            ("status", Some(sub_matches)) => with_server_env(&env, sub_matches, |project| {
+                acme::cert_status(&env, &project)
+            }),
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("env", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("add", Some(sub_matches)) => {
+                let name = sub_matches.value_of("name").unwrap_or_default();
+                with_project(&env, |project| environment::env_add(&env, project, name))
+            }
+            ("list", Some(_sub_matches)) => {
+                with_project(&env, |project| environment::env_list(&env, &project))
+            }
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("preview", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("up", Some(sub_matches)) => {
+                let branch = sub_matches.value_of("branch").unwrap_or_default();
+                with_project(&env, |project| {
+                    require_wp(&project)?;
+                    preview::preview_up(&env, project, branch)
+                })
+            }
+            ("down", Some(sub_matches)) => {
+                let branch = sub_matches.value_of("branch").unwrap_or_default();
+                with_project(&env, |project| preview::preview_down(&env, project, branch))
+            }
+            ("list", Some(_sub_matches)) => with_project(&env, |project| {
+                preview::preview_list(&project);
+                Ok(())
+            }),
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("backup", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("now", Some(_sub_matches)) => {
+                with_project(&env, |project| backup::backup_now(&env, &project))
+            }
+            ("list", Some(_sub_matches)) => {
+                with_project(&env, |project| backup::backup_list(&env, &project))
+            }
+            ("offsite", Some(_sub_matches)) => {
+                with_project(&env, |project| backup::configure_offsite(&env, project))
+            }
+            ("restore", Some(sub_matches)) => {
+                let id = sub_matches.value_of("id").unwrap_or_default();
+                let to_dev = sub_matches.is_present("to-dev");
+                with_project(&env, |project| {
+                    backup::backup_restore(&env, &project, id, to_dev)
+                })
+            }
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("status", Some(_sub_matches)) => {
+            with_project(&env, |project| backup::backup_status(&env, &project))
         }
-        ("ssh", Some(_sub_matches)) => with_server(&env, |server| server::ssh(&env, server)),
-        ("wp-ssh", Some(_sub_matches)) => server::wp_cli_ssh(&env, 2345, None),
-        ("server-wp-ssh", Some(_sub_matches)) => {
-            with_server(&env, |server| server::wp_cli_ssh(&env, 2345, Some(&server)))
+        ("ssh", Some(sub_matches)) => {
+            with_server(&env, sub_matches, |server| server::ssh(&env, server))
         }
-        ("aws", Some(sub_matches)) => match sub_matches.subcommand() {
-            ("provision", Some(_sub_matches)) => {
-                aws::provision_server(&env, false).map_err(|e| e.into())
+        ("wp-ssh", Some(sub_matches)) => {
+            if sub_matches.value_of("env") == Some(environment::DEV) {
+                return with_project(&env, |project| {
+                    require_wp(&project)?;
+                    server::wp_cli_ssh(&env, 2345, None)
+                });
+            }
+            with_server_env(&env, sub_matches, |project| {
+                require_wp(&project)?;
+                let server = project.require_server(&env)?;
+                server::wp_cli_ssh(&env, project.prod_wp_cli_port(), Some(&server))
+            })
+        }
+        ("server-wp-ssh", Some(_sub_matches)) => with_project(&env, |project| {
+            require_wp(&project)?;
+            let server = project.require_server(&env)?;
+            server::wp_cli_ssh(&env, project.prod_wp_cli_port(), Some(&server))
+        }),
+        ("promote", Some(sub_matches)) => {
+            let from = sub_matches.value_of("from").unwrap_or_default();
+            let to = sub_matches.value_of("to").unwrap_or_default();
+            let content = sub_matches.is_present("content");
+            with_project(&env, |project| {
+                require_wp(&project)?;
+                environment::promote(&env, &project, from, to, content)
+            })
+        }
+        ("updates", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("list", Some(sub_matches)) => with_env(&env, sub_matches, |project, on_server| {
+                require_wp(&project)?;
+                updates::updates_list(&env, &project, on_server)
+            }),
+            ("apply", Some(sub_matches)) => {
+                let names = sub_matches.values_of_lossy("names").unwrap_or_default();
+                let skip_smoke = sub_matches.is_present("no-smoke");
+                with_project(&env, |project| {
+                    require_wp(&project)?;
+                    updates::updates_apply(&env, &project, &names, skip_smoke)
+                })
             }
+            ("install", Some(sub_matches)) => with_env(&env, sub_matches, |project, on_server| {
+                require_wp(&project)?;
+                updates::updates_install(&env, &project, on_server)
+            }),
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
+        },
+        ("aws", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("provision", Some(_sub_matches)) => aws::provision_server(&env, false),
             _ => {
                 // Credentials config
-                aws::aws_config(&env).map_err(|e| e.into())
+                aws::aws_config(&env)
+            }
+        },
+        ("config", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("doctor", Some(sub_matches)) => {
+                config::doctor_cmd(&env, sub_matches.is_present("fix"))
             }
+            (other, _) => Err(cli_err!(Other, "Unrecognized: {}", other)),
         },
+        ("completions", Some(sub_matches)) => {
+            completion::completions_cmd(sub_matches.value_of("shell").unwrap_or_default())
+        }
+        ("complete-names", Some(sub_matches)) => {
+            completion::complete_cmd(&env, sub_matches.value_of("kind").unwrap_or_default())
+        }
         other => {
             env.error_msg(&format!("Command not recognized, {:?}", other));
-            match clap_app.print_long_help() {
+            match cli::cli_app().print_long_help() {
                 Ok(_) => {
                     println!("");
                     Ok(())
                 }
-                Err(e) => Err(format_err!("Clap error: {:?}", e)),
+                Err(e) => Err(cli_err!(Other, "Clap error: {:?}", e)),
             }
         }
     }
@@ -0,0 +1,141 @@
+// Shell completions. The static part is generated by clap,
+// names of projects, servers and dev services are completed
+// by calling back into the cli with the hidden `complete-names`.
+// Install with `source <(wop completions bash)`, similarly
+// for zsh, or `wop completions fish | source`.
+
+use crate::cli;
+use crate::compose::{self, ComposeEnv};
+use crate::er::Result;
+use crate::project;
+use crate::server;
+use crate::utils::CliEnv;
+
+/// Name the cli is installed as
+const BIN_NAME: &str = "wop";
+
+const BASH_DYNAMIC: &str = r#"
+_wop_dynamic() {
+    local cur prev kind=""
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+    case "${COMP_WORDS[1]} ${prev}" in
+        "rebuild rebuild"|"rebuild --api"|"docker exec") kind=services ;;
+        "domain add") kind=projects ;;
+    esac
+    if [[ "${prev}" == "--server" ]]; then
+        kind=servers
+    fi
+    if [[ -n "${kind}" ]]; then
+        COMPREPLY=( $(compgen -W "$(wop complete-names ${kind} 2>/dev/null)" -- "${cur}") )
+        return 0
+    fi
+    _wop "$@"
+}
+complete -F _wop_dynamic -o bashdefault -o default wop
+"#;
+
+const ZSH_DYNAMIC: &str = r#"
+_wop_dynamic() {
+    local kind=""
+    case "${words[2]} ${words[CURRENT-1]}" in
+        "rebuild rebuild"|"rebuild --api"|"docker exec") kind=services ;;
+        "domain add") kind=projects ;;
+    esac
+    if [[ "${words[CURRENT-1]}" == "--server" ]]; then
+        kind=servers
+    fi
+    if [[ -n "${kind}" ]]; then
+        compadd -- ${(f)"$(wop complete-names ${kind} 2>/dev/null)"}
+        return
+    fi
+    _wop "$@"
+}
+compdef _wop_dynamic wop
+"#;
+
+const FISH_DYNAMIC: &str = r#"
+complete -c wop -n "__fish_seen_subcommand_from rebuild" -f -a "(wop complete-names services 2>/dev/null)"
+complete -c wop -n "__fish_seen_subcommand_from docker; and __fish_seen_subcommand_from exec" -f -a "(wop complete-names services 2>/dev/null)"
+complete -c wop -n "__fish_seen_subcommand_from domain; and __fish_seen_subcommand_from add" -f -a "(wop complete-names projects 2>/dev/null)"
+complete -c wop -l server -x -a "(wop complete-names servers 2>/dev/null)"
+"#;
+
+/// Completion script of shell, clap generated
+/// with dynamic completions added
+pub fn script(shell: &str) -> Result<String> {
+    let (clap_shell, dynamic) = match shell {
+        "bash" => (clap::Shell::Bash, BASH_DYNAMIC),
+        "zsh" => (clap::Shell::Zsh, ZSH_DYNAMIC),
+        "fish" => (clap::Shell::Fish, FISH_DYNAMIC),
+        other => return Err(cli_err!(Other, "Unsupported shell: {}", other)),
+    };
+    let mut generated = Vec::new();
+    cli::cli_app().gen_completions_to(BIN_NAME, clap_shell, &mut generated);
+    let mut script = String::from_utf8(generated)?;
+    if let clap::Shell::Zsh = clap_shell {
+        // Generated for fpath, where the file is the function body
+        // and ends by calling it. Sourced, the wrapper is used instead
+        let call = format!("_{} \"$@\"", BIN_NAME);
+        script = script
+            .lines()
+            .filter(|line| line.trim() != call)
+            .collect::<Vec<_>>()
+            .join("\n");
+        script.push('\n');
+    }
+    script.push_str(dynamic);
+    Ok(script)
+}
+
+pub fn completions_cmd(shell: &str) -> Result<()> {
+    print!("{}", script(shell)?);
+    Ok(())
+}
+
+/// Services of dev compose of current project, without
+/// writing the resolved file
+fn dev_services(env: &CliEnv) -> Result<Vec<String>> {
+    let project = project::resolve_current_project(env)?;
+    let server_base = env.workdir_dir.join("server/base");
+    let compose = compose::resolve(env, &project.dir(env), ComposeEnv::Dev, &server_base)?;
+    Ok(compose.services.keys().cloned().collect())
+}
+
+/// Names for dynamic completion, one per line. Errors
+/// give no names, as there is nowhere to show them
+pub fn complete_cmd(env: &CliEnv, kind: &str) -> Result<()> {
+    let names = match kind {
+        "projects" => project::get_projects(env).ok(),
+        "servers" => server::get_servers(env).ok(),
+        "services" => dev_services(env).ok(),
+        other => return Err(cli_err!(Other, "Unknown completion: {}", other)),
+    };
+    for name in names.unwrap_or_default() {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_dynamic_completions() {
+        let bash = script("bash").unwrap();
+        assert!(bash.contains("_wop()"));
+        assert!(bash.ends_with(BASH_DYNAMIC));
+        let zsh = script("zsh").unwrap();
+        assert!(zsh.starts_with("#compdef wop"));
+        // Only the call in the wrapper is left
+        assert_eq!(
+            zsh.lines()
+                .filter(|line| line.trim() == "_wop \"$@\"")
+                .count(),
+            1
+        );
+        assert!(script("fish").unwrap().contains("complete-names servers"));
+        assert!(script("tcsh").is_err());
+    }
+}
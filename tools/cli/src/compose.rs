@@ -0,0 +1,792 @@
+// Typed docker-compose model. Base files are loaded and merged
+// here with compose override rules, then validated and
+// rendered as a single resolved file per environment,
+// instead of passing stacked -f files to docker-compose.
+
+use crate::er::{self, Result};
+use crate::project::ProjectConfig;
+use crate::utils::CliEnv;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Compose project name, the one compose gave stacked
+/// files from the dir of the first. Prefixes containers,
+/// volumes and networks
+pub const PROJECT_NAME: &str = "base";
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ComposeYml {
+    #[serde(default)]
+    pub version: String,
+    #[serde(default, deserialize_with = "map_null_as_default")]
+    pub services: BTreeMap<String, ComposeService>,
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "map_null_as_default"
+    )]
+    pub volumes: BTreeMap<String, ComposeVolume>,
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "map_null_as_default"
+    )]
+    pub networks: BTreeMap<String, ComposeNetwork>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ComposeService {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<ComposeBuild>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "list_or_map_keys"
+    )]
+    pub depends_on: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "list_or_map_keys"
+    )]
+    pub networks: Vec<String>,
+    #[serde(default)]
+    pub volumes: Vec<String>,
+    #[serde(default, deserialize_with = "string_map")]
+    pub environment: BTreeMap<String, String>,
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "string_map"
+    )]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<ComposeHealthcheck>,
+    /// Other options, as command or user, kept as is
+    /// and replaced as a whole when overridden
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Build context, or context with options
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ComposeBuild {
+    Context(String),
+    Config {
+        context: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dockerfile: Option<String>,
+        #[serde(
+            default,
+            skip_serializing_if = "BTreeMap::is_empty",
+            deserialize_with = "string_map"
+        )]
+        args: BTreeMap<String, String>,
+    },
+}
+impl ComposeBuild {
+    pub fn context(&self) -> &str {
+        match self {
+            ComposeBuild::Context(context) => context,
+            ComposeBuild::Config { context, .. } => context,
+        }
+    }
+
+    fn context_mut(&mut self) -> &mut String {
+        match self {
+            ComposeBuild::Context(context) => context,
+            ComposeBuild::Config { context, .. } => context,
+        }
+    }
+}
+impl From<String> for ComposeBuild {
+    fn from(context: String) -> Self {
+        ComposeBuild::Context(context)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ComposeHealthcheck {
+    /// String or list form
+    pub test: serde_yaml::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+}
+
+/// Named volume, using defaults when empty
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ComposeVolume {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external: Option<ComposeExternal>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ComposeNetwork {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external: Option<ComposeExternal>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ComposeExternal {
+    pub name: String,
+}
+
+/// Allows `name:` without value for volumes and networks
+fn map_null_as_default<'de, D, T>(d: D) -> std::result::Result<BTreeMap<String, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    let map = Option::<BTreeMap<String, Option<T>>>::deserialize(d)?;
+    Ok(map
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(k, v)| (k, v.unwrap_or_default()))
+        .collect())
+}
+
+/// List, or map where only keys are used, as
+/// depends_on with conditions, or networks with aliases
+fn list_or_map_keys<'de, D>(d: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ListOrMap {
+        List(Vec<String>),
+        Map(BTreeMap<String, serde_yaml::Value>),
+    }
+    Ok(match Option::<ListOrMap>::deserialize(d)? {
+        Some(ListOrMap::List(list)) => list,
+        Some(ListOrMap::Map(map)) => map.into_iter().map(|(k, _)| k).collect(),
+        None => Vec::new(),
+    })
+}
+
+/// Map with scalar values, or list of "KEY=value",
+/// as environment and labels
+fn string_map<'de, D>(d: D) -> std::result::Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ListOrMap {
+        List(Vec<String>),
+        Map(BTreeMap<String, serde_yaml::Value>),
+    }
+    Ok(match Option::<ListOrMap>::deserialize(d)? {
+        Some(ListOrMap::List(list)) => list
+            .into_iter()
+            .map(|entry| {
+                let mut parts = entry.splitn(2, '=');
+                let key = parts.next().unwrap_or_default().to_string();
+                (key, parts.next().unwrap_or_default().to_string())
+            })
+            .collect(),
+        Some(ListOrMap::Map(map)) => map
+            .into_iter()
+            .map(|(k, v)| {
+                let v = match v {
+                    serde_yaml::Value::String(s) => s,
+                    serde_yaml::Value::Number(n) => n.to_string(),
+                    serde_yaml::Value::Bool(b) => b.to_string(),
+                    serde_yaml::Value::Null => String::new(),
+                    other => serde_yaml::to_string(&other)
+                        .unwrap_or_default()
+                        .trim_start_matches("---")
+                        .trim()
+                        .to_string(),
+                };
+                (k, v)
+            })
+            .collect(),
+        None => BTreeMap::new(),
+    })
+}
+
+/// Container path of a volume entry, "src:dst:ro" -> "dst",
+/// used to let overrides replace mounts
+fn volume_target(volume: &str) -> String {
+    let parts = volume.split(':').collect::<Vec<_>>();
+    let target = if parts.len() >= 2 { parts[1] } else { parts[0] };
+    target.trim_end_matches('/').to_string()
+}
+
+/// Resolves "." and ".." without touching the filesystem,
+/// as paths may be on the server
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
+
+fn is_relative_path(path: &str) -> bool {
+    path.starts_with("./") || path.starts_with("../") || path == "." || path == ".."
+}
+
+impl ComposeService {
+    /// Host port publishing the given container port, if any
+    pub fn published_port(&self, container_port: u16) -> Option<u16> {
+        self.ports.iter().find_map(|port| {
+            let parts = port.split(':').collect::<Vec<_>>();
+            if parts.len() < 2 {
+                return None;
+            }
+            let target = parts[parts.len() - 1].split('/').next().unwrap_or_default();
+            if target.parse::<u16>().ok() != Some(container_port) {
+                return None;
+            }
+            parts[parts.len() - 2].parse::<u16>().ok()
+        })
+    }
+
+    /// Adds build args, keeping context and dockerfile.
+    /// Services without build are left as is
+    pub fn add_build_args(&mut self, new_args: BTreeMap<String, String>) {
+        if new_args.is_empty() {
+            return;
+        }
+        self.build = match self.build.take() {
+            Some(ComposeBuild::Context(context)) => Some(ComposeBuild::Config {
+                context,
+                dockerfile: None,
+                args: new_args,
+            }),
+            Some(ComposeBuild::Config {
+                context,
+                dockerfile,
+                mut args,
+            }) => {
+                args.extend(new_args);
+                Some(ComposeBuild::Config {
+                    context,
+                    dockerfile,
+                    args,
+                })
+            }
+            None => None,
+        };
+    }
+
+    /// Merges an override into this service. Single values are
+    /// replaced, ports are appended, environment and labels merged
+    /// by key, and volumes by container path
+    pub fn merge(&mut self, other: ComposeService) {
+        if other.image.is_some() {
+            self.image = other.image;
+        }
+        if other.build.is_some() {
+            self.build = other.build;
+        }
+        if other.restart.is_some() {
+            self.restart = other.restart;
+        }
+        if other.healthcheck.is_some() {
+            self.healthcheck = other.healthcheck;
+        }
+        for dep in other.depends_on {
+            if !self.depends_on.contains(&dep) {
+                self.depends_on.push(dep);
+            }
+        }
+        for port in other.ports {
+            if !self.ports.contains(&port) {
+                self.ports.push(port);
+            }
+        }
+        for network in other.networks {
+            if !self.networks.contains(&network) {
+                self.networks.push(network);
+            }
+        }
+        for volume in other.volumes {
+            let target = volume_target(&volume);
+            self.volumes.retain(|v| volume_target(v) != target);
+            self.volumes.push(volume);
+        }
+        self.environment.extend(other.environment);
+        self.labels.extend(other.labels);
+        self.other.extend(other.other);
+    }
+
+    /// Makes build contexts and bind mount sources
+    /// relative to base_dir absolute
+    fn resolve_paths(&mut self, base_dir: &Path) {
+        if let Some(build) = &mut self.build {
+            let context = build.context_mut();
+            if !context.contains("://") && !context.starts_with("git@") {
+                let path = Path::new(context.as_str());
+                if path.is_relative() {
+                    *context = normalize(&base_dir.join(path))
+                        .to_string_lossy()
+                        .to_string();
+                }
+            }
+        }
+        for volume in self.volumes.iter_mut() {
+            let mut parts = volume.splitn(2, ':');
+            let source = parts.next().unwrap_or_default();
+            if let (true, Some(rest)) = (is_relative_path(source), parts.next()) {
+                *volume = format!(
+                    "{}:{}",
+                    normalize(&base_dir.join(source)).to_string_lossy(),
+                    rest
+                );
+            }
+        }
+    }
+}
+
+impl ComposeYml {
+    pub fn parse(yml: &str) -> Result<ComposeYml> {
+        serde_yaml::from_str(yml).map_err(|e| cli_err!(Docker, "Compose yml error: {}", e))
+    }
+
+    /// Loads a compose file, making relative paths in it
+    /// absolute, relative to base_dir. Docker-compose resolves
+    /// them relative to the first file, also in overrides
+    pub fn load(file: &Path, base_dir: &Path) -> Result<ComposeYml> {
+        let yml = std::fs::read_to_string(file)
+            .map_err(|e| er::io_msg(format!("Could not read {:?}", file), e))?;
+        let mut compose = Self::parse(&yml).map_err(|e| cli_err!(Docker, "{:?}: {}", file, e))?;
+        for service in compose.services.values_mut() {
+            service.resolve_paths(base_dir);
+        }
+        Ok(compose)
+    }
+
+    /// Loads and merges files in order, as with stacked -f
+    /// arguments. Relative paths resolve against base_dir,
+    /// which may be a path on the server
+    pub fn load_merged(files: &[PathBuf], base_dir: &Path) -> Result<ComposeYml> {
+        let mut merged = ComposeYml::default();
+        for file in files {
+            merged.merge(Self::load(file, base_dir)?);
+        }
+        Ok(merged)
+    }
+
+    pub fn merge(&mut self, other: ComposeYml) {
+        if self.version.is_empty() || !other.version.is_empty() {
+            self.version = other.version;
+        }
+        for (name, service) in other.services {
+            match self.services.get_mut(&name) {
+                Some(current) => current.merge(service),
+                None => {
+                    self.services.insert(name, service);
+                }
+            }
+        }
+        self.volumes.extend(other.volumes);
+        self.networks.extend(other.networks);
+    }
+
+    /// Problems docker-compose would fail on, or that
+    /// are likely mistakes
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut host_ports = BTreeMap::new();
+        for (name, service) in &self.services {
+            if service.image.is_none() && service.build.is_none() {
+                problems.push(format!("{}: needs either image or build", name));
+            }
+            for dep in &service.depends_on {
+                if !self.services.contains_key(dep) {
+                    problems.push(format!("{}: depends on unknown service {}", name, dep));
+                }
+            }
+            for network in &service.networks {
+                if network != "default" && !self.networks.contains_key(network) {
+                    problems.push(format!("{}: network {} is not declared", name, network));
+                }
+            }
+            for volume in &service.volumes {
+                let source = volume.split(':').next().unwrap_or_default();
+                let is_named = volume.contains(':')
+                    && !source.starts_with('/')
+                    && !source.starts_with('~')
+                    && !is_relative_path(source);
+                if is_named && !self.volumes.contains_key(source) {
+                    problems.push(format!("{}: volume {} is not declared", name, source));
+                }
+            }
+            for port in &service.ports {
+                let parts = port.split(':').collect::<Vec<_>>();
+                let valid = parts.len() <= 3
+                    && parts.iter().all(|part| {
+                        let part = part.split('/').next().unwrap_or_default();
+                        !part.is_empty()
+                            && part
+                                .split('-')
+                                .all(|p| p.parse::<u16>().is_ok() || p.contains('.'))
+                    });
+                if !valid {
+                    problems.push(format!("{}: invalid port {}", name, port));
+                    continue;
+                }
+                if parts.len() >= 2 {
+                    let host_port = parts[..parts.len() - 1].join(":");
+                    if let Some(other) = host_ports.insert(host_port.clone(), name.clone()) {
+                        problems.push(format!(
+                            "{}: host port {} is also used by {}",
+                            name, host_port, other
+                        ));
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    /// Services with dependencies before dependents,
+    /// as they need to be started
+    pub fn service_order(&self) -> Vec<String> {
+        fn visit(
+            compose: &ComposeYml,
+            name: &str,
+            order: &mut Vec<String>,
+            path: &mut Vec<String>,
+        ) {
+            if order.iter().any(|n| n == name) || path.iter().any(|n| n == name) {
+                return;
+            }
+            let service = match compose.services.get(name) {
+                Some(service) => service,
+                None => return,
+            };
+            path.push(name.to_string());
+            for dep in &service.depends_on {
+                visit(compose, dep, order, path);
+            }
+            path.pop();
+            order.push(name.to_string());
+        }
+        let mut order = Vec::new();
+        for name in self.services.keys() {
+            visit(self, name, &mut order, &mut Vec::new());
+        }
+        order
+    }
+
+    pub fn render(&self) -> Result<String> {
+        serde_yaml::to_string(self).map_err(|e| cli_err!(Docker, "{:?}", e))
+    }
+}
+
+/// Environment to render compose file for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComposeEnv {
+    Dev,
+    Prod,
+    /// Prod including the backup service,
+    /// used to run backup commands
+    ProdBackup,
+}
+impl ComposeEnv {
+    /// Resolved file, relative to project dir
+    pub fn file(self) -> &'static str {
+        match self {
+            ComposeEnv::Dev => "docker/compose.dev.yml",
+            ComposeEnv::Prod => "docker/compose.prod.yml",
+            ComposeEnv::ProdBackup => "docker/compose.prod-backup.yml",
+        }
+    }
+
+    /// Server files, relative to workdir/server,
+    /// then project file relative to project dir
+    fn files(self) -> (Vec<&'static str>, &'static str) {
+        match self {
+            ComposeEnv::Dev => (
+                vec![
+                    "base/docker-compose.yml",
+                    "dev/docker-compose.dev.yml",
+                    "base/docker-reimage.yml",
+                ],
+                "docker/dev.yml",
+            ),
+            ComposeEnv::Prod => (
+                vec![
+                    "base/docker-compose.yml",
+                    "prod/docker-compose.prod.yml",
+                    "base/docker-reimage.yml",
+                ],
+                "docker/prod.yml",
+            ),
+            ComposeEnv::ProdBackup => (
+                vec![
+                    "base/docker-compose.yml",
+                    "prod/docker-compose.prod.yml",
+                    "base/docker-reimage.yml",
+                    "base/docker-backup.yml",
+                ],
+                "docker/prod.yml",
+            ),
+        }
+    }
+
+    /// Arguments to docker-compose using the resolved file,
+    /// relative to project dir
+    pub fn args(self) -> Vec<String> {
+        self.args_for(PROJECT_NAME)
+    }
+
+    /// Arguments using the given compose project name
+    pub fn args_for(self, compose_project: &str) -> Vec<String> {
+        vec![
+            "-p".to_string(),
+            compose_project.to_string(),
+            "-f".to_string(),
+            self.file().to_string(),
+        ]
+    }
+}
+
+/// Loads and merges compose files of environment from local
+/// workdir and project. Relative paths are resolved against
+/// server_base, which is the base dir of server files where
+/// the result will be run
+pub fn resolve(
+    env: &CliEnv,
+    project_dir: &Path,
+    compose_env: ComposeEnv,
+    server_base: &Path,
+) -> Result<ComposeYml> {
+    let (server_files, project_file) = compose_env.files();
+    let mut files = server_files
+        .into_iter()
+        .map(|file| env.workdir_dir.join("server").join(file))
+        .collect::<Vec<_>>();
+    files.push(project_dir.join(project_file));
+    ComposeYml::load_merged(&files, server_base)
+}
+
+/// Resolves, validates and writes the single compose
+/// file of environment into project docker/ dir
+pub fn write_resolved(
+    env: &CliEnv,
+    project_dir: &Path,
+    compose_env: ComposeEnv,
+    server_base: &Path,
+) -> Result<ComposeYml> {
+    let compose = resolve(env, project_dir, compose_env, server_base)?;
+    write_validated(project_dir, compose_env, &compose)?;
+    Ok(compose)
+}
+
+/// Validates and writes an already resolved compose
+/// file of environment into project docker/ dir
+pub fn write_validated(
+    project_dir: &Path,
+    compose_env: ComposeEnv,
+    compose: &ComposeYml,
+) -> Result<()> {
+    let problems = compose.validate();
+    if !problems.is_empty() {
+        return Err(cli_err!(
+            Docker,
+            "Invalid compose config for {:?}:\n{}",
+            compose_env,
+            problems.join("\n")
+        ));
+    }
+    let file = project_dir.join(compose_env.file());
+    crate::utils::write_file(&file, &compose.render()?)?;
+    Ok(())
+}
+
+/// Prints resolved compose file of environment,
+/// followed by any validation problems
+pub fn render_cmd(env: &CliEnv, project: ProjectConfig, compose_env: ComposeEnv) -> Result<()> {
+    let compose = match compose_env {
+        ComposeEnv::Dev => {
+            let module = crate::modules::of(&project)?;
+            module.gen_dev(env, &project)?;
+            let server_base = env.workdir_dir.join("server/base");
+            let mut compose = resolve(env, &project.dir(env), compose_env, &server_base)?;
+            module.apply(&project, &mut compose, true);
+            compose
+        }
+        ComposeEnv::Prod | ComposeEnv::ProdBackup => {
+            let server = project.require_server(env)?;
+            crate::modules::of(&project)?.gen_prod(env, &project)?;
+            crate::project::resolve_prod(env, &project, &server, compose_env)?
+        }
+    };
+    println!("{}", compose.render()?);
+    let problems = compose.validate();
+    if problems.is_empty() {
+        println!("{}", console::style("Valid").green());
+    }
+    for problem in problems {
+        println!("{}", console::style(problem).red());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"
+version: '3.3'
+services:
+  db:
+    build: mysql-custom
+    volumes:
+      - db_data:/var/lib/mysql
+    environment:
+      MYSQL_DATABASE: wordpress
+  wp:
+    image: wordpress:5
+    depends_on:
+      - db
+    volumes:
+      - "wp-uploads:/var/www/html/wp-content/uploads/"
+    environment:
+      IS_DOCKER: 1
+    ports:
+      - "8000:80"
+volumes:
+  db_data: {}
+  wp-uploads:
+"#;
+
+    const OVERRIDE: &str = r#"
+version: '3.3'
+services:
+  wp:
+    build: ../dev/wp-dev
+    environment:
+      - IS_DOCKER=0
+      - WORDPRESS_DEBUG=1
+    volumes:
+      - "./uploads:/var/www/html/wp-content/uploads"
+    ports:
+      - "8443:443"
+"#;
+
+    fn merged() -> ComposeYml {
+        let base_dir = Path::new("/srv/server/base");
+        let mut compose = ComposeYml::parse(BASE).unwrap();
+        let mut other = ComposeYml::parse(OVERRIDE).unwrap();
+        for service in other.services.values_mut() {
+            service.resolve_paths(base_dir);
+        }
+        compose.merge(other);
+        compose
+    }
+
+    #[test]
+    fn merges_with_override_rules() {
+        let compose = merged();
+        let wp = &compose.services["wp"];
+        assert_eq!(wp.image.as_ref().unwrap(), "wordpress:5");
+        assert_eq!(
+            wp.build.as_ref().unwrap().context(),
+            "/srv/server/dev/wp-dev"
+        );
+        assert_eq!(wp.environment["IS_DOCKER"], "0");
+        assert_eq!(wp.environment["WORDPRESS_DEBUG"], "1");
+        assert_eq!(wp.ports, vec!["8000:80", "8443:443"]);
+        // Same container path replaces the mount
+        assert_eq!(
+            wp.volumes,
+            vec!["/srv/server/base/uploads:/var/www/html/wp-content/uploads"]
+        );
+        assert!(compose.volumes.contains_key("wp-uploads"));
+    }
+
+    #[test]
+    fn validates_references() {
+        let mut compose = merged();
+        assert!(compose.validate().is_empty());
+        compose
+            .services
+            .get_mut("wp")
+            .unwrap()
+            .depends_on
+            .push("cache".into());
+        compose
+            .services
+            .get_mut("db")
+            .unwrap()
+            .ports
+            .push("8000:3306".into());
+        compose.volumes.remove("db_data");
+        let problems = compose.validate();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+    }
+
+    #[test]
+    fn renders_parseable_yml() {
+        let compose = merged();
+        let rendered = ComposeYml::parse(&compose.render().unwrap()).unwrap();
+        assert_eq!(rendered.services.len(), 2);
+        assert_eq!(rendered.services["wp"].environment.len(), 2);
+    }
+
+    #[test]
+    fn finds_published_port() {
+        let compose = merged();
+        assert_eq!(compose.services["wp"].published_port(443), Some(8443));
+        assert_eq!(compose.services["wp"].published_port(22), None);
+        let mut db = ComposeService::default();
+        db.ports.push("127.0.0.1:3307:3306/tcp".into());
+        assert_eq!(db.published_port(3306), Some(3307));
+    }
+
+    #[test]
+    fn adds_build_args() {
+        let compose = merged();
+        let mut db = compose.services["db"].clone();
+        let mut args = BTreeMap::new();
+        args.insert("WORDPRESS_TAG".to_string(), "5.3-php7.4-apache".to_string());
+        db.add_build_args(args.clone());
+        assert_eq!(
+            db.build,
+            Some(ComposeBuild::Config {
+                context: "mysql-custom".into(),
+                dockerfile: None,
+                args,
+            })
+        );
+        let mut image = ComposeService::default();
+        image.add_build_args(BTreeMap::new());
+        assert!(image.build.is_none());
+    }
+
+    #[test]
+    fn orders_dependencies_first() {
+        let compose = merged();
+        assert_eq!(compose.service_order(), vec!["db", "wp"]);
+    }
+}
@@ -0,0 +1,396 @@
+// Versions of config files under the projects .config dir.
+// Files have a version field, and are migrated to the current
+// version when read, so older files keep working as configs
+// change. `config doctor` checks all files, and references
+// between them.
+
+use crate::er::{self, Result};
+use crate::git::GitConfig;
+use crate::modules;
+use crate::project::ProjectConfig;
+use crate::server::ServerConfig;
+use crate::utils::{self, CliEnv, ConfigDir};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Migrates a config object from one version to the next
+type Migration = fn(&mut Map<String, Value>);
+
+/// Files from before versioning have no version, read as 0.
+/// They match version 1, later fields are defaulted by serde
+fn unversioned(_config: &mut Map<String, Value>) {}
+
+/// The offsite passphrase is kept on the server only, as
+/// project configs are shared through the workspace repo
+fn drop_offsite_passphrase(config: &mut Map<String, Value>) {
+    if let Some(Value::Object(offsite)) = config.get_mut("backup_offsite") {
+        offsite.remove("passphrase");
+    }
+}
+
+/// Domain and aliases moved from the site config to the
+/// project, as also single site projects have a domain
+fn site_domain_to_project(config: &mut Map<String, Value>) {
+    let (domain, aliases) = match config.get_mut("site") {
+        Some(Value::Object(site)) => (site.remove("domain"), site.remove("aliases")),
+        _ => return,
+    };
+    if let Some(domain) = domain {
+        config.entry("domain").or_insert(domain);
+    }
+    if let Some(aliases) = aliases {
+        config.entry("domain_aliases").or_insert(aliases);
+    }
+}
+
+/// Migrations by version they migrate from. The current
+/// version is the number of migrations
+const PROJECT_MIGRATIONS: [Migration; 3] =
+    [unversioned, drop_offsite_passphrase, site_domain_to_project];
+const SERVER_MIGRATIONS: [Migration; 1] = [unversioned];
+const GIT_MIGRATIONS: [Migration; 1] = [unversioned];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigKind {
+    Project,
+    Server,
+    Git,
+}
+impl ConfigKind {
+    fn migrations(self) -> &'static [Migration] {
+        match self {
+            ConfigKind::Project => &PROJECT_MIGRATIONS,
+            ConfigKind::Server => &SERVER_MIGRATIONS,
+            ConfigKind::Git => &GIT_MIGRATIONS,
+        }
+    }
+
+    pub fn version(self) -> u64 {
+        self.migrations().len() as u64
+    }
+
+    fn dir(self, env: &CliEnv) -> &ConfigDir {
+        match self {
+            ConfigKind::Project => &env.config_dirs.projects,
+            ConfigKind::Server => &env.config_dirs.servers,
+            ConfigKind::Git => &env.config_dirs.git_accounts,
+        }
+    }
+
+    fn dir_name(self) -> &'static str {
+        match self {
+            ConfigKind::Project => "projects",
+            ConfigKind::Server => "servers",
+            ConfigKind::Git => "git_accounts",
+        }
+    }
+}
+
+/// Config object migrated to current version,
+/// and the version it was read as
+pub fn migrate(kind: ConfigKind, content: &str) -> Result<(Map<String, Value>, u64)> {
+    let value = serde_json::from_str::<Value>(content)
+        .map_err(|e| cli_err!(Config, "Invalid json: {}", e))?;
+    let mut config = match value {
+        Value::Object(config) => config,
+        _ => return Err(cli_err!(Config, "Expected json object")),
+    };
+    let version = config.get("version").and_then(Value::as_u64).unwrap_or(0);
+    let current = kind.version();
+    if version > current {
+        return Err(cli_err!(
+            Config,
+            "Version {} is newer than {} of this cli, it needs an update",
+            version,
+            current
+        ));
+    }
+    for migration in &kind.migrations()[version as usize..] {
+        migration(&mut config);
+    }
+    config.insert("version".to_string(), Value::from(current));
+    Ok((config, version))
+}
+
+/// Reads config file, migrated to current version
+pub fn read<T: DeserializeOwned>(kind: ConfigKind, path: &Path) -> Result<T> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| er::io_msg(format!("Could not read {:?}", path), e))?;
+    let (config, _version) = migrate(kind, &content)?;
+    serde_json::from_value(Value::Object(config))
+        .map_err(|e| cli_err!(Config, "Invalid config {:?}: {}", path, e))
+}
+
+/// Json of config with current version first
+pub fn to_string<T: Serialize>(kind: ConfigKind, config: &T) -> Result<String> {
+    let mut versioned = Map::new();
+    versioned.insert("version".to_string(), Value::from(kind.version()));
+    let value =
+        serde_json::to_value(config).map_err(|e| cli_err!(Config, "Invalid config: {}", e))?;
+    match value {
+        Value::Object(fields) => versioned.extend(fields),
+        _ => return Err(cli_err!(Config, "Expected config to be an object")),
+    }
+    serde_json::to_string_pretty(&Value::Object(versioned))
+        .map_err(|e| cli_err!(Config, "Invalid config: {}", e))
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    /// Config file, relative to config dir
+    pub file: String,
+    pub problem: String,
+    /// Errors keep commands from working, others are warnings
+    pub error: bool,
+}
+
+/// Names of config files in dir, skipping hidden
+/// entries like the .pem dir of servers
+fn config_names(dir: &ConfigDir) -> Result<Vec<String>> {
+    let mut names = utils::files_in_dir(&dir.0)
+        .map_err(er::io)?
+        .into_iter()
+        .filter(|name| !name.starts_with('.') && dir.has_file(name))
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+struct Checked<T> {
+    name: String,
+    config: T,
+}
+
+/// Reads and migrates all files of kind, adding issues of those
+/// that can not be read. With fix, migrated files are written
+fn check_kind<T: DeserializeOwned + Serialize>(
+    env: &CliEnv,
+    kind: ConfigKind,
+    fix: bool,
+    issues: &mut Vec<Issue>,
+) -> Result<Vec<Checked<T>>> {
+    let dir = kind.dir(env);
+    let mut checked = Vec::new();
+    for name in config_names(dir)? {
+        let file = format!("{}/{}", kind.dir_name(), name);
+        let mut issue = |problem: String, error: bool| {
+            issues.push(Issue {
+                file: file.clone(),
+                problem,
+                error,
+            })
+        };
+        let content = std::fs::read_to_string(dir.filepath(&name)).map_err(er::io)?;
+        let (migrated, version) = match migrate(kind, &content) {
+            Ok(migrated) => migrated,
+            Err(e) => {
+                issue(format!("{}", e), true);
+                continue;
+            }
+        };
+        let config = match serde_json::from_value::<T>(Value::Object(migrated)) {
+            Ok(config) => config,
+            Err(e) => {
+                issue(format!("Invalid config: {}", e), true);
+                continue;
+            }
+        };
+        if version < kind.version() {
+            if fix {
+                dir.write(&name, &to_string(kind, &config)?)?;
+                issue(
+                    format!("Migrated from version {} to {}", version, kind.version()),
+                    false,
+                );
+            } else {
+                issue(
+                    format!(
+                        "Version {}, migrates to {} with --fix",
+                        version,
+                        kind.version()
+                    ),
+                    false,
+                );
+            }
+        }
+        checked.push(Checked { name, config });
+    }
+    Ok(checked)
+}
+
+/// Issues of all config files, and of references between them
+pub fn check(env: &CliEnv, fix: bool) -> Result<Vec<Issue>> {
+    let mut issues = Vec::new();
+    let projects = check_kind::<ProjectConfig>(env, ConfigKind::Project, fix, &mut issues)?;
+    let servers = check_kind::<ServerConfig>(env, ConfigKind::Server, fix, &mut issues)?;
+    let git_accounts = check_kind::<GitConfig>(env, ConfigKind::Git, fix, &mut issues)?;
+    // References are by file name
+    let server_names = config_names(&env.config_dirs.servers)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let git_names = config_names(&env.config_dirs.git_accounts)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    for project in &projects {
+        let file = format!("projects/{}", project.name);
+        let mut issue = |problem: String| {
+            issues.push(Issue {
+                file: file.clone(),
+                problem,
+                error: true,
+            })
+        };
+        let config = &project.config;
+        if config.name != project.name {
+            issue(format!("Name {} differs from file name", config.name));
+        }
+        if !config.server_name.is_empty() && !server_names.contains(&config.server_name) {
+            issue(format!("Server {} does not exist", config.server_name));
+        }
+        for (env_name, env_config) in &config.environments {
+            if !server_names.contains(&env_config.server_name) {
+                issue(format!(
+                    "Server {} of env {} does not exist",
+                    env_config.server_name, env_name
+                ));
+            }
+        }
+        if !config.git_user.is_empty() && !git_names.contains(&config.git_user) {
+            issue(format!("Git account {} does not exist", config.git_user));
+        }
+        if let Some(module) = &config.module {
+            if modules::get(module).is_none() {
+                issue(format!("Unknown module {}", module));
+            }
+        }
+    }
+    for server in &servers {
+        let file = format!("servers/{}", server.name);
+        if server.config.name != server.name {
+            issues.push(Issue {
+                file: file.clone(),
+                problem: format!("Name {} differs from file name", server.config.name),
+                error: true,
+            });
+        }
+        if !server.config.pem_path(env).is_file() {
+            issues.push(Issue {
+                file,
+                problem: format!("Pem file .pem/{} does not exist", server.config.pem),
+                error: true,
+            });
+        }
+    }
+    for account in &git_accounts {
+        if account.config.user != account.name {
+            issues.push(Issue {
+                file: format!("git_accounts/{}", account.name),
+                problem: format!("User {} differs from file name", account.config.user),
+                error: true,
+            });
+        }
+    }
+    Ok(issues)
+}
+
+/// Prints issues of config files, failing when there are errors
+pub fn doctor_cmd(env: &CliEnv, fix: bool) -> Result<()> {
+    let issues = check(env, fix)?;
+    if issues.is_empty() {
+        println!(
+            "No issues in {}",
+            env.config_dirs.config_root.to_string_lossy()
+        );
+        return Ok(());
+    }
+    for issue in &issues {
+        let problem = if issue.error {
+            console::style(&issue.problem).red()
+        } else {
+            console::style(&issue.problem).yellow()
+        };
+        println!("{}: {}", issue.file, problem);
+    }
+    let errors = issues.iter().filter(|issue| issue.error).count();
+    if errors > 0 {
+        return Err(cli_err!(Config, "{} errors in config files", errors));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_unversioned() {
+        let (config, version) = migrate(ConfigKind::Server, r#"{"name": "web"}"#).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(config["version"], Value::from(ConfigKind::Server.version()));
+        assert!(migrate(ConfigKind::Server, r#"{"version": 1000}"#).is_err());
+        assert!(migrate(ConfigKind::Server, "[]").is_err());
+    }
+
+    #[test]
+    fn drops_offsite_passphrase() {
+        let (config, _version) = migrate(
+            ConfigKind::Project,
+            r#"{"version": 1, "backup_offsite": {"bucket": "b", "passphrase": "secret"}}"#,
+        )
+        .unwrap();
+        assert_eq!(config["backup_offsite"], serde_json::json!({"bucket": "b"}));
+    }
+
+    #[test]
+    fn moves_site_domain_to_project() {
+        let (config, _version) = migrate(
+            ConfigKind::Project,
+            r#"{"version": 2, "site": {"domain": "a.com", "aliases": ["www.a.com"], "http_port": 8100}}"#,
+        )
+        .unwrap();
+        assert_eq!(config["domain"], Value::from("a.com"));
+        assert_eq!(config["domain_aliases"], serde_json::json!(["www.a.com"]));
+        assert_eq!(config["site"], serde_json::json!({"http_port": 8100}));
+    }
+
+    #[test]
+    fn finds_dangling_references() {
+        let projects_dir =
+            std::env::temp_dir().join(format!("config-doctor-{}", std::process::id()));
+        let env = CliEnv::new(projects_dir.clone(), projects_dir.join("workdir"));
+        let dirs = &env.config_dirs;
+        dirs.projects
+            .write(
+                "site",
+                r#"{"name": "site", "git_repo_uri": "", "git_user": "gone", "server_name": "web"}"#,
+            )
+            .unwrap();
+        dirs.servers
+            .write(
+                "web",
+                r#"{"version": 1, "name": "web", "url": "", "pem": "web.pem", "instance_id": null, "elastic_ip": null}"#,
+            )
+            .unwrap();
+        dirs.servers.write(".pem/web.pem", "").unwrap();
+        let issues = check(&env, true).unwrap();
+        std::fs::remove_dir_all(&projects_dir).unwrap();
+        assert_eq!(
+            issues,
+            vec![
+                Issue {
+                    file: "projects/site".into(),
+                    problem: "Migrated from version 0 to 3".into(),
+                    error: false,
+                },
+                Issue {
+                    file: "projects/site".into(),
+                    problem: "Git account gone does not exist".into(),
+                    error: true,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,666 @@
+// Debug configs for editors, all from the same path mappings
+// of local plugins and themes to where they are mounted in the
+// wordpress container. Existing files are edited in place,
+// replacing only the "Wop" entry, so comments and other
+// entries are kept.
+
+use crate::er::{self, Result};
+use crate::project::ProjectConfig;
+use crate::utils::CliEnv;
+use crate::wp::{self, WpLocalSiteData};
+
+/// Name of our entry in editor configs
+const DEBUG_NAME: &str = "Wop";
+/// Port xdebug connects to, from the container
+const XDEBUG_PORT: u16 = 9001;
+/// Port the editor listens on, forwarded from xdebug port
+const IDE_PORT: u16 = 9002;
+/// Dev site through the dev proxy
+const DEV_HOST: &str = "localhost";
+const DEV_PORT: u16 = 80;
+
+const VSCODE_FILE: &str = ".vscode/launch.json";
+const PHPSTORM_FILE: &str = ".idea/workspace.xml";
+const DBGP_FILE: &str = "dbgp.json";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Editor {
+    VsCode,
+    PhpStorm,
+    Dbgp,
+}
+impl Editor {
+    /// Editors by name, "all" giving every editor
+    pub fn from_name(name: &str) -> Result<Vec<Editor>> {
+        match name {
+            "vscode" => Ok(vec![Editor::VsCode]),
+            "phpstorm" => Ok(vec![Editor::PhpStorm]),
+            "dbgp" => Ok(vec![Editor::Dbgp]),
+            "all" => Ok(vec![Editor::VsCode, Editor::PhpStorm, Editor::Dbgp]),
+            other => Err(cli_err!(Config, "Unknown editor: {}", other)),
+        }
+    }
+}
+
+/// Path in container, and local path relative to project
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathMapping {
+    pub server: String,
+    pub local: String,
+}
+
+/// Plugins and themes, sorted, then the wp root
+/// as fallback for other files
+pub fn path_mappings(site_local: &WpLocalSiteData) -> Vec<PathMapping> {
+    let mut mappings = site_local
+        .plugins
+        .values()
+        .map(|plugin| &plugin.paths)
+        .chain(site_local.themes.values().map(|theme| &theme.paths))
+        .map(|paths| PathMapping {
+            server: paths.server_path.string(),
+            local: paths.from_project.string(),
+        })
+        .collect::<Vec<_>>();
+    mappings.sort_by(|a, b| a.server.cmp(&b.server));
+    mappings.push(PathMapping {
+        server: "/var/www/html".to_string(),
+        local: "wp".to_string(),
+    });
+    mappings
+}
+
+/// Edits existing file content, if any, into config
+/// with given mappings, of project in given dir
+type EditFn = fn(Option<&str>, &[PathMapping], &str) -> Result<String>;
+
+/// Writes debug configs of given editors into project
+pub fn gen_debug_config(env: &CliEnv, project: &ProjectConfig, editors: &[Editor]) -> Result<()> {
+    let site_local = wp::get_local_site_data(env, project)?;
+    let mappings = path_mappings(&site_local);
+    for editor in editors {
+        let (file, edit): (&str, EditFn) = match editor {
+            Editor::VsCode => (VSCODE_FILE, vscode_launch),
+            Editor::PhpStorm => (PHPSTORM_FILE, phpstorm_workspace),
+            Editor::Dbgp => (DBGP_FILE, dbgp_config),
+        };
+        let path = project.dir_and(env, file);
+        let existing = if path.is_file() {
+            Some(std::fs::read_to_string(&path).map_err(er::io)?)
+        } else {
+            None
+        };
+        let project_dir = project.dir(env);
+        let content = edit(
+            existing.as_ref().map(String::as_str),
+            &mappings,
+            &project_dir.to_string_lossy(),
+        )
+        .map_err(|e| cli_err!(Config, "{}: {}", file, e))?;
+        project.write_file(env, file, &content)?;
+        println!("Wrote {}", file);
+    }
+    if editors.contains(&Editor::PhpStorm) {
+        println!(
+            "In PhpStorm, set the Xdebug debug port to {} and start listening",
+            IDE_PORT
+        );
+    }
+    Ok(())
+}
+
+fn vscode_entry(mappings: &[PathMapping]) -> serde_json::Value {
+    let path_mappings = mappings
+        .iter()
+        .map(|m| {
+            (
+                m.server.clone(),
+                serde_json::Value::String(format!("${{workspaceRoot}}/{}", m.local)),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    serde_json::json!({
+        "name": DEBUG_NAME,
+        "request": "launch",
+        "type": "php",
+        "pathMappings": path_mappings,
+        "port": IDE_PORT,
+    })
+}
+
+/// Launch.json with our entry replaced or appended,
+/// keeping the rest of the text as is
+fn vscode_launch(
+    existing: Option<&str>,
+    mappings: &[PathMapping],
+    _project_dir: &str,
+) -> Result<String> {
+    let entry = vscode_entry(mappings);
+    let existing = match existing {
+        Some(existing) => existing,
+        None => {
+            let launch = serde_json::json!({
+                "version": "0.2.0",
+                "configurations": [entry],
+            });
+            return to_pretty(&launch).map(|json| json + "\n");
+        }
+    };
+    let root = Jsonc::parse(existing)?;
+    let members = match &root.node {
+        JsonNode::Object(members) => members,
+        _ => return Err(cli_err!(Config, "Expected object")),
+    };
+    let configurations = match members.iter().find(|(key, _)| key == "configurations") {
+        Some((_, configurations)) => configurations,
+        None => return Err(cli_err!(Config, "Expected configurations key")),
+    };
+    let entries = match &configurations.node {
+        JsonNode::Array(entries) => entries,
+        _ => return Err(cli_err!(Config, "Expected configurations array")),
+    };
+    let ours = entries.iter().find(|entry| match &entry.node {
+        JsonNode::Object(members) => members
+            .iter()
+            .any(|(key, value)| key == "name" && value.node == JsonNode::Str(DEBUG_NAME.into())),
+        _ => false,
+    });
+    let mut edited = String::with_capacity(existing.len());
+    match (ours, entries.last()) {
+        (Some(ours), _) => {
+            let indent = line_indent(existing, ours.start);
+            edited.push_str(&existing[..ours.start]);
+            edited.push_str(&indented(&to_pretty(&entry)?, indent));
+            edited.push_str(&existing[ours.end..]);
+        }
+        (None, Some(last)) => {
+            let indent = line_indent(existing, last.start);
+            edited.push_str(&existing[..last.end]);
+            edited.push_str(",\n");
+            edited.push_str(indent);
+            edited.push_str(&indented(&to_pretty(&entry)?, indent));
+            edited.push_str(&existing[last.end..]);
+        }
+        (None, None) => {
+            // After the opening bracket
+            let outer = line_indent(existing, configurations.start);
+            let indent = format!("{}    ", outer);
+            let open = configurations.start + 1;
+            edited.push_str(&existing[..open]);
+            edited.push('\n');
+            edited.push_str(&indent);
+            edited.push_str(&indented(&to_pretty(&entry)?, &indent));
+            edited.push('\n');
+            edited.push_str(outer);
+            edited.push_str(existing[open..].trim_start());
+        }
+    }
+    Ok(edited)
+}
+
+fn to_pretty(value: &serde_json::Value) -> Result<String> {
+    serde_json::to_string_pretty(value).map_err(|e| cli_err!(Other, "Serialize error: {:?}", e))
+}
+
+/// Indents all but the first line
+fn indented(text: &str, indent: &str) -> String {
+    text.replace('\n', &format!("\n{}", indent))
+}
+
+/// Whitespace at the start of line containing pos
+fn line_indent(text: &str, pos: usize) -> &str {
+    let line_start = text[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = &text[line_start..];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Json value with its position in source
+#[derive(Debug)]
+struct Spanned {
+    start: usize,
+    end: usize,
+    node: JsonNode,
+}
+
+#[derive(Debug, PartialEq)]
+enum JsonNode {
+    Object(Vec<(String, Spanned)>),
+    Array(Vec<Spanned>),
+    Str(String),
+    Other,
+}
+impl PartialEq for Spanned {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+/// Reads json with comments and trailing commas, as vscode
+/// writes it, keeping where values are
+struct Jsonc<'a> {
+    src: &'a str,
+    pos: usize,
+}
+impl<'a> Jsonc<'a> {
+    fn parse(src: &'a str) -> Result<Spanned> {
+        let mut parser = Jsonc { src, pos: 0 };
+        let value = parser.value()?;
+        parser.skip_blank()?;
+        if parser.pos < src.len() {
+            return parser.err("Unexpected content after value");
+        }
+        Ok(value)
+    }
+
+    fn err<T>(&self, msg: &str) -> Result<T> {
+        let line = self.src[..self.pos].matches('\n').count() + 1;
+        Err(cli_err!(Config, "{} on line {}", msg, line))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).cloned()
+    }
+
+    /// Skips whitespace and comments
+    fn skip_blank(&mut self) -> Result<()> {
+        loop {
+            let rest = &self.src[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                match trimmed.get(2..).and_then(|comment| comment.find("*/")) {
+                    Some(end) => self.pos += end + 4,
+                    None => return self.err("Unclosed comment"),
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        self.skip_blank()?;
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.err(&format!("Expected {}", c as char))
+        }
+    }
+
+    fn value(&mut self) -> Result<Spanned> {
+        self.skip_blank()?;
+        let start = self.pos;
+        let node = match self.peek() {
+            Some(b'{') => self.object()?,
+            Some(b'[') => self.array()?,
+            Some(b'"') => JsonNode::Str(self.string()?),
+            Some(_) => {
+                // Numbers, booleans and null
+                let rest = &self.src[self.pos..];
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || ",]}/".contains(c))
+                    .unwrap_or(rest.len());
+                if len == 0 {
+                    return self.err("Expected value");
+                }
+                self.pos += len;
+                JsonNode::Other
+            }
+            None => return self.err("Unexpected end"),
+        };
+        Ok(Spanned {
+            start,
+            end: self.pos,
+            node,
+        })
+    }
+
+    /// Content of string, with escapes left as they are
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        let bytes = self.src.as_bytes();
+        while self.pos < bytes.len() {
+            match bytes[self.pos] {
+                b'\\' => self.pos += 2,
+                b'"' => {
+                    self.pos += 1;
+                    return Ok(self.src[start..self.pos - 1].to_string());
+                }
+                _ => self.pos += 1,
+            }
+        }
+        self.err("Unclosed string")
+    }
+
+    /// Whether list continues, after a comma or at its end
+    fn list_continues(&mut self, close: u8) -> Result<bool> {
+        self.skip_blank()?;
+        match self.peek() {
+            Some(b',') => {
+                self.pos += 1;
+                self.skip_blank()?;
+                // Trailing comma
+                if self.peek() == Some(close) {
+                    self.pos += 1;
+                    Ok(false)
+                } else {
+                    Ok(true)
+                }
+            }
+            Some(c) if c == close => {
+                self.pos += 1;
+                Ok(false)
+            }
+            _ => self.err(&format!("Expected , or {}", close as char)),
+        }
+    }
+
+    fn object(&mut self) -> Result<JsonNode> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_blank()?;
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(JsonNode::Object(members));
+        }
+        loop {
+            self.skip_blank()?;
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            if !self.list_continues(b'}')? {
+                return Ok(JsonNode::Object(members));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<JsonNode> {
+        self.expect(b'[')?;
+        let mut entries = Vec::new();
+        self.skip_blank()?;
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(JsonNode::Array(entries));
+        }
+        loop {
+            entries.push(self.value()?);
+            if !self.list_continues(b']')? {
+                return Ok(JsonNode::Array(entries));
+            }
+        }
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn phpstorm_server(mappings: &[PathMapping]) -> String {
+    let mut server = format!(
+        "      <server host=\"{}\" name=\"{}\" port=\"{}\" use_path_mappings=\"true\">\n",
+        DEV_HOST, DEBUG_NAME, DEV_PORT
+    );
+    server.push_str("        <path_mappings>\n");
+    for mapping in mappings {
+        server.push_str(&format!(
+            "          <mapping local-root=\"$PROJECT_DIR$/{}\" remote-root=\"{}\" />\n",
+            xml_escape(&mapping.local),
+            xml_escape(&mapping.server)
+        ));
+    }
+    server.push_str("        </path_mappings>\n");
+    server.push_str("      </server>\n");
+    server
+}
+
+/// Start of line when only whitespace precedes pos on it
+fn blank_line_start(text: &str, pos: usize) -> usize {
+    let line_start = text[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    if text[line_start..pos].trim().is_empty() {
+        line_start
+    } else {
+        pos
+    }
+}
+
+/// Workspace.xml with our server of the PhpServers component
+/// replaced or added. Other servers and components are kept
+fn phpstorm_workspace(
+    existing: Option<&str>,
+    mappings: &[PathMapping],
+    _project_dir: &str,
+) -> Result<String> {
+    let server = phpstorm_server(mappings);
+    let component = format!(
+        "  <component name=\"PhpServers\">\n    <servers>\n{}    </servers>\n  </component>\n",
+        server
+    );
+    let existing = match existing {
+        Some(existing) => existing,
+        None => {
+            return Ok(format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<project version=\"4\">\n{}</project>\n",
+            component
+        ))
+        }
+    };
+    let component_start = match existing.find("<component name=\"PhpServers\"") {
+        Some(start) => start,
+        None => {
+            // New component at the end of project
+            let end = match existing.rfind("</project>") {
+                Some(end) => blank_line_start(existing, end),
+                None => return Err(cli_err!(Config, "Expected project element")),
+            };
+            return Ok(format!(
+                "{}{}{}",
+                &existing[..end],
+                component,
+                &existing[end..]
+            ));
+        }
+    };
+    let open_end = match existing[component_start..].find('>') {
+        Some(i) => component_start + i + 1,
+        None => return Err(cli_err!(Config, "Unclosed PhpServers tag")),
+    };
+    if existing[..open_end].ends_with("/>") {
+        let start = blank_line_start(existing, component_start);
+        let end = open_end + existing[open_end..].find('\n').map(|i| i + 1).unwrap_or(0);
+        return Ok(format!(
+            "{}{}{}",
+            &existing[..start],
+            component,
+            &existing[end..]
+        ));
+    }
+    let close_start = match existing[open_end..].find("</component>") {
+        Some(i) => open_end + i,
+        None => return Err(cli_err!(Config, "Unclosed PhpServers component")),
+    };
+    let mut body = existing[open_end..close_start].to_string();
+    // Removing our earlier server
+    let mut search_from = 0;
+    while let Some(i) = body[search_from..].find("<server ") {
+        let tag_start = search_from + i;
+        let tag_end = match body[tag_start..].find('>') {
+            Some(i) => tag_start + i + 1,
+            None => return Err(cli_err!(Config, "Unclosed server tag")),
+        };
+        let tag = &body[tag_start..tag_end];
+        if !tag.contains(&format!(" name=\"{}\"", DEBUG_NAME)) {
+            search_from = tag_end;
+            continue;
+        }
+        let element_end = if tag.ends_with("/>") {
+            tag_end
+        } else {
+            match body[tag_end..].find("</server>") {
+                Some(i) => tag_end + i + "</server>".len(),
+                None => return Err(cli_err!(Config, "Unclosed server element")),
+            }
+        };
+        let start = blank_line_start(&body, tag_start);
+        let end = if body[element_end..].starts_with('\n') {
+            element_end + 1
+        } else {
+            element_end
+        };
+        body.replace_range(start..end, "");
+        search_from = start;
+    }
+    match body.find("</servers>") {
+        Some(i) => {
+            let at = blank_line_start(&body, i);
+            body.insert_str(at, &server);
+        }
+        None => {
+            let at = blank_line_start(&body, body.len());
+            body.insert_str(at, &format!("    <servers>\n{}    </servers>\n", server));
+        }
+    }
+    Ok(format!(
+        "{}{}{}",
+        &existing[..open_end],
+        body,
+        &existing[close_start..]
+    ))
+}
+
+/// Generic config for editors using a DBGp proxy, or taking
+/// path maps directly, like vdebug. Local paths are absolute,
+/// as there is no workspace to be relative to
+fn dbgp_config(
+    _existing: Option<&str>,
+    mappings: &[PathMapping],
+    project_dir: &str,
+) -> Result<String> {
+    let path_mappings = mappings
+        .iter()
+        .map(|m| {
+            (
+                m.server.clone(),
+                serde_json::Value::String(format!("{}/{}", project_dir, m.local)),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    let config = serde_json::json!({
+        "name": DEBUG_NAME,
+        "xdebug_port": XDEBUG_PORT,
+        "ide_port": IDE_PORT,
+        "path_mappings": path_mappings,
+    });
+    to_pretty(&config).map(|json| json + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mappings() -> Vec<PathMapping> {
+        vec![
+            PathMapping {
+                server: "/var/www/html/wp-content/plugins/shop".into(),
+                local: "plugins/shop".into(),
+            },
+            PathMapping {
+                server: "/var/www/html".into(),
+                local: "wp".into(),
+            },
+        ]
+    }
+
+    #[test]
+    fn keeps_comments_in_launch_json() {
+        let existing = r#"{
+    // Our own configs
+    "version": "0.2.0",
+    "configurations": [
+        {
+            "name": "Listen", /* keep */
+            "type": "php",
+        },
+        {"name": "Wop", "port": 1},
+    ]
+}
+"#;
+        let edited = vscode_launch(Some(existing), &mappings(), "").unwrap();
+        assert!(edited.contains("// Our own configs"));
+        assert!(edited.contains("\"name\": \"Listen\", /* keep */"));
+        assert!(!edited.contains("\"port\": 1"));
+        assert!(edited.contains("          \"port\": 9002\n        },\n    ]"));
+        // Edited file parses, with the entry replaced
+        let root = Jsonc::parse(&edited).unwrap();
+        match root.node {
+            JsonNode::Object(members) => match &members[1].1.node {
+                JsonNode::Array(entries) => assert_eq!(entries.len(), 2),
+                other => panic!("Expected array, got {:?}", other),
+            },
+            other => panic!("Expected object, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn appends_to_launch_configurations() {
+        let existing = "{\n  \"configurations\": [] // none yet\n}\n";
+        let edited = vscode_launch(Some(existing), &mappings(), "").unwrap();
+        assert!(
+            edited.starts_with("{\n  \"configurations\": [\n      {\n        \"name\": \"Wop\"")
+        );
+        assert!(edited.ends_with("\n  ] // none yet\n}\n"));
+        let edited_again = vscode_launch(Some(&edited), &mappings(), "").unwrap();
+        assert_eq!(edited, edited_again);
+    }
+
+    #[test]
+    fn replaces_phpstorm_server() {
+        let existing = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project version="4">
+  <component name="PhpServers">
+    <servers>
+      <server host="example.com" name="Other" port="443" />
+      <server host="localhost" name="Wop" port="80" use_path_mappings="true">
+        <path_mappings>
+          <mapping local-root="$PROJECT_DIR$/old" remote-root="/old" />
+        </path_mappings>
+      </server>
+    </servers>
+  </component>
+  <component name="RunManager" />
+</project>
+"#;
+        let edited = phpstorm_workspace(Some(existing), &mappings(), "").unwrap();
+        assert!(!edited.contains("/old"));
+        assert!(edited.contains("<server host=\"example.com\" name=\"Other\" port=\"443\" />"));
+        assert!(edited.contains(
+            "<mapping local-root=\"$PROJECT_DIR$/plugins/shop\" \
+             remote-root=\"/var/www/html/wp-content/plugins/shop\" />"
+        ));
+        assert_eq!(edited.matches("name=\"Wop\"").count(), 1);
+        assert!(edited.ends_with("  <component name=\"RunManager\" />\n</project>\n"));
+        let edited_again = phpstorm_workspace(Some(&edited), &mappings(), "").unwrap();
+        assert_eq!(edited, edited_again);
+        let added = phpstorm_workspace(
+            Some("<project version=\"4\">\n</project>\n"),
+            &mappings(),
+            "",
+        )
+        .unwrap();
+        assert!(added.contains("  <component name=\"PhpServers\">\n    <servers>\n      <server "));
+    }
+
+    #[test]
+    fn keeps_vscode_debug_config_name() {
+        let app = crate::cli::cli_app();
+        let matches = app.get_matches_from(vec!["wop", "wp", "vscode-debug-config"]);
+        assert_eq!(crate::hooks::command_name(&matches), "wp debug-config");
+    }
+}
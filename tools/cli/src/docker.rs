@@ -1,28 +1,22 @@
+use crate::compose::{self, ComposeEnv, ComposeService, ComposeYml, PROJECT_NAME};
+use crate::docker_api::{DockerApi, Progress};
+use crate::er::{self, Result};
+use crate::modules;
 use crate::project::ProjectConfig;
 use crate::utils::{self, CliEnv};
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use serde_json::Value;
 use std::collections::BTreeMap;
-use std::io;
+use std::io::{self, Write};
+use std::path::Path;
 use std::process;
 
-#[derive(Serialize, Debug)]
-pub struct ComposeYml {
-    pub version: &'static str,
-    pub services: BTreeMap<String, ComposeService>,
-}
-
-#[derive(Serialize, Debug)]
-pub struct ComposeService {
-    pub volumes: Vec<String>,
-    pub environment: BTreeMap<String, String>,
-}
-
 pub fn rebuild_container(
     env: &CliEnv,
     current_process: utils::CurrentProcess,
     project: ProjectConfig,
     service: String,
-) -> io::Result<utils::CurrentProcess> {
+) -> Result<utils::CurrentProcess> {
     println!("Rebuilding and restarting service: {}", service);
     // Todo: Should do the following only if dev is running
     // Todo: Option to remove volumes?
@@ -51,10 +45,36 @@ pub fn dev_cmd(
     current_process: utils::CurrentProcess,
     project: ProjectConfig,
     user_args: Vec<String>,
-) -> io::Result<utils::CurrentProcess> {
+) -> Result<utils::CurrentProcess> {
     dev_cmds(env, current_process, project, vec![user_args])
 }
 
+/// Compose file arguments for dev, relative
+/// to project dir
+pub fn dev_compose_args() -> Vec<String> {
+    ComposeEnv::Dev.args()
+}
+
+/// Writes docker/compose.dev.yml, merged from base files
+/// and the generated docker/dev.yml
+pub fn write_dev_compose(env: &CliEnv, project: &ProjectConfig) -> Result<ComposeYml> {
+    let server_base = env.workdir_dir.join("server/base");
+    let project_dir = project.dir(env);
+    let mut compose = compose::resolve(env, &project_dir, ComposeEnv::Dev, &server_base)?;
+    modules::of(project)?.apply(project, &mut compose, true);
+    compose::write_validated(&project_dir, ComposeEnv::Dev, &compose)?;
+    Ok(compose)
+}
+
+/// Host port of the dev proxy
+pub fn dev_http_port(compose: &ComposeYml) -> u16 {
+    compose
+        .services
+        .get("proxy")
+        .and_then(|proxy| proxy.published_port(80))
+        .unwrap_or(80)
+}
+
 /// Allows multiple commands
 // todo: Bit verbose to take String at times
 pub fn dev_cmds(
@@ -62,30 +82,15 @@ pub fn dev_cmds(
     mut current_process: utils::CurrentProcess,
     project: ProjectConfig,
     cmds: Vec<Vec<String>>,
-) -> io::Result<utils::CurrentProcess> {
+) -> Result<utils::CurrentProcess> {
     // Generating local docker
     // It would be nice to detect changes beforehand
-    // Also it may be a little out of place with wp
-    // specifics here. Some module system would be cool
-    crate::wp::create_wp_docker_yml(env, project.clone())?;
+    modules::of(&project)?.gen_dev(env, &project)?;
+    write_dev_compose(env, &project)?;
 
     let project_dir = project.dir(env);
-    std::env::set_current_dir(project_dir)?;
-    // Add base compose files
-    let mut args: Vec<String> = [
-        "-f",
-        "../../workdir/server/base/docker-compose.yml",
-        "-f",
-        "../../workdir/server/dev/docker-compose.dev.yml",
-        "-f",
-        "../../workdir/server/base/docker-reimage.yml",
-    ]
-    .into_iter()
-    .map(|i| i.to_string())
-    .collect();
-    // Then add local compose file(s?)
-    args.push("-f".into());
-    args.push("docker/dev.yml".into());
+    std::env::set_current_dir(project_dir).map_err(er::io)?;
+    let args = dev_compose_args();
     for mut user_args in cmds {
         let mut args = args.clone();
         if user_args.len() > 0 {
@@ -98,7 +103,565 @@ pub fn dev_cmds(
         // when used with .spawn()
         let mut cmd = process::Command::new("docker-compose");
         cmd.args(args);
-        current_process = current_process.spawn_and_wait(cmd, false)?;
+        current_process = current_process
+            .spawn_and_wait(cmd, false)
+            .map_err(|e| er::io_msg("Could not run docker-compose", e))?;
     }
     Ok(current_process)
 }
+
+// Docker engine api mode. Containers are created from the
+// resolved dev compose file with the labels and names compose
+// uses, so they can be managed with either.
+
+/// Container name of a service, as compose names them
+pub fn container_name(service: &str) -> String {
+    format!("{}_{}_1", PROJECT_NAME, service)
+}
+
+fn project_label() -> String {
+    format!("com.docker.compose.project={}", PROJECT_NAME)
+}
+
+fn service_label(service: &str) -> String {
+    format!("com.docker.compose.service={}", service)
+}
+
+/// Generates and resolves dev compose file
+fn dev_compose(env: &CliEnv, project: &ProjectConfig) -> Result<ComposeYml> {
+    modules::of(project)?.gen_dev(env, project)?;
+    write_dev_compose(env, project)
+}
+
+fn print_progress(progress: &Progress) {
+    if let Some(stream) = &progress.stream {
+        print!("{}", stream);
+    } else if let Some(status) = &progress.status {
+        match &progress.id {
+            Some(id) => println!("{}: {}", id, status),
+            None => println!("{}", status),
+        }
+    }
+    let _ = io::stdout().flush();
+}
+
+/// Image of service, which when built is the image
+/// option if given, otherwise named as compose does
+fn service_image(name: &str, service: &ComposeService) -> Result<String> {
+    match (&service.build, &service.image) {
+        (Some(_), Some(image)) => Ok(image.clone()),
+        (Some(_), None) => Ok(format!("{}_{}", PROJECT_NAME, name)),
+        (None, Some(image)) => Ok(image.clone()),
+        (None, None) => Err(cli_err!(Config, "Service {} has no image or build", name)),
+    }
+}
+
+/// Builds image when build is forced or missing,
+/// pulls when not built and missing
+fn ensure_image(
+    api: &DockerApi,
+    name: &str,
+    service: &ComposeService,
+    force_build: bool,
+) -> Result<String> {
+    let image = service_image(name, service)?;
+    match &service.build {
+        Some(build) => {
+            if force_build || !api.image_exists(&image)? {
+                println!("{}", console::style(format!("Building {}", name)).green());
+                let (dockerfile, args) = match build {
+                    compose::ComposeBuild::Config {
+                        dockerfile, args, ..
+                    } => (dockerfile.as_ref().map(String::as_str), args.clone()),
+                    compose::ComposeBuild::Context(_) => (None, BTreeMap::new()),
+                };
+                api.build_image(
+                    Path::new(build.context()),
+                    dockerfile,
+                    &image,
+                    &args,
+                    print_progress,
+                )?;
+            }
+        }
+        None => {
+            if !api.image_exists(&image)? {
+                println!("{}", console::style(format!("Pulling {}", image)).green());
+                api.pull_image(&image, print_progress)?;
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// "1m30s" -> nanoseconds, as used by api healthchecks
+fn parse_duration_ns(duration: &str) -> Option<i64> {
+    let mut total = 0.0;
+    let mut number = String::new();
+    let mut chars = duration.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let unit = match (c, chars.peek()) {
+            ('m', Some('s')) => {
+                chars.next();
+                1e6
+            }
+            ('u', Some('s')) => {
+                chars.next();
+                1e3
+            }
+            ('n', Some('s')) => {
+                chars.next();
+                1.0
+            }
+            ('h', _) => 3600e9,
+            ('m', _) => 60e9,
+            ('s', _) => 1e9,
+            _ => return None,
+        };
+        total += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(total as i64)
+}
+
+/// String split on whitespace, or list, as command
+fn yaml_args(value: &serde_yaml::Value) -> Option<Vec<String>> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.split_whitespace().map(String::from).collect()),
+        serde_yaml::Value::Sequence(seq) => Some(
+            seq.iter()
+                .filter_map(|v| match v {
+                    serde_yaml::Value::String(s) => Some(s.clone()),
+                    serde_yaml::Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Name of named volume or network, prefixed by
+/// project unless external
+fn resource_name(name: &str, external: Option<&compose::ComposeExternal>) -> String {
+    match external {
+        Some(external) => external.name.clone(),
+        None => format!("{}_{}", PROJECT_NAME, name),
+    }
+}
+
+fn service_networks(compose: &ComposeYml, service: &ComposeService) -> Vec<(String, String)> {
+    let networks = if service.networks.is_empty() {
+        vec!["default".to_string()]
+    } else {
+        service.networks.clone()
+    };
+    networks
+        .into_iter()
+        .map(|network| {
+            let external = compose
+                .networks
+                .get(&network)
+                .and_then(|n| n.external.as_ref());
+            let full_name = resource_name(&network, external);
+            (network, full_name)
+        })
+        .collect()
+}
+
+/// Container create config from compose service
+fn container_config(
+    compose: &ComposeYml,
+    name: &str,
+    service: &ComposeService,
+    image: &str,
+    network: &str,
+) -> Value {
+    let mut labels = service.labels.clone();
+    labels.insert("com.docker.compose.project".into(), PROJECT_NAME.into());
+    labels.insert("com.docker.compose.service".into(), name.into());
+    labels.insert("com.docker.compose.container-number".into(), "1".into());
+    labels.insert("com.docker.compose.oneoff".into(), "False".into());
+    let env = service
+        .environment
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>();
+    // Binds for mounts, anonymous volumes for container paths
+    let mut binds = Vec::new();
+    let mut volumes = serde_json::Map::new();
+    for volume in &service.volumes {
+        let parts = volume.splitn(2, ':').collect::<Vec<_>>();
+        if parts.len() == 1 {
+            volumes.insert(parts[0].to_string(), serde_json::json!({}));
+            continue;
+        }
+        let source = if parts[0].starts_with('/') {
+            parts[0].to_string()
+        } else {
+            let external = compose
+                .volumes
+                .get(parts[0])
+                .and_then(|v| v.external.as_ref());
+            resource_name(parts[0], external)
+        };
+        binds.push(format!("{}:{}", source, parts[1]));
+    }
+    // "ip:host:container/proto", "host:container" or "container"
+    let mut exposed = serde_json::Map::new();
+    let mut bindings = serde_json::Map::new();
+    for port in &service.ports {
+        let parts = port.split(':').collect::<Vec<_>>();
+        let container = parts[parts.len() - 1];
+        let container = if container.contains('/') {
+            container.to_string()
+        } else {
+            format!("{}/tcp", container)
+        };
+        exposed.insert(container.clone(), serde_json::json!({}));
+        let (host_ip, host_port) = match parts.len() {
+            3 => (parts[0], parts[1]),
+            2 => ("", parts[0]),
+            _ => continue,
+        };
+        bindings.insert(
+            container,
+            serde_json::json!([{ "HostIp": host_ip, "HostPort": host_port }]),
+        );
+    }
+    let mut config = serde_json::json!({
+        "Image": image,
+        "Env": env,
+        "Labels": labels,
+        "ExposedPorts": exposed,
+        "Volumes": volumes,
+        "HostConfig": {
+            "Binds": binds,
+            "PortBindings": bindings,
+            "RestartPolicy": { "Name": service.restart.clone().unwrap_or_default() },
+            "NetworkMode": network,
+        },
+        "NetworkingConfig": {
+            "EndpointsConfig": {
+                network: { "Aliases": [name] }
+            }
+        },
+    });
+    let options = [("command", "Cmd"), ("entrypoint", "Entrypoint")];
+    for (option, key) in options.iter() {
+        if let Some(args) = service.other.get(*option).and_then(yaml_args) {
+            config[*key] = serde_json::json!(args);
+        }
+    }
+    let string_options = [
+        ("user", "User"),
+        ("working_dir", "WorkingDir"),
+        ("hostname", "Hostname"),
+    ];
+    for (option, key) in string_options.iter() {
+        if let Some(serde_yaml::Value::String(value)) = service.other.get(*option) {
+            config[*key] = serde_json::json!(value);
+        }
+    }
+    if let Some(healthcheck) = &service.healthcheck {
+        let test = match &healthcheck.test {
+            serde_yaml::Value::String(cmd) => vec!["CMD-SHELL".to_string(), cmd.clone()],
+            other => yaml_args(other).unwrap_or_default(),
+        };
+        let duration = |d: &Option<String>| d.as_ref().and_then(|d| parse_duration_ns(d));
+        config["Healthcheck"] = serde_json::json!({
+            "Test": test,
+            "Interval": duration(&healthcheck.interval).unwrap_or(0),
+            "Timeout": duration(&healthcheck.timeout).unwrap_or(0),
+            "Retries": healthcheck.retries.unwrap_or(0),
+        });
+    }
+    config
+}
+
+/// Creates networks, volumes and the container of a
+/// service, returns container id
+fn create_service(
+    api: &DockerApi,
+    compose: &ComposeYml,
+    name: &str,
+    service: &ComposeService,
+    image: &str,
+) -> Result<String> {
+    let networks = service_networks(compose, service);
+    for (network, full_name) in &networks {
+        let mut labels = BTreeMap::new();
+        labels.insert("com.docker.compose.project".into(), PROJECT_NAME.into());
+        labels.insert("com.docker.compose.network".into(), network.clone());
+        api.ensure_network(full_name, &labels)?;
+    }
+    for volume in &service.volumes {
+        let source = volume.split(':').next().unwrap_or_default();
+        if let Some(named) = compose.volumes.get(source) {
+            if named.external.is_none() {
+                let mut labels = BTreeMap::new();
+                labels.insert("com.docker.compose.project".into(), PROJECT_NAME.into());
+                labels.insert("com.docker.compose.volume".into(), source.to_string());
+                api.ensure_volume(&resource_name(source, None), &labels)?;
+            }
+        }
+    }
+    let config = container_config(compose, name, service, image, &networks[0].1);
+    let id = api.create_container(&container_name(name), &config)?;
+    let aliases = vec![name.to_string()];
+    for (_, full_name) in networks.iter().skip(1) {
+        api.connect_network(full_name, &id, &aliases)?;
+    }
+    Ok(id)
+}
+
+/// Services with their dependencies, in start order.
+/// All services when none are given
+fn with_dependencies(compose: &ComposeYml, services: &[String]) -> Result<Vec<String>> {
+    for service in services {
+        if !compose.services.contains_key(service) {
+            return Err(cli_err!(Config, "Unknown service: {}", service));
+        }
+    }
+    let order = compose.service_order();
+    if services.is_empty() {
+        return Ok(order);
+    }
+    let mut needed = services.to_vec();
+    let mut i = 0;
+    while i < needed.len() {
+        if let Some(service) = compose.services.get(&needed[i]) {
+            for dep in &service.depends_on {
+                if !needed.contains(dep) {
+                    needed.push(dep.clone());
+                }
+            }
+        }
+        i += 1;
+    }
+    Ok(order.into_iter().filter(|s| needed.contains(s)).collect())
+}
+
+/// Creates and starts dev services detached, through the
+/// docker api. Existing containers are started if stopped
+pub fn api_up(env: &CliEnv, project: &ProjectConfig, services: &[String]) -> Result<()> {
+    let compose = dev_compose(env, project)?;
+    let api = DockerApi::connect()?;
+    for name in with_dependencies(&compose, services)? {
+        let service = &compose.services[&name];
+        let existing = api.containers(&[project_label(), service_label(&name)])?;
+        let state = match existing.first() {
+            Some(container) if container.state == "running" => "running",
+            Some(container) => {
+                api.start_container(&container.id)?;
+                "started"
+            }
+            None => {
+                let image = ensure_image(&api, &name, service, false)?;
+                let id = create_service(&api, &compose, &name, service, &image)?;
+                api.start_container(&id)?;
+                "created"
+            }
+        };
+        println!("{:<24} {}", name, console::style(state).green());
+    }
+    Ok(())
+}
+
+/// Removes, builds and starts a service through the docker api
+pub fn api_rebuild(env: &CliEnv, project: &ProjectConfig, name: &str) -> Result<()> {
+    let compose = dev_compose(env, project)?;
+    let service = match compose.services.get(name) {
+        Some(service) => service,
+        None => return Err(cli_err!(Config, "Unknown service: {}", name)),
+    };
+    let api = DockerApi::connect()?;
+    for container in api.containers(&[project_label(), service_label(name)])? {
+        println!("Removing {}", container.names.join(", "));
+        api.stop_container(&container.id, 10)?;
+        api.remove_container(&container.id, false)?;
+    }
+    let image = ensure_image(&api, name, service, true)?;
+    let id = create_service(&api, &compose, name, service, &image)?;
+    api.start_container(&id)?;
+    let inspect = api.inspect_container(&id)?;
+    println!(
+        "{:<24} {}",
+        name,
+        console::style(&inspect.state.status).green()
+    );
+    Ok(())
+}
+
+/// State of a dev container
+#[derive(Serialize, Debug)]
+pub struct ServiceState {
+    pub service: String,
+    pub container: String,
+    pub state: String,
+    pub health: Option<String>,
+    pub exit_code: i64,
+    pub status: String,
+}
+
+pub fn api_status(json: bool) -> Result<()> {
+    let api = DockerApi::connect()?;
+    let mut states = Vec::new();
+    for container in api.containers(&[project_label()])? {
+        let inspect = api.inspect_container(&container.id)?;
+        states.push(ServiceState {
+            service: container
+                .labels
+                .get("com.docker.compose.service")
+                .cloned()
+                .unwrap_or_default(),
+            container: inspect.name.trim_start_matches('/').to_string(),
+            state: inspect.state.status,
+            health: inspect.state.health.map(|h| h.status),
+            exit_code: inspect.state.exit_code,
+            status: container.status,
+        });
+    }
+    states.sort_by(|a, b| a.service.cmp(&b.service));
+    if json {
+        println!("{}", serde_json::to_string_pretty(&states)?);
+        return Ok(());
+    }
+    for state in states {
+        let styled = if state.state == "running" {
+            console::style(state.state).green()
+        } else {
+            console::style(state.state).red()
+        };
+        println!(
+            "{:<24} {:<10} {:<10} {}",
+            state.service,
+            styled,
+            state.health.unwrap_or_else(|| "-".into()),
+            state.status
+        );
+    }
+    Ok(())
+}
+
+/// Runs command in running container of service,
+/// returns exit code
+pub fn api_exec(service: &str, cmd: &[String]) -> Result<i64> {
+    let api = DockerApi::connect()?;
+    let containers = api.containers(&[project_label(), service_label(service)])?;
+    let container = match containers.iter().find(|c| c.state == "running") {
+        Some(container) => container,
+        None => return Err(cli_err!(Docker, "No running container for {}", service)),
+    };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    api.exec(&container.id, cmd, &mut out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("30s", Some(30_000_000_000)),
+            ("1m30s", Some(90_000_000_000)),
+            ("500ms", Some(500_000_000)),
+            ("1h", Some(3_600_000_000_000)),
+            ("1.5s", Some(1_500_000_000)),
+            ("10us", Some(10_000)),
+            ("10d", None),
+            ("10", None),
+        ];
+        for (duration, expected) in cases.iter() {
+            assert_eq!(parse_duration_ns(duration), *expected, "{}", duration);
+        }
+    }
+
+    #[test]
+    fn maps_service_to_container_config() {
+        let compose = ComposeYml::parse(
+            r#"
+version: '3.3'
+services:
+  wp:
+    image: wordpress:5
+    restart: always
+    environment:
+      WORDPRESS_DB_HOST: db:3306
+    volumes:
+      - wp-uploads:/var/www/html/wp-content/uploads
+      - shared:/shared
+      - /srv/themes:/var/www/html/wp-content/themes:ro
+      - /tmp/cache
+    ports:
+      - "127.0.0.1:8000:80"
+      - "2345:22"
+      - "9000/udp"
+    command: apache2-foreground -X
+    healthcheck:
+      test: curl -f http://localhost
+      interval: 1m30s
+      timeout: 500ms
+      retries: 3
+volumes:
+  wp-uploads:
+  shared:
+    external:
+      name: shared_data
+"#,
+        )
+        .unwrap();
+        let config = container_config(
+            &compose,
+            "wp",
+            &compose.services["wp"],
+            "wordpress:5",
+            "base_default",
+        );
+        let expected = serde_json::json!({
+            "Image": "wordpress:5",
+            "Env": ["WORDPRESS_DB_HOST=db:3306"],
+            "Labels": {
+                "com.docker.compose.project": "base",
+                "com.docker.compose.service": "wp",
+                "com.docker.compose.container-number": "1",
+                "com.docker.compose.oneoff": "False",
+            },
+            "ExposedPorts": { "80/tcp": {}, "22/tcp": {}, "9000/udp": {} },
+            "Volumes": { "/tmp/cache": {} },
+            "HostConfig": {
+                "Binds": [
+                    "base_wp-uploads:/var/www/html/wp-content/uploads",
+                    "shared_data:/shared",
+                    "/srv/themes:/var/www/html/wp-content/themes:ro",
+                ],
+                "PortBindings": {
+                    "80/tcp": [{ "HostIp": "127.0.0.1", "HostPort": "8000" }],
+                    "22/tcp": [{ "HostIp": "", "HostPort": "2345" }],
+                },
+                "RestartPolicy": { "Name": "always" },
+                "NetworkMode": "base_default",
+            },
+            "NetworkingConfig": {
+                "EndpointsConfig": { "base_default": { "Aliases": ["wp"] } }
+            },
+            "Cmd": ["apache2-foreground", "-X"],
+            "Healthcheck": {
+                "Test": ["CMD-SHELL", "curl -f http://localhost"],
+                "Interval": 90_000_000_000i64,
+                "Timeout": 500_000_000,
+                "Retries": 3,
+            },
+        });
+        assert_eq!(config, expected);
+    }
+}
@@ -0,0 +1,503 @@
+// Minimal Docker Engine API client over the unix socket,
+// used for container lifecycle, builds with progress and
+// exec, without going through a docker-compose process.
+// Requests are plain HTTP/1.1 with one connection each.
+
+use crate::er::{self, CliError, Result, ResultExt};
+use failure::Fail;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+/// Error on the api socket, as docker error
+fn socket_err(e: io::Error) -> failure::Error {
+    let msg = format!("Docker api: {}", e);
+    failure::Error::from(e.context(CliError::Docker(msg)))
+}
+
+fn response_err(e: serde_json::Error) -> failure::Error {
+    cli_err!(Docker, "Invalid docker api response: {}", e)
+}
+
+/// Api version, supported since Docker 17.06
+const API_VERSION: &str = "v1.30";
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerSummary {
+    pub id: String,
+    #[serde(default)]
+    pub names: Vec<String>,
+    pub state: String,
+    pub status: String,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerInspect {
+    pub name: String,
+    pub state: ContainerState,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerState {
+    pub status: String,
+    pub exit_code: i64,
+    #[serde(default)]
+    pub health: Option<ContainerHealth>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerHealth {
+    pub status: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct IdResponse {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ExecInspect {
+    exit_code: Option<i64>,
+}
+
+/// Line of build or pull progress
+#[derive(Deserialize, Debug, Default)]
+pub struct Progress {
+    #[serde(default)]
+    pub stream: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Response body, decoding chunked transfer encoding
+/// when used, otherwise read until connection closes
+struct Body {
+    reader: BufReader<UnixStream>,
+    chunked: bool,
+    chunk_left: usize,
+    done: bool,
+}
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        if !self.chunked {
+            return self.reader.read(buf);
+        }
+        if self.chunk_left == 0 {
+            let mut size_line = String::new();
+            self.reader.read_line(&mut size_line)?;
+            let size = size_line.trim().split(';').next().unwrap_or_default();
+            self.chunk_left = usize::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid chunk size"))?;
+            if self.chunk_left == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let max = std::cmp::min(buf.len(), self.chunk_left);
+        let read = self.reader.read(&mut buf[..max])?;
+        self.chunk_left -= read;
+        if self.chunk_left == 0 {
+            // Crlf after chunk data
+            let mut crlf = String::new();
+            self.reader.read_line(&mut crlf)?;
+        }
+        Ok(read)
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Body,
+}
+impl Response {
+    fn text(mut self) -> Result<String> {
+        let mut text = String::new();
+        self.body.read_to_string(&mut text).map_err(socket_err)?;
+        Ok(text)
+    }
+
+    /// Body as error when status is not 2xx or 304
+    fn check(self) -> Result<Response> {
+        if (200..300).contains(&self.status) || self.status == 304 {
+            return Ok(self);
+        }
+        let status = self.status;
+        let text = self.text()?;
+        let message = serde_json::from_str::<Value>(&text)
+            .ok()
+            .and_then(|v| v["message"].as_str().map(String::from))
+            .unwrap_or(text);
+        Err(cli_err!(
+            Docker,
+            "Docker api error {}: {}",
+            status,
+            message.trim()
+        ))
+    }
+}
+
+/// Percent encodes query values
+pub fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub struct DockerApi {
+    socket: PathBuf,
+}
+impl DockerApi {
+    /// Socket from DOCKER_HOST when it is a unix socket,
+    /// otherwise the default. Pings to check it is reachable
+    pub fn connect() -> Result<DockerApi> {
+        let socket = match std::env::var("DOCKER_HOST") {
+            Ok(host) if host.starts_with("unix://") => PathBuf::from(&host["unix://".len()..]),
+            Ok(host) if !host.is_empty() => {
+                return Err(cli_err!(
+                    Docker,
+                    "Only unix socket DOCKER_HOST supported: {}",
+                    host
+                ))
+            }
+            _ => PathBuf::from(DEFAULT_SOCKET),
+        };
+        let api = DockerApi { socket };
+        api.ping()
+            .or_cli(CliError::Docker, "Docker api not reachable")?;
+        Ok(api)
+    }
+
+    fn send(&self, method: &str, path: &str, content_type: &str, body: &[u8]) -> Result<Response> {
+        let mut stream = UnixStream::connect(&self.socket).map_err(|e| {
+            let msg = format!("Connect {:?}: {}", self.socket, e);
+            failure::Error::from(e.context(CliError::Docker(msg)))
+        })?;
+        let head = format!(
+            "{} /{}{} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            method,
+            API_VERSION,
+            path,
+            content_type,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).map_err(socket_err)?;
+        stream.write_all(body).map_err(socket_err)?;
+        stream.flush().map_err(socket_err)?;
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).map_err(socket_err)?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or_else(|| cli_err!(Docker, "Invalid response: {}", status_line.trim()))?;
+        let mut chunked = false;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).map_err(socket_err)?;
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            let lower = header.to_lowercase();
+            if lower.starts_with("transfer-encoding:") && lower.contains("chunked") {
+                chunked = true;
+            }
+        }
+        Ok(Response {
+            status,
+            body: Body {
+                reader,
+                chunked,
+                chunk_left: 0,
+                done: false,
+            },
+        })
+    }
+
+    fn send_json(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Response> {
+        let body = match body {
+            Some(body) => serde_json::to_vec(body)?,
+            None => Vec::new(),
+        };
+        self.send(method, path, "application/json", &body)?.check()
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let text = self.send_json("GET", path, None)?.text()?;
+        serde_json::from_str(&text).map_err(response_err)
+    }
+
+    /// Reads json progress lines, calling on_progress
+    /// for each. Errors in the stream are returned
+    fn progress_stream<F>(response: Response, mut on_progress: F) -> Result<()>
+    where
+        F: FnMut(&Progress),
+    {
+        let reader = BufReader::new(response.body);
+        for line in reader.lines() {
+            let line = line.map_err(socket_err)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let progress = match serde_json::from_str::<Progress>(&line) {
+                Ok(progress) => progress,
+                Err(_) => continue,
+            };
+            if let Some(error) = &progress.error {
+                return Err(cli_err!(Docker, "{}", error.trim()));
+            }
+            on_progress(&progress);
+        }
+        Ok(())
+    }
+
+    pub fn ping(&self) -> Result<()> {
+        self.send("GET", "/_ping", "text/plain", &[])?.check()?;
+        Ok(())
+    }
+
+    /// Containers, including stopped, having all given labels
+    pub fn containers(&self, labels: &[String]) -> Result<Vec<ContainerSummary>> {
+        let filters = serde_json::json!({ "label": labels });
+        self.get_json(&format!(
+            "/containers/json?all=1&filters={}",
+            url_encode(&filters.to_string())
+        ))
+    }
+
+    pub fn inspect_container(&self, id: &str) -> Result<ContainerInspect> {
+        self.get_json(&format!("/containers/{}/json", url_encode(id)))
+    }
+
+    /// Creates container, config as in api, returns id
+    pub fn create_container(&self, name: &str, config: &Value) -> Result<String> {
+        let text = self
+            .send_json(
+                "POST",
+                &format!("/containers/create?name={}", url_encode(name)),
+                Some(config),
+            )?
+            .text()?;
+        Ok(serde_json::from_str::<IdResponse>(&text)
+            .map_err(response_err)?
+            .id)
+    }
+
+    pub fn start_container(&self, id: &str) -> Result<()> {
+        self.send_json(
+            "POST",
+            &format!("/containers/{}/start", url_encode(id)),
+            None,
+        )?;
+        Ok(())
+    }
+
+    pub fn stop_container(&self, id: &str, timeout_secs: u32) -> Result<()> {
+        self.send_json(
+            "POST",
+            &format!("/containers/{}/stop?t={}", url_encode(id), timeout_secs),
+            None,
+        )?;
+        Ok(())
+    }
+
+    /// Removes container, with anonymous volumes if remove_volumes
+    pub fn remove_container(&self, id: &str, remove_volumes: bool) -> Result<()> {
+        self.send_json(
+            "DELETE",
+            &format!(
+                "/containers/{}?force=1&v={}",
+                url_encode(id),
+                if remove_volumes { 1 } else { 0 }
+            ),
+            None,
+        )?;
+        Ok(())
+    }
+
+    pub fn image_exists(&self, image: &str) -> Result<bool> {
+        let response = self.send(
+            "GET",
+            &format!("/images/{}/json", url_encode(image)),
+            "application/json",
+            &[],
+        )?;
+        if response.status == 404 {
+            return Ok(false);
+        }
+        response.check()?;
+        Ok(true)
+    }
+
+    /// Pulls image, defaulting to latest tag
+    pub fn pull_image<F>(&self, image: &str, on_progress: F) -> Result<()>
+    where
+        F: FnMut(&Progress),
+    {
+        // Tag is after last colon, unless colon is part of registry host
+        let (name, tag) = match image.rfind(':') {
+            Some(i) if !image[i..].contains('/') => (&image[..i], &image[i + 1..]),
+            _ => (image, "latest"),
+        };
+        let response = self.send_json(
+            "POST",
+            &format!(
+                "/images/create?fromImage={}&tag={}",
+                url_encode(name),
+                url_encode(tag)
+            ),
+            None,
+        )?;
+        Self::progress_stream(response, on_progress)
+    }
+
+    /// Builds image with tag from context dir, sent as tar
+    pub fn build_image<F>(
+        &self,
+        context: &Path,
+        dockerfile: Option<&str>,
+        tag: &str,
+        args: &BTreeMap<String, String>,
+        on_progress: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&Progress),
+    {
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.follow_symlinks(true);
+        builder
+            .append_dir_all(".", context)
+            .map_err(|e| er::io_msg(format!("Build context {:?}", context), e))?;
+        let context_tar = builder.into_inner().map_err(er::io)?;
+        let mut path = format!(
+            "/build?t={}&rm=1&buildargs={}",
+            url_encode(tag),
+            url_encode(&serde_json::to_string(args)?)
+        );
+        if let Some(dockerfile) = dockerfile {
+            path.push_str(&format!("&dockerfile={}", url_encode(dockerfile)));
+        }
+        let response = self
+            .send("POST", &path, "application/x-tar", &context_tar)?
+            .check()?;
+        Self::progress_stream(response, on_progress)
+    }
+
+    /// Creates network with labels if it doesn't exist
+    pub fn ensure_network(&self, name: &str, labels: &BTreeMap<String, String>) -> Result<()> {
+        let response = self.send(
+            "GET",
+            &format!("/networks/{}", url_encode(name)),
+            "application/json",
+            &[],
+        )?;
+        if response.status != 404 {
+            response.check()?;
+            return Ok(());
+        }
+        let config = serde_json::json!({
+            "Name": name,
+            "CheckDuplicate": true,
+            "Labels": labels,
+        });
+        self.send_json("POST", "/networks/create", Some(&config))?;
+        Ok(())
+    }
+
+    /// Connects container to network, with aliases
+    pub fn connect_network(&self, network: &str, id: &str, aliases: &[String]) -> Result<()> {
+        let config = serde_json::json!({
+            "Container": id,
+            "EndpointConfig": { "Aliases": aliases },
+        });
+        self.send_json(
+            "POST",
+            &format!("/networks/{}/connect", url_encode(network)),
+            Some(&config),
+        )?;
+        Ok(())
+    }
+
+    /// Creates volume, does nothing when it exists
+    pub fn ensure_volume(&self, name: &str, labels: &BTreeMap<String, String>) -> Result<()> {
+        let config = serde_json::json!({
+            "Name": name,
+            "Labels": labels,
+        });
+        self.send_json("POST", "/volumes/create", Some(&config))?;
+        Ok(())
+    }
+
+    /// Runs command in container, writing output to out
+    /// as it comes. Returns exit code
+    pub fn exec<W: Write>(&self, id: &str, cmd: &[String], out: &mut W) -> Result<i64> {
+        let config = serde_json::json!({
+            "Cmd": cmd,
+            "AttachStdout": true,
+            "AttachStderr": true,
+            // With tty, output is raw instead of multiplexed
+            "Tty": true,
+        });
+        let text = self
+            .send_json(
+                "POST",
+                &format!("/containers/{}/exec", url_encode(id)),
+                Some(&config),
+            )?
+            .text()?;
+        let exec_id = serde_json::from_str::<IdResponse>(&text)
+            .map_err(response_err)?
+            .id;
+        let start = serde_json::json!({ "Detach": false, "Tty": true });
+        let mut response = self.send_json(
+            "POST",
+            &format!("/exec/{}/start", url_encode(&exec_id)),
+            Some(&start),
+        )?;
+        io::copy(&mut response.body, out).map_err(socket_err)?;
+        let inspect: ExecInspect =
+            self.get_json(&format!("/exec/{}/json", url_encode(&exec_id)))?;
+        Ok(inspect.exit_code.unwrap_or(-1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_filters() {
+        assert_eq!(
+            url_encode(r#"{"label":["a=b"]}"#),
+            "%7B%22label%22%3A%5B%22a%3Db%22%5D%7D"
+        );
+        assert_eq!(url_encode("base_wp-cli_1"), "base_wp-cli_1");
+    }
+}
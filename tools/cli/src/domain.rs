@@ -0,0 +1,65 @@
+// Domain of prod, pointed to the server elastic ip
+// through Route 53, replacing the bare ip in urls.
+
+use crate::aws;
+use crate::er::{CliError, Result, ResultExt};
+use crate::modules;
+use crate::project::{self, ProjectConfig};
+use crate::server::SshConn;
+use crate::site;
+use crate::utils::CliEnv;
+
+/// Points domain and aliases of project to its server,
+/// then updates proxy and app urls to use it
+pub fn domain_add(env: &CliEnv, project_name: &str, env_name: &str, domain: &str) -> Result<()> {
+    let mut project = project::get_config(env, project_name)
+        .or_cli(
+            CliError::Config,
+            format!("Could not read project {}", project_name),
+        )?
+        .in_env(env_name)?;
+    let server = project.require_server(env)?;
+    let elastic_ip = match &server.elastic_ip {
+        Some(elastic_ip) => elastic_ip.public_ip.clone(),
+        None => return Err(cli_err!(Config, "Server {} has no elastic ip", server.name)),
+    };
+    let domain = domain.trim().trim_end_matches('.').to_lowercase();
+    if domain.is_empty() {
+        return Err(cli_err!(Config, "Empty domain"));
+    }
+    project.domain = Some(domain.clone());
+    let hosts = project.hosts();
+    println!("Pointing {} to {}", hosts.join(", "), elastic_ip);
+    aws::upsert_a_records(env, &hosts, &elastic_ip)?;
+    project::write_config(env, &project)?;
+    update_urls(env, &project, &server)
+}
+
+/// Syncs prod compose with the new EXTERNAL url, restarting
+/// the proxy, and points the app of the project module to it
+fn update_urls(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    server: &crate::server::ServerConfig,
+) -> Result<()> {
+    let url = match project.external_url(server) {
+        Some(url) => url,
+        None => return Err(cli_err!(Config, "Could not resolve external url")),
+    };
+    let conn = SshConn::connect(env, server)?;
+    project::sync_prod_compose(env, project, server, &conn)?;
+    if project.site.is_some() {
+        site::sync_front(env, server, &conn)?;
+    }
+    conn.exec(format!(
+        "cd {}; docker-compose {} up -d proxy",
+        project::server_project_dir(project, server),
+        project::prod_compose_args(project).join(" ")
+    ))?;
+    modules::of(project)?.set_url(env, project, &url)?;
+    println!("Prod is at {}", url);
+    if project.tls.is_none() {
+        println!("Https certificates can be set up with `cert init`");
+    }
+    Ok(())
+}
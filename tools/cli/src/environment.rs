@@ -0,0 +1,296 @@
+// Named environments of a project. Dev runs locally, prod is
+// the server and settings of the project config itself, and
+// others, like staging, are each bound to a server with their
+// own domain and site settings, and an optional compose
+// overlay merged on top of the prod compose files.
+
+use crate::acme::TlsConfig;
+use crate::er::{self, Result};
+use crate::project::{self, ProjectConfig};
+use crate::server::{self, SshConn};
+use crate::site::{self, SiteConfig};
+use crate::utils::CliEnv;
+use crate::wp;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub const DEV: &str = "dev";
+pub const PROD: &str = "prod";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EnvConfig {
+    pub server_name: String,
+    /// Compose file in project docker dir,
+    /// merged over the prod compose files
+    #[serde(default)]
+    pub compose_overlay: Option<String>,
+    #[serde(default)]
+    pub site: Option<SiteConfig>,
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub domain_aliases: Vec<String>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+impl EnvConfig {
+    /// Environment settings of a project view,
+    /// as written back to the project config
+    pub fn from_view(view: &ProjectConfig, compose_overlay: Option<String>) -> EnvConfig {
+        EnvConfig {
+            server_name: view.server_name.clone(),
+            compose_overlay,
+            site: view.site.clone(),
+            domain: view.domain.clone(),
+            domain_aliases: view.domain_aliases.clone(),
+            tls: view.tls.clone(),
+        }
+    }
+}
+
+/// Names of environments of project, in promotion order
+pub fn env_names(project: &ProjectConfig) -> Vec<String> {
+    let mut names = vec![DEV.to_string()];
+    names.extend(project.environments.keys().cloned());
+    names.push(PROD.to_string());
+    names
+}
+
+/// Adds or modifies a named environment of project
+pub fn env_add(env: &CliEnv, mut project: ProjectConfig, name: &str) -> Result<()> {
+    if name == DEV || name == PROD || name.is_empty() {
+        return Err(cli_err!(
+            Config,
+            "{} is not a name of a server environment",
+            name
+        ));
+    }
+    let current = project.environments.get(name).cloned();
+    let servers = server::get_servers(env)?;
+    let current_server = current
+        .as_ref()
+        .map(|c| c.server_name.clone())
+        .unwrap_or_else(|| project.server_name.clone());
+    let selected = env.select(
+        "Server",
+        &servers,
+        servers.iter().position(|s| *s == current_server),
+    )?;
+    let server_name = match servers.get(selected) {
+        Some(server_name) => server_name.clone(),
+        None => return Err(cli_err!(Config, "Error selecting server")),
+    };
+    let compose_overlay = env.get_input(
+        "Compose overlay in docker dir, merged when present",
+        Some(
+            current
+                .as_ref()
+                .and_then(|c| c.compose_overlay.clone())
+                .unwrap_or_else(|| format!("{}.yml", name)),
+        ),
+    )?;
+    let mut env_config = current.unwrap_or_default();
+    env_config.server_name = server_name.clone();
+    env_config.compose_overlay = if compose_overlay.trim().is_empty() {
+        None
+    } else {
+        Some(compose_overlay.trim().to_string())
+    };
+    project
+        .environments
+        .insert(name.to_string(), env_config.clone());
+
+    // Shared servers need allocated ports, otherwise the
+    // environment would run as the compose project of prod
+    let view = project.in_env(name)?;
+    let others = site::server_projects(env, &server_name)?
+        .into_iter()
+        .filter(|p| p.deployment_name() != view.deployment_name())
+        .map(|p| p.deployment_name())
+        .collect::<Vec<_>>();
+    if view.site.is_none() && !others.is_empty() {
+        env_config.site = Some(site::allocate(env, &view, &server_name)?);
+        project.environments.insert(name.to_string(), env_config);
+        println!(
+            "{}",
+            console::style(format!(
+                "Server also runs {}, allocated ports for the environment. Run `site init --env {}` to set its domain",
+                others.join(", "),
+                name
+            ))
+            .yellow()
+        );
+    }
+    project::write_config(env, &project)?;
+    println!("Environment {} on server {}", name, server_name);
+    Ok(())
+}
+
+/// Lists environments of project with their servers
+pub fn env_list(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    for name in env_names(project) {
+        if name == DEV {
+            println!("{}: local", name);
+            continue;
+        }
+        let view = project.in_env(&name)?;
+        let url = match view.get_server(env) {
+            Some(server) => view.external_url(&server).unwrap_or_default(),
+            None => String::new(),
+        };
+        println!("{}: server {} {}", name, view.server_name, url);
+    }
+    Ok(())
+}
+
+/// Wp-cli connection to environment,
+/// tunneled through its server unless dev
+fn env_cli_conn(env: &CliEnv, project: &ProjectConfig, name: &str) -> Result<SshConn> {
+    if name == DEV {
+        wp::wp_cli_conn(env, project, false)
+    } else {
+        wp::wp_cli_conn(env, &project.in_env(name)?, true)
+    }
+}
+
+/// Moves a file between environments through a local
+/// temp file, to the same path in target
+fn transfer(from: &SshConn, to: &SshConn, remote_file: &str) -> Result<()> {
+    let remote_path = PathBuf::from(remote_file);
+    let local_file = std::env::temp_dir().join(match remote_path.file_name() {
+        Some(file_name) => file_name.to_owned(),
+        None => return Err(cli_err!(Other, "Not a file: {}", remote_file)),
+    });
+    let from_sftp = from.sftp()?;
+    let bytes = SshConn::download_file(&from_sftp, &remote_path, &local_file)?;
+    drop(from_sftp);
+    from.exec(format!("rm {}", remote_file))?;
+    let to_sftp = to.sftp()?;
+    SshConn::transfer_file(
+        &to_sftp,
+        &local_file,
+        &remote_path,
+        bytes,
+        None,
+        &indicatif::ProgressBar::new(bytes),
+    )?;
+    drop(to_sftp);
+    std::fs::remove_file(&local_file).map_err(er::io)?;
+    Ok(())
+}
+
+/// Archives paths relative to wp-content in source
+/// environment, and extracts them in target
+fn copy_wp_content(from: &SshConn, to: &SshConn, paths: &[String], name: &str) -> Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    let remote_tar = format!("/tmp/promote-{}.tar.gz", name);
+    let status = from.exec(format!(
+        "cd /var/www/html/wp-content && tar -czf {} {}",
+        remote_tar,
+        paths.join(" ")
+    ))?;
+    if status != 0 {
+        return Err(cli_err!(Ssh, "Could not archive {}", name));
+    }
+    transfer(from, to, &remote_tar)?;
+    let status = to.exec(format!(
+        "tar -xzf {} -C /var/www/html/wp-content && rm {}",
+        remote_tar, remote_tar
+    ))?;
+    if status != 0 {
+        return Err(cli_err!(Ssh, "Could not extract {}", name));
+    }
+    Ok(())
+}
+
+/// Copies project plugins and themes from one environment
+/// to another, and optionally the database and uploads,
+/// replacing the site url
+pub fn promote(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    from_name: &str,
+    to_name: &str,
+    content: bool,
+) -> Result<()> {
+    if from_name == to_name {
+        return Err(cli_err!(Config, "Promoting to the same environment"));
+    }
+    if content && to_name != DEV {
+        let answer = env.get_input(
+            &format!(
+                "Replaces database and uploads of {} with {}, continue (y/n)",
+                to_name, from_name
+            ),
+            Some("n".into()),
+        )?;
+        if answer != "y" {
+            println!("Aborted");
+            return Ok(());
+        }
+    }
+    let from = env_cli_conn(env, project, from_name)?;
+    let to = env_cli_conn(env, project, to_name)?;
+
+    // Code, the project plugins and themes
+    let local_data = wp::get_local_site_data(env, project)?;
+    let mut code_paths = local_data
+        .plugins
+        .keys()
+        .map(|name| format!("plugins/{}", name))
+        .collect::<Vec<_>>();
+    code_paths.extend(
+        local_data
+            .themes
+            .keys()
+            .map(|name| format!("themes/{}", name)),
+    );
+    code_paths.sort();
+    println!("Promoting code from {} to {}", from_name, to_name);
+    copy_wp_content(&from, &to, &code_paths, "code")?;
+
+    if content {
+        println!("Promoting content from {} to {}", from_name, to_name);
+        let option = |conn: &SshConn, name: &str| -> Result<String> {
+            let output =
+                conn.exec_capture(format!("wp option get {}", name), Some("/var/www/html"))?;
+            Ok(output.trim().to_string())
+        };
+        let from_url = option(&from, "home")?;
+        let to_url = option(&to, "home")?;
+        let remote_sql = "/tmp/promote-db.sql";
+        let status = from.exec(format!("cd /var/www/html && wp db export {}", remote_sql))?;
+        if status != 0 {
+            return Err(cli_err!(Ssh, "Could not export database of {}", from_name));
+        }
+        transfer(&from, &to, remote_sql)?;
+        let status = to.exec(format!(
+            "cd /var/www/html && wp db import {} && rm {}",
+            remote_sql, remote_sql
+        ))?;
+        if status != 0 {
+            return Err(cli_err!(Ssh, "Could not import database into {}", to_name));
+        }
+        if from_url != to_url && !from_url.is_empty() && !to_url.is_empty() {
+            let status = to.exec(format!(
+                "cd /var/www/html && wp search-replace {} {} --all-tables --skip-columns=guid",
+                from_url, to_url
+            ))?;
+            if status != 0 {
+                return Err(cli_err!(
+                    Ssh,
+                    "Could not replace {} with {}",
+                    from_url,
+                    to_url
+                ));
+            }
+        }
+        copy_wp_content(&from, &to, &["uploads".to_string()], "uploads")?;
+    }
+    wp::activate_local(&to, local_data)?;
+    println!("Promoted {} to {}", from_name, to_name);
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use super::{read_response, ApiError, GitConfig, GitHost};
+use actix_web::http;
+use awc::Client;
+use futures::Future;
+
+/// Gitea, self-hosted, through the v1 rest api
+pub struct Gitea {
+    pub base_url: String,
+}
+
+impl Gitea {
+    fn api_url(&self, path: &str) -> String {
+        format!("{}/api/v1/{}", self.base_url.trim_end_matches('/'), path)
+    }
+}
+
+impl GitHost for Gitea {
+    fn check_repo_exists(
+        &self,
+        git_account: &GitConfig,
+        owner: &str,
+        name: &str,
+    ) -> Box<dyn Future<Item = bool, Error = ApiError>> {
+        let send = Client::default()
+            .get(self.api_url(&format!("repos/{}/{}", owner, name)))
+            .header(
+                http::header::AUTHORIZATION,
+                format!("token {}", git_account.token),
+            )
+            .timeout(std::time::Duration::from_secs(15))
+            .send();
+        Box::new(read_response(send).and_then(|(status, body)| match status {
+            http::StatusCode::OK => Ok(true),
+            http::StatusCode::NOT_FOUND => Ok(false),
+            other => Err(ApiError::StatusError(other.as_u16(), body)),
+        }))
+    }
+
+    fn create_repo(
+        &self,
+        git_account: &GitConfig,
+        name: &str,
+    ) -> Box<dyn Future<Item = (), Error = ApiError>> {
+        let send = Client::default()
+            .post(self.api_url("user/repos"))
+            .header(
+                http::header::AUTHORIZATION,
+                format!("token {}", git_account.token),
+            )
+            .timeout(std::time::Duration::from_secs(15))
+            .send_json(&serde_json::json!({
+                "name": name,
+                "private": true,
+            }));
+        Box::new(read_response(send).and_then(|(status, body)| match status {
+            http::StatusCode::CREATED => Ok(()),
+            other => Err(ApiError::StatusError(other.as_u16(), body)),
+        }))
+    }
+}
@@ -0,0 +1,156 @@
+use super::{ApiError, GitConfig, GitHost};
+use actix_web::{http, web};
+use awc::Client;
+use futures::stream::Stream;
+use futures::Future;
+use graphql_client::*;
+
+/// Github, through the graphql api
+pub struct Github;
+
+impl GitHost for Github {
+    fn check_repo_exists(
+        &self,
+        git_account: &GitConfig,
+        owner: &str,
+        name: &str,
+    ) -> Box<dyn Future<Item = bool, Error = ApiError>> {
+        let query = CheckRepoExists::build_query(check_repo_exists::Variables {
+            owner: owner.to_string(),
+            name: name.to_string(),
+        });
+        let response = github_api_query::<_, check_repo_exists::ResponseData>(
+            &git_account.token,
+            &git_account.user,
+            query,
+        );
+        Box::new(response.and_then(|r| {
+            println!("{:#?}", r);
+            Ok(r.repository.is_some())
+        }))
+    }
+
+    fn create_repo(
+        &self,
+        git_account: &GitConfig,
+        name: &str,
+    ) -> Box<dyn Future<Item = (), Error = ApiError>> {
+        let git_account = git_account.clone();
+        let name = name.to_string();
+        Box::new(viewer_info(&git_account).and_then(move |viewer_info| {
+            create_remote_repo(&git_account, name, viewer_info.viewer.id).and_then(
+                |create_remote_repo| {
+                    if create_remote_repo.create_repository.is_some() {
+                        Ok(())
+                    } else {
+                        Err(ApiError::DeserializeError(
+                            "Create repo failed".to_string(),
+                            format!("{:?}", create_remote_repo),
+                        ))
+                    }
+                },
+            )
+        }))
+    }
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/github_schema.graphql",
+    query_path = "src/graphql/check_repo_exists.graphql",
+    response_derives = "Debug"
+)]
+struct CheckRepoExists;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/github_schema.graphql",
+    query_path = "src/graphql/viewer_info.graphql",
+    response_derives = "Debug"
+)]
+struct ViewerInfo;
+pub fn viewer_info(
+    git_account: &GitConfig,
+) -> impl Future<Item = viewer_info::ResponseData, Error = ApiError> {
+    // Make github request
+    let query = ViewerInfo::build_query(viewer_info::Variables {});
+    github_api_query::<_, viewer_info::ResponseData>(&git_account.token, &git_account.user, query)
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/github_schema.graphql",
+    query_path = "src/graphql/create_remote_repo.graphql",
+    response_derives = "Debug"
+)]
+struct CreateRemoteRepo;
+pub fn create_remote_repo(
+    git_account: &GitConfig,
+    name: String,
+    owner_id: String,
+) -> impl Future<Item = create_remote_repo::ResponseData, Error = ApiError> {
+    // Make github request
+    let query = CreateRemoteRepo::build_query(create_remote_repo::Variables { name, owner_id });
+    github_api_query::<_, create_remote_repo::ResponseData>(
+        &git_account.token,
+        &git_account.user,
+        query,
+    )
+}
+
+fn github_api_query<V: serde::Serialize, R: serde::de::DeserializeOwned>(
+    token: &str,
+    github_user: &str,
+    query: QueryBody<V>,
+) -> impl Future<Item = R, Error = ApiError> {
+    let client = Client::default();
+    client
+        .post("https://api.github.com/graphql")
+        .bearer_auth(token)
+        .header(http::header::USER_AGENT, github_user)
+        .timeout(std::time::Duration::from_secs(15))
+        .send_json(&query)
+        .map_err(|e| ApiError::NetworkError(format!("{:?}", e)))
+        .and_then(|r| {
+            r.map_err(|e| ApiError::NetworkError(format!("{:?}", e)))
+                .fold(web::BytesMut::new(), move |mut body, chunk| {
+                    body.extend_from_slice(&chunk);
+                    Ok::<_, ApiError>(body)
+                })
+                .and_then(move |body| {
+                    let body = body.freeze().to_vec();
+                    let body = String::from_utf8_lossy(&body);
+                    //println!("{}", &body);
+                    // Graphql client seems to work with the data
+                    // below "data" key.
+                    let body2 = body.clone();
+                    serde_json::from_str::<serde_json::Value>(&body)
+                        .map_err(|e| ApiError::DeserializeError(format!("{:?}", e), body2.into()))
+                        .and_then(|json_value| {
+                            //println!("{:#?}", &json_value);
+                            // The json may contain an error message
+                            // {"repository":null},"errors":[{"type":"NOT_FOUND","path":["repository"],
+                            // "locations":[{"line":2,"column":5}],
+                            // "message":"Could not resolve to a Repository with the name 'unknow-repo-name'."}
+                            match json_value {
+                                serde_json::Value::Object(map) => match map.get("data") {
+                                    Some(data_value) => serde_json::from_value::<R>(
+                                        data_value.clone(),
+                                    )
+                                    .map_err(|e| {
+                                        ApiError::DeserializeError(format!("{:?}", e), body.into())
+                                    }),
+                                    None => Err(ApiError::DeserializeError(
+                                        "Data key not found in root".to_string(),
+                                        body.into(),
+                                    )),
+                                },
+                                _ => Err(ApiError::DeserializeError(
+                                    "Json root not object".to_string(),
+                                    body.into(),
+                                )),
+                            }
+                        })
+                })
+        })
+}
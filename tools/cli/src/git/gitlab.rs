@@ -0,0 +1,71 @@
+use super::{read_response, ApiError, GitConfig, GitHost};
+use actix_web::http;
+use awc::Client;
+use futures::Future;
+
+/// Gitlab, gitlab.com or self-hosted, through the v4 rest api
+pub struct Gitlab {
+    pub base_url: String,
+}
+
+impl Gitlab {
+    fn api_url(&self, path: &str) -> String {
+        format!("{}/api/v4/{}", self.base_url.trim_end_matches('/'), path)
+    }
+}
+
+/// Url encodes path as one segment, slashes included
+pub(super) fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl GitHost for Gitlab {
+    fn check_repo_exists(
+        &self,
+        git_account: &GitConfig,
+        owner: &str,
+        name: &str,
+    ) -> Box<dyn Future<Item = bool, Error = ApiError>> {
+        // Projects can be looked up by url encoded full path,
+        // the owner can be nested groups
+        let full_path = encode_path(&format!("{}/{}", owner, name));
+        let url = self.api_url(&format!("projects/{}", full_path));
+        let send = Client::default()
+            .get(url)
+            .header("PRIVATE-TOKEN", git_account.token.as_str())
+            .timeout(std::time::Duration::from_secs(15))
+            .send();
+        Box::new(read_response(send).and_then(|(status, body)| match status {
+            http::StatusCode::OK => Ok(true),
+            http::StatusCode::NOT_FOUND => Ok(false),
+            other => Err(ApiError::StatusError(other.as_u16(), body)),
+        }))
+    }
+
+    fn create_repo(
+        &self,
+        git_account: &GitConfig,
+        name: &str,
+    ) -> Box<dyn Future<Item = (), Error = ApiError>> {
+        let send = Client::default()
+            .post(self.api_url("projects"))
+            .header("PRIVATE-TOKEN", git_account.token.as_str())
+            .timeout(std::time::Duration::from_secs(15))
+            .send_json(&serde_json::json!({
+                "name": name,
+                "path": name,
+                "visibility": "private",
+            }));
+        Box::new(read_response(send).and_then(|(status, body)| match status {
+            http::StatusCode::CREATED => Ok(()),
+            other => Err(ApiError::StatusError(other.as_u16(), body)),
+        }))
+    }
+}
@@ -0,0 +1,1280 @@
+use crate::config::{self, ConfigKind};
+use crate::er;
+use crate::utils::{self, CliEnv};
+use actix_web::{
+    http::{self, uri::Uri},
+    web,
+};
+use failure::{Error, Fail};
+use futures::stream::Stream;
+use futures::{
+    future::{self, Either},
+    Future,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+mod gitea;
+mod github;
+mod gitlab;
+
+/// Git host of an account. Self-hosted providers
+/// are given by their base url, like `https://gitea.example.com`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum GitProvider {
+    Github,
+    Gitlab {
+        base_url: String,
+    },
+    Gitea {
+        base_url: String,
+        /// Port of ssh remotes, when not 22
+        #[serde(default)]
+        ssh_port: Option<u16>,
+    },
+}
+
+impl Default for GitProvider {
+    fn default() -> Self {
+        GitProvider::Github
+    }
+}
+
+impl GitProvider {
+    /// Base url for web and https remotes
+    pub fn web_base(&self) -> String {
+        match self {
+            GitProvider::Github => "https://github.com".to_string(),
+            GitProvider::Gitlab { base_url } | GitProvider::Gitea { base_url, .. } => {
+                base_url.trim_end_matches('/').to_string()
+            }
+        }
+    }
+
+    /// Host name, as found in remote uris
+    pub fn host(&self) -> String {
+        let web_base = self.web_base();
+        match web_base.parse::<Uri>() {
+            Ok(uri) => match uri.host() {
+                Some(host) => host.to_string(),
+                None => web_base,
+            },
+            Err(_) => web_base,
+        }
+    }
+
+    /// Port of ssh remotes, when not 22
+    pub fn ssh_port(&self) -> Option<u16> {
+        match self {
+            GitProvider::Gitea { ssh_port, .. } => *ssh_port,
+            _ => None,
+        }
+    }
+
+    pub fn api(&self) -> Box<dyn GitHost> {
+        match self {
+            GitProvider::Github => Box::new(github::Github),
+            GitProvider::Gitlab { base_url } => Box::new(gitlab::Gitlab {
+                base_url: base_url.clone(),
+            }),
+            GitProvider::Gitea { base_url, .. } => Box::new(gitea::Gitea {
+                base_url: base_url.clone(),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GitConfig {
+    pub user: String,
+    pub email: String,
+    /// Used for api requests, and for https remotes
+    pub token: String,
+    /// Whether new remotes should use ssh rather than https
+    #[serde(default)]
+    pub ssh: bool,
+    /// Private key file for ssh remotes. When not given,
+    /// ssh-agent is used
+    #[serde(default)]
+    pub ssh_key: Option<String>,
+    #[serde(default)]
+    pub provider: GitProvider,
+}
+
+impl GitConfig {
+    pub fn repo_uri(&self, repo_name: String) -> String {
+        if self.ssh {
+            match self.provider.ssh_port() {
+                // Scp-like uris can't have a port
+                Some(port) => format!(
+                    "ssh://git@{}:{}/{}/{}.git",
+                    self.provider.host(),
+                    port,
+                    self.user,
+                    repo_name
+                ),
+                None => format!(
+                    "git@{}:{}/{}.git",
+                    self.provider.host(),
+                    self.user,
+                    repo_name
+                ),
+            }
+        } else {
+            format!("{}/{}/{}", self.provider.web_base(), self.user, repo_name)
+        }
+    }
+}
+
+pub fn has_config(env: &CliEnv, user: &str) -> bool {
+    env.config_dirs.git_accounts.has_file(user)
+}
+
+pub fn get_config(env: &CliEnv, user: &str) -> er::Result<GitConfig> {
+    config::read(
+        ConfigKind::Git,
+        &env.config_dirs.git_accounts.filepath(user),
+    )
+}
+
+pub fn get_accounts(env: &CliEnv) -> er::Result<Vec<String>> {
+    utils::files_in_dir(&env.config_dirs.git_accounts.0).map_err(er::io)
+}
+
+pub fn select_account(env: &CliEnv, default: Option<String>) -> er::Result<GitConfig> {
+    let entries = get_accounts(&env)?;
+    let i = env
+        .select(
+            "Git account",
+            &entries,
+            default.and_then(|key| entries.iter().position(|e| *e == key)),
+        )
+        .map_err(er::io)?;
+    let key = entries
+        .get(i)
+        .ok_or_else(|| cli_err!(Config, "Could not resolve account"))?
+        .to_owned();
+    get_config(env, &key)
+}
+
+/// Add or modify git account
+pub fn add_user(env: &CliEnv) -> er::Result<()> {
+    // Todo: It would be nice to allow "proxy" git communications
+    // where the project participants is administered
+
+    // List current accounts
+    let current_files = get_accounts(env)?;
+    if current_files.len() > 0 {
+        println!("Current accounts:");
+        for file in current_files {
+            println!("{}", file);
+        }
+    } else {
+        println!("No existing accounts");
+    }
+
+    let user = env.get_input("Git user", None)?;
+    let current_config = if has_config(env, &user) {
+        println!("Account config exists for: {}", &user);
+        println!("Modifying entry.");
+        Some(get_config(env, &user)?)
+    } else {
+        None
+    };
+    let provider = select_provider(env, current_config.as_ref().map(|c| &c.provider))?;
+    let email = env.get_input(
+        "Git email",
+        current_config.as_ref().map(|c| c.email.to_string()),
+    )?;
+    let token = env.get_input(
+        "Git token",
+        current_config.as_ref().map(|c| c.token.to_string()),
+    )?;
+    let protocols = vec!["https", "ssh"];
+    let ssh = env.select(
+        "Protocol for remotes",
+        &protocols,
+        current_config
+            .as_ref()
+            .map(|c| if c.ssh { 1 } else { 0 }),
+    )? == 1;
+    let ssh_key = if ssh {
+        let ssh_key = env.get_input(
+            "Ssh key file (empty for ssh-agent)",
+            current_config.as_ref().and_then(|c| c.ssh_key.clone()),
+        )?;
+        if ssh_key != "" {
+            Some(ssh_key)
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    let config = GitConfig {
+        user,
+        email,
+        token,
+        ssh,
+        ssh_key,
+        provider,
+    };
+
+    let content_str = config::to_string(ConfigKind::Git, &config)?;
+    env.config_dirs
+        .git_accounts
+        .write(&config.user, &content_str)?;
+    println!("Wrote git account: {}", &config.user);
+    Ok(())
+}
+
+/// Select git host, with base url for self-hosted
+pub fn select_provider(env: &CliEnv, current: Option<&GitProvider>) -> er::Result<GitProvider> {
+    let providers = vec!["Github", "Gitlab", "Gitea"];
+    let default = current.map(|p| match p {
+        GitProvider::Github => 0,
+        GitProvider::Gitlab { .. } => 1,
+        GitProvider::Gitea { .. } => 2,
+    });
+    match env.select("Git host", &providers, default)? {
+        0 => Ok(GitProvider::Github),
+        1 => {
+            let current_base = match current {
+                Some(GitProvider::Gitlab { base_url }) => base_url.clone(),
+                _ => "https://gitlab.com".to_string(),
+            };
+            let base_url = env.get_input("Gitlab base url", Some(current_base))?;
+            Ok(GitProvider::Gitlab { base_url })
+        }
+        2 => {
+            let (current_base, current_port) = match current {
+                Some(GitProvider::Gitea { base_url, ssh_port }) => (
+                    Some(base_url.clone()),
+                    ssh_port.map(|port| port.to_string()),
+                ),
+                _ => (None, Some("22".to_string())),
+            };
+            let base_url = env.get_input("Gitea base url", current_base)?;
+            let ssh_port = match env
+                .get_input("Gitea ssh port", current_port)?
+                .parse::<u16>()
+            {
+                Ok(22) => None,
+                Ok(port) => Some(port),
+                Err(_) => return Err(cli_err!(Config, "Invalid ssh port")),
+            };
+            Ok(GitProvider::Gitea { base_url, ssh_port })
+        }
+        _ => Err(cli_err!(Other, "Unrecognized select")),
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum RepoExistsError {
+    RepoParseFailed(String),
+    HostNotSupported,
+    ApiError(ApiError),
+}
+
+pub struct GitUriParts {
+    pub host: String,
+    pub owner: String,
+    pub repo_name: String,
+}
+
+/// Parses https and ssh uris. Ssh uris can be given either
+/// as `ssh://git@host/owner/repo.git` or scp-like `git@host:owner/repo.git`
+pub fn parse_git_uri(repo_uri: &str) -> Result<GitUriParts, String> {
+    // Rewrite scp-like syntax to an ssh uri so it can be parsed as a uri
+    let repo_uri = match scp_like_to_ssh(repo_uri) {
+        Some(ssh_uri) => ssh_uri,
+        None => repo_uri.to_string(),
+    };
+    let repo_uri = match repo_uri.parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(_) => {
+            return Err("Could not parse uri".into());
+        }
+    };
+    // Whether the host is supported is up to the account's provider
+    let host = match repo_uri.host() {
+        Some(host) => host,
+        None => return Err("Host not found".into()),
+    };
+    let path = Path::new(repo_uri.path());
+    let path_names = path
+        .components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if path_names.len() < 2 {
+        return Err("Repo owner and name not found".into());
+    };
+    // Owner is everything before the name, as
+    // gitlab groups can be nested
+    let name = path_names[path_names.len() - 1];
+    let repo_owner = path_names[..path_names.len() - 1]
+        .iter()
+        .map(|name| name.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    // Strip .git from repo_name
+    let repo_name = {
+        let repo_name = PathBuf::from(name);
+        // Strip .git from repo_name if there
+        if repo_name.extension() == Some(std::ffi::OsStr::new("git")) {
+            match repo_name.file_stem() {
+                Some(stem) => stem.to_owned(),
+                None => return Err("Repo name not recognized".into()),
+            }
+        } else {
+            name.to_os_string()
+        }
+    };
+    Ok(GitUriParts {
+        host: host.to_string(),
+        owner: repo_owner,
+        repo_name: repo_name.to_string_lossy().to_string(),
+    })
+}
+
+/// `git@github.com:owner/repo.git` -> `ssh://git@github.com/owner/repo.git`
+/// Returns None when the uri is not scp-like
+fn scp_like_to_ssh(repo_uri: &str) -> Option<String> {
+    if repo_uri.contains("://") {
+        return None;
+    }
+    let colon = repo_uri.find(':')?;
+    let (host_part, path) = (&repo_uri[..colon], &repo_uri[colon + 1..]);
+    // A slash before the colon would make it a local path
+    if host_part.contains('/') || path.starts_with('/') {
+        return None;
+    }
+    Some(format!("ssh://{}/{}", host_part, path))
+}
+
+/// Whether the uri is using ssh, as opposed to https
+pub fn is_ssh_uri(repo_uri: &str) -> bool {
+    repo_uri.starts_with("ssh://") || scp_like_to_ssh(repo_uri).is_some()
+}
+
+pub struct InspectGit {
+    pub dir: PathBuf,
+    pub has_dir: bool,
+    pub has_files: bool,
+    pub repo: Option<git2::Repository>,
+    pub origin_url: Option<String>,
+}
+
+pub fn inspect_git(dir: PathBuf) -> er::Result<InspectGit> {
+    let (has_dir, has_files, repo, origin_url) = if !dir.is_dir() {
+        // Dir does not yet exist
+        (false, false, None, None)
+    } else if dir.is_file() {
+        return Err(cli_err!(Io, "Given directory is a file"));
+    } else {
+        println!("Directory exists");
+        let has_files = match crate::utils::entries_in_dir(&dir) {
+            Ok(entries) => entries.len() > 0,
+            Err(err) => return Err(er::io(err)),
+        };
+        let mut git_dir = dir.clone();
+        git_dir.push(".git");
+        if git_dir.is_dir() {
+            println!("Git repo found");
+            match git2::Repository::open(&dir) {
+                Ok(repo) => {
+                    let origin_url = match repo.find_remote("origin") {
+                        Ok(remote) => match remote.url() {
+                            Some(origin_url) => Some(origin_url.to_owned()),
+                            None => {
+                                return Err(cli_err!(Git, "Could not parse origin url to utf8"));
+                            }
+                        },
+                        Err(_err) => {
+                            println!("No remote named `origin` in repository");
+                            None
+                        }
+                    };
+                    (true, has_files, Some(repo), origin_url)
+                }
+                Err(err) => {
+                    return Err(er::git_msg("Failed opening existing repository", err));
+                }
+            }
+        } else {
+            println!("Directory found without repository");
+            (true, has_files, None, None)
+        }
+    };
+    Ok(InspectGit {
+        dir,
+        has_dir,
+        has_files,
+        repo,
+        origin_url,
+    })
+}
+
+// 6b44cec414da957b9c31be12a38af1345f7d8481
+// Todo: Differentiate no access and non-exist
+// if not already?
+pub fn check_repo_exists(
+    git_account: &GitConfig,
+    repo_uri: &str,
+) -> impl Future<Item = bool, Error = RepoExistsError> {
+    let uri_parts = match parse_git_uri(repo_uri) {
+        Ok(parts) => parts,
+        Err(err) => return Either::A(future::err(RepoExistsError::RepoParseFailed(err))),
+    };
+    if uri_parts.host != git_account.provider.host() {
+        return Either::A(future::err(RepoExistsError::HostNotSupported));
+    }
+    Either::B(
+        git_account
+            .provider
+            .api()
+            .check_repo_exists(git_account, &uri_parts.owner, &uri_parts.repo_name)
+            .map_err(|e| RepoExistsError::ApiError(e)),
+    )
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ApiError {
+    NetworkError(String),
+    DeserializeError(String, String),
+    /// Unexpected status code, with response body
+    StatusError(u16, String),
+}
+
+/// Operations against the api of a git host
+pub trait GitHost {
+    fn check_repo_exists(
+        &self,
+        git_account: &GitConfig,
+        owner: &str,
+        name: &str,
+    ) -> Box<dyn Future<Item = bool, Error = ApiError>>;
+
+    /// Creates a private repository owned by the account user
+    fn create_repo(
+        &self,
+        git_account: &GitConfig,
+        name: &str,
+    ) -> Box<dyn Future<Item = (), Error = ApiError>>;
+}
+
+/// Reads status and full body from a response
+pub(crate) fn read_response<F, S>(
+    send: F,
+) -> impl Future<Item = (http::StatusCode, String), Error = ApiError>
+where
+    F: Future<Item = awc::ClientResponse<S>, Error = awc::error::SendRequestError>,
+    S: Stream<Item = web::Bytes, Error = actix_web::error::PayloadError>,
+{
+    send.map_err(|e| ApiError::NetworkError(format!("{:?}", e)))
+        .and_then(|r| {
+            let status = r.status();
+            r.map_err(|e| ApiError::NetworkError(format!("{:?}", e)))
+                .fold(web::BytesMut::new(), move |mut body, chunk| {
+                    body.extend_from_slice(&chunk);
+                    Ok::<_, ApiError>(body)
+                })
+                .map(move |body| {
+                    let body = body.freeze().to_vec();
+                    (status, String::from_utf8_lossy(&body).to_string())
+                })
+        })
+}
+
+/// Remote callbacks resolving credentials. Tries in order:
+/// * ssh key file from the account, or ssh-agent, for ssh remotes
+/// * user and token from the account, for https remotes
+/// * the system's git credential helper
+/// Each method is only attempted once, as libgit2 will call
+/// again when authentication fails.
+pub fn credential_callbacks<'a>(git_config: Option<&'a GitConfig>) -> git2::RemoteCallbacks<'a> {
+    let mut tried_ssh = false;
+    let mut tried_account = false;
+    let mut tried_helper = false;
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.credentials(move |url, username_from_url, cred_type| {
+        let username = username_from_url.unwrap_or("git");
+        if cred_type.is_ssh_key() && !tried_ssh {
+            tried_ssh = true;
+            return match git_config.and_then(|c| c.ssh_key.as_ref()) {
+                Some(ssh_key) => git2::Cred::ssh_key(username, None, Path::new(ssh_key), None),
+                None => git2::Cred::ssh_key_from_agent(username),
+            };
+        }
+        if cred_type.is_user_pass_plaintext() {
+            if let (Some(git_config), false) = (git_config, tried_account) {
+                tried_account = true;
+                return git2::Cred::userpass_plaintext(&git_config.user, &git_config.token);
+            }
+            if !tried_helper {
+                tried_helper = true;
+                let config = git2::Config::open_default()?;
+                return git2::Cred::credential_helper(&config, url, username_from_url);
+            }
+        }
+        if cred_type.is_username() {
+            return git2::Cred::username(username);
+        }
+        Err(git2::Error::from_str(&format!(
+            "No more credentials to try for {} ({:?})",
+            url, cred_type
+        )))
+    });
+    callbacks
+}
+
+/// Clone git repo
+/// Credentials are resolved from the given account if any,
+/// otherwise the credential helper or ssh-agent
+pub fn clone_repo(
+    git_config: Option<&GitConfig>,
+    repo_uri: &str,
+    clone_to: &Path,
+) -> Result<git2::Repository, git2::Error> {
+    clone_branch(git_config, repo_uri, None, clone_to)
+}
+
+/// Clone git repo, checking out given branch
+/// instead of the remote's default
+pub fn clone_branch(
+    git_config: Option<&GitConfig>,
+    repo_uri: &str,
+    branch: Option<&str>,
+    clone_to: &Path,
+) -> Result<git2::Repository, git2::Error> {
+    // Progress bars
+    let indexed_status = indicatif::ProgressBar::new(0);
+    indexed_status.set_style(
+        indicatif::ProgressStyle::default_bar().template("{bar:25} {pos}/{len} objects {msg}"),
+    );
+    // Attempt to clone repository
+    let mut options = git2::FetchOptions::new();
+    let mut callbacks = credential_callbacks(git_config);
+    callbacks.transfer_progress(|p| {
+        indexed_status.set_length(p.total_objects() as u64);
+        indexed_status.set_position(p.indexed_objects() as u64);
+        indexed_status.set_message(&format!(
+            "{}",
+            indicatif::HumanBytes(p.received_bytes() as u64)
+        ));
+        true
+    });
+    options.remote_callbacks(callbacks);
+    let mut clone_builder = git2::build::RepoBuilder::new();
+    clone_builder.fetch_options(options);
+    if let Some(branch) = branch {
+        clone_builder.branch(branch);
+    }
+    let result = clone_builder.clone(repo_uri, clone_to);
+    indexed_status.finish();
+    match &result {
+        Ok(_repo) => println!("Cloned repo"),
+        Err(err) => eprintln!("Clone error {:?}", err),
+    }
+    result
+}
+
+pub fn set_account_config(config: &GitConfig, repo: &git2::Repository) -> Result<(), git2::Error> {
+    let mut c = repo.config()?;
+    c.open_level(git2::ConfigLevel::Local)?;
+    c.set_str("user.name", &config.user)?;
+    c.set_str("user.email", &config.email)?;
+    Ok(())
+}
+
+pub fn name_email_from_repo(repo: &git2::Repository) -> Result<(String, String), git2::Error> {
+    let c = repo.config()?;
+    let name = c.get_string("user.name")?;
+    let email = c.get_string("user.email")?;
+    Ok((name, email))
+}
+
+/// Resolves the registered account matching the repository's
+/// configured user.name, if any
+pub fn account_for_repo(env: &CliEnv, repo: &git2::Repository) -> Option<GitConfig> {
+    match name_email_from_repo(repo) {
+        Ok((name, _email)) => {
+            if has_config(env, &name) {
+                get_config(env, &name).ok()
+            } else {
+                None
+            }
+        }
+        Err(_) => None,
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum SetupGitError {
+    GitError(String),
+    ApiError(ApiError),
+    CheckRepoError(RepoExistsError),
+}
+impl fmt::Display for SetupGitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Setup error as cli error, failed calls
+/// to the git host as network errors
+fn setup_err(e: SetupGitError) -> Error {
+    let msg = e.to_string();
+    let cli_error = match &e {
+        SetupGitError::ApiError(ApiError::NetworkError(_))
+        | SetupGitError::CheckRepoError(RepoExistsError::ApiError(ApiError::NetworkError(_))) => {
+            er::CliError::Network(msg)
+        }
+        _ => er::CliError::Git(msg),
+    };
+    Error::from(e.context(cli_error))
+}
+
+pub fn setup_git_dir<'a>(
+    env: &'a CliEnv,
+    dir_git: InspectGit,
+    git_config: GitConfig,
+    git_repo_uri: String,
+) -> impl Future<Item = (), Error = failure::Error> + 'a {
+    // Decide what to do based on status of inspected directory git
+    let init_repo = match dir_git.repo {
+        Some(repo) => {
+            match dir_git.origin_url {
+                Some(_origin_url) => {
+                    // We are pretty much done when project already
+                    // has git repository with origin_url.
+                    // Todo: Could propose to save credientials somehow
+                    Either::A(future::ok((repo, git_config)))
+                }
+                None => {
+                    // Repo without origin
+                    // If remote does not exist, we can create it and
+                    // initialize with this repository
+                    // If remote exists, we fail communicating the problem
+                    Either::B(Either::A(
+                        check_repo_exists(&git_config, &git_repo_uri)
+                            .map_err(|e| setup_err(SetupGitError::CheckRepoError(e)))
+                            .and_then(move |exists| {
+                                if exists {
+                                    env.error_msg("There is both a local repository, and a remote.");
+                                    env.error_msg("Please move local files, redo process and manually merge if intended.");
+                                    Either::A(future::err(setup_err(SetupGitError::GitError("Both local and remote repo".into()))))
+                                } else {
+                                    Either::B(do_create_remote(env, git_config, git_repo_uri.clone())
+                                        .map_err(|e| Error::from(e))
+                                        .and_then(move |git_config| {
+                                            // Repository created, now add as origin to local repository.
+                                            // Credentials are resolved through callbacks when needed,
+                                            // so they are not stored in the url
+                                            match repo.remote("origin", &git_repo_uri) {
+                                                Ok(_remote) => (),
+                                                Err(err) => return Err(setup_err(SetupGitError::GitError(format!("{:?}", err))))
+                                            };
+                                            // Should be ready for push,
+                                            // possibly there should be a push here
+                                            Ok((repo, git_config))
+                                        }))
+                                }
+                            }),
+                    ))
+                }
+            }
+        }
+        None => {
+            // No repo yet.
+            // If there is no remote, create it and initialize repo
+            // If remote exist, we can clone if dir is empty,
+            // else fail communicating need for empty dir
+            // Possibly we could do some merging, but may be better
+            // to let user handle
+            Either::B(Either::B(
+                check_repo_exists(&git_config, &git_repo_uri)
+                    .map_err(|e| setup_err(SetupGitError::CheckRepoError(e)))
+                    .and_then(move |exists| {
+                        let target_path = dir_git.dir.clone();
+                        if exists {
+                            // We need empty, or no dir to clone
+                            if dir_git.has_dir && dir_git.has_files {
+                                env.error_msg("A remote repository exist and the target folder is not empty.");
+                                env.error_msg("Please move files out, re-run the process, then manually merge files as intended.");
+                                return Either::A(future::err(setup_err(SetupGitError::GitError("Non empty dir".into()))));
+                            }
+                            // Clone existing
+                            match clone_repo(Some(&git_config), &git_repo_uri, &target_path) {
+                                Ok(repo) => Either::A(future::ok((repo, git_config))),
+                                Err(err) => Either::A(future::err(setup_err(SetupGitError::GitError(format!("Could not clone repository: {:?}", err)))))
+                            }
+                        } else {
+                            // Repository does not exist, attempt to create
+                            // Local repo does not exist, but there might be
+                            // files
+                            Either::B(do_create_remote(env, git_config, git_repo_uri.clone())
+                                .and_then(move |git_config| {
+                                    // Remote repository created
+                                    match init_repo(&target_path) {
+                                        Ok(repo) => {
+                                            match repo.remote("origin", &git_repo_uri) {
+                                                Ok(_remote) => (),
+                                                Err(err) => return Err(setup_err(SetupGitError::GitError(format!("{:?}", err))))
+                                            };
+                                            // Should be ready for push,
+                                            // possibly there should be a push here
+                                            Ok((repo, git_config))
+                                        }
+                                        Err(err) => Err(setup_err(SetupGitError::GitError(format!("Could not init local repository: {:?}", err))))
+                                    }
+                                }))
+                        }
+                    }),
+            ))
+        }
+    };
+    init_repo.and_then(|(repo, git_config)| {
+        // Here, repo should be initialized
+        // Just setting config git user and email here,
+        // and confirming repo location
+        match set_account_config(&git_config, &repo) {
+            Ok(_) => (),
+            Err(err) => return Err(setup_err(SetupGitError::GitError(format!("{:?}", err)))),
+        };
+        match repo.workdir() {
+            Some(work_dir) => {
+                println!("Repository at: {}", work_dir.display());
+            }
+            None => println!("Warning: Working directory of new git repository not resolved."),
+        };
+        Ok(())
+    })
+}
+
+fn do_create_remote<'a>(
+    env: &'a CliEnv,
+    git_config: GitConfig,
+    git_repo_uri: String,
+) -> impl Future<Item = GitConfig, Error = failure::Error> + 'a {
+    // Create remote
+    // Currently require owner to be the same as git_account
+    let uri_parts = match parse_git_uri(&git_repo_uri) {
+        Ok(parts) => parts,
+        Err(err) => {
+            return Either::A(future::err(setup_err(SetupGitError::GitError(format!(
+                "Git uri parse error: {}",
+                err
+            )))))
+        }
+    };
+    let repo_name2 = uri_parts.repo_name.clone();
+    if &uri_parts.owner != &git_config.user {
+        env.error_msg("Cannot currently create repository with a different owner from user.");
+        env.error_msg("Create the repository manually, then re-run this process,");
+        env.error_msg("or create a repository with the given git account.");
+    }
+    Either::B(
+        git_config
+            .provider
+            .api()
+            .create_repo(&git_config, &uri_parts.repo_name)
+            .map_err(|e| setup_err(SetupGitError::ApiError(e)))
+            .map(move |_| {
+                // todo: --set-upstream?
+                println!("Created private repository: {}", repo_name2);
+                git_config
+            }),
+    )
+}
+
+pub fn add_all(repo: &git2::Repository) -> er::Result<git2::Tree> {
+    let mut index = match repo.index() {
+        Ok(index) => index,
+        Err(e) => {
+            eprintln!("Couldn't get repo index: {:?}", e);
+            return Err(er::git_msg("Get index failed", e));
+        }
+    };
+    let cb = &mut |path: &std::path::Path, _matched_spec: &[u8]| -> i32 {
+        // Return 0 = add, 1 = skip, -1 = error
+        match repo.status_file(path) {
+            Ok(status) => {
+                if status.contains(git2::Status::WT_MODIFIED) {
+                    println!("Modified: {:?}", path);
+                    0
+                } else if status.contains(git2::Status::WT_NEW) {
+                    println!("New: {:?}", path);
+                    0
+                } else {
+                    1
+                }
+            }
+            Err(_) => {
+                eprintln!("Could not get status for file: {:?}", path);
+                -1
+            }
+        }
+    };
+    // Add all
+    // todo: If any changes
+    match index.add_all(
+        vec!["*".to_string()],
+        git2::IndexAddOption::DEFAULT,
+        Some(cb as &mut git2::IndexMatchedPath),
+    ) {
+        Ok(_) => println!("Added files"),
+        Err(e) => {
+            eprintln!("Error adding files: {:?}", e);
+            return Err(cli_err!(Git, "Error adding files"));
+        }
+    }
+    match index.write() {
+        Ok(_) => (),
+        Err(e) => return Err(er::git_msg("Failed to write index", e)),
+    }
+    // Index tree
+    let tree = match index.write_tree() {
+        Ok(oid) => match repo.find_tree(oid) {
+            Ok(tree) => tree,
+            Err(e) => return Err(er::git_msg("Error finding index tree", e)),
+        },
+        Err(e) => return Err(er::git_msg("Error writing tree", e)),
+    };
+    Ok(tree)
+}
+
+/// Whether there are changes in the working directory or index
+pub fn has_changes(repo: &git2::Repository) -> er::Result<bool> {
+    let mut status_options = git2::StatusOptions::new();
+    status_options.include_untracked(true);
+    let statuses = repo
+        .statuses(Some(&mut status_options))
+        .map_err(|e| er::git_msg("Could not get status", e))?;
+    Ok(!statuses.is_empty())
+}
+
+/// Signature from name and email in repository config
+pub fn signature(repo: &git2::Repository) -> er::Result<git2::Signature<'static>> {
+    match name_email_from_repo(&repo) {
+        Ok((conf_name, conf_email)) => match git2::Signature::now(&conf_name, &conf_email) {
+            Ok(signature) => Ok(signature),
+            Err(e) => Err(er::git_msg("Error creating signature", e)),
+        },
+        Err(e) => {
+            eprintln!("Failed getting name and email from config: {:?}", e);
+            Err(cli_err!(Git, "Failed getting name and email from config"))
+        }
+    }
+}
+
+/// Branch name used for new repositories, from
+/// `init.defaultBranch` in git config, or master
+pub fn default_branch() -> String {
+    git2::Config::open_default()
+        .and_then(|config| config.get_string("init.defaultBranch"))
+        .unwrap_or_else(|_| "master".to_string())
+}
+
+/// Inits a repository with HEAD pointing to the default branch
+pub fn init_repo(path: &Path) -> Result<git2::Repository, git2::Error> {
+    let mut init_options = git2::RepositoryInitOptions::new();
+    init_options.initial_head(&default_branch());
+    git2::Repository::init_opts(path, &init_options)
+}
+
+/// Name of the branch HEAD points to. Also resolves
+/// unborn branches, as in a repository without commits
+pub fn current_branch(repo: &git2::Repository) -> er::Result<String> {
+    let head_ref = repo
+        .find_reference("HEAD")
+        .map_err(|e| er::git_msg("Error getting head", e))?;
+    match head_ref.symbolic_target() {
+        Some(target) if target.starts_with("refs/heads/") => {
+            Ok(target["refs/heads/".len()..].to_string())
+        }
+        _ => Err(cli_err!(
+            Git,
+            "HEAD is detached, or not pointing to a branch"
+        )),
+    }
+}
+
+/// Commit of HEAD, None when branch is unborn
+pub fn head_commit(repo: &git2::Repository) -> er::Result<Option<git2::Commit>> {
+    match repo.head() {
+        Ok(head) => match head.peel_to_commit() {
+            Ok(commit) => Ok(Some(commit)),
+            Err(e) => Err(er::git_msg("Failed getting commit of head", e)),
+        },
+        Err(ref e)
+            if e.code() == git2::ErrorCode::UnbornBranch
+                || e.code() == git2::ErrorCode::NotFound =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(er::git_msg("Error getting head", e)),
+    }
+}
+
+/// Commits tree on current branch. Returns false
+/// when there was nothing to commit
+pub fn commit(repo: &git2::Repository, tree: git2::Tree, message: &str) -> er::Result<bool> {
+    // Commit
+    let signature = signature(repo)?;
+    // Get parent commit, none for first commit
+    // https://stackoverflow.com/questions/27672722/libgit2-commit-example
+    let head_commit = head_commit(repo)?;
+    if let Some(head_commit) = head_commit.as_ref() {
+        if head_commit.tree_id() == tree.id() {
+            println!("Nothing to commit");
+            return Ok(false);
+        }
+    }
+    let parents = head_commit.iter().collect::<Vec<_>>();
+    match repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        &message,
+        &tree,
+        &parents,
+    ) {
+        Ok(oid) => {
+            println!("Committed {} on {}", oid, current_branch(repo)?);
+            Ok(true)
+        }
+        Err(e) => Err(er::git_msg("Commit error", e)),
+    }
+}
+
+/// Pushes current branch to origin, and sets
+/// origin as upstream of the branch
+pub fn push_origin(repo: &git2::Repository, git_config: Option<&GitConfig>) -> er::Result<()> {
+    let branch = current_branch(repo)?;
+    if head_commit(repo)?.is_none() {
+        return Err(cli_err!(Git, "No commits on {}, nothing to push", branch));
+    }
+    // Get origin remote
+    let mut origin = match repo.find_remote("origin") {
+        Ok(remote) => remote,
+        Err(e) => return Err(er::git_msg("Error getting origin remote", e)),
+    };
+    // Make callback with reference push feedback
+    let mut remote_callbacks = credential_callbacks(git_config);
+    // A rejected reference fails the push
+    remote_callbacks.push_update_reference(|ref_name, status| match status {
+        None => {
+            println!("Reference push ok: {}", ref_name);
+            Ok(())
+        }
+        Some(error) => Err(git2::Error::from_str(&format!(
+            "Push of {} rejected: {}",
+            ref_name, error
+        ))),
+    });
+    let mut push_options = git2::PushOptions::new();
+    push_options.remote_callbacks(remote_callbacks);
+    // Do push, local branch to same name on remote
+    let branch_ref = format!("refs/heads/{}", branch);
+    let refspec = format!("{}:{}", branch_ref, branch_ref);
+    match origin.push(&[refspec.as_str()], Some(&mut push_options)) {
+        Ok(_) => (),
+        Err(e) => return Err(er::git_msg("Push error", e)),
+    }
+    // Equivalent of --set-upstream
+    repo.config()
+        .and_then(|mut config| {
+            config.set_str(&format!("branch.{}.remote", branch), "origin")?;
+            config.set_str(&format!("branch.{}.merge", branch), &branch_ref)
+        })
+        .map_err(|e| er::git_msg("Could not set upstream", e))
+}
+
+/// Fetches a branch from origin, returning the fetched commit,
+/// or None when the branch does not exist on origin
+pub fn fetch_origin<'r>(
+    repo: &'r git2::Repository,
+    branch: &str,
+    git_config: Option<&GitConfig>,
+) -> er::Result<Option<git2::AnnotatedCommit<'r>>> {
+    let mut origin = repo
+        .find_remote("origin")
+        .map_err(|e| er::git_msg("Error getting origin remote", e))?;
+    let mut fetch_options = git2::FetchOptions::new();
+    fetch_options.remote_callbacks(credential_callbacks(git_config));
+    println!("Fetching origin {}", branch);
+    let tracking_ref = format!("refs/remotes/origin/{}", branch);
+    let refspec = format!("+refs/heads/{}:{}", branch, tracking_ref);
+    origin
+        .fetch(&[refspec.as_str()], Some(&mut fetch_options), None)
+        .map_err(|e| er::git_msg("Fetch error", e))?;
+    let tracking_ref = match repo.find_reference(&tracking_ref) {
+        Ok(tracking_ref) => tracking_ref,
+        Err(ref e) if e.code() == git2::ErrorCode::NotFound => {
+            println!("Branch {} not found on origin", branch);
+            return Ok(None);
+        }
+        Err(e) => return Err(er::git_msg(format!("Could not find {}", tracking_ref), e)),
+    };
+    repo.reference_to_annotated_commit(&tracking_ref)
+        .map(Some)
+        .map_err(|e| er::git_msg("Could not resolve fetched commit", e))
+}
+
+pub enum MergeOutcome {
+    UpToDate,
+    FastForward,
+    /// Merged, the index is ready to be committed
+    Merged,
+    /// Merge left conflicts in index and working directory
+    Conflicts,
+}
+
+/// Merges fetched commit into current head.
+/// Does not commit a merge, see `commit_merge`
+pub fn merge_fetched(
+    repo: &git2::Repository,
+    fetched: &git2::AnnotatedCommit,
+) -> er::Result<MergeOutcome> {
+    let (analysis, _preference) = repo
+        .merge_analysis(&[fetched])
+        .map_err(|e| er::git_msg("Merge analysis failed", e))?;
+    if analysis.is_up_to_date() {
+        Ok(MergeOutcome::UpToDate)
+    } else if analysis.is_unborn() {
+        // No local commits, point branch to fetched
+        let branch_ref = format!("refs/heads/{}", current_branch(repo)?);
+        repo.reference(&branch_ref, fetched.id(), false, "Initial pull")
+            .map_err(|e| er::git_msg("Failed creating branch", e))?;
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+            .map_err(|e| er::git_msg("Checkout failed", e))?;
+        Ok(MergeOutcome::FastForward)
+    } else if analysis.is_fast_forward() {
+        let mut head_ref = repo
+            .head()
+            .map_err(|e| er::git_msg("Error getting head", e))?;
+        head_ref
+            .set_target(fetched.id(), "Fast-forward")
+            .map_err(|e| er::git_msg("Fast-forward failed", e))?;
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+            .map_err(|e| er::git_msg("Checkout failed", e))?;
+        Ok(MergeOutcome::FastForward)
+    } else {
+        repo.merge(&[fetched], None, None)
+            .map_err(|e| er::git_msg("Merge failed", e))?;
+        if has_conflicts(repo)? {
+            Ok(MergeOutcome::Conflicts)
+        } else {
+            Ok(MergeOutcome::Merged)
+        }
+    }
+}
+
+pub fn has_conflicts(repo: &git2::Repository) -> er::Result<bool> {
+    let index = repo
+        .index()
+        .map_err(|e| er::git_msg("Get index failed", e))?;
+    Ok(index.has_conflicts())
+}
+
+/// Commits the current index as a merge of head and given commit,
+/// and clears the merge state
+pub fn commit_merge(
+    repo: &git2::Repository,
+    fetched: &git2::AnnotatedCommit,
+    message: &str,
+) -> er::Result<()> {
+    let signature = signature(repo)?;
+    let mut index = repo
+        .index()
+        .map_err(|e| er::git_msg("Get index failed", e))?;
+    let tree = index
+        .write_tree()
+        .and_then(|oid| repo.find_tree(oid))
+        .map_err(|e| er::git_msg("Error writing tree", e))?;
+    let head_commit = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .map_err(|e| er::git_msg("Failed getting commit of head", e))?;
+    let fetched_commit = repo
+        .find_commit(fetched.id())
+        .map_err(|e| er::git_msg("Failed getting fetched commit", e))?;
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &[&head_commit, &fetched_commit],
+    )
+    .map_err(|e| er::git_msg("Commit error", e))?;
+    repo.cleanup_state()
+        .map_err(|e| er::git_msg("Cleanup state failed", e))
+}
+
+/// A conflicted file with content of both sides and their
+/// common ancestor, None when deleted or not there
+pub struct Conflict {
+    pub path: PathBuf,
+    pub ancestor: Option<Vec<u8>>,
+    pub ours: Option<Vec<u8>>,
+    pub theirs: Option<Vec<u8>>,
+}
+
+pub fn conflicts(repo: &git2::Repository) -> er::Result<Vec<Conflict>> {
+    let index = repo
+        .index()
+        .map_err(|e| er::git_msg("Get index failed", e))?;
+    let index_conflicts = index
+        .conflicts()
+        .map_err(|e| er::git_msg("Could not read conflicts", e))?;
+    let blob_content = |entry: &Option<git2::IndexEntry>| -> er::Result<Option<Vec<u8>>> {
+        match entry {
+            Some(entry) => match repo.find_blob(entry.id) {
+                Ok(blob) => Ok(Some(blob.content().to_vec())),
+                Err(e) => Err(er::git_msg("Could not read blob", e)),
+            },
+            None => Ok(None),
+        }
+    };
+    let mut conflicts = Vec::new();
+    for conflict in index_conflicts {
+        let conflict = conflict.map_err(|e| er::git_msg("Conflict error", e))?;
+        // Deleted on one side, there is only the other side
+        // and ancestor. Deleted on both, only the ancestor
+        let entry = conflict
+            .our
+            .as_ref()
+            .or(conflict.their.as_ref())
+            .or(conflict.ancestor.as_ref());
+        let path = match entry {
+            Some(entry) => PathBuf::from(String::from_utf8_lossy(&entry.path).to_string()),
+            None => return Err(cli_err!(Git, "Conflict without entries")),
+        };
+        conflicts.push(Conflict {
+            path,
+            ancestor: blob_content(&conflict.ancestor)?,
+            ours: blob_content(&conflict.our)?,
+            theirs: blob_content(&conflict.their)?,
+        });
+    }
+    Ok(conflicts)
+}
+
+/// Writes resolved content of a conflicted file (relative to
+/// workdir) and marks it resolved in the index. None removes the file
+pub fn resolve_conflict(
+    repo: &git2::Repository,
+    path: &Path,
+    content: Option<&[u8]>,
+) -> er::Result<()> {
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir,
+        None => return Err(cli_err!(Git, "Repository has no working directory")),
+    };
+    let full_path = workdir.join(path);
+    let mut index = repo
+        .index()
+        .map_err(|e| er::git_msg("Get index failed", e))?;
+    match content {
+        Some(content) => {
+            std::fs::write(&full_path, content)?;
+            // Adding a path clears its conflict entries
+            index
+                .add_path(path)
+                .map_err(|e| er::git_msg(format!("Failed adding {:?}", path), e))?;
+        }
+        None => {
+            if full_path.is_file() {
+                std::fs::remove_file(&full_path)?;
+            }
+            index
+                .remove_path(path)
+                .map_err(|e| er::git_msg(format!("Failed removing {:?}", path), e))?;
+        }
+    }
+    index
+        .write()
+        .map_err(|e| er::git_msg("Failed to write index", e))
+}
+
+/// Rebases current branch onto fetched commit. For each applied
+/// commit, `on_conflict` is called when the index has conflicts,
+/// and is expected to resolve them.
+/// The rebase is aborted if resolution fails.
+pub fn rebase_onto<F>(
+    repo: &git2::Repository,
+    fetched: &git2::AnnotatedCommit,
+    mut on_conflict: F,
+) -> er::Result<()>
+where
+    F: FnMut(&git2::Repository) -> er::Result<()>,
+{
+    let signature = signature(repo)?;
+    let mut rebase = repo
+        .rebase(None, Some(fetched), None, None)
+        .map_err(|e| er::git_msg("Could not start rebase", e))?;
+    while let Some(operation) = rebase.next() {
+        let result = operation
+            .map_err(|e| er::git_msg("Rebase operation failed", e))
+            .and_then(|_| {
+                if has_conflicts(repo)? {
+                    on_conflict(repo)?;
+                }
+                match rebase.commit(None, &signature, None) {
+                    Ok(_) => Ok(()),
+                    // Nothing to commit when the change already exists upstream
+                    Err(ref e) if e.code() == git2::ErrorCode::Applied => Ok(()),
+                    Err(e) => Err(er::git_msg("Rebase commit failed", e)),
+                }
+            });
+        if let Err(e) = result {
+            eprintln!("Aborting rebase");
+            let _ = rebase.abort();
+            return Err(e);
+        }
+    }
+    rebase
+        .finish(Some(&signature))
+        .map_err(|e| er::git_msg("Could not finish rebase", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nested_owner() {
+        let parts = parse_git_uri("git@gitlab.com:group/sub/site.git").unwrap();
+        assert_eq!(parts.owner, "group/sub");
+        assert_eq!(parts.repo_name, "site");
+        assert_eq!(gitlab::encode_path("group/sub/site"), "group%2Fsub%2Fsite");
+    }
+
+    #[test]
+    fn uses_gitea_ssh_port() {
+        let account = GitConfig {
+            user: "me".into(),
+            email: "".into(),
+            token: "".into(),
+            ssh: true,
+            ssh_key: None,
+            provider: GitProvider::Gitea {
+                base_url: "https://git.example.com".into(),
+                ssh_port: Some(2222),
+            },
+        };
+        let uri = account.repo_uri("site".into());
+        assert_eq!(uri, "ssh://git@git.example.com:2222/me/site.git");
+        let parts = parse_git_uri(&uri).unwrap();
+        assert_eq!(parts.host, "git.example.com");
+        assert_eq!(parts.owner, "me");
+    }
+}
@@ -0,0 +1,161 @@
+// Hooks of cli commands. A project declares shell commands to
+// run before and after commands, by command path:
+//   "hooks": { "deploy": { "pre": ["npm run build"] } }
+// Hooks run with sh in the project dir, when the cli is run
+// inside it or the command is given the project. A failing
+// pre hook stops the command, post hooks run only when the
+// command succeeded.
+// Only shell commands for now, plugins could be added as
+// another kind of hook.
+
+use crate::er::{self, Result};
+use crate::project::{self, ProjectConfig};
+use crate::utils::CliEnv;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CommandHooks {
+    #[serde(default)]
+    pub pre: Vec<String>,
+    #[serde(default)]
+    pub post: Vec<String>,
+}
+
+/// Path of subcommands, like "wp sync-local"
+pub fn command_name(matches: &clap::ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut matches = matches;
+    while let (name, Some(sub_matches)) = matches.subcommand() {
+        names.push(name);
+        matches = sub_matches;
+    }
+    names.join(" ")
+}
+
+/// Runs hook with sh, with command and project in env vars
+fn run_hook(hook: &str, dir: &Path, command: &str, project: &ProjectConfig) -> Result<()> {
+    let status = process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .current_dir(dir)
+        .env("WOP_COMMAND", command)
+        .env("WOP_PROJECT", &project.name)
+        .env("WOP_PROJECT_DIR", dir)
+        .status()
+        .map_err(|e| er::io_msg(format!("Could not run hook {}", hook), e))?;
+    if !status.success() {
+        return Err(cli_err!(Other, "Hook of {} failed: {}", command, hook));
+    }
+    Ok(())
+}
+
+fn run_hooks(hooks: &[String], dir: &Path, command: &str, project: &ProjectConfig) -> Result<()> {
+    for hook in hooks {
+        run_hook(hook, dir, command, project)?;
+    }
+    Ok(())
+}
+
+/// Commands that run without hooks. Completions are run by
+/// the shell on every tab press, and config repairs broken
+/// project configs, which hooks need to be read
+const WITHOUT_HOOKS: [&str; 3] = ["completions", "complete-names", "config"];
+
+/// Project the command is given, like `domain add <project>`,
+/// otherwise the project of the current dir. None when there
+/// is no such project, an error when its config is broken
+fn target_project(env: &CliEnv, matches: &clap::ArgMatches) -> Result<Option<ProjectConfig>> {
+    let mut matches = matches;
+    while let (_, Some(sub_matches)) = matches.subcommand() {
+        matches = sub_matches;
+    }
+    let name = match matches.value_of("project") {
+        Some(name) => name.to_string(),
+        None => match project::current_project_name(env) {
+            Ok(name) => name,
+            Err(_) => return Ok(None),
+        },
+    };
+    if !env.config_dirs.projects.has_file(&name) {
+        return Ok(None);
+    }
+    project::get_config(env, &name).map(Some)
+}
+
+/// Runs command with hooks of the project it targets, if any.
+/// The command is given the env back
+pub fn with_hooks<F>(env: CliEnv, matches: &clap::ArgMatches, f: F) -> Result<()>
+where
+    F: FnOnce(CliEnv) -> Result<()>,
+{
+    let skip = matches
+        .subcommand_name()
+        .map_or(true, |name| WITHOUT_HOOKS.contains(&name));
+    if skip {
+        return f(env);
+    }
+    let command = command_name(matches);
+    // Outside of projects there are no hooks
+    let project = match target_project(&env, matches)? {
+        Some(project) => project,
+        None => return f(env),
+    };
+    let hooks = match project.hooks.get(&command) {
+        Some(hooks) => hooks.clone(),
+        None => return f(env),
+    };
+    let dir = project.dir(&env);
+    run_hooks(&hooks.pre, &dir, &command, &project)?;
+    f(env)?;
+    run_hooks(&hooks.post, &dir, &command, &project)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_commands_by_path() {
+        let app = crate::cli::cli_app();
+        let matches = app.get_matches_from(vec!["wop", "wp", "sync-local"]);
+        assert_eq!(command_name(&matches), "wp sync-local");
+    }
+
+    #[test]
+    fn targets_given_project() {
+        let projects_dir = std::env::temp_dir().join(format!("hooks-{}", std::process::id()));
+        let env = CliEnv::new(projects_dir.clone(), projects_dir.join("workdir"));
+        let dirs = &env.config_dirs;
+        dirs.projects
+            .write(
+                "site",
+                r#"{"name": "site", "git_repo_uri": "", "git_user": "", "server_name": ""}"#,
+            )
+            .unwrap();
+        dirs.projects.write("broken", "{").unwrap();
+        let target = |args: Vec<&str>| {
+            let matches = crate::cli::cli_app().get_matches_from(args);
+            target_project(&env, &matches)
+        };
+        let site = target(vec!["wop", "domain", "add", "site", "a.com"]);
+        let missing = target(vec!["wop", "domain", "add", "gone", "a.com"]);
+        let broken = target(vec!["wop", "domain", "add", "broken", "a.com"]);
+        std::fs::remove_dir_all(&projects_dir).unwrap();
+        assert_eq!(
+            site.unwrap().map(|project| project.name),
+            Some("site".into())
+        );
+        assert!(missing.unwrap().is_none());
+        assert!(broken.is_err());
+    }
+
+    #[test]
+    fn fails_on_failing_hook() {
+        let project = ProjectConfig::default();
+        let dir = std::env::temp_dir();
+        assert!(run_hook("exit 0", &dir, "dev", &project).is_ok());
+        assert!(run_hook("exit 1", &dir, "dev", &project).is_err());
+    }
+}
@@ -1,16 +1,41 @@
 // `error_chain!` can recurse deeply
 #![recursion_limit = "1024"]
 
-mod app;
+/// Cli error of category with formatted message, as failure
+/// error, like `cli_err!(Config, "No project {}", name)`
+macro_rules! cli_err {
+    ($kind:ident, $($arg:tt)*) => {
+        failure::Error::from(crate::er::CliError::$kind(format!($($arg)*)))
+    };
+}
+
+mod acme;
 mod aws;
+mod backup;
 mod cli;
 mod cli_app;
+mod completion;
+mod compose;
+mod config;
+mod debug_config;
 mod docker;
+mod docker_api;
+mod domain;
+mod environment;
 mod git;
+mod hooks;
+mod modules;
+mod preview;
 mod project;
 mod project_path;
+mod scaffold;
 mod server;
+mod site;
+mod sql;
+mod updates;
 mod utils;
+mod versions;
+mod watch;
 mod workspace;
 mod wp;
 // (Currently) Superseded by ssh which comes with sftp
@@ -23,170 +48,282 @@ extern crate failure_derive;
 
 mod er {
     use failure::{Error, Fail};
-    use std::fmt;
+    use std::io;
 
     pub type Result<T> = std::result::Result<T, Error>;
 
-    pub trait FailExt {
-        fn err<T>(self) -> Result<T>;
-        fn error(self) -> Error;
+    /// Category of an error. Exit codes are stable,
+    /// so scripts can rely on them
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Kind {
+        Other,
+        Config,
+        Network,
+        Ssh,
+        Docker,
+        Git,
+        Db,
     }
-    impl<F: Fail> FailExt for F {
-        fn err<T>(self) -> Result<T> {
-            Err(Error::from(self))
+    impl Kind {
+        pub fn exit_code(self) -> i32 {
+            match self {
+                Kind::Other => 1,
+                Kind::Config => 10,
+                Kind::Network => 11,
+                Kind::Ssh => 12,
+                Kind::Docker => 13,
+                Kind::Git => 14,
+                Kind::Db => 15,
+            }
         }
-        fn error(self) -> Error {
-            Error::from(self)
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Kind::Other => "other",
+                Kind::Config => "config",
+                Kind::Network => "network",
+                Kind::Ssh => "ssh",
+                Kind::Docker => "docker",
+                Kind::Git => "git",
+                Kind::Db => "db",
+            }
         }
     }
 
-    // Ssh2 error
+    /// Errors of the cli by category. Used as context
+    /// of library errors, which are kept as cause
     #[derive(Debug, Fail)]
-    pub struct Ssh {
-        msg: Option<String>,
-        #[fail(cause)]
-        e: ssh2::Error,
-    }
-    impl Ssh {
-        pub fn e(e: ssh2::Error) -> Self {
-            Ssh { msg: None, e }
-        }
-        pub fn msg<S: Into<String>>(msg: S, e: ssh2::Error) -> Self {
-            Ssh {
-                msg: Some(msg.into()),
-                e,
-            }
-        }
+    pub enum CliError {
+        #[fail(display = "{}", _0)]
+        Config(String),
+        #[fail(display = "{}", _0)]
+        Network(String),
+        #[fail(display = "{}", _0)]
+        Ssh(String),
+        #[fail(display = "{}", _0)]
+        Docker(String),
+        #[fail(display = "{}", _0)]
+        Git(String),
+        #[fail(display = "{}", _0)]
+        Db(String),
+        /// Local files and processes
+        #[fail(display = "{}", _0)]
+        Io(String),
+        #[fail(display = "{}", _0)]
+        Other(String),
+        /// Exit with code of a command that was run,
+        /// which already reported its error
+        #[fail(display = "Exited with {}", _0)]
+        Exit(i32),
     }
-    impl fmt::Display for Ssh {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            match &self.msg {
-                Some(msg) => write!(f, "{}: {:?}", msg, self.e),
-                None => {
-                    write!(f, "Ssh error: {:?}", self.e)
-                    /*
-                    // I think codes depend on context, like session or sftp
-                    match self.e.code() {
-
-                    }*/
-                }
+    impl CliError {
+        pub fn kind(&self) -> Kind {
+            match self {
+                CliError::Config(_) => Kind::Config,
+                CliError::Network(_) => Kind::Network,
+                CliError::Ssh(_) => Kind::Ssh,
+                CliError::Docker(_) => Kind::Docker,
+                CliError::Git(_) => Kind::Git,
+                CliError::Db(_) => Kind::Db,
+                CliError::Io(_) | CliError::Other(_) | CliError::Exit(_) => Kind::Other,
             }
         }
     }
-    // Io error
-    #[derive(Debug, Fail)]
-    pub struct Io {
-        msg: Option<String>,
-        #[fail(cause)]
-        e: std::io::Error,
-    }
-    impl Io {
-        pub fn e(e: std::io::Error) -> Self {
-            Io { msg: None, e }
-        }
-        pub fn msg<S: Into<String>>(msg: S, e: std::io::Error) -> Self {
-            Io {
-                msg: Some(msg.into()),
-                e,
-            }
-        }
+
+    fn caused<F: Fail>(cause: F, cli_error: CliError) -> Error {
+        Error::from(cause.context(cli_error))
     }
-    impl fmt::Display for Io {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            match &self.msg {
-                Some(msg) => write!(f, "{}: {:?}", msg, self.e),
-                None => write!(f, "Io error: {:?}", self.e),
-            }
+
+    fn is_network(kind: io::ErrorKind) -> bool {
+        match kind {
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::TimedOut => true,
+            _ => false,
         }
     }
-    impl From<std::io::Error> for Io {
-        fn from(e: std::io::Error) -> Self {
-            Io::e(e)
-        }
+
+    /// Io error, a network error when a connection failed
+    pub fn io(e: io::Error) -> Error {
+        io_msg("Io error", e)
     }
 
-    // Walkdir error
-    #[derive(Debug, Fail)]
-    pub struct Walkdir {
-        msg: Option<String>,
-        #[fail(cause)]
-        e: walkdir::Error,
-    }
-    impl Walkdir {
-        pub fn e(e: walkdir::Error) -> Error {
-            Error::from(Walkdir { msg: None, e })
+    pub fn io_msg<S: Into<String>>(msg: S, e: io::Error) -> Error {
+        let msg = format!("{}: {}", msg.into(), e);
+        if is_network(e.kind()) {
+            caused(e, CliError::Network(msg))
+        } else {
+            caused(e, CliError::Io(msg))
         }
-        pub fn msg<S: Into<String>>(msg: S, e: walkdir::Error) -> Error {
-            Error::from(Walkdir {
-                msg: Some(msg.into()),
-                e,
+    }
+
+    /// Io error of a connection, as network error
+    pub fn network_msg<S: Into<String>>(msg: S, e: io::Error) -> Error {
+        let msg = format!("{}: {}", msg.into(), e);
+        caused(e, CliError::Network(msg))
+    }
+
+    pub fn ssh(e: ssh2::Error) -> Error {
+        ssh_msg("Ssh error", e)
+    }
+
+    pub fn ssh_msg<S: Into<String>>(msg: S, e: ssh2::Error) -> Error {
+        let msg = format!("{}: {}", msg.into(), e);
+        caused(e, CliError::Ssh(msg))
+    }
+
+    pub fn git(e: git2::Error) -> Error {
+        git_msg("Git error", e)
+    }
+
+    pub fn git_msg<S: Into<String>>(msg: S, e: git2::Error) -> Error {
+        let msg = format!("{}: {}", msg.into(), e.message());
+        caused(e, CliError::Git(msg))
+    }
+
+    pub fn db(e: mysql_utils::er::MyLibError) -> Error {
+        db_msg("Db error", e)
+    }
+
+    pub fn db_msg<S: Into<String>>(msg: S, e: mysql_utils::er::MyLibError) -> Error {
+        let msg = format!("{}: {}", msg.into(), e);
+        caused(e, CliError::Db(msg))
+    }
+
+    pub fn walkdir(e: walkdir::Error) -> Error {
+        let msg = format!("Walkdir error: {}", e);
+        caused(e, CliError::Io(msg))
+    }
+
+    pub trait ResultExt<T> {
+        /// Wraps error in a cli error made from msg, like
+        /// `.or_cli(CliError::Config, "Could not read config")`
+        fn or_cli<S: Into<String>>(self, cli_error: fn(String) -> CliError, msg: S) -> Result<T>;
+    }
+    impl<T, E: Into<Error>> ResultExt<T> for std::result::Result<T, E> {
+        fn or_cli<S: Into<String>>(self, cli_error: fn(String) -> CliError, msg: S) -> Result<T> {
+            self.map_err(|e| {
+                let e: Error = e.into();
+                Error::from(e.context(cli_error(msg.into())))
             })
         }
     }
-    impl fmt::Display for Walkdir {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            match &self.msg {
-                Some(msg) => write!(f, "{}: {:?}", msg, self.e),
-                None => write!(f, "Walkdir error: {:?}", self.e),
-            }
-        }
+
+    /// Cli error of error, or of its causes
+    fn find_cli_error(e: &Error) -> Option<&CliError> {
+        e.iter_chain().find_map(|fail| {
+            fail.downcast_ref::<CliError>().or_else(|| {
+                fail.downcast_ref::<failure::Context<CliError>>()
+                    .map(|context| context.get_context())
+            })
+        })
     }
 
-    // MyLibError (mysql-utils)
-    #[derive(Debug, Fail)]
-    pub struct MyLib {
-        msg: Option<String>,
-        #[fail(cause)]
-        e: mysql_utils::er::MyLibError,
-    }
-    impl MyLib {
-        pub fn e(e: mysql_utils::er::MyLibError) -> Self {
-            MyLib { msg: None, e }
+    /// Kind from the outermost cli error, or from
+    /// known library errors in the chain
+    pub fn kind_of(e: &Error) -> Kind {
+        if let Some(cli_error) = find_cli_error(e) {
+            return cli_error.kind();
         }
-        pub fn msg<S: Into<String>>(msg: S, e: mysql_utils::er::MyLibError) -> Self {
-            MyLib {
-                msg: Some(msg.into()),
-                e,
+        for fail in e.iter_chain() {
+            if fail.downcast_ref::<ssh2::Error>().is_some() {
+                return Kind::Ssh;
             }
-        }
-    }
-    impl fmt::Display for MyLib {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            match &self.msg {
-                Some(msg) => write!(f, "{}: {:?}", msg, self.e),
-                None => {
-                    write!(f, "MyLib error: {:?}", self.e)
+            if fail.downcast_ref::<git2::Error>().is_some() {
+                return Kind::Git;
+            }
+            if fail.downcast_ref::<mysql_utils::er::MyLibError>().is_some() {
+                return Kind::Db;
+            }
+            if let Some(io_error) = fail.downcast_ref::<io::Error>() {
+                if is_network(io_error.kind()) {
+                    return Kind::Network;
                 }
             }
         }
+        Kind::Other
     }
-}
 
-fn main() {
-    if let Err(ref e) = cli_app::run() {
+    pub fn exit_code(e: &Error) -> i32 {
+        match find_cli_error(e) {
+            Some(CliError::Exit(code)) => *code,
+            _ => kind_of(e).exit_code(),
+        }
+    }
+
+    /// Prints error with its causes to stderr, as json
+    /// when asked, for tooling
+    pub fn report(e: &Error, json: bool) {
         use std::io::Write;
-        let stderr = &mut ::std::io::stderr();
+        if let Some(CliError::Exit(_)) = find_cli_error(e) {
+            return;
+        }
+        let stderr = &mut std::io::stderr();
         let errmsg = "Error writing to stderr";
-
+        let causes = e
+            .iter_causes()
+            .map(|cause| cause.to_string())
+            .collect::<Vec<_>>();
+        if json {
+            let kind = kind_of(e);
+            let report = serde_json::json!({
+                "error": e.to_string(),
+                "kind": kind.name(),
+                "code": kind.exit_code(),
+                "causes": causes,
+            });
+            writeln!(stderr, "{}", report).expect(errmsg);
+            return;
+        }
         writeln!(stderr, "error: {}", e).expect(errmsg);
+        for cause in causes {
+            writeln!(stderr, "{}", cause).expect(errmsg);
+        }
+        // Shown with RUST_BACKTRACE
+        let backtrace = e.backtrace().to_string();
+        if !backtrace.is_empty() {
+            writeln!(stderr, "{}", backtrace).expect(errmsg);
+        }
+    }
 
-        let mut fail = e.as_fail();
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-        // Not sure which/what makes sense
-        if let Some(bt) = fail.cause().and_then(|cause| cause.backtrace()) {
-            println!("{}", bt)
-        } else {
-            println!("{}", e.backtrace())
+        #[test]
+        fn exit_code_from_outer_cli_error() {
+            let io_error = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+            let e = Error::from(io_error);
+            assert_eq!(kind_of(&e), Kind::Network);
+            let e = Err::<(), _>(e)
+                .or_cli(CliError::Config, "Could not resolve project")
+                .unwrap_err();
+            assert_eq!(exit_code(&e), 10);
+            assert_eq!(e.iter_causes().count(), 1);
+            assert_eq!(exit_code(&cli_err!(Other, "Other")), 1);
+            assert_eq!(exit_code(&CliError::Exit(3).into()), 3);
         }
 
-        while let Some(cause) = fail.cause() {
-            println!("{}", cause);
-
-            // Make `fail` the reference to the cause of the previous fail, making the
-            // loop "dig deeper" into the cause chain.
-            fail = cause;
+        #[test]
+        fn maps_errors_where_they_occur() {
+            let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+            assert_eq!(exit_code(&io(refused)), 11);
+            let missing = io::Error::new(io::ErrorKind::NotFound, "missing");
+            assert_eq!(exit_code(&io_msg("Could not read", missing)), 1);
+            assert_eq!(exit_code(&git(git2::Error::from_str("no repo"))), 14);
+            assert_eq!(exit_code(&cli_err!(Docker, "Build failed")), 13);
         }
+    }
+}
 
-        ::std::process::exit(1);
+fn main() {
+    let matches = cli::cli_app().get_matches();
+    let json = cli_app::json_errors(&matches);
+    if let Err(e) = cli_app::run(matches) {
+        er::report(&e, json);
+        std::process::exit(er::exit_code(&e));
     }
 }
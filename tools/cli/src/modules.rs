@@ -0,0 +1,78 @@
+// Environment modules. Generic commands like dev, deploy and
+// compose render call into the module of the project for the
+// steps that depend on what runs in the environment, like
+// wordpress mounts of plugins and themes in dev.
+// A project selects its module by name with the module field,
+// wordpress when not set.
+
+use crate::compose::ComposeYml;
+use crate::er::Result;
+use crate::project::ProjectConfig;
+use crate::utils::CliEnv;
+use crate::wp::WpModule;
+
+/// Module of projects without a module field
+pub const DEFAULT: &str = "wordpress";
+
+pub trait EnvModule: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Writes docker/dev.yml of project, merged
+    /// over the server dev compose files
+    fn gen_dev(&self, env: &CliEnv, project: &ProjectConfig) -> Result<()>;
+
+    /// Writes docker/prod.yml of project
+    fn gen_prod(&self, env: &CliEnv, project: &ProjectConfig) -> Result<()>;
+
+    /// Adjusts compose after files are merged
+    fn apply(&self, _project: &ProjectConfig, _compose: &mut ComposeYml, _dev: bool) {}
+
+    /// Points the app in prod to a new external url,
+    /// after the proxy is restarted with it
+    fn set_url(&self, env: &CliEnv, project: &ProjectConfig, url: &str) -> Result<()>;
+}
+
+static MODULES: [&dyn EnvModule; 1] = [&WpModule];
+
+/// Module by name
+pub fn get(name: &str) -> Option<&'static dyn EnvModule> {
+    MODULES.iter().find(|module| module.name() == name).cloned()
+}
+
+pub fn names() -> Vec<&'static str> {
+    MODULES.iter().map(|module| module.name()).collect()
+}
+
+/// Module of project
+pub fn of(project: &ProjectConfig) -> Result<&'static dyn EnvModule> {
+    let name = project
+        .module
+        .as_ref()
+        .map(String::as_str)
+        .unwrap_or(DEFAULT);
+    match get(name) {
+        Some(module) => Ok(module),
+        None => Err(cli_err!(
+            Config,
+            "Unknown module {}, available: {}",
+            name,
+            names().join(", ")
+        )),
+    }
+}
+
+/// Module of project, erroring when it is not the given
+/// one, for commands only a single module supports
+pub fn require(project: &ProjectConfig, name: &str) -> Result<()> {
+    let module = of(project)?;
+    if module.name() != name {
+        return Err(cli_err!(
+            Config,
+            "Project {} has module {}, command needs {}",
+            project.name,
+            module.name(),
+            name
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,338 @@
+// Preview environments of project branches on the prod server.
+// Each preview is a separate compose project with its own
+// db clone, and is routed through the prod proxy
+// at /preview/<slug>/
+
+use crate::compose::{ComposeService, ComposeVolume, ComposeYml};
+use crate::er::{self, Result};
+use crate::git;
+use crate::project::{self, ProjectConfig};
+use crate::server::{ServerConfig, SshConn};
+use crate::site;
+use crate::utils::{self, CliEnv};
+use crate::versions;
+use crate::wp;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Wp-cli ssh port of first preview. Stepping by two
+/// as tunnels use the port above
+const FIRST_WP_CLI_PORT: u16 = 2350;
+
+/// Tries of the preview db, two seconds apart,
+/// before giving up on cloning the database
+const DB_WAIT_TRIES: u32 = 60;
+
+/// Temporary checkout of a branch, removed when dropped
+/// so it is also cleaned up when preview up fails
+struct Checkout(PathBuf);
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        if self.0.is_dir() {
+            if let Err(e) = std::fs::remove_dir_all(&self.0) {
+                eprintln!("Failed removing checkout {:?}: {:?}", self.0, e);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreviewConfig {
+    pub branch: String,
+    pub wp_cli_port: u16,
+}
+
+/// Compose project name. Compose strips most non
+/// alphanumeric chars, so keeping to those
+pub fn compose_project(project: &ProjectConfig, slug: &str) -> String {
+    format!("{}preview{}", project.name, slug)
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Default network of preview compose project,
+/// joined by the prod proxy
+pub fn network_name(project: &ProjectConfig, slug: &str) -> String {
+    format!("{}_default", compose_project(project, slug))
+}
+
+/// Wordpress service name, also used as host
+/// from the proxy
+pub fn wp_service(slug: &str) -> String {
+    format!("preview-{}", slug)
+}
+
+fn compose_file(slug: &str) -> String {
+    format!("preview-{}.yml", slug)
+}
+
+fn compose_cmd(project: &ProjectConfig, slug: &str, args: &str) -> String {
+    format!(
+        "docker-compose -p {} -f docker/{} {}",
+        compose_project(project, slug),
+        compose_file(slug),
+        args
+    )
+}
+
+/// Creates docker/preview-<slug>.yml, a standalone
+/// compose file with db, wordpress and wp-cli
+fn create_preview_yml(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    server: &ServerConfig,
+    slug: &str,
+    preview: &PreviewConfig,
+) -> Result<()> {
+    let server_dir = |dir: &str| {
+        server
+            .home_dir_and(&format!("workdir/server/{}", dir))
+            .to_string_lossy()
+            .to_string()
+    };
+    let mut db_env = BTreeMap::new();
+    db_env.insert("MYSQL_RANDOM_ROOT_PASSWORD".to_string(), "1".to_string());
+    db_env.insert("MYSQL_DATABASE".to_string(), "wordpress".to_string());
+    db_env.insert("MYSQL_USER".to_string(), "wordpress".to_string());
+    db_env.insert("MYSQL_PASSWORD".to_string(), "wordpress".to_string());
+    let mut wp_env = BTreeMap::new();
+    wp_env.insert("WORDPRESS_DB_HOST".to_string(), "db:3306".to_string());
+    wp_env.insert("WORDPRESS_DB_USER".to_string(), "wordpress".to_string());
+    wp_env.insert("WORDPRESS_DB_PASSWORD".to_string(), "wordpress".to_string());
+    wp_env.insert("WORDPRESS_DB_NAME".to_string(), "wordpress".to_string());
+    wp_env.insert("IS_DOCKER".to_string(), "1".to_string());
+    let mut cli_env = BTreeMap::new();
+    cli_env.insert("WAIT_FOR".to_string(), "db:3306".to_string());
+
+    let mut services = BTreeMap::new();
+    services.insert(
+        "db".to_string(),
+        ComposeService {
+            build: Some(server_dir("base/mysql-custom").into()),
+            restart: Some("always".into()),
+            volumes: vec!["db_data:/var/lib/mysql".into()],
+            environment: db_env,
+            ..Default::default()
+        },
+    );
+    let mut wp = ComposeService {
+        build: Some(server_dir("prod/wp-prod").into()),
+        restart: Some("always".into()),
+        depends_on: vec!["db".into()],
+        volumes: vec!["wp-root:/var/www/html".into()],
+        environment: wp_env,
+        ..Default::default()
+    };
+    wp.add_build_args(versions::build_args(
+        project.wp_version.as_ref().map(String::as_str),
+        project.php_version.as_ref().map(String::as_str),
+        false,
+    ));
+    services.insert(wp_service(slug), wp);
+    services.insert(
+        "wp-cli".to_string(),
+        ComposeService {
+            build: Some(server_dir("base/wp-cli").into()),
+            depends_on: vec!["db".into(), wp_service(slug)],
+            ports: vec![format!("{}:22", preview.wp_cli_port)],
+            volumes: vec!["wp-root:/var/www/html".into()],
+            environment: cli_env,
+            ..Default::default()
+        },
+    );
+    let mut volumes = BTreeMap::new();
+    volumes.insert("db_data".to_string(), ComposeVolume::default());
+    volumes.insert("wp-root".to_string(), ComposeVolume::default());
+    let yml = ComposeYml {
+        version: "3.3".into(),
+        services,
+        volumes,
+        ..Default::default()
+    };
+    let yml_str = serde_yaml::to_string(&yml).map_err(|e| cli_err!(Other, "{:?}", e))?;
+    project.write_file(env, &format!("docker/{}", compose_file(slug)), &yml_str)?;
+    println!("Wrote {}", compose_file(slug));
+    Ok(())
+}
+
+/// Regenerates prod.yml with current previews,
+/// and recreates the proxy to join or leave networks
+fn update_prod_proxy(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    server: &ServerConfig,
+    conn: &SshConn,
+) -> Result<()> {
+    project::sync_prod_compose(env, project, server, conn)?;
+    conn.exec(format!(
+        "cd {}; docker-compose {} up -d proxy",
+        project::server_project_dir(project, server),
+        project::prod_compose_args(project).join(" ")
+    ))?;
+    Ok(())
+}
+
+/// Checks out branch and starts it as a preview
+/// environment on the project server, with a clone
+/// of the prod database
+pub fn preview_up(env: &CliEnv, mut project: ProjectConfig, branch: &str) -> Result<()> {
+    let server = project.require_server(env)?;
+    let slug = utils::slug(branch);
+    if slug.is_empty() {
+        return Err(cli_err!(
+            Config,
+            "Could not make preview name from: {}",
+            branch
+        ));
+    }
+    let preview = match project.previews.get(&slug) {
+        Some(preview) => preview.clone(),
+        None => {
+            // Avoiding ports of other sites on the server
+            let mut used = site::used_ports(env, &server.name, &project.deployment_name())?;
+            used.extend(project.previews.values().map(|p| p.wp_cli_port));
+            if let Some(site) = &project.site {
+                used.push(site.wp_cli_port);
+            }
+            let mut wp_cli_port = FIRST_WP_CLI_PORT;
+            while used.contains(&wp_cli_port) || used.contains(&(wp_cli_port + 1)) {
+                wp_cli_port += 2;
+            }
+            PreviewConfig {
+                branch: branch.to_string(),
+                wp_cli_port,
+            }
+        }
+    };
+    // Fresh checkout of the branch
+    let checkout_dir = std::env::temp_dir().join(format!("{}-preview-{}", project.name, slug));
+    if checkout_dir.is_dir() {
+        std::fs::remove_dir_all(&checkout_dir)?;
+    }
+    let checkout = Checkout(checkout_dir.clone());
+    let git_config = git::get_config(env, &project.git_user).ok();
+    git::clone_branch(
+        git_config.as_ref(),
+        &project.git_repo_uri,
+        Some(branch),
+        &checkout_dir,
+    )
+    .map_err(|e| er::git_msg(format!("Could not check out {}", branch), e))?;
+
+    // Start containers
+    create_preview_yml(env, &project, &server, &slug, &preview)?;
+    let conn = SshConn::connect(env, &server)?;
+    project::sync_docker_file(env, &project, &server, &conn, &compose_file(&slug))?;
+    let cd = format!("cd {}", project::server_project_dir(&project, &server));
+    let status = conn.exec(format!(
+        "{}; {}",
+        cd,
+        compose_cmd(&project, &slug, "up -d --build")
+    ))?;
+    if status != 0 {
+        return Err(cli_err!(Docker, "Failed starting preview containers"));
+    }
+
+    // Clone prod database when preview db accepts connections
+    println!("Cloning database");
+    let wait_db = format!(
+        "tries=0; until {} > /dev/null 2>&1; do \
+            tries=$((tries+1)); \
+            if [ $tries -ge {} ]; then echo 'Preview db did not start' >&2; exit 1; fi; \
+            sleep 2; \
+        done",
+        compose_cmd(
+            &project,
+            &slug,
+            "exec -T db mysqladmin ping -uwordpress -pwordpress --silent"
+        ),
+        DB_WAIT_TRIES
+    );
+    let dump = format!(
+        "docker-compose {} exec -T db mysqldump --no-tablespaces -uwordpress -pwordpress wordpress",
+        project::prod_compose_args(&project).join(" ")
+    );
+    let import = compose_cmd(
+        &project,
+        &slug,
+        "exec -T db mysql -uwordpress -pwordpress wordpress",
+    );
+    let status = conn.exec(format!("{} && {} && {} | {}", cd, wait_db, dump, import))?;
+    if status != 0 {
+        return Err(cli_err!(Db, "Failed cloning database"));
+    }
+    drop(conn);
+
+    // Sync branch files and point site to preview host
+    let local_data = wp::local_site_data_in(checkout_dir.clone())?;
+    let cli_conn = SshConn::connect_wp_cli(env, preview.wp_cli_port, Some(&server))?;
+    wp::sync_files_to_prod(&cli_conn, &local_data)?;
+    let site_url = format!("http://{}", wp_service(&slug));
+    cli_conn.exec_capture(
+        format!(
+            "wp option update siteurl {} && wp option update home {}",
+            site_url, site_url
+        ),
+        Some("/var/www/html"),
+    )?;
+    wp::activate_local(&cli_conn, local_data)?;
+    drop(cli_conn);
+    drop(checkout);
+
+    // Route through proxy
+    project.previews.insert(slug.clone(), preview);
+    project::write_config(env, &project)?;
+    let conn = SshConn::connect(env, &server)?;
+    update_prod_proxy(env, &project, &server, &conn)?;
+    match project.external_url(&server) {
+        Some(external) => println!(
+            "Preview at: {}",
+            console::style(format!("{}/preview/{}/", external, slug)).green()
+        ),
+        None => println!("Preview at: /preview/{}/", slug),
+    }
+    Ok(())
+}
+
+/// Removes preview environment of branch,
+/// including its volumes
+pub fn preview_down(env: &CliEnv, mut project: ProjectConfig, branch: &str) -> Result<()> {
+    let server = project.require_server(env)?;
+    // Allow both branch and slug
+    let slug = utils::slug(branch);
+    if project.previews.remove(&slug).is_none() {
+        return Err(cli_err!(Config, "No preview found for: {}", branch));
+    }
+    project::write_config(env, &project)?;
+    let conn = SshConn::connect(env, &server)?;
+    // Proxy must leave the network before it is removed
+    update_prod_proxy(env, &project, &server, &conn)?;
+    conn.exec(format!(
+        "cd {}; {} && rm docker/{}",
+        project::server_project_dir(&project, &server),
+        compose_cmd(&project, &slug, "down --volumes"),
+        compose_file(&slug)
+    ))?;
+    let local_yml = project.dir_and(env, &format!("docker/{}", compose_file(&slug)));
+    if local_yml.is_file() {
+        std::fs::remove_file(local_yml)?;
+    }
+    println!("Removed preview: {}", slug);
+    Ok(())
+}
+
+/// Lists previews of project
+pub fn preview_list(project: &ProjectConfig) {
+    if project.previews.is_empty() {
+        println!("No previews");
+    }
+    for (slug, preview) in &project.previews {
+        println!(
+            "{}: {} (wp-cli port {})",
+            slug, preview.branch, preview.wp_cli_port
+        );
+    }
+}
@@ -1,24 +1,70 @@
-use crate::er::{self, Result};
+use crate::acme::TlsConfig;
+use crate::backup::OffsiteConfig;
+use crate::compose::{self, ComposeEnv};
+use crate::config::{self, ConfigKind};
+use crate::environment::{self, EnvConfig};
+use crate::er::{self, CliError, Result, ResultExt};
 use crate::git;
+use crate::hooks::CommandHooks;
+use crate::modules;
+use crate::preview::PreviewConfig;
 use crate::server;
+use crate::site::{self, SiteConfig};
 use crate::utils::{self, CliEnv};
-use failure::{format_err, Error};
+use failure::Error;
 use futures::{
     future::{self, Either},
     Future,
 };
 use serde::{Deserialize, Serialize};
 use server::SshConn;
-use std::fs;
-use std::io;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct ProjectConfig {
     pub name: String,
     pub git_repo_uri: String,
     pub git_user: String,
     pub server_name: String,
+    /// Preview environments on server, by slug
+    #[serde(default)]
+    pub previews: BTreeMap<String, PreviewConfig>,
+    /// Offsite backups to S3 compatible storage
+    #[serde(default)]
+    pub backup_offsite: Option<OffsiteConfig>,
+    /// Set when the project is one of several
+    /// sites on its server
+    #[serde(default)]
+    pub site: Option<SiteConfig>,
+    /// Public domain of prod
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Other host names of prod
+    #[serde(default)]
+    pub domain_aliases: Vec<String>,
+    /// Https with certificates from an acme server
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Server environments other than prod, by name
+    #[serde(default)]
+    pub environments: BTreeMap<String, EnvConfig>,
+    /// Wordpress image version, like "5.3"
+    #[serde(default)]
+    pub wp_version: Option<String>,
+    /// Php version of the wordpress image, like "7.4"
+    #[serde(default)]
+    pub php_version: Option<String>,
+    /// Environment module, wordpress when not set
+    #[serde(default)]
+    pub module: Option<String>,
+    /// Shell commands run before and after cli
+    /// commands, by command like "dev" or "wp sync-local"
+    #[serde(default)]
+    pub hooks: BTreeMap<String, CommandHooks>,
+    /// Set on views of the project in a named environment
+    #[serde(skip)]
+    pub env_name: Option<String>,
 }
 
 impl ProjectConfig {
@@ -32,6 +78,90 @@ impl ProjectConfig {
         path
     }
 
+    /// Project as deployed in a named environment, with its
+    /// server and settings. Prod is the project itself,
+    /// previews and backups are of prod only
+    pub fn in_env(&self, name: &str) -> Result<ProjectConfig> {
+        match name {
+            environment::PROD => Ok(self.clone()),
+            environment::DEV => Err(cli_err!(Config, "Dev runs locally, not on a server")),
+            _ => match self.environments.get(name) {
+                Some(env_config) => Ok(ProjectConfig {
+                    server_name: env_config.server_name.clone(),
+                    previews: BTreeMap::new(),
+                    backup_offsite: None,
+                    site: env_config.site.clone(),
+                    domain: env_config.domain.clone(),
+                    domain_aliases: env_config.domain_aliases.clone(),
+                    tls: env_config.tls.clone(),
+                    env_name: Some(name.to_string()),
+                    ..self.clone()
+                }),
+                None => Err(cli_err!(
+                    Config,
+                    "No environment {}, add it with `env add {}`",
+                    name,
+                    name
+                )),
+            },
+        }
+    }
+
+    /// Name of the project in its environment,
+    /// separating deployments on a shared server
+    pub fn deployment_name(&self) -> String {
+        match &self.env_name {
+            Some(env_name) => format!("{}-{}", self.name, env_name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Compose overlay of environment, relative to project dir
+    pub fn compose_overlay(&self) -> Option<String> {
+        self.env_name
+            .as_ref()
+            .and_then(|env_name| self.environments.get(env_name))
+            .and_then(|env_config| env_config.compose_overlay.as_ref())
+            .map(|overlay| format!("docker/{}", overlay))
+    }
+
+    /// Compose project name on server, scoped to the
+    /// project when it is one of several sites
+    pub fn compose_project(&self) -> String {
+        match &self.site {
+            Some(site) => site
+                .compose_project
+                .clone()
+                .unwrap_or_else(|| site::compose_project_name(&self.deployment_name())),
+            None => compose::PROJECT_NAME.to_string(),
+        }
+    }
+
+    /// Domain and aliases, the names of the prod site
+    pub fn hosts(&self) -> Vec<String> {
+        let mut hosts = self.domain.iter().cloned().collect::<Vec<_>>();
+        hosts.extend(self.domain_aliases.iter().cloned());
+        hosts
+    }
+
+    /// Url of prod, by domain when set, otherwise elastic ip
+    pub fn external_url(&self, server: &server::ServerConfig) -> Option<String> {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        match (&self.domain, &server.elastic_ip) {
+            (Some(domain), _) => Some(format!("{}://{}", scheme, domain)),
+            (None, Some(elastic_ip)) => Some(format!("http://{}", elastic_ip.public_ip)),
+            (None, None) => None,
+        }
+    }
+
+    /// Wp-cli ssh port on server
+    pub fn prod_wp_cli_port(&self) -> u16 {
+        match &self.site {
+            Some(site) => site.wp_cli_port,
+            None => 2345,
+        }
+    }
+
     pub fn get_server(&self, env: &CliEnv) -> Option<server::ServerConfig> {
         match server::get_config(env, &self.server_name) {
             Ok(server_config) => Some(server_config),
@@ -39,13 +169,20 @@ impl ProjectConfig {
         }
     }
 
+    /// Server of project, as config error when missing
+    pub fn require_server(&self, env: &CliEnv) -> Result<server::ServerConfig> {
+        server::get_config(env, &self.server_name).or_cli(
+            CliError::Config,
+            format!("Missing server {} of project config", self.server_name),
+        )
+    }
+
     /// Writes to a file given a path relative to
     /// project root
-    pub fn write_file(&self, env: &CliEnv, file: &str, content: &str) -> io::Result<()> {
+    pub fn write_file(&self, env: &CliEnv, file: &str, content: &str) -> Result<()> {
         let mut file_path = self.dir(env);
         file_path.push(file);
-        utils::ensure_parent_dir(&file_path)?;
-        fs::write(file_path, content)
+        utils::write_file(&file_path, content)
     }
 }
 
@@ -60,7 +197,7 @@ pub fn has_config(env: &CliEnv, project: &str) -> bool {
 // (not so composy)
 // or something lessen boilerplate a little
 // shorter name
-pub fn resolve_current_project_interactive(env: &CliEnv) -> io::Result<ProjectConfig> {
+pub fn resolve_current_project_interactive(env: &CliEnv) -> Result<ProjectConfig> {
     match resolve_current_project(env) {
         Ok(project_confir) => Ok(project_confir),
         Err(_e) => {
@@ -72,51 +209,74 @@ pub fn resolve_current_project_interactive(env: &CliEnv) -> io::Result<ProjectCo
                 println!("Only one project, selecting {}!", projects[0]);
                 get_config(env, &projects[0])
             } else {
-                env.select("Select project", &projects, None)
-                    .and_then(|i| match projects.get(i) {
-                        Some(project_name) => get_config(env, project_name),
-                        None => utils::io_err("Error selecting project"),
-                    })
+                let i = env
+                    .select("Select project", &projects, None)
+                    .map_err(er::io)?;
+                match projects.get(i) {
+                    Some(project_name) => get_config(env, project_name),
+                    None => Err(cli_err!(Other, "Error selecting project")),
+                }
             }
         }
     }
 }
 
-/// Resolve project from current directory, or error
-pub fn resolve_current_project(env: &CliEnv) -> io::Result<ProjectConfig> {
-    let cd = std::env::current_dir()?;
+/// Name of project dir the current directory is in, or error
+pub fn current_project_name(env: &CliEnv) -> Result<String> {
+    let cd = std::env::current_dir().map_err(er::io)?;
     let cd = cd
         .strip_prefix(&env.projects_dir)
-        .map_err(|e| utils::io_error(format!("{:?}", e)))?;
+        .map_err(|e| cli_err!(Config, "Not in projects dir: {:?}", e))?;
     // Then use first component
     match cd.components().next() {
-        Some(std::path::Component::Normal(os_str)) => {
-            match get_config(env, &os_str.to_string_lossy()) {
-                Ok(project_config) => Ok(project_config),
-                Err(e) => utils::io_err(format!("Could not resolve project config: {:?}", e)),
-            }
-        }
-        _ => utils::io_err("Could not resolve project dir"),
+        Some(std::path::Component::Normal(os_str)) => Ok(os_str.to_string_lossy().to_string()),
+        _ => Err(cli_err!(Config, "Could not resolve project dir")),
     }
 }
 
-pub fn get_config(env: &CliEnv, project: &str) -> io::Result<ProjectConfig> {
-    let json_file = std::fs::File::open(env.config_dirs.projects.filepath(project))?;
-    let buf_reader = io::BufReader::new(json_file);
-    let config = serde_json::from_reader::<_, ProjectConfig>(buf_reader)?;
-    Ok(config)
+/// Resolve project from current directory, or error
+pub fn resolve_current_project(env: &CliEnv) -> Result<ProjectConfig> {
+    let name = current_project_name(env)?;
+    match get_config(env, &name) {
+        Ok(project_config) => Ok(project_config),
+        Err(e) => Err(cli_err!(Config, "Could not resolve project config: {}", e)),
+    }
+}
+
+pub fn get_config(env: &CliEnv, project: &str) -> Result<ProjectConfig> {
+    config::read(
+        ConfigKind::Project,
+        &env.config_dirs.projects.filepath(project),
+    )
+}
+
+/// Writes project config. Settings of an environment
+/// view go into its entry of the project config
+pub fn write_config(env: &CliEnv, config: &ProjectConfig) -> Result<()> {
+    if let Some(env_name) = &config.env_name {
+        let mut base = get_config(env, &config.name)?;
+        let overlay = base
+            .environments
+            .get(env_name)
+            .and_then(|env_config| env_config.compose_overlay.clone());
+        base.environments
+            .insert(env_name.clone(), EnvConfig::from_view(config, overlay));
+        return write_config(env, &base);
+    }
+    let content_str = config::to_string(ConfigKind::Project, config)?;
+    env.config_dirs.projects.write(&config.name, &content_str)
 }
 
 /// Returns names of projects
-pub fn get_projects(env: &CliEnv) -> io::Result<Vec<String>> {
-    utils::files_in_dir(&env.config_dirs.projects.0)
+pub fn get_projects(env: &CliEnv) -> Result<Vec<String>> {
+    utils::files_in_dir(&env.config_dirs.projects.0).map_err(er::io)
 }
 
 pub fn project_dir(env: &CliEnv, project: &str) -> PathBuf {
     env.get_project_path(project)
 }
 
-fn init_project_config(env: &CliEnv) -> io::Result<(ProjectConfig, git::InspectGit)> {
+fn init_project_config(env: &CliEnv) -> Result<(ProjectConfig, git::InspectGit)> {
     let name = env.get_input("Project name", None)?;
     let current_config = if has_config(env, &name) {
         println!("Project config exists for: {}", &name);
@@ -144,7 +304,7 @@ fn init_project_config(env: &CliEnv) -> io::Result<(ProjectConfig, git::InspectG
             origin_url.to_owned()
         }
         None => {
-            let user_uri = format!("https://github.com/{}", &git_user);
+            let user_uri = format!("{}/{}", git_account.provider.web_base(), &git_user);
             let mut repo_type_options = vec![
                 format!(
                     "User repo ({})",
@@ -165,13 +325,13 @@ fn init_project_config(env: &CliEnv) -> io::Result<(ProjectConfig, git::InspectG
                 0 => {
                     // User repo
                     let repo_name = env.get_input(&format!("User repo {}/", &user_uri), None)?;
-                    format!("{}/{}", &user_uri, &repo_name)
+                    git_account.repo_uri(repo_name)
                 }
                 1 => {
                     // Full repo uri
                     env.get_input("Repo uri", None)?
                 }
-                _ => return utils::io_err("Unrecognized select"),
+                _ => return Err(cli_err!(Other, "Unrecognized select")),
             }
         }
     };
@@ -185,10 +345,11 @@ fn init_project_config(env: &CliEnv) -> io::Result<(ProjectConfig, git::InspectG
                 .as_ref()
                 .and_then(|c| servers.iter().position(|e| *e == c.server_name)),
         )
+        .map_err(er::io)
         .and_then(|i| {
             servers
                 .get(i)
-                .ok_or_else(|| utils::io_error("Could not resolve server"))
+                .ok_or_else(|| cli_err!(Other, "Could not resolve server"))
         })?
         .to_owned();
 
@@ -197,16 +358,16 @@ fn init_project_config(env: &CliEnv) -> io::Result<(ProjectConfig, git::InspectG
         git_repo_uri,
         git_user,
         server_name,
+        // Settings of other commands are kept
+        ..current_config.unwrap_or_default()
     };
     println!("{:?}", &config);
 
-    let content_str = serde_json::to_string_pretty(&config)?;
-
     // Todo: Consider keeping some in <project>/.project
     // Possibly this should be done after git setup after
     // this function, but might also be advantages saving the
     // data. The logic should handle it if re-running
-    env.config_dirs.projects.write(&config.name, &content_str)?;
+    write_config(env, &config)?;
     Ok((config, project_git))
 }
 
@@ -215,12 +376,12 @@ fn init_project_config(env: &CliEnv) -> io::Result<(ProjectConfig, git::InspectG
 pub fn init_cmd<'a>(env: &'a CliEnv) -> impl Future<Item = (), Error = Error> + 'a {
     let (config, project_git) = match init_project_config(env) {
         Ok(config) => config,
-        Err(io_err) => return Either::A(future::err(Error::from(io_err))),
+        Err(e) => return Either::A(future::err(e)),
     };
     // Get projects git account
     let git_config = match git::get_config(env, &config.git_user) {
         Ok(git_config) => git_config,
-        Err(io_err) => return Either::A(future::err(Error::from(io_err))),
+        Err(e) => return Either::A(future::err(e)),
     };
     Either::B(git::setup_git_dir(
         env,
@@ -230,53 +391,195 @@ pub fn init_cmd<'a>(env: &'a CliEnv) -> impl Future<Item = (), Error = Error> +
     ))
 }
 
-/// Prod container command through ssh
-pub fn prod(env: &CliEnv, project: &ProjectConfig, mut user_args: Vec<String>) -> Result<()> {
-    // Sync docker file
-    crate::wp::create_docker_prod_yml(env, &project)?;
-    let server = match project.get_server(env) {
-        Some(server) => server,
-        None => {
-            return Err(format_err!(
-                "Missing server in project config, required for prod"
-            ))
+/// Opens the project repository, along with the
+/// project's git account when available
+fn open_project_repo(
+    env: &CliEnv,
+    project: &ProjectConfig,
+) -> Result<(git2::Repository, Option<git::GitConfig>)> {
+    let repo = match git2::Repository::open(project.dir(env)) {
+        Ok(repo) => repo,
+        Err(e) => {
+            env.error_msg("Could not open project repository, try running init");
+            return Err(er::git_msg("Open repository failed", e));
+        }
+    };
+    let git_config = match git::get_config(env, &project.git_user) {
+        Ok(git_config) => Some(git_config),
+        Err(e) => {
+            eprintln!("Could not get git account {}: {:?}", project.git_user, e);
+            None
         }
     };
+    Ok((repo, git_config))
+}
+
+/// Prints branch, changed files and commits
+/// ahead/behind origin from last fetch
+pub fn git_status(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    let (repo, _git_config) = open_project_repo(env, project)?;
+    let branch = git::current_branch(&repo)?;
+    println!("On branch {}", console::style(&branch).green());
+    match (
+        git::head_commit(&repo)?,
+        repo.find_reference(&format!("refs/remotes/origin/{}", branch)),
+    ) {
+        (Some(head), Ok(tracking)) => match tracking.target() {
+            Some(tracking_oid) => {
+                let (ahead, behind) = repo
+                    .graph_ahead_behind(head.id(), tracking_oid)
+                    .map_err(er::git)?;
+                println!("Ahead {}, behind {} origin/{}", ahead, behind, branch);
+            }
+            None => (),
+        },
+        (None, _) => println!("No commits yet"),
+        (_, Err(_)) => println!("Branch not yet on origin"),
+    }
+    let mut status_options = git2::StatusOptions::new();
+    status_options.include_untracked(true);
+    let statuses = repo.statuses(Some(&mut status_options)).map_err(er::git)?;
+    if statuses.is_empty() {
+        println!("No changes");
+    }
+    for entry in statuses.iter() {
+        let status = entry.status();
+        let label = if status.intersects(git2::Status::WT_NEW | git2::Status::INDEX_NEW) {
+            "New"
+        } else if status.intersects(git2::Status::WT_DELETED | git2::Status::INDEX_DELETED) {
+            "Deleted"
+        } else if status.intersects(git2::Status::WT_RENAMED | git2::Status::INDEX_RENAMED) {
+            "Renamed"
+        } else if status.is_conflicted() {
+            "Conflicted"
+        } else {
+            "Modified"
+        };
+        println!(
+            "{:>10}: {}",
+            label,
+            entry.path().unwrap_or("<non-utf8 path>")
+        );
+    }
+    Ok(())
+}
+
+/// Adds all changes and commits on current branch
+pub fn git_commit(env: &CliEnv, project: &ProjectConfig, message: Option<String>) -> Result<()> {
+    let (repo, _git_config) = open_project_repo(env, project)?;
+    let tree = git::add_all(&repo)?;
+    let message = match message {
+        Some(message) => message,
+        None => env.get_input(
+            "Message",
+            Some(format!(
+                "{} commit: {}",
+                project.name,
+                utils::now_formatted()
+            )),
+        )?,
+    };
+    git::commit(&repo, tree, &message)?;
+    Ok(())
+}
+
+/// Pushes current branch to origin using the
+/// project's git account
+pub fn git_push(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    let (repo, git_config) = open_project_repo(env, project)?;
+    git::push_origin(&repo, git_config.as_ref())?;
+    Ok(())
+}
+
+/// Compose file arguments for prod, relative
+/// to project dir on server
+pub fn prod_compose_args(project: &ProjectConfig) -> Vec<String> {
+    ComposeEnv::Prod.args_for(&project.compose_project())
+}
+
+/// Resolves prod compose file of environment, with paths
+/// on server, adapted to site setup if any
+pub fn resolve_prod(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    server: &server::ServerConfig,
+    compose_env: ComposeEnv,
+) -> Result<compose::ComposeYml> {
+    let server_base = server.home_dir_and("workdir/server/base");
+    let mut compose = compose::resolve(env, &project.dir(env), compose_env, &server_base)?;
+    if let Some(overlay) = project.compose_overlay() {
+        let overlay_file = project.dir_and(env, &overlay);
+        if overlay_file.is_file() {
+            compose.merge(compose::ComposeYml::load(&overlay_file, &server_base)?);
+        }
+    }
+    site::apply(project, &mut compose);
+    modules::of(project)?.apply(project, &mut compose, false);
+    Ok(compose)
+}
+
+/// Regenerates docker/prod.yml, then writes resolved prod compose
+/// files, with and without backup service, and syncs them to server
+pub fn sync_prod_compose(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    server: &server::ServerConfig,
+    conn: &SshConn,
+) -> Result<()> {
+    modules::of(project)?.gen_prod(env, project)?;
+    for compose_env in &[ComposeEnv::Prod, ComposeEnv::ProdBackup] {
+        let compose = resolve_prod(env, project, server, *compose_env)?;
+        compose::write_validated(&project.dir(env), *compose_env, &compose)?;
+        let file = compose_env.file().trim_start_matches("docker/");
+        sync_docker_file(env, project, server, conn, file)?;
+    }
+    Ok(())
+}
+
+pub fn prod(env: &CliEnv, project: &ProjectConfig, mut user_args: Vec<String>) -> Result<()> {
+    let server = project.require_server(env)?;
     let conn = SshConn::connect(env, &server)?;
-    let sftp = conn.sftp()?;
-    crate::server::SyncSet::from_file(
-        project.dir_and(env, "docker/prod.yml"),
-        server.home_dir_and(&format!("projects/{}/docker", project.name)),
-        &sftp,
-        false,
-    )?
-    .sync_plain(&sftp)?;
-    drop(sftp);
+    // Sync docker files
+    sync_prod_compose(env, project, &server, &conn)?;
     // Start docker
     // todo: Consider some better path handling
     // Currently expecting to be called from project dir,
     // Could consider absolute paths also
-    let mut args: Vec<String> = [
-        "../../workdir/server/base/docker-compose.yml",
-        "../../workdir/server/prod/docker-compose.prod.yml",
-        "../../workdir/server/base/docker-reimage.yml",
-        "docker/prod.yml",
-    ]
-    .into_iter()
-    .flat_map(|e| vec!["-f", e])
-    .map(|e| String::from(e))
-    .collect();
+    let mut args = prod_compose_args(project);
     // Apply user_args or default to "up"
     if user_args.len() > 0 {
         args.append(&mut user_args);
     } else {
         args.push("up".into());
     }
-    let cd = format!(
-        "cd {}/projects/{}",
-        server.home_dir().to_string_lossy(),
-        project.name
-    );
+    let cd = format!("cd {}", server_project_dir(project, &server));
     conn.exec(format!("{}; docker-compose {}", cd, args.join(" ")))?;
     Ok(())
 }
+
+/// Project directory on server
+pub fn server_project_dir(project: &ProjectConfig, server: &server::ServerConfig) -> String {
+    server
+        .home_dir_and(&format!("projects/{}", project.deployment_name()))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Syncs a file in the project docker folder to server
+pub fn sync_docker_file(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    server: &server::ServerConfig,
+    conn: &SshConn,
+    file: &str,
+) -> Result<()> {
+    let sftp = conn.sftp()?;
+    crate::server::SyncSet::from_file(
+        project.dir_and(env, &format!("docker/{}", file)),
+        PathBuf::from(server_project_dir(project, server)).join("docker"),
+        &sftp,
+        false,
+    )?
+    .sync_plain(&sftp)?;
+    Ok(())
+}
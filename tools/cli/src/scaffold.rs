@@ -0,0 +1,301 @@
+// Generators for new plugins and themes in the project
+// plugins/ and themes/ folders, where they are picked
+// up by wp::get_local_site_data
+
+use crate::docker;
+use crate::er::Result;
+use crate::project::ProjectConfig;
+use crate::utils::{self, CliEnv};
+use crate::wp;
+use std::path::PathBuf;
+
+/// Prefix for php functions, "my-plugin" -> "my_plugin"
+fn fn_prefix(slug: &str) -> String {
+    slug.replace('-', "_")
+}
+
+/// "my-plugin" -> "My Plugin", when name is given as slug
+fn title(name: &str) -> String {
+    if name.contains(' ') {
+        return name.to_string();
+    }
+    name.split(|c| c == '-' || c == '_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Files relative to the new item dir, with content
+type Files = Vec<(String, String)>;
+
+fn write_files(dir: &PathBuf, files: Files) -> Result<()> {
+    for (file, content) in files {
+        let path = dir.join(&file);
+        utils::write_file(&path, &content)?;
+        println!("Created {}", path.to_string_lossy());
+    }
+    Ok(())
+}
+
+fn new_item_dir(env: &CliEnv, project: &ProjectConfig, kind: &str, slug: &str) -> Result<PathBuf> {
+    if slug.is_empty() {
+        return Err(cli_err!(Config, "Name must contain letters or numbers"));
+    }
+    let dir = project.dir_and(env, &format!("{}s/{}", kind, slug));
+    if dir.exists() {
+        return Err(cli_err!(
+            Config,
+            "A {} named {} exists already: {:?}",
+            kind,
+            slug,
+            dir
+        ));
+    }
+    Ok(dir)
+}
+
+/// package.json using @wordpress/scripts, which
+/// builds src/index.js into build/
+fn build_files(slug: &str) -> Files {
+    let package_json = serde_json::json!({
+        "name": slug,
+        "version": "0.1.0",
+        "private": true,
+        "scripts": {
+            "build": "wp-scripts build",
+            "start": "wp-scripts start"
+        },
+        "devDependencies": {
+            "@wordpress/scripts": "^5.0.0"
+        }
+    });
+    vec![
+        (
+            "package.json".into(),
+            serde_json::to_string_pretty(&package_json).unwrap_or_default() + "\n",
+        ),
+        (
+            "src/index.js".into(),
+            format!("console.log('{} loaded');\n", slug),
+        ),
+        (".gitignore".into(), "node_modules/\n".into()),
+    ]
+}
+
+/// Php enqueueing build/index.js when built
+fn enqueue_build_php(prefix: &str, hook: &str, url_fn: &str, dir_fn: &str) -> String {
+    format!(
+        r#"
+function {prefix}_enqueue_build() {{
+    $asset_file = {dir_fn} . '/build/index.asset.php';
+    if ( ! file_exists( $asset_file ) ) {{
+        return;
+    }}
+    $asset = include $asset_file;
+    wp_enqueue_script(
+        '{prefix}',
+        {url_fn} . '/build/index.js',
+        $asset['dependencies'],
+        $asset['version'],
+        true
+    );
+}}
+add_action( '{hook}', '{prefix}_enqueue_build' );
+"#,
+        prefix = prefix,
+        hook = hook,
+        url_fn = url_fn,
+        dir_fn = dir_fn
+    )
+}
+
+fn plugin_files(name: &str, slug: &str, build: bool) -> Files {
+    let prefix = fn_prefix(slug);
+    let mut php = format!(
+        r#"<?php
+/**
+ * Plugin Name: {title}
+ * Description: {title} plugin
+ * Version: 0.1.0
+ * Text Domain: {slug}
+ */
+
+if ( ! defined( 'ABSPATH' ) ) {{
+    exit;
+}}
+"#,
+        title = title(name),
+        slug = slug
+    );
+    let mut files = Vec::new();
+    if build {
+        php.push_str(&enqueue_build_php(
+            &prefix,
+            "wp_enqueue_scripts",
+            "plugins_url( '', __FILE__ )",
+            "__DIR__",
+        ));
+        files.append(&mut build_files(slug));
+    }
+    // Read by get_local_site_data, deps are
+    // installed and activated with sync_local
+    let plugin_json = serde_json::json!({ "deps": [] });
+    files.push((format!("{}.php", slug), php));
+    files.push((
+        "plugin.json".into(),
+        serde_json::to_string_pretty(&plugin_json).unwrap_or_default() + "\n",
+    ));
+    files
+}
+
+fn theme_files(name: &str, slug: &str, child_of: Option<&str>, build: bool) -> Files {
+    let prefix = fn_prefix(slug);
+    let template = match child_of {
+        Some(parent) => format!("\nTemplate: {}", parent),
+        None => String::new(),
+    };
+    let style = format!(
+        "/*\nTheme Name: {title}\nDescription: {title} theme{template}\nVersion: 0.1.0\nText Domain: {slug}\n*/\n",
+        title = title(name),
+        template = template,
+        slug = slug
+    );
+    let mut functions = String::from("<?php\n");
+    match child_of {
+        Some(_) => functions.push_str(&format!(
+            r#"
+function {prefix}_enqueue_styles() {{
+    wp_enqueue_style( 'parent-style', get_template_directory_uri() . '/style.css' );
+    wp_enqueue_style( '{slug}-style', get_stylesheet_uri(), array( 'parent-style' ) );
+}}
+add_action( 'wp_enqueue_scripts', '{prefix}_enqueue_styles' );
+"#,
+            prefix = prefix,
+            slug = slug
+        )),
+        None => functions.push_str(&format!(
+            r#"
+function {prefix}_setup() {{
+    add_theme_support( 'title-tag' );
+    add_theme_support( 'post-thumbnails' );
+}}
+add_action( 'after_setup_theme', '{prefix}_setup' );
+
+function {prefix}_enqueue_styles() {{
+    wp_enqueue_style( '{slug}-style', get_stylesheet_uri() );
+}}
+add_action( 'wp_enqueue_scripts', '{prefix}_enqueue_styles' );
+"#,
+            prefix = prefix,
+            slug = slug
+        )),
+    }
+    let mut files = Vec::new();
+    if build {
+        functions.push_str(&enqueue_build_php(
+            &prefix,
+            "wp_enqueue_scripts",
+            "get_stylesheet_directory_uri()",
+            "get_stylesheet_directory()",
+        ));
+        files.append(&mut build_files(slug));
+    }
+    files.push(("style.css".into(), style));
+    files.push(("functions.php".into(), functions));
+    // Child themes fall back to parent templates
+    if child_of.is_none() {
+        files.push((
+            "index.php".into(),
+            r#"<?php get_header(); ?>
+
+<main>
+<?php
+if ( have_posts() ) {
+    while ( have_posts() ) {
+        the_post();
+        the_title( '<h2>', '</h2>' );
+        the_content();
+    }
+}
+?>
+</main>
+
+<?php get_footer(); ?>
+"#
+            .into(),
+        ));
+    }
+    files
+}
+
+/// Recreates dev containers with mounts of the new item,
+/// and activates local plugins and themes. As dev might not
+/// be running, errors are only reported
+fn mount_and_activate(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    docker::dev_cmd(
+        env,
+        utils::CurrentProcess::new(),
+        project.clone(),
+        vec![
+            "up".to_string(),
+            "-d".to_string(),
+            "wordpress-container".to_string(),
+            "wp-cli".to_string(),
+        ],
+    )?;
+    wp::sync_local(env, project.clone(), false)
+}
+
+fn activation_hint(e: failure::Error) {
+    eprintln!("Could not activate in dev: {}", e);
+    println!("When dev is running, activate with `wp sync-local`");
+}
+
+/// Creates plugins/<slug> with plugin header, plugin.json,
+/// and optionally build tooling, then mounts and activates it
+pub fn new_plugin(env: &CliEnv, project: ProjectConfig, name: &str, build: bool) -> Result<()> {
+    let slug = utils::slug(name);
+    let dir = new_item_dir(env, &project, "plugin", &slug)?;
+    write_files(&dir, plugin_files(name, &slug, build))?;
+    if let Err(e) = mount_and_activate(env, &project) {
+        activation_hint(e);
+    }
+    Ok(())
+}
+
+/// Creates themes/<slug> with style.css header, functions.php,
+/// and optionally build tooling, then mounts and activates it.
+/// Parent themes not in the project are installed from wordpress.org
+pub fn new_theme(
+    env: &CliEnv,
+    project: ProjectConfig,
+    name: &str,
+    child_of: Option<&str>,
+    build: bool,
+) -> Result<()> {
+    let slug = utils::slug(name);
+    let dir = new_item_dir(env, &project, "theme", &slug)?;
+    write_files(&dir, theme_files(name, &slug, child_of, build))?;
+    let activate = || -> Result<()> {
+        mount_and_activate(env, &project)?;
+        let cli_conn = wp::wp_cli_conn(env, &project, false)?;
+        if let Some(parent) = child_of {
+            let local_data = wp::get_local_site_data(env, &project)?;
+            if !local_data.themes.contains_key(parent) {
+                wp::install_theme(&cli_conn, parent)?;
+            }
+        }
+        wp::activate_theme(&cli_conn, &slug)
+    };
+    if let Err(e) = activate() {
+        activation_hint(e);
+    }
+    Ok(())
+}
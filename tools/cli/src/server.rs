@@ -1,6 +1,6 @@
-use crate::er::{self, FailExt, Result};
+use crate::config::{self, ConfigKind};
+use crate::er::{self, Result};
 use crate::utils::{self, CliEnv};
-use failure::format_err;
 use failure::ResultExt;
 use serde::{Deserialize, Serialize};
 use std::io;
@@ -42,31 +42,33 @@ pub fn has_config(env: &CliEnv, server: &str) -> bool {
     env.config_dirs.servers.has_file(server)
 }
 
-pub fn get_config(env: &CliEnv, server: &str) -> io::Result<ServerConfig> {
-    let json_file = std::fs::File::open(env.config_dirs.servers.filepath(server))?;
-    let buf_reader = io::BufReader::new(json_file);
-    let config = serde_json::from_reader::<_, ServerConfig>(buf_reader)?;
-    Ok(config)
+pub fn get_config(env: &CliEnv, server: &str) -> Result<ServerConfig> {
+    config::read(
+        ConfigKind::Server,
+        &env.config_dirs.servers.filepath(server),
+    )
 }
 
-pub fn get_servers(env: &CliEnv) -> io::Result<Vec<String>> {
-    utils::files_in_dir(&env.config_dirs.servers.0)
+pub fn get_servers(env: &CliEnv) -> Result<Vec<String>> {
+    utils::files_in_dir(&env.config_dirs.servers.0).map_err(er::io)
 }
 
-pub fn select_server(env: &CliEnv) -> io::Result<ServerConfig> {
+pub fn select_server(env: &CliEnv) -> Result<ServerConfig> {
     let servers = get_servers(env)?;
-    env.select("Select server", &servers, None)
-        .and_then(|i| match servers.get(i) {
-            Some(server_name) => get_config(env, server_name),
-            None => utils::io_err("Error selecting server"),
-        })
+    let i = env
+        .select("Select server", &servers, None)
+        .map_err(er::io)?;
+    match servers.get(i) {
+        Some(server_name) => get_config(env, server_name),
+        None => Err(cli_err!(Other, "Error selecting server")),
+    }
 }
 
 /// Manually input or edit a server
 /// When running `provision`, a config will also be created
-pub fn add_server(env: &CliEnv) -> io::Result<()> {
+pub fn add_server(env: &CliEnv) -> Result<()> {
     // List current servers
-    let current_files = utils::files_in_dir(&env.config_dirs.servers.0)?;
+    let current_files = get_servers(env)?;
     if current_files.len() > 0 {
         println!("Current servers:");
         for file in current_files {
@@ -102,12 +104,8 @@ pub fn add_server(env: &CliEnv) -> io::Result<()> {
     write_config(env, config)
 }
 
-pub fn write_config(env: &CliEnv, config: ServerConfig) -> io::Result<()> {
-    let content_str = match serde_json::to_string_pretty(&config) {
-        Ok(content_str) => content_str,
-        Err(_) => return Err(io::Error::from(io::ErrorKind::InvalidData)),
-    };
-
+pub fn write_config(env: &CliEnv, config: ServerConfig) -> Result<()> {
+    let content_str = config::to_string(ConfigKind::Server, &config)?;
     env.config_dirs.servers.write(&config.name, &content_str)
 }
 
@@ -133,21 +131,22 @@ impl SshConn {
     pub fn connect(env: &CliEnv, server: &ServerConfig) -> Result<Self> {
         // http://api.libssh.org/master/libssh_tutorial.html
         println!("Connecting to {}", server.url);
-        let tcp = TcpStream::connect(&server.url)?;
+        let tcp = TcpStream::connect(&server.url)
+            .map_err(|e| er::network_msg(format!("Could not connect to {}", server.url), e))?;
         let mut session = match ssh2::Session::new() {
             Some(session) => session,
-            None => return Err(format_err!("Could not create session struct")),
+            None => return Err(cli_err!(Ssh, "Could not create session struct")),
         };
         println!("Connected");
         match session.handshake(&tcp) {
             Ok(_) => (),
-            Err(e) => return er::Ssh::msg("Failed handshake", e).err(),
+            Err(e) => return Err(er::ssh_msg("Failed handshake", e)),
         }
         // Todo: Verify public key
         // Don't know how to get this in advance
         /*let known_hosts = match session.known_hosts() {
             Ok(known_hosts) => known_hosts,
-            Err(e) => return utils::io_err(format!("Could not get known hosts: {:?}", e))
+            Err(e) => return Err(er::ssh_msg("Could not get known hosts", e))
         };*/
         //known_hosts.
         /*
@@ -179,10 +178,10 @@ impl SshConn {
         let pem_file = server.pem_path(env);
         match session.userauth_pubkey_file("ec2-user", None, &pem_file, None) {
             Ok(_) => (),
-            Err(e) => return er::Ssh::msg("Authentication failed", e).err(),
+            Err(e) => return Err(er::ssh_msg("Authentication failed", e)),
         }
         if !session.authenticated() {
-            return Err(format_err!("Authenticated failed"));
+            return Err(cli_err!(Ssh, "Authenticated failed"));
         } else {
             println!("Authenticated to server: {}", server.name);
         }
@@ -209,22 +208,23 @@ impl SshConn {
         };
         let url = format!("127.0.0.1:{}", port);
         println!("Connecting to {}", url);
-        let tcp = TcpStream::connect(&url)?;
+        let tcp = TcpStream::connect(&url)
+            .map_err(|e| er::network_msg(format!("Could not connect to {}", url), e))?;
         let mut session = match ssh2::Session::new() {
             Some(session) => session,
-            None => return Err(format_err!("Could not create session struct")),
+            None => return Err(cli_err!(Ssh, "Could not create session struct")),
         };
         println!("Connected");
         match session.handshake(&tcp) {
             Ok(_) => (),
-            Err(e) => return er::Ssh::msg("Failed handshake", e).err(),
+            Err(e) => return Err(er::ssh_msg("Failed handshake", e)),
         }
         match session.userauth_password("www-data", "www-data") {
             Ok(_) => (),
-            Err(e) => return er::Ssh::msg("Authentication failed", e).err(),
+            Err(e) => return Err(er::ssh_msg("Authentication failed", e)),
         }
         if !session.authenticated() {
-            return Err(format_err!("Authenticated failed"));
+            return Err(cli_err!(Ssh, "Authenticated failed"));
         } else {
             println!("Authenticated to wp-cli");
         }
@@ -241,7 +241,7 @@ impl SshConn {
         self.session.set_blocking(true);
         match self.session.channel_session() {
             Ok(channel) => Ok(channel),
-            Err(e) => er::Ssh::msg("Error opening channel", e).err(),
+            Err(e) => Err(er::ssh_msg("Error opening channel", e)),
         }
     }
 
@@ -270,7 +270,7 @@ impl SshConn {
             Ok(_) => (),
             Err(e) => {
                 eprintln!("Could not request pty");
-                return er::Ssh::msg("Could not request pty", e).err();
+                return Err(er::ssh_msg("Could not request pty", e));
             }
         }
         Self::update_pty_size(&mut channel);
@@ -278,7 +278,7 @@ impl SshConn {
             Ok(_) => (),
             Err(e) => {
                 eprintln!("Could not request shell");
-                return er::Ssh::msg("Could not request shell", e).err();
+                return Err(er::ssh_msg("Could not request shell", e));
             }
         }
         // Switch to raw mode for this stdin
@@ -289,7 +289,7 @@ impl SshConn {
             Ok(restorer) => restorer,
             Err(e) => {
                 eprintln!("Could not enter raw mode");
-                return er::Io::msg("Could not enter raw mode", e).err();
+                return Err(er::io_msg("Could not enter raw mode", e));
             }
         };
         let mut inp = std::io::stdin();
@@ -379,12 +379,12 @@ impl SshConn {
                                 use std::io::Error;
                                 // todo: Better detection
                                 if e.description() != "would block" {
-                                    return er::Io::msg("Read failed", e).err();
+                                    return Err(er::io_msg("Read failed", e));
                                 }
                                 break;
                             }
                             _ => {
-                                return er::Io::msg("Read failed", e).err();
+                                return Err(er::io_msg("Read failed", e));
                             }
                         }
                     }
@@ -488,13 +488,18 @@ impl SshConn {
         let mut channel = self.channel()?;
         match channel.exec(&cmd) {
             Ok(_) => (),
-            Err(e) => return er::Ssh::msg(format!("Error executing command: {}", cmd), e).err(),
+            Err(e) => return Err(er::ssh_msg(format!("Error executing command: {}", cmd), e)),
         }
         let mut captured = String::with_capacity(128);
         use std::io::Read;
-        channel.read_to_string(&mut captured)?;
+        channel
+            .read_to_string(&mut captured)
+            .map_err(|e| er::io_msg("Could not read output", e))?;
         let mut stderr_capture = String::new();
-        channel.stderr().read_to_string(&mut stderr_capture)?;
+        channel
+            .stderr()
+            .read_to_string(&mut stderr_capture)
+            .map_err(|e| er::io_msg("Could not read output", e))?;
         if stderr_capture.len() > 0 {
             eprintln!("Stderr: {}", stderr_capture);
         }
@@ -502,7 +507,8 @@ impl SshConn {
         if exit_code == 0 {
             Ok(captured)
         } else {
-            Err(format_err!(
+            Err(cli_err!(
+                Ssh,
                 "Command with non-zero exit code: {}, {}",
                 exit_code,
                 cmd
@@ -516,7 +522,7 @@ impl SshConn {
         let mut channel = self.channel()?;
         match channel.exec(&cmd) {
             Ok(_) => (),
-            Err(e) => return er::Ssh::msg(format!("Error executing command: {}", cmd), e).err(),
+            Err(e) => return Err(er::ssh_msg(format!("Error executing command: {}", cmd), e)),
         }
         let mut out = std::io::stdout();
         let mut err = std::io::stderr();
@@ -531,7 +537,7 @@ impl SshConn {
             Ok(_) => (),
             Err(e) => {
                 eprintln!("Error closing channel: {:?}", e);
-                return Err(format_err!("Error closing channel"));
+                return Err(cli_err!(Ssh, "Error closing channel"));
             }
         }
         // Wait for remote channel to close
@@ -548,7 +554,7 @@ impl SshConn {
                 }
                 Ok(status)
             }
-            Err(e) => er::Ssh::msg("Error getting exit status", e).err(),
+            Err(e) => Err(er::ssh_msg("Error getting exit status", e)),
         }
     }
 
@@ -556,7 +562,7 @@ impl SshConn {
         let sftp = self
             .session
             .sftp()
-            .map_err(|e| er::Ssh::msg("Failed to start sftp subsystem", e))?;
+            .map_err(|e| er::ssh_msg("Failed to start sftp subsystem", e))?;
         Ok(sftp)
     }
 
@@ -570,7 +576,7 @@ impl SshConn {
     ) -> Result<()> {
         progress_bar.set_message(&format!("{:?}", remote_path));
         progress_bar.set_length(bytes);
-        let mut local_handle = std::fs::File::open(abs_path).map_err(er::Io::e)?;
+        let mut local_handle = std::fs::File::open(abs_path).map_err(er::io)?;
         // As per ssh2::Sft::create(), using WRITE | TRUNCATE here to mean create
         println!("Opening {:?}", remote_path);
         let mut remote_handle = sftp
@@ -580,7 +586,7 @@ impl SshConn {
                 0o640,
                 ssh2::OpenType::File,
             )
-            .map_err(er::Ssh::e)?;
+            .map_err(er::ssh)?;
         Self::copy(&mut local_handle, &mut remote_handle, &progress_bar)?;
         drop(remote_handle);
         if let Some(modified) = modified {
@@ -593,8 +599,7 @@ impl SshConn {
                 atime: None,
                 mtime: Some(modified),
             };
-            sftp.setstat(&remote_path, stat_setter)
-                .map_err(er::Ssh::e)?;
+            sftp.setstat(&remote_path, stat_setter).map_err(er::ssh)?;
         }
         use std::io::Write;
         let _ = std::io::stdout().flush();
@@ -606,6 +611,21 @@ impl SshConn {
         Ok(())
     }
 
+    /// Downloads a remote file with progress
+    pub fn download_file(sftp: &ssh2::Sftp, remote_path: &Path, local_path: &Path) -> Result<u64> {
+        let mut remote_handle = sftp.open(remote_path).map_err(er::ssh)?;
+        let bytes = remote_handle
+            .stat()
+            .ok()
+            .and_then(|stat| stat.size)
+            .unwrap_or(0);
+        let progress_bar = indicatif::ProgressBar::new(bytes);
+        progress_bar.set_message(&format!("{:?}", remote_path));
+        utils::ensure_parent_dir(local_path)?;
+        let mut local_handle = std::fs::File::create(local_path).map_err(er::io)?;
+        Self::copy(&mut remote_handle, &mut local_handle, &progress_bar)
+    }
+
     /// Utility function to copy from a read to a write handle
     /// while displaying progress
     /// Based on io::copy
@@ -639,9 +659,9 @@ impl SshConn {
                     len
                 }
                 Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
-                Err(e) => return Err(er::Io::e(e).into()),
+                Err(e) => return Err(er::io(e)),
             };
-            writer.write_all(&buf[..len]).map_err(er::Io::e)?;
+            writer.write_all(&buf[..len]).map_err(er::io)?;
             written += len as u64;
         }
     }
@@ -661,7 +681,7 @@ impl<'a> SftpExt for ssh2::Sftp<'a> {
                 if e.code() == 2 {
                     Ok(None)
                 } else {
-                    return Err(failure::Error::from(er::Ssh::e(e)));
+                    return Err(er::ssh(e));
                 }
             }
         }
@@ -675,7 +695,8 @@ impl<'a> SftpExt for ssh2::Sftp<'a> {
                     if stat.is_dir() {
                         break;
                     } else {
-                        return Err(format_err!(
+                        return Err(cli_err!(
+                            Ssh,
                             "Ensure dir: Expected dir, found file: {:?}",
                             ancestor
                         ));
@@ -689,7 +710,7 @@ impl<'a> SftpExt for ssh2::Sftp<'a> {
         stack.reverse();
         for ancestor in stack.into_iter() {
             println!("Creating parent directory: {:?}", ancestor);
-            self.mkdir(ancestor, 0o0700).map_err(er::Ssh::e)?;
+            self.mkdir(ancestor, 0o0700).map_err(er::ssh)?;
         }
         Ok(())
     }
@@ -745,7 +766,7 @@ impl SyncSet {
                 sync_set.resolve(&file_path, sftp, force)?;
                 Ok(sync_set)
             }
-            None => Err(format_err!("Could not get parent folder of file")),
+            None => Err(cli_err!(Io, "Could not get parent folder of file")),
         }
     }
 
@@ -769,7 +790,7 @@ impl SyncSet {
                 sync_set.resolve(&local, sftp, force)?;
                 Ok(sync_set)
             }
-            _ => Err(format_err!("Could not get parent folders")),
+            _ => Err(cli_err!(Io, "Could not get parent folders")),
         }
     }
 
@@ -795,7 +816,7 @@ impl SyncSet {
     /// Will add unless a server file exists with the same or higher
     /// modified time
     pub fn resolve(&mut self, local: &Path, sftp: &ssh2::Sftp, force: bool) -> Result<()> {
-        let root_meta = local.metadata().map_err(er::Io::e)?;
+        let root_meta = local.metadata().map_err(er::io)?;
         let root_rel_path = self.rel_from_abs(&local)?;
         let root_server_path = self.server_base.join(&root_rel_path);
         let root_server_meta = sftp.exist_stat(&root_server_path)?;
@@ -833,10 +854,10 @@ impl SyncSet {
             //println!("Walking dir: {:?}", local);
             let root_exist = root_server_meta.is_some();
             for entry in WalkDir::new(local) {
-                let entry = entry.map_err(er::Walkdir::e)?;
+                let entry = entry.map_err(er::walkdir)?;
                 let entry_path = entry.path();
                 let rel_path = self.rel_from_abs(&entry_path)?;
-                let local_meta = entry.metadata().map_err(er::Walkdir::e)?;
+                let local_meta = entry.metadata().map_err(er::walkdir)?;
                 let local_mtime = Self::modified_timestamp(&local_meta);
                 if entry_path.is_file() {
                     let do_transfer = if !root_exist || force {
@@ -881,11 +902,11 @@ impl SyncSet {
                         modified: local_mtime,
                     });
                 } else {
-                    return Err(format_err!("Unrecognized type: {:?}", entry_path));
+                    return Err(cli_err!(Io, "Unrecognized type: {:?}", entry_path));
                 }
             }
         } else {
-            return Err(format_err!("Only files and dirs supported: {:?}", local));
+            return Err(cli_err!(Io, "Only files and dirs supported: {:?}", local));
         }
         if failed_mtime {
             eprintln!("Notice: Failed reading local modified time on some entries");
@@ -896,7 +917,8 @@ impl SyncSet {
     pub fn rel_from_abs(&self, path: &Path) -> Result<PathBuf> {
         path.strip_prefix(&self.local_base)
             .map_err(|_| {
-                format_err!(
+                cli_err!(
+                    Io,
                     "Could not strip path, {:?} from: {:?}",
                     self.local_base,
                     path
@@ -938,7 +960,7 @@ impl SyncSet {
                 } => {
                     println!("Creating directory: {:?}", rel_path);
                     let remote_path = self.server_base.join(&rel_path);
-                    sftp.mkdir(&remote_path, 0o0700).map_err(er::Ssh::e)?;
+                    sftp.mkdir(&remote_path, 0o0700).map_err(er::ssh)?;
                 }
             }
         }
@@ -995,7 +1017,7 @@ impl SyncSet {
                     }
                     zip_out
                         .start_file_from_path(&rel_path, options)
-                        .map_err(|e| format_err!("Zip file error: {:?}", e))?;
+                        .map_err(|e| cli_err!(Io, "Zip file error: {:?}", e))?;
                     let mut file = fs::File::open(abs_path)?;
                     io::copy(&mut file, &mut zip_out)?;
                 }
@@ -1010,13 +1032,13 @@ impl SyncSet {
                     }
                     zip_out
                         .add_directory_from_path(&rel_path, options)
-                        .map_err(|e| format_err!("Zip directory error: {:?}", e))?;
+                        .map_err(|e| cli_err!(Io, "Zip directory error: {:?}", e))?;
                 }
             }
         }
         zip_out
             .finish()
-            .map_err(|e| format_err!("Zip finish error: {:?}", e))?;
+            .map_err(|e| cli_err!(Io, "Zip finish error: {:?}", e))?;
         Ok(())
     }
     /// Expects duration since unix epoch and returns a zip::DateTime
@@ -1025,7 +1047,7 @@ impl SyncSet {
         use std::convert::TryInto;
         let secs: i64 = secs
             .try_into()
-            .map_err(|_| format_err!("Failed to convert seconds to i64"))?;
+            .map_err(|_| cli_err!(Io, "Failed to convert seconds to i64"))?;
         let m = chrono::NaiveDateTime::from_timestamp(secs, 0);
         let date = m.date();
         let time = m.time();
@@ -1034,26 +1056,26 @@ impl SyncSet {
         let seconds: u8 = time
             .second()
             .try_into()
-            .map_err(|_| format_err!("Failed to convert second"))?;
+            .map_err(|_| cli_err!(Io, "Failed to convert second"))?;
         zip::DateTime::from_date_and_time(
             m.year()
                 .try_into()
-                .map_err(|_| format_err!("Failed to convert year"))?,
+                .map_err(|_| cli_err!(Io, "Failed to convert year"))?,
             date.month()
                 .try_into()
-                .map_err(|_| format_err!("Failed to convert month"))?,
+                .map_err(|_| cli_err!(Io, "Failed to convert month"))?,
             date.day()
                 .try_into()
-                .map_err(|_| format_err!("Failed to convert day"))?,
+                .map_err(|_| cli_err!(Io, "Failed to convert day"))?,
             time.hour()
                 .try_into()
-                .map_err(|_| format_err!("Failed to convert hour"))?,
+                .map_err(|_| cli_err!(Io, "Failed to convert hour"))?,
             time.minute()
                 .try_into()
-                .map_err(|_| format_err!("Failed to convert minute"))?,
+                .map_err(|_| cli_err!(Io, "Failed to convert minute"))?,
             seconds + 1,
         )
-        .map_err(|_| format_err!("Failed to convert to zip::DateTime"))
+        .map_err(|_| cli_err!(Io, "Failed to convert to zip::DateTime"))
     }
 }
 
@@ -1133,12 +1155,12 @@ fn read_until_zero<R: std::io::Read>(
                         use std::error::Error;
                         // todo: Better detection
                         if e.description() != "would block" {
-                            return er::Io::msg("Read failed", e).err();
+                            return Err(tunnel_err(e));
                         }
                         break;
                     }
                     _ => {
-                        return er::Io::msg("Read tunnel", e).err();
+                        return Err(tunnel_err(e));
                     }
                 }
             }
@@ -1147,9 +1169,16 @@ fn read_until_zero<R: std::io::Read>(
     Ok(())
 }
 
+fn tunnel_err(e: io::Error) -> failure::Error {
+    er::network_msg("Tunnel failed", e)
+}
+
 pub struct SshTunnel {
     join_handle: std::thread::JoinHandle<Result<()>>,
     close_sender: std::sync::mpsc::SyncSender<bool>,
+    /// Bound local port, useful when
+    /// given as 0
+    pub local_port: u16,
 }
 impl SshTunnel {
     /// Tunnels incoming requests to a port on
@@ -1163,20 +1192,35 @@ impl SshTunnel {
         remote_port: u16,
     ) -> Result<Self> {
         let conn = SshConn::connect(env, &server)?;
+        Self::through(conn, local_port, "127.0.0.1", remote_port)
+    }
+
+    /// Tunnels incoming requests to a host and port
+    /// as reached from the other side of connection,
+    /// like a service in the network of a container
+    pub fn through(
+        conn: SshConn,
+        local_port: u16,
+        remote_host: &str,
+        remote_port: u16,
+    ) -> Result<Self> {
         let (tx, rx) = std::sync::mpsc::sync_channel(1);
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", local_port)).map_err(er::Io::e)?;
+        let listener =
+            TcpListener::bind(format!("127.0.0.1:{}", local_port)).map_err(tunnel_err)?;
+        let local_port = listener.local_addr().map_err(tunnel_err)?.port();
+        let remote_host = remote_host.to_string();
         let handle = std::thread::spawn(move || -> Result<()> {
-            listener.set_nonblocking(true)?;
+            listener.set_nonblocking(true).map_err(tunnel_err)?;
             for stream in listener.incoming() {
                 match stream {
                     Ok(mut socket) => {
                         let mut channel = conn
                             .session
-                            .channel_direct_tcpip("127.0.0.1", remote_port, None)
-                            .map_err(|e| er::Ssh::msg("Failed to connect on server", e))?;
+                            .channel_direct_tcpip(&remote_host, remote_port, None)
+                            .map_err(|e| er::ssh_msg("Failed to connect on server", e))?;
                         println!("Opened tunnel");
                         conn.session.set_blocking(false);
-                        socket.set_nonblocking(true).map_err(er::Io::e)?;
+                        socket.set_nonblocking(true).map_err(tunnel_err)?;
                         // Now pipe both ways
                         let mut acc_buf = Vec::with_capacity(2048);
                         use std::io::Write;
@@ -1187,24 +1231,24 @@ impl SshTunnel {
                             read_until_zero(&mut channel, &mut read_buf, &mut acc_buf)?;
                             // Write back to socket
                             if acc_buf.len() > 0 {
-                                socket.write_all(&acc_buf).map_err(er::Io::e)?;
-                                socket.flush().map_err(er::Io::e)?;
+                                socket.write_all(&acc_buf).map_err(tunnel_err)?;
+                                socket.flush().map_err(tunnel_err)?;
                                 acc_buf.clear();
                             }
                             // Stderr
                             read_until_zero(&mut channel.stderr(), &mut read_buf, &mut acc_buf)?;
                             // Write back to socket
                             if acc_buf.len() > 0 {
-                                socket.write_all(&acc_buf).map_err(er::Io::e)?;
-                                socket.flush().map_err(er::Io::e)?;
+                                socket.write_all(&acc_buf).map_err(tunnel_err)?;
+                                socket.flush().map_err(tunnel_err)?;
                                 acc_buf.clear();
                             }
                             // Read any data on socket and forward to tunneled
                             read_until_zero(&mut socket, &mut read_buf, &mut acc_buf)?;
                             // Write back to channel
                             if acc_buf.len() > 0 {
-                                channel.write_all(&acc_buf).map_err(er::Io::e)?;
-                                channel.flush().map_err(er::Io::e)?;
+                                channel.write_all(&acc_buf).map_err(tunnel_err)?;
+                                channel.flush().map_err(tunnel_err)?;
                                 acc_buf.clear();
                             }
                             std::thread::sleep(std::time::Duration::from_millis(50));
@@ -1214,7 +1258,7 @@ impl SshTunnel {
                     Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                         std::thread::sleep(std::time::Duration::from_millis(200));
                     }
-                    Err(e) => return er::Io::msg("Tunnel listener failed", e).err(),
+                    Err(e) => return Err(er::network_msg("Tunnel listener failed", e)),
                 }
                 // Check if we have close signal
                 match rx.try_recv() {
@@ -1228,6 +1272,7 @@ impl SshTunnel {
         Ok(SshTunnel {
             join_handle: handle,
             close_sender: tx,
+            local_port,
         })
     }
 
@@ -1236,7 +1281,7 @@ impl SshTunnel {
         let thread_result = self
             .join_handle
             .join()
-            .map_err(|_| format_err!("Failed to join tunnel thread"))?;
+            .map_err(|_| cli_err!(Other, "Failed to join tunnel thread"))?;
         thread_result
     }
 }
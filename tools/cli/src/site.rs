@@ -0,0 +1,403 @@
+// Several projects hosted on one server. A project with a site
+// config runs as its own compose project with allocated ports,
+// bound to localhost, and a shared front nginx on the server
+// routes requests to it by Host header.
+// Projects without site config keep the single site setup,
+// with the proxy on port 80.
+
+use crate::acme;
+use crate::compose::{self, ComposeService, ComposeYml};
+use crate::er::Result;
+use crate::project::{self, ProjectConfig};
+use crate::server::{ServerConfig, SshConn};
+use crate::utils::CliEnv;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Ports are allocated upwards from these,
+/// wp-cli stepping by two as tunnels use the port above
+const FIRST_HTTP_PORT: u16 = 8100;
+const FIRST_WP_CLI_PORT: u16 = 2400;
+
+/// Dir of front proxy files, relative to server home
+pub const FRONT_DIR: &str = "front";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SiteConfig {
+    /// Site proxy port on server localhost
+    pub http_port: u16,
+    pub wp_cli_port: u16,
+    /// Compose project kept from a single site deployment,
+    /// so its containers and volumes stay in use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compose_project: Option<String>,
+}
+
+/// Site as routed by the front proxy
+pub struct FrontSite {
+    pub hosts: Vec<String>,
+    pub http_port: u16,
+    /// Dir of certificate in front container,
+    /// when issued
+    pub cert_dir: Option<String>,
+}
+
+/// Compose project name of a site, compose strips
+/// most non alphanumeric chars
+pub fn compose_project_name(project_name: &str) -> String {
+    project_name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Local project configs deployed on the given server,
+/// as views of their environments there
+pub fn server_projects(env: &CliEnv, server_name: &str) -> Result<Vec<ProjectConfig>> {
+    let mut projects = Vec::new();
+    for name in project::get_projects(env)? {
+        if let Ok(config) = project::get_config(env, &name) {
+            for (env_name, env_config) in &config.environments {
+                if env_config.server_name == server_name {
+                    projects.push(config.in_env(env_name)?);
+                }
+            }
+            if config.server_name == server_name {
+                projects.push(config);
+            }
+        }
+    }
+    Ok(projects)
+}
+
+/// Ports on server used by sites and previews of deployments,
+/// other than the given one
+pub fn used_ports(env: &CliEnv, server_name: &str, except: &str) -> Result<Vec<u16>> {
+    let mut ports = Vec::new();
+    for other in server_projects(env, server_name)? {
+        if other.deployment_name() == except {
+            continue;
+        }
+        if let Some(site) = &other.site {
+            ports.push(site.http_port);
+            ports.push(site.wp_cli_port);
+        }
+        ports.extend(other.previews.values().map(|p| p.wp_cli_port));
+    }
+    Ok(ports)
+}
+
+fn next_free(first: u16, step: u16, used: &[u16]) -> u16 {
+    let mut port = first;
+    // Tunnels use the port above wp-cli ports
+    while used.contains(&port) || (step > 1 && used.contains(&(port + 1))) {
+        port += step;
+    }
+    port
+}
+
+/// Site config of deployment with ports free on the server
+pub fn allocate(env: &CliEnv, project: &ProjectConfig, server_name: &str) -> Result<SiteConfig> {
+    let mut used = used_ports(env, server_name, &project.deployment_name())?;
+    used.extend(project.previews.values().map(|p| p.wp_cli_port));
+    Ok(SiteConfig {
+        http_port: next_free(FIRST_HTTP_PORT, 1, &used),
+        wp_cli_port: next_free(FIRST_WP_CLI_PORT, 2, &used),
+        compose_project: None,
+    })
+}
+
+/// Adapts resolved prod compose to run as one of several sites:
+/// proxy and wp-cli published on allocated localhost ports,
+/// replacing the fixed ones. Https is served by the front proxy
+pub fn apply(project: &ProjectConfig, compose: &mut ComposeYml) {
+    let site = match &project.site {
+        Some(site) => site,
+        None => return,
+    };
+    if let Some(proxy) = compose.services.get_mut("proxy") {
+        proxy.ports = vec![format!("127.0.0.1:{}:80", site.http_port)];
+        proxy.environment.remove("BIND_SSL");
+    }
+    if let Some(wp_cli) = compose.services.get_mut("wp-cli") {
+        wp_cli.ports = vec![format!("{}:22", site.wp_cli_port)];
+    }
+}
+
+const PROXY_LOCATION: &str = "
+    client_max_body_size 64m;
+    location / {
+        proxy_pass http://127.0.0.1:$site_port;
+        proxy_set_header Host $host;
+        proxy_set_header X-Real-IP $remote_addr;
+        proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+        proxy_set_header X-Forwarded-Proto $scheme;
+    }";
+
+/// Nginx server blocks routing host names to site proxies,
+/// unknown hosts get 404. Acme challenges are served from
+/// files, and sites with certificates are redirected to https
+pub fn front_nginx_conf(sites: &[FrontSite]) -> String {
+    let mut conf = String::from(
+        "# Generated, routes sites by host\n\
+         server {\n    listen 80 default_server;\n    return 404;\n}\n",
+    );
+    for site in sites {
+        let hosts = site.hosts.join(" ");
+        let location = PROXY_LOCATION.replace("$site_port", &site.http_port.to_string());
+        let http_location = match site.cert_dir {
+            Some(_) => "\n    location / {\n        return 301 https://$host$request_uri;\n    }"
+                .to_string(),
+            None => location.clone(),
+        };
+        conf.push_str(&format!(
+            "
+server {{
+    listen 80;
+    server_name {hosts};
+    location ^~ /.well-known/acme-challenge/ {{
+        alias /var/www/acme/challenge/;
+    }}{http_location}
+}}
+",
+            hosts = hosts,
+            http_location = http_location
+        ));
+        if let Some(cert_dir) = &site.cert_dir {
+            conf.push_str(&format!(
+                "
+server {{
+    listen 443 ssl;
+    server_name {hosts};
+    ssl_certificate {cert_dir}/cert.pem;
+    ssl_certificate_key {cert_dir}/key.pem;{location}
+}}
+",
+                hosts = hosts,
+                cert_dir = cert_dir,
+                location = location
+            ));
+        }
+    }
+    conf
+}
+
+/// Front nginx on host network, reaching site
+/// proxies on localhost
+fn front_compose_yml() -> Result<String> {
+    let mut other = BTreeMap::new();
+    other.insert(
+        "network_mode".to_string(),
+        serde_yaml::Value::String("host".into()),
+    );
+    let mut services = BTreeMap::new();
+    services.insert(
+        "nginx".to_string(),
+        ComposeService {
+            image: Some("nginx:stable-alpine".into()),
+            restart: Some("always".into()),
+            volumes: vec![
+                "./conf.d:/etc/nginx/conf.d:ro".into(),
+                "./acme/challenge:/var/www/acme/challenge:ro".into(),
+                "./acme/certs:/etc/nginx/certs:ro".into(),
+            ],
+            other,
+            ..Default::default()
+        },
+    );
+    let yml = ComposeYml {
+        version: "3.3".into(),
+        services,
+        ..Default::default()
+    };
+    yml.render()
+}
+
+/// Writes front proxy config with all sites on server,
+/// then starts it and reloads the config
+pub fn sync_front(env: &CliEnv, server: &ServerConfig, conn: &SshConn) -> Result<()> {
+    let front_dir = server.home_dir_and(FRONT_DIR);
+    conn.exec(format!(
+        "mkdir -p {dir}/conf.d {dir}/acme/challenge {dir}/acme/certs",
+        dir = front_dir.to_string_lossy()
+    ))?;
+    let mut sites = Vec::new();
+    for project in server_projects(env, &server.name)? {
+        let site = match &project.site {
+            Some(site) => site,
+            None => continue,
+        };
+        let domain = match &project.domain {
+            Some(domain) => domain,
+            None => continue,
+        };
+        // Only referring to certificates that are issued,
+        // as nginx won't start with missing files
+        let cert_file = front_dir.join(format!("acme/certs/{}/cert.pem", domain));
+        let has_cert = project.tls.is_some()
+            && conn.exec(format!("test -f {}", cert_file.to_string_lossy()))? == 0;
+        sites.push(FrontSite {
+            hosts: project.hosts(),
+            http_port: site.http_port,
+            cert_dir: if has_cert {
+                Some(format!("/etc/nginx/certs/{}", domain))
+            } else {
+                None
+            },
+        });
+    }
+    acme::write_remote(
+        conn,
+        &front_dir.join("docker-compose.yml"),
+        front_compose_yml()?.as_bytes(),
+        0o644,
+    )?;
+    acme::write_remote(
+        conn,
+        &front_dir.join("conf.d/sites.conf"),
+        front_nginx_conf(&sites).as_bytes(),
+        0o644,
+    )?;
+    let status = conn.exec(format!(
+        "cd {}; docker-compose -p front up -d && docker-compose -p front exec -T nginx nginx -s reload",
+        front_dir.to_string_lossy()
+    ))?;
+    if status != 0 {
+        return Err(cli_err!(Docker, "Failed starting front proxy"));
+    }
+    println!("Front proxy routes {} site(s)", sites.len());
+    Ok(())
+}
+
+/// Sets up project as one of several sites on its server,
+/// allocating ports and routing its domain through the front proxy
+pub fn site_init(env: &CliEnv, mut project: ProjectConfig) -> Result<()> {
+    let server = project.require_server(env)?;
+    let domain = env.get_input("Domain", project.domain.clone())?;
+    let aliases = env.get_input(
+        "Other host names, comma separated",
+        Some(project.domain_aliases.join(",")),
+    )?;
+    project.domain_aliases = aliases
+        .split(',')
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+        .collect::<Vec<_>>();
+    project.domain = Some(domain);
+    let conn = SshConn::connect(env, &server)?;
+    let site = match project.site.clone() {
+        Some(current) => current,
+        None => SiteConfig {
+            compose_project: kept_compose_project(env, &project, &server, &conn)?,
+            ..allocate(env, &project, &server.name)?
+        },
+    };
+    let moved = project.site.is_none() && site.compose_project.is_some();
+    project.site = Some(site.clone());
+    println!(
+        "{} on ports {} (http), {} (wp-cli), compose project {}",
+        project.hosts().join(", "),
+        site.http_port,
+        site.wp_cli_port,
+        project.compose_project()
+    );
+    project::write_config(env, &project)?;
+
+    let single = server_projects(env, &server.name)?
+        .into_iter()
+        .filter(|p| p.site.is_none())
+        .map(|p| p.deployment_name())
+        .collect::<Vec<_>>();
+    if !single.is_empty() {
+        println!(
+            "{}",
+            console::style(format!(
+                "Projects without site config use port 80 and need `site init` before the front proxy can start: {}",
+                single.join(", ")
+            ))
+            .yellow()
+        );
+    }
+    project::sync_prod_compose(env, &project, &server, &conn)?;
+    if moved {
+        // Recreated on the site ports, freeing port 80 for the front proxy
+        println!("Moving the running deployment to its site ports");
+        let status = conn.exec(format!(
+            "cd {}; docker-compose {} up -d",
+            project::server_project_dir(&project, &server),
+            project::prod_compose_args(&project).join(" ")
+        ))?;
+        if status != 0 {
+            return Err(cli_err!(
+                Docker,
+                "Could not restart deployment on site ports"
+            ));
+        }
+    }
+    sync_front(env, &server, &conn)?;
+    println!("Start the site with `prod up -d`");
+    Ok(())
+}
+
+/// Compose project of a single site deployment on the server,
+/// kept when the project becomes a site so it keeps its volumes.
+/// With other single site projects on the server, the user
+/// confirms the deployment is of this project
+fn kept_compose_project(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    server: &ServerConfig,
+    conn: &SshConn,
+) -> Result<Option<String>> {
+    let volume = format!("{}_db_data", compose::PROJECT_NAME);
+    if conn.exec(format!("docker volume inspect {} > /dev/null 2>&1", volume))? != 0 {
+        return Ok(None);
+    }
+    let others = server_projects(env, &server.name)?
+        .into_iter()
+        .filter(|p| p.site.is_none() && p.deployment_name() != project.deployment_name())
+        .map(|p| p.deployment_name())
+        .collect::<Vec<_>>();
+    if !others.is_empty() {
+        let prompt = format!(
+            "A single site deployment is on the server, projects without site config: {}. Is it {}?",
+            others.join(", "),
+            project.deployment_name()
+        );
+        if env.select(&prompt, &vec!["No", "Yes"], Some(0))? != 1 {
+            return Ok(None);
+        }
+    }
+    Ok(Some(compose::PROJECT_NAME.to_string()))
+}
+
+/// Lists sites of the project server
+pub fn site_list(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    for other in server_projects(env, &project.server_name)? {
+        match &other.site {
+            Some(site) => println!(
+                "{}: {} (http {}, wp-cli {})",
+                other.deployment_name(),
+                other.hosts().join(", "),
+                site.http_port,
+                site.wp_cli_port
+            ),
+            None => println!("{}: single site, port 80", other.deployment_name()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_free_ports() {
+        assert_eq!(next_free(FIRST_HTTP_PORT, 1, &[8100, 8101]), 8102);
+        // Port above is taken by a tunnel
+        assert_eq!(next_free(FIRST_WP_CLI_PORT, 2, &[2401]), 2402);
+        assert_eq!(next_free(FIRST_WP_CLI_PORT, 2, &[]), 2400);
+    }
+}
@@ -0,0 +1,333 @@
+// Sql shell on the database of a project environment.
+// Credentials are read from the resolved compose config.
+// Server environments are reached through a tunnel over
+// the wp-cli container, which is in the compose network.
+
+use crate::compose::{ComposeEnv, ComposeYml};
+use crate::docker;
+use crate::er::{self, Result};
+use crate::project::{self, ProjectConfig};
+use crate::server::{SshConn, SshTunnel};
+use crate::utils::CliEnv;
+use mysql_utils::output::Format;
+use mysql_utils::Db;
+use rustyline::error::ReadlineError;
+
+/// Database settings of wordpress,
+/// as seen from the compose network
+#[derive(Debug, PartialEq)]
+pub struct DbSettings {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub pass: String,
+    pub db_name: String,
+}
+
+pub fn db_settings(compose: &ComposeYml) -> Result<DbSettings> {
+    let wp = match compose.services.get("wordpress-container") {
+        Some(wp) => wp,
+        None => {
+            return Err(cli_err!(
+                Config,
+                "No wordpress-container service in compose config"
+            ))
+        }
+    };
+    let var = |name: &str| match wp.environment.get(name) {
+        Some(value) => Ok(value.clone()),
+        None => Err(cli_err!(Config, "Missing {} in wordpress-container", name)),
+    };
+    let db_host = var("WORDPRESS_DB_HOST")?;
+    let (host, port) = match db_host.rfind(':') {
+        Some(i) => (
+            db_host[..i].to_string(),
+            db_host[i + 1..]
+                .parse::<u16>()
+                .map_err(|_| cli_err!(Config, "Invalid db port in {}", db_host))?,
+        ),
+        None => (db_host.clone(), 3306),
+    };
+    Ok(DbSettings {
+        host,
+        port,
+        user: var("WORDPRESS_DB_USER")?,
+        pass: var("WORDPRESS_DB_PASSWORD")?,
+        db_name: var("WORDPRESS_DB_NAME")?,
+    })
+}
+
+/// Db connection, and tunnel when on server
+pub struct SqlSession {
+    db: Option<Db>,
+    tunnel: Option<SshTunnel>,
+}
+impl Drop for SqlSession {
+    fn drop(&mut self) {
+        // Closing db first, ending the tunneled connection
+        self.db.take();
+        if let Some(tunnel) = self.tunnel.take() {
+            if let Err(e) = tunnel.close() {
+                eprintln!("Failed closing tunnel: {:?}", e);
+            }
+        }
+    }
+}
+impl SqlSession {
+    /// Connects to dev db on its published port,
+    /// or through the wp-cli container on server
+    pub fn connect(env: &CliEnv, project: &ProjectConfig, on_server: bool) -> Result<Self> {
+        if on_server {
+            let server = project.require_server(env)?;
+            let compose = project::resolve_prod(env, project, &server, ComposeEnv::Prod)?;
+            let settings = db_settings(&compose)?;
+            let cli_conn = SshConn::connect_wp_cli(env, project.prod_wp_cli_port(), Some(&server))?;
+            let tunnel = SshTunnel::through(cli_conn, 0, &settings.host, settings.port)?;
+            let db = Db::new(
+                "127.0.0.1",
+                tunnel.local_port,
+                &settings.db_name,
+                &settings.user,
+                &settings.pass,
+            )
+            .map_err(|e| er::db_msg("Could not connect through tunnel", e))?;
+            Ok(SqlSession {
+                db: Some(db),
+                tunnel: Some(tunnel),
+            })
+        } else {
+            crate::modules::of(project)?.gen_dev(env, project)?;
+            let compose = docker::write_dev_compose(env, project)?;
+            let settings = db_settings(&compose)?;
+            let port = compose
+                .services
+                .get(&settings.host)
+                .and_then(|db| db.published_port(settings.port));
+            let port = match port {
+                Some(port) => port,
+                None => {
+                    return Err(cli_err!(
+                        Docker,
+                        "Port {} of {} is not published in dev compose",
+                        settings.port,
+                        settings.host
+                    ))
+                }
+            };
+            let db = Db::new(
+                "127.0.0.1",
+                port,
+                &settings.db_name,
+                &settings.user,
+                &settings.pass,
+            )
+            .map_err(|e| er::db_msg("Could not connect to dev db", e))?;
+            Ok(SqlSession {
+                db: Some(db),
+                tunnel: None,
+            })
+        }
+    }
+
+    /// Runs statement and prints result,
+    /// errors are printed as well
+    pub fn run(&mut self, sql: &str, format: Format) -> Result<()> {
+        let db = match self.db.as_mut() {
+            Some(db) => db,
+            None => return Err(cli_err!(Db, "Not connected")),
+        };
+        let result = db.query_set(sql).map_err(er::db)?;
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        result.write(&mut out, format).map_err(er::db)?;
+        Ok(())
+    }
+}
+
+/// Replaces text in all tables of environment, decoding
+/// php serialized values. Prints counts per table
+pub fn search_replace(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    on_server: bool,
+    from: &str,
+    to: &str,
+    dry_run: bool,
+) -> Result<()> {
+    if from.is_empty() {
+        return Err(cli_err!(Db, "Empty search string"));
+    }
+    let mut session = SqlSession::connect(env, project, on_server)?;
+    let db = match session.db.as_mut() {
+        Some(db) => db,
+        None => return Err(cli_err!(Db, "Not connected")),
+    };
+    let reports = db
+        .search_replace(from, to, dry_run)
+        .map_err(|e| er::db_msg("Search-replace failed", e))?;
+    if reports.is_empty() {
+        println!("No occurrences of {}", from);
+        return Ok(());
+    }
+    let verb = if dry_run { "Would replace" } else { "Replaced" };
+    for report in &reports {
+        let mut line = format!(
+            "{}: {} {} cells in {} rows",
+            report.table, verb, report.cells, report.rows
+        );
+        if report.skipped > 0 {
+            line.push_str(&format!(", skipped {} unsafe", report.skipped));
+        }
+        if report.no_primary_key && report.cells > 0 {
+            println!(
+                "{}",
+                console::style(format!("{}, no primary key, not updated", line)).yellow()
+            );
+        } else {
+            println!("{}", line);
+        }
+    }
+    let cells = reports.iter().map(|r| r.cells).sum::<u64>();
+    let skipped = reports.iter().map(|r| r.skipped).sum::<u64>();
+    println!("{} {} cells, skipped {}", verb, cells, skipped);
+    if skipped > 0 {
+        println!("Skipped values look serialized, but could not be decoded exactly");
+    }
+    Ok(())
+}
+
+/// Statement with its terminator removed, and format
+/// to use when it ends with \G. None while incomplete
+fn complete_statement(buffer: &str, format: Format) -> Option<(String, Format)> {
+    let trimmed = buffer.trim_end();
+    if trimmed.ends_with("\\G") {
+        Some((
+            trimmed[..trimmed.len() - 2].trim().to_string(),
+            Format::Vertical,
+        ))
+    } else if trimmed.ends_with(';') {
+        Some((trimmed[..trimmed.len() - 1].trim().to_string(), format))
+    } else {
+        None
+    }
+}
+
+/// Runs given sql, or an interactive shell when empty
+pub fn sql_cmd(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    on_server: bool,
+    sql: &str,
+    format: Format,
+) -> Result<()> {
+    let mut session = SqlSession::connect(env, project, on_server)?;
+    if !sql.trim().is_empty() {
+        let (sql, format) =
+            complete_statement(sql, format).unwrap_or_else(|| (sql.trim().to_string(), format));
+        return session.run(&sql, format);
+    }
+    sql_repl(env, &mut session, format)
+}
+
+fn sql_repl(env: &CliEnv, session: &mut SqlSession, mut format: Format) -> Result<()> {
+    let history_file = env.config_dirs.config_root.join("sql_history");
+    let mut editor = rustyline::Editor::<()>::new();
+    // Missing on first run
+    let _ = editor.load_history(&history_file);
+    println!("End statements with ; or \\G for vertical output");
+    println!("\\format table|vertical|csv|json changes output, \\q quits");
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() { "sql> " } else { "  -> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                // Discards current statement
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(cli_err!(Other, "Readline error: {:?}", e)),
+        };
+        let trimmed = line.trim();
+        if buffer.is_empty() {
+            match trimmed {
+                "" => continue,
+                "\\q" | "quit" | "exit" => break,
+                _ if trimmed.starts_with("\\format") => {
+                    editor.add_history_entry(trimmed);
+                    match trimmed["\\format".len()..].trim().parse::<Format>() {
+                        Ok(new_format) => format = new_format,
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                _ => (),
+            }
+        }
+        if !buffer.is_empty() {
+            buffer.push('\n');
+        }
+        buffer.push_str(&line);
+        if let Some((statement, statement_format)) = complete_statement(&buffer, format) {
+            editor.add_history_entry(buffer.as_str());
+            buffer.clear();
+            if statement.is_empty() {
+                continue;
+            }
+            if let Err(e) = session.run(&statement, statement_format) {
+                println!("{}", console::style(format!("{}", e)).red());
+            }
+        }
+    }
+    editor
+        .save_history(&history_file)
+        .map_err(|e| cli_err!(Other, "Could not save history: {:?}", e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_statements() {
+        assert_eq!(complete_statement("select 1", Format::Csv), None);
+        assert_eq!(
+            complete_statement("select\n 1 ;  ", Format::Csv),
+            Some(("select\n 1".to_string(), Format::Csv))
+        );
+        assert_eq!(
+            complete_statement("select 1\\G", Format::Table),
+            Some(("select 1".to_string(), Format::Vertical))
+        );
+    }
+
+    #[test]
+    fn reads_db_settings() {
+        let compose = ComposeYml::parse(
+            r#"
+version: '3.3'
+services:
+  wordpress-container:
+    image: wordpress:5
+    environment:
+      WORDPRESS_DB_HOST: db:3306
+      WORDPRESS_DB_USER: wp
+      WORDPRESS_DB_PASSWORD: secret
+      WORDPRESS_DB_NAME: site
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            db_settings(&compose).unwrap(),
+            DbSettings {
+                host: "db".into(),
+                port: 3306,
+                user: "wp".into(),
+                pass: "secret".into(),
+                db_name: "site".into(),
+            }
+        );
+    }
+}
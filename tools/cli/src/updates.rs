@@ -0,0 +1,447 @@
+// Updates of wordpress core, and of plugins and themes not
+// part of the project. Updates are applied on dev, checked
+// through the dev proxy, and the resulting versions pinned in
+// wp-lock.json of the project, which is installed on servers.
+
+use crate::compose::{self, ComposeEnv};
+use crate::docker;
+use crate::er::{self, Result};
+use crate::project::ProjectConfig;
+use crate::server::SshConn;
+use crate::utils::CliEnv;
+use crate::wp::{self, WpInstallData};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::time::Duration;
+
+const LOCK_FILE: &str = "wp-lock.json";
+
+/// Plugin statuses of files installed outside of wordpress.org
+/// installs, which wp-cli can not install by version
+const UNLOCKED_STATUSES: [&str; 2] = ["must-use", "dropin"];
+
+/// Pinned versions, by plugin and theme name
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct WpLock {
+    #[serde(default)]
+    pub core: Option<String>,
+    #[serde(default)]
+    pub plugins: BTreeMap<String, String>,
+    #[serde(default)]
+    pub themes: BTreeMap<String, String>,
+}
+
+/// Lock of project, None when not created
+pub fn read_lock(env: &CliEnv, project: &ProjectConfig) -> Result<Option<WpLock>> {
+    let lock_file = project.dir_and(env, LOCK_FILE);
+    if !lock_file.is_file() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&lock_file).map_err(er::io)?;
+    let lock = serde_json::from_str(&content)
+        .map_err(|e| cli_err!(Config, "Could not read {}: {:?}", LOCK_FILE, e))?;
+    Ok(Some(lock))
+}
+
+fn write_lock(env: &CliEnv, project: &ProjectConfig, lock: &WpLock) -> Result<()> {
+    let content = serde_json::to_string_pretty(lock).map_err(|e| cli_err!(Other, "{:?}", e))?;
+    project.write_file(env, LOCK_FILE, &format!("{}\n", content))?;
+    Ok(())
+}
+
+/// An available update of core, a plugin or a theme
+#[derive(Debug, PartialEq)]
+pub struct Update {
+    pub kind: &'static str,
+    pub name: String,
+    pub version: String,
+    pub update_version: String,
+}
+
+fn core_version(cli_conn: &SshConn) -> Result<String> {
+    let output = cli_conn.exec_capture("wp core version", Some("/var/www/html"))?;
+    Ok(output.trim().to_string())
+}
+
+#[derive(Deserialize)]
+struct CoreUpdate {
+    version: String,
+}
+
+/// Newest core version above installed, if any. Without updates,
+/// wp-cli prints a success message instead of json
+fn core_update(cli_conn: &SshConn) -> Result<Option<String>> {
+    let output =
+        cli_conn.exec_capture("wp core check-update --format=json", Some("/var/www/html"))?;
+    let output = output.trim();
+    if !output.starts_with('[') {
+        return Ok(None);
+    }
+    let updates = serde_json::from_str::<Vec<CoreUpdate>>(output)
+        .map_err(|e| cli_err!(Other, "Failed deserialize core updates: {:?}", e))?;
+    Ok(updates.into_iter().next().map(|u| u.version))
+}
+
+/// Updates of installed plugins and themes,
+/// except those of the project itself
+fn available_updates(
+    cli_conn: &SshConn,
+    install_data: &WpInstallData,
+    local_data: &wp::WpLocalSiteData,
+) -> Result<Vec<Update>> {
+    let mut updates = Vec::new();
+    if let Some(update_version) = core_update(cli_conn)? {
+        updates.push(Update {
+            kind: "core",
+            name: "wordpress".into(),
+            version: core_version(cli_conn)?,
+            update_version,
+        });
+    }
+    let new_version = |update: &str, update_version: &Option<String>| {
+        if update != "available" {
+            return None;
+        }
+        Some(
+            update_version
+                .clone()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| "newer".into()),
+        )
+    };
+    let mut plugins = install_data
+        .plugins
+        .values()
+        .filter(|p| !local_data.plugins.contains_key(&p.name))
+        .filter_map(|p| {
+            new_version(&p.update, &p.update_version).map(|update_version| Update {
+                kind: "plugin",
+                name: p.name.clone(),
+                version: p.version.clone(),
+                update_version,
+            })
+        })
+        .collect::<Vec<_>>();
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut themes = install_data
+        .themes
+        .values()
+        .filter(|t| !local_data.themes.contains_key(&t.name))
+        .filter_map(|t| {
+            new_version(&t.update, &t.update_version).map(|update_version| Update {
+                kind: "theme",
+                name: t.name.clone(),
+                version: t.version.clone(),
+                update_version,
+            })
+        })
+        .collect::<Vec<_>>();
+    themes.sort_by(|a, b| a.name.cmp(&b.name));
+    updates.extend(plugins);
+    updates.extend(themes);
+    Ok(updates)
+}
+
+fn print_updates(updates: &[Update]) {
+    for update in updates {
+        println!(
+            "{} {}: {} -> {}",
+            update.kind,
+            update.name,
+            update.version,
+            console::style(&update.update_version).green()
+        );
+    }
+}
+
+/// Lists available updates of environment, and versions
+/// differing from the lock
+pub fn updates_list(env: &CliEnv, project: &ProjectConfig, on_server: bool) -> Result<()> {
+    let cli_conn = wp::wp_cli_conn(env, project, on_server)?;
+    let local_data = wp::get_local_site_data(env, project)?;
+    let install_data = wp::wp_install_data(&cli_conn)?;
+    let updates = available_updates(&cli_conn, &install_data, &local_data)?;
+    if updates.is_empty() {
+        println!("No updates available");
+    } else {
+        print_updates(&updates);
+    }
+    if let Some(lock) = read_lock(env, project)? {
+        let current = lock_from(core_version(&cli_conn)?, &install_data, Some(&local_data));
+        for (kind, name, locked, installed) in lock_diff(&lock, &current) {
+            println!(
+                "{}",
+                console::style(format!(
+                    "{} {}: {} installed, {} locked",
+                    kind,
+                    name,
+                    installed.unwrap_or("none"),
+                    locked
+                ))
+                .yellow()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Lock of installed versions, except must-use and dropin
+/// plugins, and project plugins and themes when local data is given
+fn lock_from(
+    core: String,
+    install_data: &WpInstallData,
+    local_data: Option<&wp::WpLocalSiteData>,
+) -> WpLock {
+    WpLock {
+        core: Some(core),
+        plugins: install_data
+            .plugins
+            .values()
+            .filter(|p| !UNLOCKED_STATUSES.contains(&p.status.as_str()))
+            .filter(|p| local_data.map_or(true, |l| !l.plugins.contains_key(&p.name)))
+            .map(|p| (p.name.clone(), p.version.clone()))
+            .collect(),
+        themes: install_data
+            .themes
+            .values()
+            .filter(|t| local_data.map_or(true, |l| !l.themes.contains_key(&t.name)))
+            .map(|t| (t.name.clone(), t.version.clone()))
+            .collect(),
+    }
+}
+
+/// Locked versions differing from installed,
+/// as (kind, name, locked, installed)
+fn lock_diff<'a>(
+    lock: &'a WpLock,
+    installed: &'a WpLock,
+) -> Vec<(&'static str, &'a str, &'a str, Option<&'a str>)> {
+    let mut diff = Vec::new();
+    if let Some(core) = &lock.core {
+        if installed.core.as_ref() != Some(core) {
+            diff.push((
+                "core",
+                "wordpress",
+                core.as_str(),
+                installed.core.as_ref().map(String::as_str),
+            ));
+        }
+    }
+    let kinds = vec![
+        ("plugin", &lock.plugins, &installed.plugins),
+        ("theme", &lock.themes, &installed.themes),
+    ];
+    for (kind, locked, versions) in kinds {
+        for (name, version) in locked {
+            let current = versions.get(name);
+            if current != Some(version) {
+                diff.push((
+                    kind,
+                    name.as_str(),
+                    version.as_str(),
+                    current.map(String::as_str),
+                ));
+            }
+        }
+    }
+    diff
+}
+
+/// Requests page from address, like the dev proxy, failing
+/// on error status or php errors in the response
+pub fn smoke_check_path(addr: &str, path: &str) -> Result<()> {
+    let request_err = |e: std::io::Error| er::network_msg(format!("Could not request {}", path), e);
+    let mut stream = std::net::TcpStream::connect(addr).map_err(request_err)?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .map_err(request_err)?;
+    stream
+        .write_all(format!("GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).as_bytes())
+        .map_err(request_err)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).map_err(request_err)?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    let ok = status
+        .split_whitespace()
+        .nth(1)
+        .map(|code| code.starts_with('2') || code.starts_with('3'))
+        .unwrap_or(false);
+    if !ok {
+        return Err(cli_err!(
+            Other,
+            "Smoke check of {} failed: {}",
+            path,
+            status
+        ));
+    }
+    for error in &["Fatal error", "critical error", "Parse error"] {
+        if response.contains(error) {
+            return Err(cli_err!(Other, "Smoke check of {} found: {}", path, error));
+        }
+    }
+    Ok(())
+}
+
+fn smoke_check(http_port: u16) -> Result<()> {
+    let addr = format!("127.0.0.1:{}", http_port);
+    for path in &["/", "/wp-login.php"] {
+        smoke_check_path(&addr, path)?;
+        println!("Smoke check ok: {}", path);
+    }
+    Ok(())
+}
+
+/// Applies available updates on dev, or those given by name,
+/// then checks the site and pins installed versions in the lock
+pub fn updates_apply(
+    env: &CliEnv,
+    project: &ProjectConfig,
+    names: &[String],
+    skip_smoke: bool,
+) -> Result<()> {
+    let cli_conn = wp::wp_cli_conn(env, project, false)?;
+    let local_data = wp::get_local_site_data(env, project)?;
+    let install_data = wp::wp_install_data(&cli_conn)?;
+    let updates = available_updates(&cli_conn, &install_data, &local_data)?
+        .into_iter()
+        .filter(|u| names.is_empty() || names.contains(&u.name))
+        .collect::<Vec<_>>();
+    if updates.is_empty() {
+        println!("No updates to apply");
+    } else {
+        print_updates(&updates);
+    }
+    for update in &updates {
+        let cmd = match update.kind {
+            "core" => "wp core update && wp core update-db".to_string(),
+            kind => format!("wp {} update {}", kind, update.name),
+        };
+        println!("{}", console::style(&cmd).green());
+        let status = cli_conn.exec(format!("cd /var/www/html && {}", cmd))?;
+        if status != 0 {
+            return Err(cli_err!(Ssh, "Failed updating {}", update.name));
+        }
+    }
+    if !skip_smoke {
+        let server_base = env.workdir_dir.join("server/base");
+        let dev_compose = compose::resolve(env, &project.dir(env), ComposeEnv::Dev, &server_base)?;
+        smoke_check(docker::dev_http_port(&dev_compose))
+            .map_err(|e| cli_err!(Other, "{}, lock is not updated", e))?;
+    }
+    let install_data = wp::wp_install_data(&cli_conn)?;
+    let lock = lock_from(core_version(&cli_conn)?, &install_data, Some(&local_data));
+    write_lock(env, project, &lock)?;
+    println!("Pinned versions in {}", LOCK_FILE);
+    Ok(())
+}
+
+/// Installs versions of the lock that differ from those on
+/// the site. Plugins and themes failing to install, like those
+/// not on wordpress.org, are left as they are with a warning
+pub fn install_locked(cli_conn: &SshConn, lock: &WpLock) -> Result<()> {
+    let install_data = wp::wp_install_data(cli_conn)?;
+    let installed = lock_from(core_version(cli_conn)?, &install_data, None);
+    for (kind, name, locked, current) in lock_diff(lock, &installed) {
+        println!(
+            "Installing {} {} {}, was {}",
+            kind,
+            name,
+            locked,
+            current.unwrap_or("none")
+        );
+        let cmd = match kind {
+            "core" => format!(
+                "wp core update --version={} --force && wp core update-db",
+                locked
+            ),
+            kind => format!("wp {} install {} --version={} --force", kind, name, locked),
+        };
+        let status = cli_conn.exec(format!("cd /var/www/html && {}", cmd))?;
+        if status != 0 && kind == "core" {
+            return Err(cli_err!(Ssh, "Failed installing {} {}", name, locked));
+        } else if status != 0 {
+            println!(
+                "{}",
+                console::style(format!(
+                    "Could not install {} {} {}, skipped",
+                    kind, name, locked
+                ))
+                .yellow()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Installs the locked versions on environment
+pub fn updates_install(env: &CliEnv, project: &ProjectConfig, on_server: bool) -> Result<()> {
+    let lock = match read_lock(env, project)? {
+        Some(lock) => lock,
+        None => {
+            return Err(cli_err!(
+                Config,
+                "No {}, run `updates apply` on dev first",
+                LOCK_FILE
+            ))
+        }
+    };
+    let cli_conn = wp::wp_cli_conn(env, project, on_server)?;
+    install_locked(&cli_conn, &lock)?;
+    println!("Installed locked versions");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_lock_with_installed() {
+        let mut lock = WpLock {
+            core: Some("5.3.2".into()),
+            ..Default::default()
+        };
+        lock.plugins.insert("akismet".into(), "4.1.3".into());
+        lock.plugins.insert("pods".into(), "2.7.16".into());
+        lock.themes.insert("twentytwenty".into(), "1.1".into());
+        let mut installed = WpLock {
+            core: Some("5.3.2".into()),
+            ..Default::default()
+        };
+        installed.plugins.insert("akismet".into(), "4.1.2".into());
+        installed.themes.insert("twentytwenty".into(), "1.1".into());
+        assert_eq!(
+            lock_diff(&lock, &installed),
+            vec![
+                ("plugin", "akismet", "4.1.3", Some("4.1.2")),
+                ("plugin", "pods", "2.7.16", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_must_use_plugins_out_of_lock() {
+        let plugin = |name: &str, status: &str| wp::WpInstallPlugin {
+            name: name.into(),
+            status: status.into(),
+            update: "none".into(),
+            version: "1.0".into(),
+            update_version: None,
+        };
+        let mut install_data = WpInstallData {
+            plugins: Default::default(),
+            themes: Default::default(),
+        };
+        for p in vec![
+            plugin("akismet", "active"),
+            plugin("object-cache.php", "dropin"),
+            plugin("mu-loader", "must-use"),
+        ] {
+            install_data.plugins.insert(p.name.clone(), p);
+        }
+        let lock = lock_from("5.3.2".into(), &install_data, None);
+        assert_eq!(lock.plugins.keys().collect::<Vec<_>>(), vec!["akismet"]);
+    }
+}
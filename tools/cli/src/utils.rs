@@ -1,3 +1,4 @@
+use crate::er::{self, Result};
 use console::style;
 use dialoguer::{theme, Input, Select};
 use std::fs;
@@ -34,7 +35,7 @@ impl ConfigDir {
 
     /// Write to a file relative to config dir
     /// Will ensure parent dir exist and create if not
-    pub fn write(&self, file: &str, content: &str) -> io::Result<()> {
+    pub fn write(&self, file: &str, content: &str) -> Result<()> {
         write_file(&self.filepath(file), content)
     }
 }
@@ -201,52 +202,44 @@ pub fn files_in_dir(dir: &Path) -> io::Result<Vec<String>> {
     }
 }
 
-pub fn name_after_prefix(full_path: &Path, prefix: &Path) -> io::Result<String> {
+pub fn name_after_prefix(full_path: &Path, prefix: &Path) -> Result<String> {
     match full_path.strip_prefix(&prefix) {
         Ok(stripped) => {
             // Todo: Could verify one component
             match stripped.file_name() {
                 Some(name) => Ok(name.to_string_lossy().to_string()),
-                None => io_err(format!("Could not get name from: {:?}", stripped)),
+                None => Err(cli_err!(Io, "Could not get name from: {:?}", stripped)),
             }
         }
-        Err(e) => io_err(format!("Error strip prefix: {:?}", e)),
+        Err(e) => Err(cli_err!(Io, "Error strip prefix: {:?}", e)),
     }
 }
 
-pub fn file_name_string(path: &Path) -> io::Result<String> {
+pub fn file_name_string(path: &Path) -> Result<String> {
     match path.file_name() {
         Some(file_name) => Ok(file_name.to_string_lossy().to_string()),
-        None => io_err(format!("Could not get file_name from {:?}", path)),
+        None => Err(cli_err!(Io, "Could not get file_name from {:?}", path)),
     }
 }
 
-pub fn ensure_parent_dir(path: &Path) -> io::Result<()> {
+pub fn ensure_parent_dir(path: &Path) -> Result<()> {
     match path.parent() {
         Some(parent) => {
             if parent.is_dir() {
                 Ok(())
             } else {
                 fs::create_dir_all(parent)
+                    .map_err(|e| er::io_msg(format!("Could not create {:?}", parent), e))
             }
         }
-        None => io_err(format!("Could not resolve parent of {:?}", path)),
+        None => Err(cli_err!(Io, "Could not resolve parent of {:?}", path)),
     }
 }
 
 /// Ensures parent dir and writes content
-pub fn write_file(path: &Path, content: &str) -> io::Result<()> {
+pub fn write_file(path: &Path, content: &str) -> Result<()> {
     ensure_parent_dir(path)?;
-    fs::write(path, content)
-}
-
-/// Helper function for custom io error messages
-pub fn io_error<M: Into<String>>(msg: M) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, msg.into())
-}
-
-pub fn io_err<T, M: Into<String>>(msg: M) -> io::Result<T> {
-    Err(io::Error::new(io::ErrorKind::Other, msg.into()))
+    fs::write(path, content).map_err(|e| er::io_msg(format!("Could not write {:?}", path), e))
 }
 
 /// There should only be one current process
@@ -384,3 +377,16 @@ pub fn now_formatted() -> String {
     let datetime: chrono::DateTime<chrono::Utc> = system_time.into();
     format!("{}", datetime.format("%Y-%m-%d %T"))
 }
+
+/// Url, directory and php friendly name,
+/// "feature/New theme" -> "feature-new-theme"
+pub fn slug(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect::<String>()
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,368 @@
+// Wordpress and php versions of a project. They are given to
+// the wordpress images as build args, choosing the tag of the
+// official image, and on dev a matching xdebug release.
+// `dev --matrix` runs the dev site against several php versions
+// at once, as extra wordpress containers on the dev network.
+
+use crate::compose::{
+    ComposeExternal, ComposeNetwork, ComposeService, ComposeVolume, ComposeYml, PROJECT_NAME,
+};
+use crate::docker;
+use crate::er::{self, Result};
+use crate::project::{self, ProjectConfig};
+use crate::updates;
+use crate::utils::CliEnv;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+pub const WP_SERVICE: &str = "wordpress-container";
+
+/// Compose project and file of matrix containers
+const MATRIX_PROJECT: &str = "basematrix";
+const MATRIX_FILE: &str = "docker/compose.matrix.yml";
+const MATRIX_FIRST_PORT: u16 = 8100;
+
+/// Tag of the official wordpress image. None keeps
+/// the default of the Dockerfiles
+pub fn wordpress_tag(wp: Option<&str>, php: Option<&str>) -> Option<String> {
+    match (wp, php) {
+        (None, None) => None,
+        (Some(wp), None) => Some(format!("{}-apache", wp)),
+        (None, Some(php)) => Some(format!("php{}-apache", php)),
+        (Some(wp), Some(php)) => Some(format!("{}-php{}-apache", wp, php)),
+    }
+}
+
+/// Newest xdebug supporting php version. Settings differ
+/// from xdebug 3, which the dev Dockerfile handles
+pub fn xdebug_package(php: &str) -> &'static str {
+    let mut parts = php.split('.').map(|part| part.parse::<u32>().ok());
+    let major = parts.next().and_then(|part| part);
+    let minor = parts.next().and_then(|part| part);
+    match (major, minor) {
+        (Some(5), _) => "xdebug-2.5.5",
+        (Some(7), Some(0)) => "xdebug-2.7.2",
+        (Some(7), _) => "xdebug-2.9.8",
+        _ => "xdebug",
+    }
+}
+
+/// Build args of the wordpress images. On dev, xdebug is set
+/// with any other tag than the default, the latest one when
+/// the tag leaves php to the image
+pub fn build_args(wp: Option<&str>, php: Option<&str>, dev: bool) -> BTreeMap<String, String> {
+    let mut args = BTreeMap::new();
+    if let Some(tag) = wordpress_tag(wp, php) {
+        args.insert("WORDPRESS_TAG".to_string(), tag);
+        if dev {
+            args.insert(
+                "XDEBUG_PACKAGE".to_string(),
+                php.map_or("xdebug", xdebug_package).to_string(),
+            );
+        }
+    }
+    args
+}
+
+/// Sets build args from project versions
+/// on the wordpress service
+pub fn apply(project: &ProjectConfig, compose: &mut ComposeYml, dev: bool) {
+    let args = build_args(
+        project.wp_version.as_ref().map(String::as_str),
+        project.php_version.as_ref().map(String::as_str),
+        dev,
+    );
+    if let Some(wp) = compose.services.get_mut(WP_SERVICE) {
+        wp.add_build_args(args);
+    }
+}
+
+fn version_arg(value: Option<&str>) -> Option<String> {
+    match value.map(str::trim) {
+        Some("") | None => None,
+        Some(value) => Some(value.to_string()),
+    }
+}
+
+/// Prints versions of project, or sets those given.
+/// An empty value returns to the image default
+pub fn versions_cmd(
+    env: &CliEnv,
+    mut project: ProjectConfig,
+    wp: Option<&str>,
+    php: Option<&str>,
+) -> Result<()> {
+    if wp.is_some() || php.is_some() {
+        if wp.is_some() {
+            project.wp_version = version_arg(wp);
+        }
+        if php.is_some() {
+            project.php_version = version_arg(php);
+        }
+        project::write_config(env, &project)?;
+        println!("Run `rebuild wordpress-container` to use the new versions on dev");
+    }
+    let default = |v: &Option<String>| v.clone().unwrap_or_else(|| "image default".into());
+    println!("Wordpress: {}", default(&project.wp_version));
+    println!("Php: {}", default(&project.php_version));
+    if let Some(tag) = wordpress_tag(
+        project.wp_version.as_ref().map(String::as_str),
+        project.php_version.as_ref().map(String::as_str),
+    ) {
+        println!("Image: wordpress:{}", tag);
+    }
+    Ok(())
+}
+
+fn matrix_service(php: &str) -> String {
+    format!("wp-php{}", php.replace('.', ""))
+}
+
+/// Name of named volume in volume entry, None for bind mounts
+fn named_volume(volume: &str) -> Option<&str> {
+    let mut parts = volume.splitn(2, ':');
+    let source = parts.next().unwrap_or_default();
+    match parts.next() {
+        Some(_)
+            if !source.is_empty()
+                && !source.starts_with('/')
+                && !source.starts_with('.')
+                && !source.starts_with('~') =>
+        {
+            Some(source)
+        }
+        _ => None,
+    }
+}
+
+/// Compose file with a wordpress service per php version, on
+/// the dev network, using the dev database and volumes
+fn matrix_compose(
+    project: &ProjectConfig,
+    dev: &ComposeYml,
+    php_versions: &[String],
+) -> Result<ComposeYml> {
+    let wp = match dev.services.get(WP_SERVICE) {
+        Some(wp) => wp,
+        None => return Err(cli_err!(Docker, "No {} in dev compose", WP_SERVICE)),
+    };
+    let mut services = BTreeMap::new();
+    let mut volumes = BTreeMap::new();
+    for (i, php) in php_versions.iter().enumerate() {
+        let mut service = ComposeService {
+            build: wp.build.clone(),
+            image: wp.image.clone(),
+            volumes: wp.volumes.clone(),
+            environment: wp.environment.clone(),
+            other: wp.other.clone(),
+            ports: vec![format!("127.0.0.1:{}:80", MATRIX_FIRST_PORT + i as u16)],
+            ..Default::default()
+        };
+        service.add_build_args(build_args(
+            project.wp_version.as_ref().map(String::as_str),
+            Some(php),
+            true,
+        ));
+        for volume in &service.volumes {
+            if let Some(name) = named_volume(volume) {
+                volumes.insert(
+                    name.to_string(),
+                    ComposeVolume {
+                        external: Some(ComposeExternal {
+                            name: format!("{}_{}", PROJECT_NAME, name),
+                        }),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        services.insert(matrix_service(php), service);
+    }
+    let mut networks = BTreeMap::new();
+    networks.insert(
+        "default".to_string(),
+        ComposeNetwork {
+            external: Some(ComposeExternal {
+                name: format!("{}_default", PROJECT_NAME),
+            }),
+            ..Default::default()
+        },
+    );
+    Ok(ComposeYml {
+        version: dev.version.clone(),
+        services,
+        volumes,
+        networks,
+    })
+}
+
+fn matrix_compose_cmd(env: &CliEnv, project: &ProjectConfig, args: &[&str]) -> Result<()> {
+    let status = std::process::Command::new("docker-compose")
+        .current_dir(project.dir(env))
+        .args(&["-p", MATRIX_PROJECT, "-f", MATRIX_FILE])
+        .args(args)
+        .status()
+        .map_err(|e| er::io_msg("Could not run docker-compose", e))?;
+    if !status.success() {
+        return Err(cli_err!(Docker, "docker-compose {} failed", args.join(" ")));
+    }
+    Ok(())
+}
+
+/// Smoke checks until the site responds, or time is out
+fn wait_for_site(addr: &str) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        let result = ["/", "/wp-login.php"]
+            .iter()
+            .try_for_each(|path| updates::smoke_check_path(addr, path));
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if started.elapsed() > Duration::from_secs(90) {
+                    return Err(e);
+                }
+                std::thread::sleep(Duration::from_secs(3));
+            }
+        }
+    }
+}
+
+/// Starts the dev site with each php version in parallel,
+/// next to running dev containers, and smoke checks each
+pub fn matrix_up(env: &CliEnv, project: &ProjectConfig, php_versions: &[String]) -> Result<()> {
+    if php_versions.is_empty() {
+        return Err(cli_err!(Config, "No php versions given"));
+    }
+    crate::modules::of(project)?.gen_dev(env, project)?;
+    let dev = docker::write_dev_compose(env, project)?;
+    let matrix = matrix_compose(project, &dev, php_versions)?;
+    let problems = matrix.validate();
+    if !problems.is_empty() {
+        return Err(cli_err!(
+            Docker,
+            "Invalid matrix compose:\n{}",
+            problems.join("\n")
+        ));
+    }
+    project.write_file(env, MATRIX_FILE, &matrix.render()?)?;
+    println!(
+        "Starting php {}, dev must be running",
+        php_versions.join(", ")
+    );
+    matrix_compose_cmd(env, project, &["up", "-d", "--build"])?;
+    let mut failed = Vec::new();
+    for (i, php) in php_versions.iter().enumerate() {
+        let addr = format!("127.0.0.1:{}", MATRIX_FIRST_PORT + i as u16);
+        match wait_for_site(&addr) {
+            Ok(()) => println!(
+                "Php {}: http://{} {}",
+                php,
+                addr,
+                console::style("ok").green()
+            ),
+            Err(e) => {
+                println!(
+                    "Php {}: http://{} {}",
+                    php,
+                    addr,
+                    console::style(format!("{}", e)).red()
+                );
+                failed.push(php.clone());
+            }
+        }
+    }
+    println!("Remove with `dev --matrix-down`");
+    if !failed.is_empty() {
+        return Err(cli_err!(Docker, "Failed on php {}", failed.join(", ")));
+    }
+    Ok(())
+}
+
+/// Removes matrix containers, keeping dev volumes
+pub fn matrix_down(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+    if !project.dir_and(env, MATRIX_FILE).is_file() {
+        println!("No matrix running");
+        return Ok(());
+    }
+    matrix_compose_cmd(env, project, &["down"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_image_tag() {
+        assert_eq!(wordpress_tag(None, None), None);
+        assert_eq!(
+            wordpress_tag(Some("5.3"), Some("7.4")),
+            Some("5.3-php7.4-apache".into())
+        );
+        assert_eq!(
+            wordpress_tag(None, Some("8.1")),
+            Some("php8.1-apache".into())
+        );
+        assert_eq!(wordpress_tag(Some("5.2"), None), Some("5.2-apache".into()));
+    }
+
+    #[test]
+    fn picks_xdebug_for_php() {
+        assert_eq!(xdebug_package("5.6"), "xdebug-2.5.5");
+        assert_eq!(xdebug_package("7.0"), "xdebug-2.7.2");
+        assert_eq!(xdebug_package("7.4"), "xdebug-2.9.8");
+        assert_eq!(xdebug_package("8.2"), "xdebug");
+    }
+
+    #[test]
+    fn sets_xdebug_with_image_tag() {
+        assert!(build_args(None, None, true).is_empty());
+        let args = build_args(Some("5.3"), None, true);
+        assert_eq!(args["WORDPRESS_TAG"], "5.3-apache");
+        assert_eq!(args["XDEBUG_PACKAGE"], "xdebug");
+        let args = build_args(Some("5.3"), Some("7.2"), true);
+        assert_eq!(args["XDEBUG_PACKAGE"], "xdebug-2.9.8");
+        assert!(!build_args(Some("5.3"), None, false).contains_key("XDEBUG_PACKAGE"));
+    }
+
+    #[test]
+    fn builds_matrix_on_dev_network() {
+        let dev = ComposeYml::parse(
+            r#"
+version: '3.3'
+services:
+  wordpress-container:
+    build: /srv/dev/wp-dev
+    depends_on:
+      - db
+    ports:
+      - "8000:80"
+    volumes:
+      - "wp-uploads:/var/www/html/wp-content/uploads/"
+      - "/home/dev/project/wp:/var/www/html"
+"#,
+        )
+        .unwrap();
+        let matrix = matrix_compose(
+            &ProjectConfig::default(),
+            &dev,
+            &["7.2".to_string(), "8.0".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            matrix.services.keys().collect::<Vec<_>>(),
+            vec!["wp-php72", "wp-php80"]
+        );
+        let wp = &matrix.services["wp-php80"];
+        assert_eq!(wp.ports, vec!["127.0.0.1:8101:80".to_string()]);
+        assert!(wp.depends_on.is_empty());
+        assert_eq!(
+            matrix.volumes["wp-uploads"]
+                .external
+                .as_ref()
+                .map(|e| e.name.as_str()),
+            Some("base_wp-uploads")
+        );
+        assert!(matrix.validate().is_empty());
+    }
+}
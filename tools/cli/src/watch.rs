@@ -0,0 +1,176 @@
+// Dev mode watching plugins/ and themes/ while containers
+// run. New or removed plugins and themes are mounted and
+// activated, and proxy cache is cleared on template changes
+
+use crate::compose::{self, ComposeEnv};
+use crate::docker;
+use crate::er::{self, Result};
+use crate::project::ProjectConfig;
+use crate::utils::{self, CliEnv};
+use crate::wp;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Extensions of files affecting rendered pages
+const TEMPLATE_EXTENSIONS: [&str; 5] = ["php", "twig", "html", "css", "js"];
+
+/// Local plugins and themes, which decide dev mounts
+fn mounted_items(env: &CliEnv, project: &ProjectConfig) -> Result<BTreeSet<String>> {
+    let local_data = wp::get_local_site_data(env, project)?;
+    Ok(local_data
+        .plugins
+        .keys()
+        .map(|name| format!("plugins/{}", name))
+        .chain(
+            local_data
+                .themes
+                .keys()
+                .map(|name| format!("themes/{}", name)),
+        )
+        .collect())
+}
+
+/// Changes collected from a batch of events
+#[derive(Default)]
+struct Changes {
+    templates: bool,
+    plugin_conf: bool,
+}
+impl Changes {
+    fn add(&mut self, path: &Path) {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if TEMPLATE_EXTENSIONS.contains(&extension.as_str()) {
+            self.templates = true;
+        }
+        if path.file_name().map_or(false, |f| f == "plugin.json") {
+            self.plugin_conf = true;
+        }
+    }
+}
+
+fn event_paths(event: DebouncedEvent) -> Vec<PathBuf> {
+    match event {
+        DebouncedEvent::Create(path)
+        | DebouncedEvent::Write(path)
+        | DebouncedEvent::Remove(path)
+        | DebouncedEvent::Chmod(path) => vec![path],
+        DebouncedEvent::Rename(from, to) => vec![from, to],
+        DebouncedEvent::Error(e, path) => {
+            eprintln!("Watch error: {:?}", e);
+            path.into_iter().collect()
+        }
+        DebouncedEvent::NoticeWrite(_)
+        | DebouncedEvent::NoticeRemove(_)
+        | DebouncedEvent::Rescan => Vec::new(),
+    }
+}
+
+/// Purges cache of dev proxy on http port through
+/// its /--clear-cache endpoint
+pub fn clear_proxy_cache(http_port: u16) -> Result<()> {
+    let proxy_err = |e: std::io::Error| er::network_msg("Could not reach dev proxy", e);
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", http_port)).map_err(proxy_err)?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .map_err(proxy_err)?;
+    stream
+        .write_all(b"GET /--clear-cache HTTP/1.0\r\nHost: localhost\r\n\r\n")
+        .map_err(proxy_err)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).map_err(proxy_err)?;
+    match response.lines().next() {
+        Some(status) if status.contains(" 200") => Ok(()),
+        status => Err(cli_err!(Other, "Clear cache failed: {:?}", status)),
+    }
+}
+
+/// Starts dev containers detached, with `up -d` followed by
+/// any given args, then watches plugins/ and themes/ until ctrl-c
+pub fn dev_watch(env: &CliEnv, project: ProjectConfig, mut args: Vec<String>) -> Result<()> {
+    let mut up_args = vec!["up".to_string(), "-d".to_string()];
+    up_args.append(&mut args);
+    let mut current_process =
+        docker::dev_cmd(env, utils::CurrentProcess::new(), project.clone(), up_args)?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::watcher(tx, Duration::from_millis(500))
+        .map_err(|e| cli_err!(Io, "Could not start watcher: {:?}", e))?;
+    for dir in &["plugins", "themes"] {
+        let dir = project.dir_and(env, dir);
+        std::fs::create_dir_all(&dir).map_err(er::io)?;
+        watcher
+            .watch(&dir, RecursiveMode::Recursive)
+            .map_err(|e| cli_err!(Io, "Could not watch {:?}: {:?}", dir, e))?;
+    }
+    let server_base = env.workdir_dir.join("server/base");
+    let dev_compose = compose::resolve(env, &project.dir(env), ComposeEnv::Dev, &server_base)?;
+    let http_port = docker::dev_http_port(&dev_compose);
+    let mut mounted = mounted_items(env, &project)?;
+    println!(
+        "{}",
+        console::style("Watching plugins/ and themes/, ctrl-c to stop").green()
+    );
+    loop {
+        let event = match rx.recv() {
+            Ok(event) => event,
+            Err(_) => return Err(cli_err!(Other, "Watcher stopped")),
+        };
+        // Collect what arrives shortly after, as
+        // a new folder often comes with many files
+        let mut changes = Changes::default();
+        for path in event_paths(event) {
+            changes.add(&path);
+        }
+        while let Ok(event) = rx.recv_timeout(Duration::from_millis(300)) {
+            for path in event_paths(event) {
+                changes.add(&path);
+            }
+        }
+
+        // Files may be mid-write, keep watching and
+        // check again on the next change
+        let current = match mounted_items(env, &project) {
+            Ok(current) => current,
+            Err(e) => {
+                env.error_msg(&format!("Could not read plugins and themes: {}", e));
+                continue;
+            }
+        };
+        let mounts_changed = current != mounted;
+        if mounts_changed {
+            println!("Plugins or themes changed, recreating containers");
+            // dev.yml is regenerated by dev_cmd, and
+            // compose recreates containers with changed mounts
+            current_process = docker::dev_cmd(
+                env,
+                current_process,
+                project.clone(),
+                vec![
+                    "up".to_string(),
+                    "-d".to_string(),
+                    "wordpress-container".to_string(),
+                    "wp-cli".to_string(),
+                ],
+            )?;
+            mounted = current;
+        }
+        if mounts_changed || changes.plugin_conf {
+            if let Err(e) = wp::sync_local(env, project.clone(), false) {
+                env.error_msg(&format!("Sync local failed: {}", e));
+            }
+        }
+        if mounts_changed || changes.templates {
+            match clear_proxy_cache(http_port) {
+                Ok(()) => println!("Cleared proxy cache"),
+                Err(e) => env.error_msg(&format!("{}", e)),
+            }
+        }
+    }
+}
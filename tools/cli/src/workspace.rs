@@ -2,41 +2,39 @@
 // Current use case is setting up a git repo
 // for the workspace
 
-use crate::git::{self, SetupGitError};
+use crate::er::{self, Result};
+use crate::git;
 use crate::utils::{self, CliEnv};
 use futures::{
     future::{self, Either},
     Future,
 };
-use std::io;
 
 /// Initializes a git repo for the workspace, using
 /// defined git account
 pub fn init_git<'a>(env: &'a CliEnv) -> impl Future<Item = (), Error = failure::Error> + 'a {
     let git_config = match git::select_account(env, None) {
         Ok(git_account) => git_account,
-        Err(e) => return Either::A(future::err(failure::Error::from(e))),
+        Err(e) => return Either::A(future::err(e)),
     };
     let repo_name = match env.get_input("Repo name", Some("workspace".into())) {
         Ok(repo_name) => repo_name,
-        Err(e) => return Either::A(future::err(failure::Error::from(e))),
+        Err(e) => return Either::A(future::err(er::io(e))),
     };
 
     let dir_git = match git::inspect_git(env.config_dirs.config_root.clone()) {
         Ok(dir_git) => dir_git,
-        Err(e) => return Either::A(future::err(failure::Error::from(e))),
+        Err(e) => return Either::A(future::err(e)),
     };
 
     let git_uri = git_config.repo_uri(repo_name);
 
-    Either::B(
-        git::setup_git_dir(env, dir_git, git_config, git_uri).map_err(|e| failure::Error::from(e)),
-    )
+    Either::B(git::setup_git_dir(env, dir_git, git_config, git_uri))
 }
 
-/// Given git username and password, can recreate
+/// Given git username and repo name, can recreate
 /// the workspace (with todos)
-pub fn clone_workspace(env: &CliEnv) -> io::Result<()> {
+pub fn clone_workspace(env: &CliEnv) -> Result<()> {
     let dir_git = git::inspect_git(env.config_dirs.config_root.clone())?;
     if dir_git.repo.is_some() {
         println!("Found existing repo, not proceeding with clone");
@@ -44,46 +42,334 @@ pub fn clone_workspace(env: &CliEnv) -> io::Result<()> {
     } else if dir_git.has_files {
         println!("Found no repo, but existing files. Not proceeding with clone");
         println!("Could initialize repository instead");
-        return utils::io_err("No repo, but files");
+        return Err(cli_err!(Config, "No repo, but files"));
     }
 
     let git_user = env.get_input("Git user", None)?;
     let repo_name = env.get_input("Repo name", Some("workspace".into()))?;
-    let git_pass = env.get_pass("Git password")?;
-
-    let repo_uri = format!("https://github.com/{}/{}", git_user, repo_name);
+    let provider = git::select_provider(env, None)?;
+    let protocols = vec!["https", "ssh"];
+    let ssh = env.select("Protocol", &protocols, Some(0))? == 1;
+    // Git accounts are part of the workspace, so we can't
+    // use them here. Default to credential helper or ssh-agent
+    let auth_options = vec!["Credential helper/ssh-agent", "Token"];
+    let use_token = env.select("Authentication", &auth_options, Some(0))? == 1;
+    let token = if use_token {
+        env.get_pass("Git token")?
+    } else {
+        String::new()
+    };
+    let clone_account = git::GitConfig {
+        user: git_user,
+        email: String::new(),
+        token,
+        ssh,
+        ssh_key: None,
+        provider,
+    };
+    let repo_uri = clone_account.repo_uri(repo_name);
 
-    Ok(git::clone_repo(
-        git_user,
-        git_pass,
+    match git::clone_repo(
+        if use_token { Some(&clone_account) } else { None },
         &repo_uri,
         &env.config_dirs.config_root,
-    ))
+    ) {
+        Ok(_repo) => Ok(()),
+        Err(e) => Err(er::git_msg("Clone failed", e)),
+    }
 }
 
-// todo: Could run this automatically after config files have changed
-// when repo is initialized, could propose to add if not
-pub fn push_workspace(env: &CliEnv) -> io::Result<()> {
+fn open_workspace_repo(env: &CliEnv) -> Result<git2::Repository> {
     let dir_git = git::inspect_git(env.config_dirs.config_root.clone())?;
-    let repo = match dir_git.repo {
+    match dir_git.repo {
         Some(repo) => {
             println!("Found repo");
-            repo
+            // todo: Possibly not necessary, could test,
+            // also good to err on side of doing it
+            std::env::set_current_dir(&dir_git.dir).map_err(er::io)?;
+            Ok(repo)
         }
         None => {
             // todo: propose run init
             println!("Please try workspace init-git, or setup repository manually");
-            return utils::io_err("No repo");
+            Err(cli_err!(Git, "No repo"))
         }
-    };
-    // todo: Possibly not necessary, could test,
-    // also good to err on side of doing it
-    std::env::set_current_dir(&dir_git.dir)?;
+    }
+}
+
+// todo: Could run this automatically after config files have changed
+// when repo is initialized, could propose to add if not
+pub fn push_workspace(env: &CliEnv) -> Result<()> {
+    let repo = open_workspace_repo(env)?;
     let tree = git::add_all(&repo)?;
     let default_msg = format!("Workspace commit: {}", utils::now_formatted());
     let message = env.get_input("Message", Some(default_msg))?;
     git::commit(&repo, tree, &message)?;
-    git::push_origin_master(&repo)?;
+    let git_config = git::account_for_repo(env, &repo);
+    // Pull before push, so we don't clobber changes
+    // from others sharing the workspace
+    pull_repo(env, &repo, git_config.as_ref(), false)?;
+    git::push_origin(&repo, git_config.as_ref())?;
     println!("Commit and push to origin successful");
     Ok(())
 }
+
+/// Fetches and merges or rebases changes from origin
+pub fn pull_workspace(env: &CliEnv, rebase: bool) -> Result<()> {
+    let repo = open_workspace_repo(env)?;
+    if git::has_changes(&repo)? {
+        env.error_msg("There are uncommitted changes in the workspace.");
+        env.error_msg("Please run workspace push, which will also pull.");
+        return Err(cli_err!(Git, "Uncommitted changes"));
+    }
+    let git_config = git::account_for_repo(env, &repo);
+    pull_repo(env, &repo, git_config.as_ref(), rebase)
+}
+
+fn pull_repo(
+    env: &CliEnv,
+    repo: &git2::Repository,
+    git_config: Option<&git::GitConfig>,
+    rebase: bool,
+) -> Result<()> {
+    let branch = git::current_branch(repo)?;
+    let fetched = match git::fetch_origin(repo, &branch, git_config)? {
+        Some(fetched) => fetched,
+        None => return Ok(()),
+    };
+    if rebase {
+        git::rebase_onto(repo, &fetched, |repo| resolve_conflicts(env, repo, true))?;
+        println!("Rebased onto origin");
+        return Ok(());
+    }
+    match git::merge_fetched(repo, &fetched)? {
+        git::MergeOutcome::UpToDate => println!("Already up to date"),
+        git::MergeOutcome::FastForward => println!("Fast-forwarded to origin"),
+        git::MergeOutcome::Merged => {
+            git::commit_merge(repo, &fetched, "Merge workspace from origin")?;
+            println!("Merged changes from origin");
+        }
+        git::MergeOutcome::Conflicts => {
+            resolve_conflicts(env, repo, false)?;
+            git::commit_merge(repo, &fetched, "Merge workspace from origin")?;
+            println!("Merged changes from origin, with resolved conflicts");
+        }
+    }
+    Ok(())
+}
+
+/// Local and remote side of a conflict. When rebasing, the
+/// local commit being replayed is "theirs", and "ours" is
+/// the remote commit it is applied onto
+fn sides(conflict: &git::Conflict, rebase: bool) -> (&Option<Vec<u8>>, &Option<Vec<u8>>) {
+    if rebase {
+        (&conflict.theirs, &conflict.ours)
+    } else {
+        (&conflict.ours, &conflict.theirs)
+    }
+}
+
+/// Interactive resolution of conflicts in the index.
+/// Config files are json, so these are merged key by key
+/// against the common ancestor, asking only for keys changed
+/// differently on both sides. Other files are resolved
+/// by choosing a side.
+fn resolve_conflicts(env: &CliEnv, repo: &git2::Repository, rebase: bool) -> Result<()> {
+    use serde_json::Value;
+    for conflict in git::conflicts(repo)? {
+        println!(
+            "Conflict in: {}",
+            console::style(conflict.path.to_string_lossy()).magenta()
+        );
+        let (local, remote) = sides(&conflict, rebase);
+        match (local, remote) {
+            (Some(_), None) => println!("Changed locally, deleted remotely"),
+            (None, Some(_)) => println!("Deleted locally, changed remotely"),
+            (None, None) => println!("Deleted on both sides"),
+            (Some(_), Some(_)) => (),
+        }
+        // Some(None) for a deleted side, None when not json
+        let json = |content: &Option<Vec<u8>>| match content {
+            Some(content) => serde_json::from_slice::<Value>(content).ok().map(Some),
+            None => Some(None),
+        };
+        let ancestor = json(&conflict.ancestor).and_then(|ancestor| ancestor);
+        let resolved = match (json(local), json(remote)) {
+            (Some(local), Some(remote)) => {
+                match merge_json(env, "", ancestor.as_ref(), local, remote)? {
+                    Some(merged) => {
+                        let mut json_str = serde_json::to_string_pretty(&merged)
+                            .map_err(|e| cli_err!(Other, "{:?}", e))?;
+                        json_str.push('\n');
+                        Some(json_str.into_bytes())
+                    }
+                    None => None,
+                }
+            }
+            _ => select_side(env, &conflict, rebase)?,
+        };
+        git::resolve_conflict(
+            repo,
+            &conflict.path,
+            resolved.as_ref().map(|c| c.as_slice()),
+        )?;
+    }
+    Ok(())
+}
+
+/// Resolve by keeping one side in full
+fn select_side(env: &CliEnv, conflict: &git::Conflict, rebase: bool) -> Result<Option<Vec<u8>>> {
+    let (local, remote) = sides(conflict, rebase);
+    let describe = |content: &Option<Vec<u8>>| match content {
+        Some(content) => format!("{} bytes", content.len()),
+        None => "deleted".to_string(),
+    };
+    let options = vec![
+        format!("Keep local ({})", describe(local)),
+        format!("Keep remote ({})", describe(remote)),
+    ];
+    match env.select("Resolve", &options, Some(0))? {
+        0 => Ok(local.clone()),
+        _ => Ok(remote.clone()),
+    }
+}
+
+/// Three way merge of json, recursively by key for objects.
+/// None is a deleted file or missing key. Values changed on
+/// one side only are taken from it, values changed differently
+/// on both sides are prompted for
+fn merge_json(
+    env: &CliEnv,
+    path: &str,
+    ancestor: Option<&serde_json::Value>,
+    ours: Option<serde_json::Value>,
+    theirs: Option<serde_json::Value>,
+) -> Result<Option<serde_json::Value>> {
+    use serde_json::Value;
+    if ours == theirs || theirs.as_ref() == ancestor {
+        return Ok(ours);
+    }
+    if ours.as_ref() == ancestor {
+        return Ok(theirs);
+    }
+    match (ours, theirs) {
+        (Some(Value::Object(mut ours)), Some(Value::Object(mut theirs))) => {
+            let ancestor = match ancestor {
+                Some(Value::Object(ancestor)) => Some(ancestor),
+                _ => None,
+            };
+            let mut merged = serde_json::Map::new();
+            // Keep order of local keys, then add new remote keys
+            let keys = ours
+                .keys()
+                .chain(theirs.keys().filter(|k| !ours.contains_key(*k)))
+                .cloned()
+                .collect::<Vec<_>>();
+            for key in keys {
+                let key_path = format!("{}/{}", path, key);
+                let value = merge_json(
+                    env,
+                    &key_path,
+                    ancestor.and_then(|ancestor| ancestor.get(&key)),
+                    ours.remove(&key),
+                    theirs.remove(&key),
+                )?;
+                if let Some(value) = value {
+                    merged.insert(key, value);
+                }
+            }
+            Ok(Some(Value::Object(merged)))
+        }
+        (ours, theirs) => {
+            let describe = |value: &Option<Value>| match value {
+                Some(value) => value.to_string(),
+                None => "deleted".to_string(),
+            };
+            let options = vec![
+                format!("Local: {}", describe(&ours)),
+                format!("Remote: {}", describe(&theirs)),
+            ];
+            let prompt = if path.is_empty() {
+                "Resolve".to_string()
+            } else {
+                format!("Value of {}", path)
+            };
+            match env.select(&prompt, &options, Some(0))? {
+                0 => Ok(ours),
+                _ => Ok(theirs),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merges_json_three_way() {
+        let env = CliEnv::new(std::env::temp_dir(), std::env::temp_dir());
+        let ancestor = json!({"name": "site", "wp_version": "5.3", "domain": "a.com"});
+        // Deleted locally and domain changed remotely,
+        // php version added locally
+        let ours = json!({"name": "site", "domain": "a.com", "php_version": "7.4"});
+        let theirs = json!({"name": "site", "wp_version": "5.3", "domain": "b.com"});
+        let merged = merge_json(&env, "", Some(&ancestor), Some(ours), Some(theirs)).unwrap();
+        assert_eq!(
+            merged,
+            Some(json!({"name": "site", "domain": "b.com", "php_version": "7.4"}))
+        );
+        // Unchanged remotely, the local delete is kept
+        let merged = merge_json(&env, "", Some(&ancestor), None, Some(ancestor.clone())).unwrap();
+        assert_eq!(merged, None);
+    }
+
+    #[test]
+    fn keeps_local_side_when_rebasing() {
+        let dir = std::env::temp_dir().join(format!("workspace-rebase-{}", std::process::id()));
+        let repo = git2::Repository::init(&dir).unwrap();
+        {
+            let mut config = repo.config().unwrap();
+            config.set_str("user.name", "test").unwrap();
+            config.set_str("user.email", "test@example.com").unwrap();
+        }
+        let sig = git::signature(&repo).unwrap();
+        let commit = |content: &str, parents: &[&git2::Commit]| {
+            std::fs::write(dir.join("notes.txt"), content).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(std::path::Path::new("notes.txt")).unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            repo.commit(None, &sig, &sig, content, &tree, parents)
+                .unwrap()
+        };
+        let base = repo.find_commit(commit("base\n", &[])).unwrap();
+        let remote = commit("remote\n", &[&base]);
+        let local = commit("local\n", &[&base]);
+        repo.reference("refs/remotes/origin/master", remote, true, "Fetch")
+            .unwrap();
+        repo.reference("refs/heads/master", local, true, "Commit")
+            .unwrap();
+        repo.set_head("refs/heads/master").unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+            .unwrap();
+        let fetched = repo
+            .find_reference("refs/remotes/origin/master")
+            .and_then(|fetched| repo.reference_to_annotated_commit(&fetched))
+            .unwrap();
+        // Choosing "Keep local" for each conflict
+        git::rebase_onto(&repo, &fetched, |repo| {
+            for conflict in git::conflicts(repo)? {
+                let (local, _remote) = sides(&conflict, true);
+                let kept = local.as_ref().map(|c| c.as_slice());
+                git::resolve_conflict(repo, &conflict.path, kept)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let content = std::fs::read_to_string(dir.join("notes.txt")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(content, "local\n");
+    }
+}
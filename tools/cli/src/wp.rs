@@ -1,177 +1,134 @@
+use crate::compose::ComposeYml;
 use crate::docker;
 use crate::er::{self, Result};
+use crate::modules::EnvModule;
 use crate::project::ProjectConfig;
 use crate::project_path::ProjectItemPaths;
 use crate::server::{self, SshConn, SyncSet};
+use crate::updates;
 use crate::utils::{self, CliEnv};
-use failure::format_err;
 use serde::Deserialize;
 use std::collections::BTreeMap;
 use std::collections::{HashMap, HashSet};
-use std::io;
 use std::path::{Path, PathBuf};
 
-/// While wp-specific, dev setup might be a different category
-pub fn gen_vscode_debug_config(env: &CliEnv, project: ProjectConfig) -> io::Result<()> {
-    // Using hjson here to preserve comments in json file
-    // edit: unfortunately doesn't preserve comments, at least
-    // parses it
-    use serde_hjson::{Map, Value};
-    // Collect mapping for plugins and themes
-    let mut mappings = Map::new();
-    let site_local = get_local_site_data(env, &project)?;
-    for (_name, plugin) in site_local.plugins {
-        mappings.insert(
-            plugin.paths.server_path.string(),
-            Value::String(format!(
-                "${{workspaceRoot}}/{}",
-                plugin.paths.from_project.cow()
-            )),
-        );
+/// Name of the wordpress module
+pub const MODULE: &str = "wordpress";
+
+/// Wordpress with plugins and themes of the project
+/// mounted in dev, and wp version build args
+pub struct WpModule;
+impl EnvModule for WpModule {
+    fn name(&self) -> &'static str {
+        MODULE
     }
-    for (_name, theme) in site_local.themes {
-        mappings.insert(
-            theme.paths.server_path.string(),
-            Value::String(format!(
-                "${{workspaceRoot}}/{}",
-                theme.paths.from_project.cow()
-            )),
-        );
+
+    fn gen_dev(&self, env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+        create_wp_docker_yml(env, project.clone())
     }
-    // Lastly, add fallback to wp root for all other files
-    mappings.insert(
-        "/var/www/html".to_string(),
-        Value::String("${workspaceRoot}/wp".to_string()),
-    );
-    // Assemble entry
-    let our_debug_key = "Wop";
-    use std::iter::FromIterator;
-    let config_entry = Value::Object(Map::from_iter(vec![
-        ("name".to_string(), Value::String(our_debug_key.to_string())),
-        ("request".to_string(), Value::String("launch".to_string())),
-        ("type".to_string(), Value::String("php".to_string())),
-        ("pathMappings".to_string(), Value::Object(mappings)),
-        ("port".to_string(), Value::I64(9002)),
-    ]));
-    let mut conf_path = project.dir(env);
-    conf_path.push(".vscode");
-    conf_path.push("launch.json");
-    let json_value = if conf_path.is_file() {
-        // Existing json file, replace wop entry
-        let conf_str = std::fs::read_to_string(&conf_path)?;
-        // Deserializing to generic json value to ease
-        // dealing with potientially unknown values
-        let mut json_val = serde_hjson::from_str::<serde_hjson::Value>(&conf_str)
-            .map_err(|e| utils::io_error(format!("Json decode error: {:?}", e)))?;
-        // If there is existing entry, replace, otherwise append
-        // Ugh, next time investigate api. There are many methods for stuff like this.
-        match &mut json_val {
-            Value::Object(ref mut map) => {
-                match map.get_mut("configurations") {
-                    Some(ref mut configurations) => match configurations {
-                        Value::Array(ref mut arr) => {
-                            // Check for name = "Wop"
-                            let index = arr.iter().position(|entry| match entry {
-                                Value::Object(map) => match map.get("name") {
-                                    Some(Value::String(name_val)) => name_val == our_debug_key,
-                                    _ => false,
-                                },
-                                _ => false,
-                            });
-                            match index {
-                                Some(index) => {
-                                    // Replace
-                                    arr[index] = config_entry;
-                                }
-                                None => {
-                                    // Push
-                                    arr.push(config_entry);
-                                }
-                            }
-                        }
-                        _ => {
-                            return utils::io_err(
-                                "Expected configurations array in .vscode/launch.json",
-                            )
-                        }
-                    },
-                    None => {
-                        return utils::io_err("Expected configurations key in .vscode/launch.json")
-                    }
-                }
-            }
-            _ => return utils::io_err("Expected object in .vscode/launch.json"),
-        }
-        json_val
-    } else {
-        // New json file
-        Value::Object(Map::from_iter(vec![
-            ("version".to_string(), Value::String("0.2.0".to_string())),
-            (
-                "configurations".to_string(),
-                Value::Array(vec![config_entry]),
-            ),
-        ]))
-    };
-    let json_str = serde_json::to_string_pretty(&to_json_val(json_value))
-        .map_err(|e| utils::io_error(format!("Serialize error: {:?}", e)))?;
-    utils::ensure_parent_dir(&conf_path)?;
-    std::fs::write(conf_path, json_str)
-}
 
-// Ugh. Got type error with hjson
-fn to_json_val(v: serde_hjson::Value) -> serde_json::Value {
-    match v {
-        serde_hjson::Value::Array(v) => {
-            serde_json::Value::Array(v.into_iter().map(|e| to_json_val(e)).collect())
-        }
-        serde_hjson::Value::Bool(v) => serde_json::Value::Bool(v),
-        serde_hjson::Value::F64(v) => serde_json::Value::Number(serde_json::Number::from(
-            serde_json::de::ParserNumber::F64(v),
-        )),
-        serde_hjson::Value::I64(v) => serde_json::Value::Number(serde_json::Number::from(
-            serde_json::de::ParserNumber::I64(v),
-        )),
-        serde_hjson::Value::Null => serde_json::Value::Null,
-        serde_hjson::Value::Object(m) => {
-            serde_json::Value::Object(m.into_iter().map(|(k, v)| (k, to_json_val(v))).collect())
+    fn gen_prod(&self, env: &CliEnv, project: &ProjectConfig) -> Result<()> {
+        create_docker_prod_yml(env, project)
+    }
+
+    fn apply(&self, project: &ProjectConfig, compose: &mut ComposeYml, dev: bool) {
+        crate::versions::apply(project, compose, dev);
+    }
+
+    fn set_url(&self, env: &CliEnv, project: &ProjectConfig, url: &str) -> Result<()> {
+        let cli_conn = wp_cli_conn(env, project, true)?;
+        for option in &["siteurl", "home"] {
+            let status = cli_conn.exec(format!(
+                "cd /var/www/html; wp option update {} {}",
+                option, url
+            ))?;
+            if status != 0 {
+                return Err(cli_err!(Ssh, "Failed updating {}", option));
+            }
         }
-        serde_hjson::Value::String(s) => serde_json::Value::String(s),
-        serde_hjson::Value::U64(v) => serde_json::Value::Number(serde_json::Number::from(
-            serde_json::de::ParserNumber::U64(v),
-        )),
+        Ok(())
     }
 }
 
 pub fn create_docker_prod_yml(env: &CliEnv, project: &ProjectConfig) -> Result<()> {
-    use crate::docker::{ComposeService, ComposeYml};
+    use crate::compose::{ComposeExternal, ComposeNetwork, ComposeService};
     // Set environment variable for external url
-    let server = server::get_config(env, &project.server_name).map_err(er::Io::e)?;
+    let server = server::get_config(env, &project.server_name)?;
     let mut proxy_env = BTreeMap::new();
-    let elastic_ip = match server.elastic_ip {
-        Some(elastic_ip) => elastic_ip,
+    let external = match project.external_url(&server) {
+        Some(external) => external,
         None => {
-            eprintln!("Elastic ip is required for prod.yml");
-            return Err(format_err!("Elastic ip is required for prod.yml"));
+            eprintln!("Domain or elastic ip is required for prod.yml");
+            return Err(cli_err!(
+                Config,
+                "Domain or elastic ip is required for prod.yml"
+            ));
         }
     };
-    proxy_env.insert(
-        "EXTERNAL".to_string(),
-        format!("http://{}", elastic_ip.public_ip),
-    );
+    proxy_env.insert("EXTERNAL".to_string(), external);
+    // Acme challenges and certificates in project dir on
+    // server, https is served when a certificate is issued.
+    // With several sites this is done by the front proxy
+    let mut proxy_ports = Vec::new();
+    let mut proxy_volumes = Vec::new();
+    if project.tls.is_some() && project.site.is_none() {
+        proxy_env.insert("BIND_SSL".to_string(), "https://0.0.0.0:443".to_string());
+        proxy_env.insert("CERT_DIR".to_string(), "/var/lib/acme/certs".to_string());
+        proxy_env.insert(
+            "ACME_CHALLENGE_DIR".to_string(),
+            "/var/lib/acme/challenge".to_string(),
+        );
+        proxy_ports.push("443:443".to_string());
+        proxy_volumes.push(format!(
+            "{}:/var/lib/acme:ro",
+            crate::acme::server_acme_dir(project, &server).to_string_lossy()
+        ));
+    }
+    // Proxy joins the network of each preview environment
+    // to route /preview/<slug>/ to it
+    let mut networks = BTreeMap::new();
+    let mut proxy_networks = Vec::new();
+    if !project.previews.is_empty() {
+        proxy_networks.push("default".to_string());
+        let mut previews = Vec::new();
+        for slug in project.previews.keys() {
+            let network = format!("preview-{}", slug);
+            networks.insert(
+                network.clone(),
+                ComposeNetwork {
+                    external: Some(ComposeExternal {
+                        name: crate::preview::network_name(project, slug),
+                    }),
+                    ..Default::default()
+                },
+            );
+            proxy_networks.push(network);
+            previews.push(format!(
+                "{}=http://{}",
+                slug,
+                crate::preview::wp_service(slug)
+            ));
+        }
+        proxy_env.insert("PREVIEWS".to_string(), previews.join(","));
+    }
     let proxy = ComposeService {
-        volumes: Vec::new(),
+        networks: proxy_networks,
         environment: proxy_env,
+        ports: proxy_ports,
+        volumes: proxy_volumes,
+        ..Default::default()
     };
     let mut services = BTreeMap::new();
     services.insert("proxy".to_string(), proxy);
     let yml = ComposeYml {
-        version: "3.3",
+        version: "3.3".into(),
         services,
+        networks,
+        ..Default::default()
     };
     let yml_str = match serde_yaml::to_string::<ComposeYml>(&yml) {
         Ok(yml_str) => yml_str,
-        Err(e) => return Err(format_err!("{:?}", e)),
+        Err(e) => return Err(cli_err!(Other, "{:?}", e)),
     };
     println!("{}", &yml_str);
     project.write_file(env, "docker/prod.yml", &yml_str)?;
@@ -181,8 +138,8 @@ pub fn create_docker_prod_yml(env: &CliEnv, project: &ProjectConfig) -> Result<(
 
 /// Create mount entries for directories in
 /// plugins/ and themes/ folders
-pub fn create_wp_docker_yml(env: &CliEnv, project: ProjectConfig) -> io::Result<()> {
-    use crate::docker::{ComposeService, ComposeYml};
+pub fn create_wp_docker_yml(env: &CliEnv, project: ProjectConfig) -> Result<()> {
+    use crate::compose::ComposeService;
     // Iterate plugins and themes and collect mounts
     let mut mounts = Vec::new();
     let local_site = get_local_site_data(env, &project)?;
@@ -228,23 +185,24 @@ pub fn create_wp_docker_yml(env: &CliEnv, project: ProjectConfig) -> io::Result<
         "wordpress-container".into(),
         ComposeService {
             volumes: volumes.clone(),
-            environment: BTreeMap::new(),
+            ..Default::default()
         },
     );
     services.insert(
         "wp-cli".into(),
         ComposeService {
             volumes,
-            environment: BTreeMap::new(),
+            ..Default::default()
         },
     );
     let yml = ComposeYml {
-        version: "3.3",
+        version: "3.3".into(),
         services,
+        ..Default::default()
     };
     let yml_str = match serde_yaml::to_string::<ComposeYml>(&yml) {
         Ok(yml_str) => yml_str,
-        Err(e) => return utils::io_err(format!("{:?}", e)),
+        Err(e) => return Err(cli_err!(Other, "{:?}", e)),
     };
     println!("{}", &yml_str);
     project.write_file(env, "docker/dev.yml", &yml_str)?;
@@ -259,17 +217,19 @@ pub fn wp_cli_conn(env: &CliEnv, project: &ProjectConfig, on_server: bool) -> Re
         // Todo: Could allow to choose, or return error
         match project.get_server(&env) {
             Some(server_config) => Some(server_config),
-            None => return Err(format_err!("Could not resolve server")),
+            None => return Err(cli_err!(Config, "Could not resolve server")),
         }
     } else {
         None
     };
     let conn = if on_server {
         match server_config {
-            Some(server_config) => {
-                server::SshConn::connect_wp_cli(env, 2345, Some(&server_config))?
-            }
-            None => return Err(format_err!("No server config")),
+            Some(server_config) => server::SshConn::connect_wp_cli(
+                env,
+                project.prod_wp_cli_port(),
+                Some(&server_config),
+            )?,
+            None => return Err(cli_err!(Config, "No server config")),
         }
     } else {
         server::SshConn::connect_wp_cli(env, 2345, None)?
@@ -297,7 +257,7 @@ pub fn wp_clean(
     env: &CliEnv,
     project: ProjectConfig,
     current_process: utils::CurrentProcess,
-) -> io::Result<utils::CurrentProcess> {
+) -> Result<utils::CurrentProcess> {
     // Running docker-compose down including
     // volumes
     docker::dev_cmd(
@@ -355,7 +315,7 @@ pub fn install_plugin(cli_conn: &SshConn, plugin: &str, activate: bool) -> Resul
             println!("Plugin installed and activated: {}", plugin);
             Ok(())
         }
-        Err(e) => Err(format_err!("Couldn't install: {}, {:?}", plugin, e)),
+        Err(e) => Err(cli_err!(Ssh, "Couldn't install: {}, {:?}", plugin, e)),
     }
 }
 
@@ -365,7 +325,17 @@ pub fn activate_plugin(cli_conn: &SshConn, plugin: &str) -> Result<()> {
             println!("Plugin activated: {}", plugin);
             Ok(())
         }
-        Err(e) => Err(format_err!("Couldn't activate: {}, {:?}", plugin, e)),
+        Err(e) => Err(cli_err!(Ssh, "Couldn't activate: {}, {:?}", plugin, e)),
+    }
+}
+
+pub fn install_theme(cli_conn: &SshConn, theme: &str) -> Result<()> {
+    match cli_conn.exec(format!("wp theme install {}", theme)) {
+        Ok(_) => {
+            println!("Theme installed: {}", theme);
+            Ok(())
+        }
+        Err(e) => Err(cli_err!(Ssh, "Couldn't install theme: {}, {:?}", theme, e)),
     }
 }
 
@@ -375,7 +345,7 @@ pub fn activate_theme(cli_conn: &SshConn, theme: &str) -> Result<()> {
             println!("Theme activated: {}", theme);
             Ok(())
         }
-        Err(e) => Err(format_err!("Couldn't activate theme: {}, {:?}", theme, e)),
+        Err(e) => Err(cli_err!(Ssh, "Couldn't activate theme: {}, {:?}", theme, e)),
     }
 }
 
@@ -417,10 +387,19 @@ pub fn sync_local(env: &CliEnv, project: ProjectConfig, on_server: bool) -> Resu
     let cli_conn = wp_cli_conn(env, &project, on_server)?;
     if on_server {
         sync_files_to_prod(&cli_conn, &local_data)?;
+        if let Some(lock) = updates::read_lock(env, &project)? {
+            updates::install_locked(&cli_conn, &lock)?;
+        }
     }
-    let install_data = match wp_install_data(&cli_conn) {
+    activate_local(&cli_conn, local_data)
+}
+
+/// Installs dependencies, and activates local plugins
+/// and themes on site given by cli connection
+pub fn activate_local(cli_conn: &SshConn, local_data: WpLocalSiteData) -> Result<()> {
+    let install_data = match wp_install_data(cli_conn) {
         Ok(install_data) => install_data,
-        Err(e) => return Err(format_err!("Install data error: {}", e)),
+        Err(e) => return Err(cli_err!(Ssh, "Install data error: {}", e)),
     };
     // Ensure local plugins, themes and their dependencies are activated
 
@@ -452,7 +431,8 @@ pub fn sync_local(env: &CliEnv, project: ProjectConfig, on_server: bool) -> Resu
                 }
             }
             None => {
-                return Err(format_err!(
+                return Err(cli_err!(
+                    Config,
                     "Local plugin not found as installed on site, {}",
                     plugin_name
                 ));
@@ -471,7 +451,8 @@ pub fn sync_local(env: &CliEnv, project: ProjectConfig, on_server: bool) -> Resu
                     }
                 }
                 None => {
-                    return Err(format_err!(
+                    return Err(cli_err!(
+                        Config,
                         "Local theme not found as installed on site, {}",
                         theme_name
                     ));
@@ -509,8 +490,13 @@ pub struct PluginConf {
 }
 
 // Data from project, ie local
-pub fn get_local_site_data(env: &CliEnv, project: &ProjectConfig) -> io::Result<WpLocalSiteData> {
-    let project_dir = project.dir(env);
+pub fn get_local_site_data(env: &CliEnv, project: &ProjectConfig) -> Result<WpLocalSiteData> {
+    local_site_data_in(project.dir(env))
+}
+
+/// Local site data from a given project directory,
+/// like a checkout of another branch
+pub fn local_site_data_in(project_dir: PathBuf) -> Result<WpLocalSiteData> {
     let mut site_data = WpLocalSiteData {
         project_dir: project_dir.clone(),
         plugins: HashMap::new(),
@@ -521,12 +507,12 @@ pub fn get_local_site_data(env: &CliEnv, project: &ProjectConfig) -> io::Result<
     let mut plugins_dir = project_dir.clone();
     plugins_dir.push("plugins");
     if plugins_dir.is_dir() {
-        for plugin_path in utils::entries_in_dir(&plugins_dir)? {
+        for plugin_path in utils::entries_in_dir(&plugins_dir).map_err(er::io)? {
             if plugin_path.is_dir() {
                 let plugin_name = utils::file_name_string(&plugin_path)?;
                 let from_project = plugin_path
                     .strip_prefix(&project_dir)
-                    .map_err(|e| utils::io_error(format!("Strip path error: {:?}", e)))?;
+                    .map_err(|e| cli_err!(Other, "Strip path error: {:?}", e))?;
                 let server_path = Path::new("/var/www/html/wp-content").join(from_project);
                 let mut plugin_conf_file = plugin_path.clone();
                 site_data.plugins.insert(
@@ -542,7 +528,8 @@ pub fn get_local_site_data(env: &CliEnv, project: &ProjectConfig) -> io::Result<
                 );
                 plugin_conf_file.push("plugin.json");
                 if plugin_conf_file.is_file() {
-                    let plugin_conf_str = std::fs::read_to_string(&plugin_conf_file)?;
+                    let plugin_conf_str =
+                        std::fs::read_to_string(&plugin_conf_file).map_err(er::io)?;
                     match serde_json::from_str::<PluginConf>(&plugin_conf_str) {
                         Ok(plugin_conf) => {
                             // Local plugin.json config
@@ -561,12 +548,12 @@ pub fn get_local_site_data(env: &CliEnv, project: &ProjectConfig) -> io::Result<
     let mut themes_dir = project_dir.clone();
     themes_dir.push("themes");
     if themes_dir.is_dir() {
-        for theme_path in utils::entries_in_dir(&themes_dir)? {
+        for theme_path in utils::entries_in_dir(&themes_dir).map_err(er::io)? {
             if theme_path.is_dir() {
                 let theme_name = utils::file_name_string(&theme_path)?;
                 let from_project = theme_path
                     .strip_prefix(&project_dir)
-                    .map_err(|e| utils::io_error(format!("Strip path error: {:?}", e)))?;
+                    .map_err(|e| cli_err!(Other, "Strip path error: {:?}", e))?;
                 let server_path = Path::new("/var/www/html/wp-content").join(from_project);
                 site_data.themes.insert(
                     theme_name.clone(),
@@ -592,6 +579,9 @@ pub struct WpInstallPlugin {
     pub status: String,
     pub update: String,
     pub version: String,
+    /// None or empty when there is no update
+    #[serde(default)]
+    pub update_version: Option<String>,
 }
 #[derive(Deserialize, Debug)]
 pub struct WpInstallTheme {
@@ -599,6 +589,9 @@ pub struct WpInstallTheme {
     pub status: String,
     pub update: String,
     pub version: String,
+    /// None or empty when there is no update
+    #[serde(default)]
+    pub update_version: Option<String>,
 }
 #[derive(Deserialize, Debug)]
 pub struct WpInstallData {
@@ -606,24 +599,24 @@ pub struct WpInstallData {
     pub themes: HashMap<String, WpInstallTheme>,
 }
 pub fn wp_install_data(cli_conn: &SshConn) -> Result<WpInstallData> {
-    let plugins_output = match cli_conn.exec_capture("wp plugin list --format=json", Some("/var/www/html")) {
+    let plugins_output = match cli_conn.exec_capture("wp plugin list --format=json --fields=name,status,update,version,update_version", Some("/var/www/html")) {
         Ok(output) => output,
-        Err(e) => return Err(format_err!("Plugin list failed: {:?}", e)),
+        Err(e) => return Err(cli_err!(Ssh, "Plugin list failed: {:?}", e)),
     };
-    let themes_output = match cli_conn.exec_capture("wp theme list --format=json", Some("/var/www/html")) {
+    let themes_output = match cli_conn.exec_capture("wp theme list --format=json --fields=name,status,update,version,update_version", Some("/var/www/html")) {
         Ok(output) => output,
-        Err(e) => return Err(format_err!("Theme list failed: {:?}", e)),
+        Err(e) => return Err(cli_err!(Ssh, "Theme list failed: {:?}", e)),
     };
     let plugins = match serde_json::from_str::<Vec<WpInstallPlugin>>(&plugins_output) {
         Ok(plugins) => plugins,
         Err(e) => {
-            return Err(format_err!("Failed deserialize plugins: {:?}", e));
+            return Err(cli_err!(Other, "Failed deserialize plugins: {:?}", e));
         }
     };
     let themes = match serde_json::from_str::<Vec<WpInstallTheme>>(&themes_output) {
         Ok(themes) => themes,
         Err(e) => {
-            return Err(format_err!("Failed deserialize themes: {:?}", e));
+            return Err(cli_err!(Other, "Failed deserialize themes: {:?}", e));
         }
     };
     let data = WpInstallData {
@@ -639,10 +632,3 @@ pub fn wp_install_data(cli_conn: &SshConn) -> Result<WpInstallData> {
     println!("WpData: {:#?}", data);
     Ok(data)
 }
-
-pub fn sql_cli(env: &CliEnv, sql: &str) -> Result<()> {
-    use mysql_utils::Db;
-    let mut db = Db::new("127.0.0.1", 3307, "wordpress", "wordpress", "wordpress")?;
-    db.print_query(sql)?;
-    Ok(())
-}
\ No newline at end of file
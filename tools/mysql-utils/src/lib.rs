@@ -1,9 +1,11 @@
 mod coltypes;
 pub mod er;
+pub mod output;
+pub mod search_replace;
 pub mod table_def;
 use er::{err_msg, error_msg, Result};
 use indexmap::IndexMap;
-use mysql::Value;
+use output::{Format, ResultSet};
 use std::convert::TryFrom;
 use table_def::TableDef;
 
@@ -139,25 +141,100 @@ impl Db {
     }
 
     pub fn tables_to_sexpr_files(&mut self) -> Result<()> {
+        self.tables_to_sexpr_files_in(std::path::Path::new("tables_sexpr"))
+    }
+
+    /// Writes one folder for each table in base_dir, with
+    /// create.sql and data.scm. Folders of tables no
+    /// longer in the database are removed
+    pub fn tables_to_sexpr_files_in(&mut self, base_dir: &std::path::Path) -> Result<()> {
         use std::fs;
-        use std::path::PathBuf;
-        let base_dir = PathBuf::from("tables_sexpr");
         if !base_dir.exists() {
-            fs::create_dir(&base_dir)?;
+            fs::create_dir_all(base_dir)?;
         }
+        // Definitions may have changed since last run
+        self.table_defs.clear();
+        self.init_table_defs()?;
         let tablenames = self.tablenames()?;
         // One folder for each table
-        for tablename in tablenames {
-            let mut table_dir = base_dir.clone();
-            table_dir.push(&tablename);
+        for tablename in &tablenames {
+            let table_dir = base_dir.join(tablename);
             if !table_dir.exists() {
                 fs::create_dir(&table_dir)?;
             }
             // Inside, one file for definition
             // For now, one file with data for each table
+            fs::write(
+                table_dir.join("create.sql"),
+                self.create_table_sql(tablename)?,
+            )?;
             let data_file = fs::File::create(table_dir.join("data.scm"))?;
-            self.table_to_sexpr(&tablename, Box::new(data_file))?;
+            self.table_to_sexpr(tablename, Box::new(data_file))?;
+        }
+        for entry in fs::read_dir(base_dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().is_dir() && !tablenames.contains(&name) {
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_table_sql(&mut self, table: &str) -> Result<String> {
+        let result = Self::query(&mut self.conn, &format!("show create table `{}`", table))?;
+        for row in result {
+            let mut row = row?;
+            let value: Vec<u8> = match row.take_opt(1) {
+                Some(v) => v?,
+                None => return err_msg("Could not convert"),
+            };
+            return Ok(String::from_utf8_lossy(&value).to_string());
+        }
+        err_msg(format!("No create statement for: {}", table))
+    }
+
+    /// Latest update time of any table in the database
+    /// as "%Y%m%d%H%i%S". Mysql may give null for tables
+    /// not changed since server start
+    pub fn last_update(&mut self) -> Result<Option<String>> {
+        let result = Self::query(
+            &mut self.conn,
+            "select date_format(max(update_time), '%Y%m%d%H%i%S') \
+             from information_schema.tables where table_schema = database()",
+        )?;
+        for row in result {
+            let mut row = row?;
+            return match row.take_opt(0) {
+                Some(v) => Ok(v?),
+                None => Ok(None),
+            };
+        }
+        Ok(None)
+    }
+
+    /// Sql dump of all tables, with one insert per row
+    /// so it diffs reasonably. Can be run with the mysql client
+    pub fn dump_sql(&mut self, mut out: Box<dyn std::io::Write>) -> Result<()> {
+        let tablenames = self.tablenames()?;
+        writeln!(out, "SET NAMES utf8mb4;")?;
+        writeln!(out, "SET FOREIGN_KEY_CHECKS=0;")?;
+        for tablename in tablenames {
+            writeln!(out, "\nDROP TABLE IF EXISTS `{}`;", tablename)?;
+            writeln!(out, "{};", self.create_table_sql(&tablename)?)?;
+            let result = Self::query(&mut self.conn, &format!("select * from `{}`", tablename))?;
+            for row in result {
+                let values = row?.unwrap();
+                let values = values.iter().map(|v| v.as_sql(false)).collect::<Vec<_>>();
+                writeln!(
+                    out,
+                    "INSERT INTO `{}` VALUES ({});",
+                    tablename,
+                    values.join(",")
+                )?;
+            }
         }
+        writeln!(out, "\nSET FOREIGN_KEY_CHECKS=1;")?;
         Ok(())
     }
 
@@ -259,47 +336,16 @@ impl Db {
     }
 
     pub fn print_result(result: mysql::QueryResult<'_>) -> Result<()> {
-        use std::io::Write;
         let stdout = std::io::stdout();
         let mut o = stdout.lock();
-        // Todo: Determine column widths
-        let cols = result.columns_ref();
-        let mut i = 0;
-        for col in cols {
-            if i > 0 {
-                write!(o, " | ")?;
-            }
-            write!(o, "{}", col.name_str())?;
-            i += 1;
-        }
-        writeln!(o, "")?;
-        for row in result {
-            let row = row?;
-            let values = row.unwrap();
-            // Reusing i
-            i = 0;
-            for value in values {
-                if i > 0 {
-                    write!(o, " | ")?;
-                }
-                match value {
-                    Value::Int(v) => write!(o, "{}", v)?,
-                    Value::UInt(v) => write!(o, "{}", v)?,
-                    Value::Bytes(v) => write!(o, "{}", String::from_utf8_lossy(&v))?,
-                    Value::NULL => write!(o, "NULL")?,
-                    Value::Float(v) => write!(o, "{}", v)?,
-                    Value::Time(neg, d, h, m, s, ms) => {
-                        write!(o, "{} {} {}:{}:{}:{}", neg, d, h, m, s, ms)?;
-                    }
-                    Value::Date(y, month, d, h, m, s, ms) => {
-                        write!(o, "{}-{}-{} {}:{}:{}:{}", y, month, d, h, m, s, ms)?;
-                    }
-                }
-                i += 1;
-            }
-            writeln!(o, "")?;
-        }
-        Ok(())
+        ResultSet::from_query(result)?.write(&mut o, Format::Table)
+    }
+
+    /// Runs query, collecting the result
+    /// without echoing the query
+    pub fn query_set(&mut self, q: &str) -> Result<ResultSet> {
+        let result = self.conn.query(q)?;
+        ResultSet::from_query(result)
     }
 
     pub fn query<'a>(conn: &'a mut mysql::Conn, q: &str) -> Result<mysql::QueryResult<'a>> {
@@ -0,0 +1,295 @@
+//! Query results collected as strings, written as an
+//! aligned table, vertically (like mysql's \G), csv or json
+use crate::er::{err_msg, MyLibError, Result};
+use mysql::Value;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Vertical,
+    Csv,
+    Json,
+}
+impl std::str::FromStr for Format {
+    type Err = MyLibError;
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "table" => Ok(Format::Table),
+            "vertical" => Ok(Format::Vertical),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            other => err_msg(format!("Unknown format: {}", other)),
+        }
+    }
+}
+
+/// Value as displayed, None for NULL
+pub fn value_string(value: &Value) -> Option<String> {
+    match value {
+        Value::NULL => None,
+        Value::Int(v) => Some(v.to_string()),
+        Value::UInt(v) => Some(v.to_string()),
+        Value::Float(v) => Some(v.to_string()),
+        Value::Bytes(v) => Some(String::from_utf8_lossy(v).to_string()),
+        Value::Date(y, month, d, h, m, s, 0) => Some(format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            y, month, d, h, m, s
+        )),
+        Value::Date(y, month, d, h, m, s, us) => Some(format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}",
+            y, month, d, h, m, s, us
+        )),
+        Value::Time(neg, d, h, m, s, us) => {
+            let sign = if *neg { "-" } else { "" };
+            let hours = u32::from(*h) + d * 24;
+            if *us == 0 {
+                Some(format!("{}{:02}:{:02}:{:02}", sign, hours, m, s))
+            } else {
+                Some(format!("{}{:02}:{:02}:{:02}.{:06}", sign, hours, m, s, us))
+            }
+        }
+    }
+}
+
+/// Columns and rows of a query, or affected
+/// rows of statements without result
+#[derive(Debug, Default)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Option<String>>>,
+    pub affected_rows: u64,
+}
+impl ResultSet {
+    pub fn from_query(result: mysql::QueryResult<'_>) -> Result<ResultSet> {
+        let columns = result
+            .columns_ref()
+            .iter()
+            .map(|col| col.name_str().to_string())
+            .collect::<Vec<_>>();
+        let affected_rows = result.affected_rows();
+        let mut rows = Vec::new();
+        for row in result {
+            let values = row?.unwrap();
+            rows.push(values.iter().map(value_string).collect());
+        }
+        Ok(ResultSet {
+            columns,
+            rows,
+            affected_rows,
+        })
+    }
+
+    pub fn write(&self, out: &mut dyn Write, format: Format) -> Result<()> {
+        if self.columns.is_empty() {
+            writeln!(out, "Query OK, {} rows affected", self.affected_rows)?;
+            return Ok(());
+        }
+        match format {
+            Format::Table => self.write_table(out),
+            Format::Vertical => self.write_vertical(out),
+            Format::Csv => self.write_csv(out),
+            Format::Json => self.write_json(out),
+        }
+    }
+
+    fn write_table(&self, out: &mut dyn Write) -> Result<()> {
+        let mut widths = self
+            .columns
+            .iter()
+            .map(|col| col.chars().count())
+            .collect::<Vec<_>>();
+        for row in &self.rows {
+            for (i, value) in row.iter().enumerate() {
+                let len = value.as_ref().map(|v| v.chars().count()).unwrap_or(4);
+                if len > widths[i] {
+                    widths[i] = len;
+                }
+            }
+        }
+        let separator = widths
+            .iter()
+            .map(|w| "-".repeat(w + 2))
+            .collect::<Vec<_>>()
+            .join("+");
+        let line = |out: &mut dyn Write, cells: Vec<&str>| -> Result<()> {
+            let cells = cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| {
+                    format!(" {}{} ", cell, " ".repeat(width - cell.chars().count()))
+                })
+                .collect::<Vec<_>>();
+            writeln!(out, "|{}|", cells.join("|"))?;
+            Ok(())
+        };
+        writeln!(out, "+{}+", separator)?;
+        line(&mut *out, self.columns.iter().map(String::as_str).collect())?;
+        writeln!(out, "+{}+", separator)?;
+        for row in &self.rows {
+            line(
+                &mut *out,
+                row.iter()
+                    .map(|v| v.as_ref().map(String::as_str).unwrap_or("NULL"))
+                    .collect(),
+            )?;
+        }
+        writeln!(out, "+{}+", separator)?;
+        writeln!(out, "{} rows in set", self.rows.len())?;
+        Ok(())
+    }
+
+    fn write_vertical(&self, out: &mut dyn Write) -> Result<()> {
+        let width = self
+            .columns
+            .iter()
+            .map(|col| col.chars().count())
+            .max()
+            .unwrap_or(0);
+        for (i, row) in self.rows.iter().enumerate() {
+            writeln!(
+                out,
+                "*************************** {}. row ***************************",
+                i + 1
+            )?;
+            for (col, value) in self.columns.iter().zip(row) {
+                writeln!(
+                    out,
+                    "{}{}: {}",
+                    " ".repeat(width - col.chars().count()),
+                    col,
+                    value.as_ref().map(String::as_str).unwrap_or("NULL")
+                )?;
+            }
+        }
+        writeln!(out, "{} rows in set", self.rows.len())?;
+        Ok(())
+    }
+
+    fn write_csv(&self, out: &mut dyn Write) -> Result<()> {
+        let field = |value: &str| {
+            if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value.to_string()
+            }
+        };
+        let header = self.columns.iter().map(|c| field(c)).collect::<Vec<_>>();
+        writeln!(out, "{}", header.join(","))?;
+        for row in &self.rows {
+            let values = row
+                .iter()
+                .map(|v| v.as_ref().map(|v| field(v)).unwrap_or_default())
+                .collect::<Vec<_>>();
+            writeln!(out, "{}", values.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, out: &mut dyn Write) -> Result<()> {
+        write!(out, "[")?;
+        for (i, row) in self.rows.iter().enumerate() {
+            write!(out, "{}\n  {{", if i == 0 { "" } else { "," })?;
+            for (j, (col, value)) in self.columns.iter().zip(row).enumerate() {
+                let value = match value {
+                    Some(value) => json_string(value),
+                    None => "null".to_string(),
+                };
+                write!(
+                    out,
+                    "{}{}: {}",
+                    if j == 0 { "" } else { ", " },
+                    json_string(col),
+                    value
+                )?;
+            }
+            write!(out, "}}")?;
+        }
+        writeln!(out, "{}]", if self.rows.is_empty() { "" } else { "\n" })?;
+        Ok(())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result_set() -> ResultSet {
+        ResultSet {
+            columns: vec!["id".into(), "post_title".into()],
+            rows: vec![
+                vec![Some("1".into()), Some("Hello, \"world\"".into())],
+                vec![Some("12".into()), None],
+            ],
+            affected_rows: 0,
+        }
+    }
+
+    fn written(format: Format) -> String {
+        let mut out = Vec::new();
+        result_set().write(&mut out, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn aligns_table_columns() {
+        assert_eq!(
+            written(Format::Table),
+            "+----+----------------+\n\
+             | id | post_title     |\n\
+             +----+----------------+\n\
+             | 1  | Hello, \"world\" |\n\
+             | 12 | NULL           |\n\
+             +----+----------------+\n\
+             2 rows in set\n"
+        );
+    }
+
+    #[test]
+    fn writes_vertical() {
+        assert_eq!(
+            written(Format::Vertical),
+            "*************************** 1. row ***************************\n\
+             \x20       id: 1\n\
+             post_title: Hello, \"world\"\n\
+             *************************** 2. row ***************************\n\
+             \x20       id: 12\n\
+             post_title: NULL\n\
+             2 rows in set\n"
+        );
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(
+            written(Format::Csv),
+            "id,post_title\n1,\"Hello, \"\"world\"\"\"\n12,\n"
+        );
+    }
+
+    #[test]
+    fn escapes_json() {
+        assert_eq!(
+            written(Format::Json),
+            "[\n  {\"id\": \"1\", \"post_title\": \"Hello, \\\"world\\\"\"},\n  {\"id\": \"12\", \"post_title\": null}\n]\n"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Search-replace through text columns of all tables,
+//! keeping php serialized values valid
+use crate::er::{error_msg, Result};
+use crate::Db;
+use indexmap::IndexMap;
+use mysql::Value;
+use ser_utils::replace::{replace_value, Replaced};
+
+/// Counts of a table. Cells are counted when changed,
+/// or when skipped as replacing them is not safe
+#[derive(Debug, Default)]
+pub struct TableReplace {
+    pub table: String,
+    pub rows: u64,
+    pub cells: u64,
+    pub skipped: u64,
+    /// Tables without primary key can not be updated
+    pub no_primary_key: bool,
+}
+
+#[derive(Debug, Default)]
+struct TableColumns {
+    text_columns: Vec<String>,
+    primary_keys: Vec<String>,
+}
+
+const TEXT_TYPES: [&str; 6] = [
+    "char",
+    "varchar",
+    "tinytext",
+    "text",
+    "mediumtext",
+    "longtext",
+];
+
+fn quote_ident(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+impl Db {
+    /// Replaces `from` with `to` in all text columns of the database.
+    /// With dry run, counts are reported without updating
+    pub fn search_replace(
+        &mut self,
+        from: &str,
+        to: &str,
+        dry_run: bool,
+    ) -> Result<Vec<TableReplace>> {
+        let mut tables: IndexMap<String, TableColumns> = IndexMap::new();
+        let columns = self.conn.query(
+            "select TABLE_NAME, COLUMN_NAME, DATA_TYPE, COLUMN_KEY \
+             from information_schema.COLUMNS where TABLE_SCHEMA = database() \
+             order by TABLE_NAME, ORDINAL_POSITION",
+        )?;
+        for row in columns {
+            let (table, column, data_type, column_key): (String, String, String, String) =
+                mysql::from_row_opt(row?)
+                    .map_err(|e| error_msg(format!("Could not read columns: {:?}", e)))?;
+            let entry = tables.entry(table).or_insert_with(TableColumns::default);
+            if column_key == "PRI" {
+                entry.primary_keys.push(column.clone());
+            }
+            if TEXT_TYPES.contains(&data_type.to_lowercase().as_str()) {
+                entry.text_columns.push(column);
+            }
+        }
+        let mut reports = Vec::new();
+        for (table, columns) in tables {
+            if columns.text_columns.is_empty() {
+                continue;
+            }
+            let report = self.search_replace_table(&table, &columns, from, to, dry_run)?;
+            if report.cells > 0 || report.skipped > 0 {
+                reports.push(report);
+            }
+        }
+        Ok(reports)
+    }
+
+    fn search_replace_table(
+        &mut self,
+        table: &str,
+        columns: &TableColumns,
+        from: &str,
+        to: &str,
+        dry_run: bool,
+    ) -> Result<TableReplace> {
+        let mut report = TableReplace {
+            table: table.to_string(),
+            no_primary_key: columns.primary_keys.is_empty(),
+            ..Default::default()
+        };
+        let selected = columns
+            .primary_keys
+            .iter()
+            .chain(&columns.text_columns)
+            .map(|c| quote_ident(c))
+            .collect::<Vec<_>>();
+        let condition = columns
+            .text_columns
+            .iter()
+            .map(|c| format!("instr({}, ?) > 0", quote_ident(c)))
+            .collect::<Vec<_>>()
+            .join(" or ");
+        let sql = format!(
+            "select {} from {} where {}",
+            selected.join(", "),
+            quote_ident(table),
+            condition
+        );
+        let params = columns
+            .text_columns
+            .iter()
+            .map(|_| Value::from(from))
+            .collect::<Vec<_>>();
+        // Collecting updates, as the connection is busy
+        // while reading the result
+        let mut updates = Vec::new();
+        let num_keys = columns.primary_keys.len();
+        for row in self.conn.prep_exec(sql, params)? {
+            let values = row?.unwrap();
+            let (keys, texts) = values.split_at(num_keys);
+            let mut changed = Vec::new();
+            for (column, value) in columns.text_columns.iter().zip(texts) {
+                let bytes = match value {
+                    Value::Bytes(bytes) => bytes,
+                    _ => continue,
+                };
+                let text = match std::str::from_utf8(bytes) {
+                    Ok(text) => text,
+                    Err(_) => {
+                        report.skipped += 1;
+                        continue;
+                    }
+                };
+                match replace_value(text, from, to) {
+                    Replaced::Unchanged => (),
+                    Replaced::Changed(new_value) => changed.push((column, new_value)),
+                    Replaced::Unsafe => report.skipped += 1,
+                }
+            }
+            if changed.is_empty() {
+                continue;
+            }
+            report.rows += 1;
+            report.cells += changed.len() as u64;
+            if num_keys > 0 {
+                updates.push((keys.to_vec(), changed));
+            }
+        }
+        if dry_run || report.no_primary_key {
+            return Ok(report);
+        }
+        for (keys, changed) in updates {
+            let sets = changed
+                .iter()
+                .map(|(column, _)| format!("{} = ?", quote_ident(column)))
+                .collect::<Vec<_>>();
+            let wheres = columns
+                .primary_keys
+                .iter()
+                .map(|column| format!("{} = ?", quote_ident(column)))
+                .collect::<Vec<_>>();
+            let sql = format!(
+                "update {} set {} where {}",
+                quote_ident(table),
+                sets.join(", "),
+                wheres.join(" and ")
+            );
+            let mut params = changed
+                .into_iter()
+                .map(|(_, value)| Value::from(value))
+                .collect::<Vec<_>>();
+            params.extend(keys);
+            self.conn.prep_exec(sql, params)?;
+        }
+        Ok(report)
+    }
+}
@@ -9,7 +9,7 @@ mod util;
 use proxy::*;
 
 use actix_cors::Cors;
-use actix_web::{http::uri::Uri, web, App, HttpRequest, HttpServer};
+use actix_web::{http::uri::Uri, web, App, HttpRequest, HttpResponse, HttpServer};
 use futures::future::Either;
 use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
 use std::collections::{BTreeMap, HashMap, HashSet};
@@ -120,6 +120,33 @@ fn main() {
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("bind-ssl")
+                .long("bind-ssl")
+                .value_name("BIND_SSL")
+                .env("BIND_SSL")
+                .help("Additional https address, served when a certificate is in cert dir")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cert-dir")
+                .long("cert-dir")
+                .value_name("CERT_DIR")
+                .env("CERT_DIR")
+                .help("Dir of key.pem and cert.pem, defaults to working directory")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("acme-challenge-dir")
+                .long("acme-challenge-dir")
+                .value_name("ACME_CHALLENGE_DIR")
+                .env("ACME_CHALLENGE_DIR")
+                .help("Dir of acme http-01 challenge files, served at /.well-known/acme-challenge/")
+                .required(false)
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("dev-mode")
                 .short("d")
@@ -130,6 +157,16 @@ fn main() {
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("previews")
+                .short("p")
+                .long("previews")
+                .value_name("PREVIEWS")
+                .env("PREVIEWS")
+                .help("Preview sites served under /preview/<name>/, as name=url,name2=url2")
+                .required(false)
+                .takes_value(true),
+        )
         .get_matches();
 
     // Wp url
@@ -215,6 +252,36 @@ fn main() {
         },
         None => false,
     };
+    // Certificates
+    let cert_dir = PathBuf::from(matches.value_of("cert-dir").unwrap_or("."));
+    let key_file = cert_dir.join("key.pem");
+    let cert_file = cert_dir.join("cert.pem");
+    // Additional https bind, when certificate is issued
+    let bind_ssl: Option<String> = match matches.value_of("bind-ssl") {
+        Some(bind_ssl) => match bind_ssl.parse::<Uri>() {
+            Ok(uri) => match (uri.host(), uri.port_u16()) {
+                (Some(host), port) => {
+                    if key_file.is_file() && cert_file.is_file() {
+                        Some(format!("{}:{}", host, port.unwrap_or(443)))
+                    } else {
+                        println!("No certificate in {:?}, serving without https", cert_dir);
+                        None
+                    }
+                }
+                _ => {
+                    println!("Could not parse ssl bind uri, scheme://host[:port]");
+                    std::process::exit(2);
+                }
+            },
+            Err(err) => {
+                println!("Could not parse ssl bind uri: {}", err);
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
+    let acme_challenge_dir: Option<PathBuf> =
+        matches.value_of("acme-challenge-dir").map(PathBuf::from);
     // Dev mode
     let dev_mode: bool = match matches.value_of("dev-mode") {
         Some(dev_mode) => match dev_mode {
@@ -223,6 +290,23 @@ fn main() {
         },
         None => false,
     };
+    // Previews
+    let previews: Vec<(String, String)> = match matches.value_of("previews") {
+        Some(previews) => previews
+            .split(',')
+            .filter(|preview| !preview.trim().is_empty())
+            .map(
+                |preview| match preview.trim().splitn(2, '=').collect::<Vec<_>>()[..] {
+                    [name, url] => (name.to_string(), url.trim_end_matches('/').to_string()),
+                    _ => {
+                        println!("Could not parse preview, expected name=url: {}", preview);
+                        std::process::exit(2);
+                    }
+                },
+            )
+            .collect(),
+        None => Vec::new(),
+    };
     let (bind, bind_no_scheme) = match (
         bind_uri.scheme_part().map(|s| s.as_str()),
         bind_uri.host(),
@@ -342,20 +426,39 @@ fn main() {
                 static_paths: None,
             },
         );
+        // Previews, uploads are served from the main site
+        for (name, preview_url) in previews {
+            let preview_key = PathConfigKey(format!("preview/{}", name));
+            write_path_configs.insert(
+                preview_key.clone(),
+                PathConfig {
+                    request_base: preview_url.clone(),
+                    url_path_prefix: format!("preview/{}", name),
+                    key: preview_key,
+                    replacements: vec![
+                        (
+                            preview_url.clone() + "/wp-content/uploads",
+                            external_uri.clone() + "/wp-content/uploads",
+                        ),
+                        (preview_url, external_uri.clone() + "/preview/" + &name),
+                    ],
+                    forward: HashSet::new(),
+                    static_paths: None,
+                },
+            );
+        }
     }
     let sys = actix_rt::System::new("proxy");
     let index_data = search::initial_index_data();
 
-    let ssl_builder = if ssl {
+    let ssl_builder = || {
         // Ssl builder
         let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
         builder
-            .set_private_key_file("key.pem", SslFiletype::PEM)
+            .set_private_key_file(&key_file, SslFiletype::PEM)
             .unwrap();
-        builder.set_certificate_chain_file("cert.pem").unwrap();
-        Some(builder)
-    } else {
-        None
+        builder.set_certificate_chain_file(&cert_file).unwrap();
+        builder
     };
 
     // Setting up services
@@ -375,6 +478,8 @@ fn main() {
                 page_exp_minute_interval: 3,
                 dev_mode,
             })
+            .data(AcmeChallengeDir(acme_challenge_dir.clone()))
+            .service(web::resource("/.well-known/acme-challenge/{token}").to(acme_challenge))
             .service(web::resource("/--id-changed/{id}").to_async(id_changed)) // todo: post
             .service(web::resource("/--pod-added/{pod}").to_async(pod_added))
             .service(web::resource("/--clear-cache").to(clear_cache))
@@ -401,6 +506,18 @@ fn main() {
                     do_request_forward(data, req, payload, PathConfigKey("wp-json".into()), None)
                 },
             ))
+            // Previews are not cached, as the cache is keyed by path
+            .service(web::resource("/preview/{name}/{url_path:.*}").to_async(
+                |req: HttpRequest, data: web::Data<AppData>, payload: web::Payload| {
+                    let key = PathConfigKey(format!("preview/{}", req.match_info().query("name")));
+                    let exists = data.path_configs.read().unwrap().contains_key(&key);
+                    if exists {
+                        Either::A(do_request_forward(data, req, payload, key, None))
+                    } else {
+                        Either::B(futures::future::ok(HttpResponse::NotFound().finish()))
+                    }
+                },
+            ))
             .service(web::resource("/{url_path:.*}").to_async(
                 move |req: HttpRequest, data: web::Data<AppData>, payload: web::Payload| {
                     // Quick exception here for a page in customizer
@@ -421,11 +538,18 @@ fn main() {
                 },
             ))
     });
-    let server = if let Some(ssl_builder) = ssl_builder {
-        server.bind_ssl(bind_no_scheme, ssl_builder)
+    let server = if ssl {
+        server.bind_ssl(bind_no_scheme, ssl_builder())
     } else {
         server.bind(bind_no_scheme)
     };
+    let server = match (server, bind_ssl) {
+        (Ok(server), Some(bind_ssl)) => {
+            println!("Listening on https://{}", bind_ssl);
+            server.bind_ssl(bind_ssl, ssl_builder())
+        }
+        (server, _) => server,
+    };
     match server {
         Ok(server) => {
             server.start();
@@ -444,3 +568,25 @@ fn main() {
         }
     }
 }
+
+/// Dir of acme challenge files, if any
+struct AcmeChallengeDir(Option<PathBuf>);
+
+/// Serves http-01 challenge responses put in the
+/// challenge dir while a certificate is issued
+fn acme_challenge(req: HttpRequest, dir: web::Data<AcmeChallengeDir>) -> HttpResponse {
+    let token = req.match_info().query("token");
+    let valid = !token.is_empty()
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    match (&dir.0, valid) {
+        (Some(dir), true) => match std::fs::read(dir.join(token)) {
+            Ok(content) => HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .body(content),
+            Err(_) => HttpResponse::NotFound().finish(),
+        },
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
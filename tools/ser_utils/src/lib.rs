@@ -1,6 +1,7 @@
 pub mod php;
 pub mod json;
 pub mod php_json;
+pub mod replace;
 pub mod sexpr;
 
 #[cfg(test)]
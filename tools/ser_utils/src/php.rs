@@ -40,6 +40,16 @@ pub fn deserialize(s: &[u8]) -> Result<PhpValue, String> {
     Ok(php_value)
 }
 
+/// Deserializes value, failing when there
+/// is more input after it
+pub fn deserialize_exact(s: &[u8]) -> Result<PhpValue, String> {
+    let (rest, php_value) = deserialize_value(s)?;
+    if !rest.is_empty() {
+        return Err(format!("Unexpected input after value: {} bytes", rest.len()));
+    }
+    Ok(php_value)
+}
+
 // Todo: Consider some "query dsl", if need arises
 // one example: https://www.reddit.com/r/rust/comments/cl3sp7/gjson_json_parser_for_rust_get_json_values_quickly/
 
@@ -110,17 +120,17 @@ pub fn serialize(value: PhpValue, mut s: String) -> String {
         }
     }
 }
-const A_U8: u8 = 'a' as u8;
-const I_U8: u8 = 'i' as u8;
-const S_U8: u8 = 's' as u8;
-const B_U8: u8 = 'b' as u8;
-const D_U8: u8 = 'd' as u8;
-const N_U8: u8 = 'N' as u8;
-const O_U8: u8 = 'O' as u8;
-const C_U8: u8 = 'C' as u8;
+const A_U8: u8 = b'a';
+const I_U8: u8 = b'i';
+const S_U8: u8 = b's';
+const B_U8: u8 = b'b';
+const D_U8: u8 = b'd';
+const N_U8: u8 = b'N';
+const O_U8: u8 = b'O';
+const C_U8: u8 = b'C';
 
 fn deserialize_value(s: &[u8]) -> Result<(&[u8], PhpValue), String> {
-    if s.len() < 1 {
+    if s.is_empty() {
         return Err("Could not deserialize value from empty string".to_string());
     }
     match s[0] {
@@ -169,10 +179,10 @@ fn deserialize_value(s: &[u8]) -> Result<(&[u8], PhpValue), String> {
             // Bool
             let s = expect_char(&s[1..], ':')?;
             // Shortcut checking for also for ';'
-            if s.len() < 2 || s[1] != ';' as u8 {
+            if s.len() < 2 || s[1] != b';' {
                 return Err("Error parsing boolean".to_string());
             }
-            if s[2] == '1' as u8 {
+            if s[0] == b'1' {
                 Ok((&s[2..], PhpValue::Bool(true)))
             } else {
                 // Assuming '0', false
@@ -181,15 +191,17 @@ fn deserialize_value(s: &[u8]) -> Result<(&[u8], PhpValue), String> {
         }
         D_U8 => {
             let s = expect_char(&s[1..], ':')?;
-            let (s, whole) = read_number(s)?;
-            let s = expect_char(s, '.')?;
-            let (s, fraction) = read_number(s)?;
-            // Slightly suboptimal
-            let f = match format!("{}.{}", whole, fraction).parse::<f64>() {
+            // Reading as text, fraction may have leading
+            // zeros, and there may be an exponent
+            let end = match s.iter().position(|c| *c == b';') {
+                Some(end) => end,
+                None => return Err("Error parsing f64, missing ;".to_string())
+            };
+            let f = match String::from_utf8_lossy(&s[..end]).parse::<f64>() {
                 Ok(f) => f,
                 Err(e) => return Err(format!("Error parsing f64, {:?}", e))
             };
-            let s = expect_char(s, ';')?;
+            let s = expect_char(&s[end..], ';')?;
             Ok((s, PhpValue::Float(f)))
         }
         N_U8 => {
@@ -242,52 +254,55 @@ fn deserialize_value(s: &[u8]) -> Result<(&[u8], PhpValue), String> {
         }
         other => {
             //println!("{}", String::from_utf8_lossy(s));
-            return Err(format!("Unexpected char: {}", other as char))
+            Err(format!("Unexpected char: {}", other as char))
         },
     }
 }
 
-const N1: u8 = '1' as u8;
-const N2: u8 = '2' as u8;
-const N3: u8 = '3' as u8;
-const N4: u8 = '4' as u8;
-const N5: u8 = '5' as u8;
-const N6: u8 = '6' as u8;
-const N7: u8 = '7' as u8;
-const N8: u8 = '8' as u8;
-const N9: u8 = '9' as u8;
-const N0: u8 = '0' as u8;
+const N1: u8 = b'1';
+const N2: u8 = b'2';
+const N3: u8 = b'3';
+const N4: u8 = b'4';
+const N5: u8 = b'5';
+const N6: u8 = b'6';
+const N7: u8 = b'7';
+const N8: u8 = b'8';
+const N9: u8 = b'9';
+const N0: u8 = b'0';
 fn read_number(s: &[u8]) -> Result<(&[u8], i64), String> {
     let mut n: i64 = 0;
     let mut i = 0;
-    let len = s.len();
-    let (neg, s) = if len > 0 && s[0] == '-' as u8 {
+    let (neg, s) = if !s.is_empty() && s[0] == b'-' {
         (true, &s[1..])
     } else {
         (false, s)
     };
+    let len = s.len();
     while i < len {
-        n = match s[i] {
-            N1 => n * 10 + 1,
-            N2 => n * 10 + 2,
-            N3 => n * 10 + 3,
-            N4 => n * 10 + 4,
-            N5 => n * 10 + 5,
-            N6 => n * 10 + 6,
-            N7 => n * 10 + 7,
-            N8 => n * 10 + 8,
-            N9 => n * 10 + 9,
-            N0 => n * 10,
+        let digit = match s[i] {
+            N1 => 1,
+            N2 => 2,
+            N3 => 3,
+            N4 => 4,
+            N5 => 5,
+            N6 => 6,
+            N7 => 7,
+            N8 => 8,
+            N9 => 9,
+            N0 => 0,
             _ => break
         };
-        i = i + 1;
+        n = match n.checked_mul(10).and_then(|n| n.checked_add(digit)) {
+            Some(n) => n,
+            None => return Err("Number too large".to_string())
+        };
+        i += 1;
     }
     // Return read number and number of digits
     if i == 0 {
-        println!("{}", String::from_utf8_lossy(s));
         Err("No digits found".to_string())
     } else {
-        Ok((&s[i..], if neg { n * -1 } else { n }))
+        Ok((&s[i..], if neg { -n } else { n }))
     }
 }
 #[inline]
@@ -303,7 +318,7 @@ fn read_pair(s: &[u8]) -> Result<(&[u8], (PhpValue, PhpValue)), String> {
 // If we had been sure, we could skip most of these
 #[inline]
 fn expect_char(s: &[u8], c: char) -> Result<&[u8], String> {
-    if s.len() > 0 && s[0] == c as u8 {
+    if !s.is_empty() && s[0] == c as u8 {
         Ok(&s[1..])
     } else {
         Err(format!("Expected char not found: {}", c))
@@ -19,9 +19,8 @@ pub fn serialize_json_lines(mut b: String, value: PhpValue, indent: usize) -> St
             // Represent array as json array with
             // tuple arrays [key, value] inside
             // This preserves int keys from php
-            b.push_str("[");
-            let mut curr = 0;
-            for (k, v) in map {
+            b.push('[');
+            for (curr, (k, v)) in map.into_iter().enumerate() {
                 if curr > 0 {
                     b.push_str(",\n");
                 } else {
@@ -30,7 +29,7 @@ pub fn serialize_json_lines(mut b: String, value: PhpValue, indent: usize) -> St
                 for _ in 0..indent {
                     b.push('\t');
                 }
-                b.push_str("[");
+                b.push('[');
                 match k {
                     ArrKey::Int(idx) => {
                         b.push_str(&idx.to_string());
@@ -42,7 +41,6 @@ pub fn serialize_json_lines(mut b: String, value: PhpValue, indent: usize) -> St
                 b.push_str(", ");
                 b = serialize_json_lines(b, v, indent + 1);
                 b.push(']');
-                curr += 1;
             }
             b.push('\n');
             if indent > 1 {
@@ -67,8 +65,7 @@ pub fn serialize_json_lines(mut b: String, value: PhpValue, indent: usize) -> St
             b.push('{');
             b = json_escape_string(&class_name, b);
             b.push_str(": [");
-            let mut curr = 0;
-            for (member_key, member_value) in members {
+            for (curr, (member_key, member_value)) in members.into_iter().enumerate() {
                 if curr > 0 {
                     b.push_str(",\n");
                 } else {
@@ -77,12 +74,11 @@ pub fn serialize_json_lines(mut b: String, value: PhpValue, indent: usize) -> St
                 for _ in 0..indent {
                     b.push('\t');
                 }
-                b.push_str("[");
+                b.push('[');
                 b = json_escape_string(&member_key, b);
                 b.push_str(", ");
                 b = serialize_json_lines(b, member_value, indent + 1);
                 b.push(']');
-                curr += 1;
             }
             b.push('\n');
             if indent > 1 {
@@ -0,0 +1,166 @@
+//! Search-replace in values which may be php serialized,
+//! like wordpress options and meta. Serialized values are
+//! decoded, also when nested in strings, and serialized
+//! again with string lengths matching the new content
+use crate::php::{self, PhpValue};
+
+#[derive(Debug, PartialEq)]
+pub enum Replaced {
+    /// Search string not found
+    Unchanged,
+    Changed(String),
+    /// Looks serialized, but can not be read and written back
+    /// exactly, so replacing could corrupt it
+    Unsafe,
+}
+
+/// Similar to wordpress is_serialized()
+pub fn looks_serialized(value: &str) -> bool {
+    let value = value.trim();
+    if value == "N;" {
+        return true;
+    }
+    let bytes = value.as_bytes();
+    if bytes.len() < 4 || bytes[1] != b':' {
+        return false;
+    }
+    match bytes[bytes.len() - 1] {
+        b';' | b'}' => (),
+        _ => return false,
+    }
+    matches!(bytes[0], b'a' | b'O' | b'C' | b's' | b'i' | b'd' | b'b')
+}
+
+/// Replaces all occurrences of `from` in value,
+/// inside serialized values when serialized
+pub fn replace_value(value: &str, from: &str, to: &str) -> Replaced {
+    if from.is_empty() || !value.contains(from) {
+        return Replaced::Unchanged;
+    }
+    if !looks_serialized(value) {
+        return Replaced::Changed(value.replace(from, to));
+    }
+    let php_value = match php::deserialize_exact(value.as_bytes()) {
+        Ok(php_value) => php_value,
+        Err(_) => return Replaced::Unsafe,
+    };
+    // Making sure nothing but the replaced strings change
+    if php::serialize(php_value.clone(), String::with_capacity(value.len())) != value {
+        return Replaced::Unsafe;
+    }
+    match replace_php(php_value, from, to) {
+        Some(php_value) => Replaced::Changed(php::serialize(
+            php_value,
+            String::with_capacity(value.len()),
+        )),
+        None => Replaced::Unsafe,
+    }
+}
+
+/// Replaces in strings of value, None when
+/// some part can not be replaced safely
+fn replace_php(value: PhpValue, from: &str, to: &str) -> Option<PhpValue> {
+    match value {
+        PhpValue::Str(s) => match replace_value(&s, from, to) {
+            Replaced::Unchanged => Some(PhpValue::Str(s)),
+            Replaced::Changed(s) => Some(PhpValue::Str(s)),
+            Replaced::Unsafe => None,
+        },
+        PhpValue::Arr(map) => {
+            let mut replaced = indexmap::IndexMap::with_capacity(map.len());
+            for (key, value) in map {
+                replaced.insert(key, replace_php(value, from, to)?);
+            }
+            Some(PhpValue::Arr(replaced))
+        }
+        PhpValue::Object(class_name, members) => {
+            let mut replaced = indexmap::IndexMap::with_capacity(members.len());
+            for (name, value) in members {
+                replaced.insert(name, replace_php(value, from, to)?);
+            }
+            Some(PhpValue::Object(class_name, replaced))
+        }
+        // Custom format of the class, which may have
+        // its own lengths
+        PhpValue::Serializable(class_name, serialized) => {
+            if serialized.contains(from) {
+                None
+            } else {
+                Some(PhpValue::Serializable(class_name, serialized))
+            }
+        }
+        other => Some(other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FROM: &str = "http://old.test";
+    const TO: &str = "https://new.example.com";
+
+    #[test]
+    fn replaces_plain_values() {
+        assert_eq!(
+            replace_value("<a href=\"http://old.test/page\">", FROM, TO),
+            Replaced::Changed("<a href=\"https://new.example.com/page\">".into())
+        );
+        assert_eq!(replace_value("nothing here", FROM, TO), Replaced::Unchanged);
+    }
+
+    #[test]
+    fn updates_serialized_lengths() {
+        let value = r#"a:2:{s:3:"url";s:20:"http://old.test/shop";s:4:"keep";b:1;}"#;
+        assert_eq!(
+            replace_value(value, FROM, TO),
+            Replaced::Changed(
+                r#"a:2:{s:3:"url";s:28:"https://new.example.com/shop";s:4:"keep";b:1;}"#.into()
+            )
+        );
+    }
+
+    #[test]
+    fn replaces_in_nested_serialized() {
+        // Serialized array inside a serialized string
+        let inner = r#"a:1:{i:0;s:15:"http://old.test";}"#;
+        let value = format!(r#"a:1:{{s:5:"inner";s:{}:"{}";}}"#, inner.len(), inner);
+        let new_inner = r#"a:1:{i:0;s:23:"https://new.example.com";}"#;
+        let expected = format!(
+            r#"a:1:{{s:5:"inner";s:{}:"{}";}}"#,
+            new_inner.len(),
+            new_inner
+        );
+        assert_eq!(replace_value(&value, FROM, TO), Replaced::Changed(expected));
+    }
+
+    #[test]
+    fn replaces_in_objects() {
+        let value = r#"O:8:"stdClass":2:{s:4:"home";s:15:"http://old.test";s:5:"ratio";d:0.05;}"#;
+        assert_eq!(
+            replace_value(value, FROM, TO),
+            Replaced::Changed(
+                r#"O:8:"stdClass":2:{s:4:"home";s:23:"https://new.example.com";s:5:"ratio";d:0.05;}"#
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn leaves_broken_serialized() {
+        // Wrong length, as left by a naive replace
+        let value = r#"a:1:{i:0;s:10:"http://old.test";}"#;
+        assert_eq!(replace_value(value, FROM, TO), Replaced::Unsafe);
+        let value = r#"C:11:"ArrayObject":29:{x:i:0;s:15:"http://old.test";}"#;
+        assert_eq!(replace_value(value, FROM, TO), Replaced::Unsafe);
+    }
+
+    #[test]
+    fn detects_serialized() {
+        assert!(looks_serialized("b:1;"));
+        assert!(looks_serialized("N;"));
+        assert!(looks_serialized("a:0:{}"));
+        assert!(!looks_serialized("s:"));
+        assert!(!looks_serialized("http://old.test"));
+    }
+}
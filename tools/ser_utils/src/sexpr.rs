@@ -20,7 +20,7 @@ pub fn serialize_php(mut b: String, value: PhpValue, indent: usize) -> String {
             // Represent array as json array with
             // tuple arrays [key, value] inside
             // This preserves int keys from php
-            b.push_str("(");
+            b.push('(');
             for (k, v) in map {
                 b.push('\n');
                 for _ in 0..indent {
@@ -48,7 +48,7 @@ pub fn serialize_php(mut b: String, value: PhpValue, indent: usize) -> String {
             b.push(')');
         }
         PhpValue::Null => {
-            b.push_str("N");
+            b.push('N');
         }
         PhpValue::Float(f) => {
             b.push_str(&f.to_string());
@@ -67,7 +67,7 @@ pub fn serialize_php(mut b: String, value: PhpValue, indent: usize) -> String {
                 for _ in 0..indent {
                     b.push('\t');
                 }
-                b.push_str("(");
+                b.push('(');
                 b = escape_string(&member_key, b);
                 b.push(' ');
                 b = serialize_php(b, member_value, indent + 1);